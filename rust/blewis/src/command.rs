@@ -1,6 +1,23 @@
-use crate::{data_type::DataType, decoder::handle_decode, errors::DecodeError, store::Store};
+#![allow(clippy::unusual_byte_groupings)]
+
+use crate::{
+    data_type::{BoopArray, BoopBool, BoopError, BoopSet, BoopString, DataType, Int, StreamId},
+    decoder::{handle_decode, Frame},
+    errors::DecodeError,
+    store::{
+        bits::BitOp,
+        list::End,
+        scan::ScanFilter,
+        set::SetOp,
+        sorted_set::ZRangeBy,
+        stream::{Fields, XAddId, XTrim},
+        SetTtl, Store,
+    },
+};
 use anyhow::Ok;
-use bytes::Buf;
+use bytes::{Buf, Bytes};
+use ordered_float::OrderedFloat;
+use std::time::Duration;
 
 /// CmdType is the type of command that is to be parsed/executed.
 #[derive(Debug, PartialEq, Eq)]
@@ -8,21 +25,412 @@ pub enum CmdType {
     Get,
     GetSet,
     GetDel,
-    Set,
+    GetVersioned,
+    Set(SetFlags),
+    MGet,
+    MSet {
+        atomic: bool,
+    },
+    Cas {
+        expected: DataType,
+    },
+    Expire {
+        ms: u64,
+    },
+    Ttl,
+    Persist,
+    Scan {
+        cursor: u64,
+        count: usize,
+        filter: ScanFilter,
+    },
+    Exists,
+    Del,
+    Type,
+    DbSize,
+    FlushDb {
+        in_background: bool,
+    },
+    FlushAll {
+        in_background: bool,
+    },
+    Rename,
+    Push(End),
+    Pop(End),
+    LIndex {
+        index: i64,
+    },
+    LRange {
+        start: i64,
+        stop: i64,
+    },
+    LSet {
+        index: i64,
+    },
+    LTrim {
+        start: i64,
+        stop: i64,
+    },
+    LLen,
+    BPop {
+        end: End,
+        timeout_ms: u64,
+    },
+    Append,
+    StrLen,
+    GetRange {
+        start: i64,
+        stop: i64,
+    },
+    SetRange {
+        offset: usize,
+    },
+    SetBit {
+        offset: u64,
+        bit: bool,
+    },
+    GetBit {
+        offset: u64,
+    },
+    BitCount {
+        range: Option<(i64, i64)>,
+    },
+    BitPos {
+        bit: bool,
+        start: i64,
+        stop: Option<i64>,
+    },
+    BitOp(BitOp),
+    ZAdd {
+        nx: bool,
+        xx: bool,
+    },
+    ZRem,
+    ZScore,
+    ZIncrBy {
+        by: OrderedFloat<f64>,
+    },
+    ZRank {
+        rev: bool,
+    },
+    ZRange {
+        by: ZRangeBy,
+        rev: bool,
+        with_scores: bool,
+    },
+    SAdd,
+    SRem,
+    SIsMember,
+    SCard,
+    SMembers,
+    SRandMember {
+        count: i64,
+    },
+    SetOp(SetOp),
+    SetOpStore(SetOp),
+    PfAdd,
+    PfCount,
+    PfMerge,
+    XAdd {
+        id: XAddId,
+        trim: Option<XTrim>,
+    },
+    XRange {
+        start: StreamId,
+        end: StreamId,
+        count: Option<usize>,
+        rev: bool,
+    },
+    XLen,
+    XTrim(XTrim),
+    XGroupCreate {
+        group: DataType,
+        start: Option<StreamId>,
+        mkstream: bool,
+    },
+    XReadGroup {
+        group: DataType,
+        consumer: DataType,
+        from: Option<StreamId>,
+        count: Option<usize>,
+        noack: bool,
+    },
+    XAck {
+        group: DataType,
+        ids: Vec<StreamId>,
+    },
+    XPending {
+        group: DataType,
+    },
+    Multi,
+    Exec,
+    Discard,
+    Watch,
+    Unwatch,
+    Select(usize),
+    Move {
+        db: usize,
+    },
+    SwapDb(usize, usize),
+    On {
+        db: usize,
+        cmd: Box<Command>,
+    },
+    MemoryUsage {
+        samples: usize,
+    },
+    Info,
+    Publish,
+    Subscribe,
+    PSubscribe,
+    Unsubscribe,
+    PUnsubscribe,
+    Tracking {
+        on: bool,
+    },
+    Save {
+        in_background: bool,
+    },
+}
+
+impl CmdType {
+    /// Whether the command keeps state across commands, or works across databases, in which case
+    /// it can only be ran through a `Session` rather than against a single store
+    pub(crate) fn needs_session(&self) -> bool {
+        matches!(
+            self,
+            CmdType::Multi
+                | CmdType::Exec
+                | CmdType::Discard
+                | CmdType::Watch
+                | CmdType::Unwatch
+                | CmdType::FlushAll { .. }
+                | CmdType::Select(_)
+                | CmdType::Move { .. }
+                | CmdType::SwapDb(..)
+                | CmdType::On { .. }
+                | CmdType::Subscribe
+                | CmdType::PSubscribe
+                | CmdType::Unsubscribe
+                | CmdType::PUnsubscribe
+                | CmdType::Tracking { .. }
+                | CmdType::Save { .. }
+        )
+    }
+
+    /// Whether the command can grow the memory that the store holds, in which case the store has
+    /// to make room for it before it runs, see `Store::make_room`. Commands that only ever shrink
+    /// or read values can always run.
+    pub(crate) fn grows_memory(&self) -> bool {
+        matches!(
+            self,
+            CmdType::GetSet
+                | CmdType::Set(_)
+                | CmdType::MSet { .. }
+                | CmdType::Cas { .. }
+                | CmdType::Push(_)
+                | CmdType::LSet { .. }
+                | CmdType::Append
+                | CmdType::SetRange { .. }
+                | CmdType::SetBit { .. }
+                | CmdType::BitOp(_)
+                | CmdType::ZAdd { .. }
+                | CmdType::ZIncrBy { .. }
+                | CmdType::SAdd
+                | CmdType::SetOpStore(_)
+                | CmdType::PfAdd
+                | CmdType::PfMerge
+                | CmdType::XAdd { .. }
+                | CmdType::XGroupCreate { .. }
+                | CmdType::XReadGroup { .. }
+        )
+    }
+}
+
+/// The options which can be given to the SET command via its flags byte
+#[derive(Debug, PartialEq, Eq, Default, Clone, Copy)]
+pub struct SetFlags {
+    /// Only set the value if the key does not exist yet
+    nx: bool,
+    /// Only set the value if the key already exists
+    xx: bool,
+    /// Reply with the previous value instead of whether the value was written
+    get: bool,
+    /// Keep the expiry of the existing entry instead of clearing it
+    keep_ttl: bool,
+    /// Expire the value after this many milliseconds
+    expire_in: Option<u64>,
+    /// Only set the value if the key exists and still has this version
+    version: Option<u64>,
+}
+
+const SET_NX: u8 = 0b_0000_0001;
+const SET_XX: u8 = 0b_0000_0010;
+const SET_GET: u8 = 0b_0000_0100;
+const SET_KEEP_TTL: u8 = 0b_0000_1000;
+const SET_PX: u8 = 0b_0001_0000;
+const SET_VERSION: u8 = 0b_0010_0000;
+
+impl SetFlags {
+    fn from_byte(flags: u8) -> anyhow::Result<Self> {
+        let flags = SetFlags {
+            nx: flags & SET_NX != 0,
+            xx: flags & SET_XX != 0,
+            get: flags & SET_GET != 0,
+            keep_ttl: flags & SET_KEEP_TTL != 0,
+            expire_in: None,
+            version: None,
+        };
+
+        if flags.nx && flags.xx {
+            anyhow::bail!(DecodeError::InvalidArgument(
+                "SET flags NX and XX are mutually exclusive"
+            ))
+        }
+
+        Ok(flags)
+    }
+
+    /// How the expiry of the key should be treated when it is written to
+    fn ttl(&self) -> SetTtl {
+        match self.expire_in {
+            Some(ms) => SetTtl::ExpireIn(ms),
+            None if self.keep_ttl => SetTtl::Keep,
+            None => SetTtl::Clear,
+        }
+    }
 }
 
+const SCAN_MATCH: u8 = 0b_0000_0001;
+const SCAN_TYPE: u8 = 0b_0000_0010;
+
+/// The default amount of entries SCAN examines, if the client asks for zero
+const SCAN_DEFAULT_COUNT: usize = 10;
+
+/// FLUSHALL flag which frees the removed entries on a background thread
+const FLUSH_ASYNC: u8 = 0b_0000_0001;
+
+/// MSET flag which makes the whole batch visible to other clients atomically
+const MSET_ATOMIC: u8 = 0b_0000_0001;
+
+/// BITCOUNT flag which limits the count to a range of bytes
+const BITCOUNT_RANGE: u8 = 0b_0000_0001;
+
+/// BITPOS flags which limit the search to start at, and optionally stop at, a byte
+const BITPOS_START: u8 = 0b_0000_0001;
+const BITPOS_STOP: u8 = 0b_0000_0010;
+
+/// ZADD flags which only add new members, or only update existing members
+const ZADD_NX: u8 = 0b_0000_0001;
+const ZADD_XX: u8 = 0b_0000_0010;
+
+/// ZRANK and ZRANGE flag which orders members from the highest score
+const Z_REV: u8 = 0b_0000_0001;
+
+/// ZRANGE flags
+const ZRANGE_BY_SCORE: u8 = 0b_0000_0010;
+const ZRANGE_LIMIT: u8 = 0b_0000_0100;
+const ZRANGE_WITH_SCORES: u8 = 0b_0000_1000;
+const ZRANGE_MIN_EXCLUSIVE: u8 = 0b_0001_0000;
+const ZRANGE_MAX_EXCLUSIVE: u8 = 0b_0010_0000;
+
+/// XADD flags which pick the ID, either all of it or only its sequence number, and trim the stream
+const XADD_AUTO_ID: u8 = 0b_0000_0001;
+const XADD_AUTO_SEQ: u8 = 0b_0000_0010;
+const XADD_MAXLEN: u8 = 0b_0000_0100;
+const XADD_MINID: u8 = 0b_0000_1000;
+
+/// XRANGE flags
+const XRANGE_REV: u8 = 0b_0000_0001;
+const XRANGE_COUNT: u8 = 0b_0000_0010;
+
+/// XTRIM flag which trims by ID instead of by length
+const XTRIM_MINID: u8 = 0b_0000_0001;
+
+/// XGROUPCREATE flags which create the stream if needed, and start the group at the last entry
+const XGROUP_MKSTREAM: u8 = 0b_0000_0001;
+const XGROUP_LAST: u8 = 0b_0000_0010;
+
+/// XREADGROUP flags
+const XREADGROUP_COUNT: u8 = 0b_0000_0001;
+const XREADGROUP_NOACK: u8 = 0b_0000_0010;
+const XREADGROUP_PENDING: u8 = 0b_0000_0100;
+
 /// Command is the parsed structure of a Command that manipulates the system in some way.
 #[derive(Debug, PartialEq, Eq)]
 pub struct Command {
-    cmd_type: CmdType,
-    key: DataType,
-    val: Option<DataType>,
+    pub(crate) cmd_type: CmdType,
+    /// The key the command operates on. Commands which don't operate on a key, like SCAN, hold an
+    /// empty array here.
+    pub(crate) key: DataType,
+    pub(crate) val: Option<DataType>,
 }
 
 impl Command {
+    /// The keys whose values the command reads, and whose replies a client may therefore cache.
+    /// Commands that write to a key read nothing worth caching.
+    pub(crate) fn read_keys(&self) -> &[DataType] {
+        match self.cmd_type {
+            CmdType::Get
+            | CmdType::GetVersioned
+            | CmdType::Ttl
+            | CmdType::Type
+            | CmdType::LIndex { .. }
+            | CmdType::LRange { .. }
+            | CmdType::LLen
+            | CmdType::StrLen
+            | CmdType::GetRange { .. }
+            | CmdType::GetBit { .. }
+            | CmdType::BitCount { .. }
+            | CmdType::BitPos { .. }
+            | CmdType::ZScore
+            | CmdType::ZRank { .. }
+            | CmdType::ZRange { .. }
+            | CmdType::SIsMember
+            | CmdType::SCard
+            | CmdType::SMembers
+            | CmdType::XRange { .. }
+            | CmdType::XLen => std::slice::from_ref(&self.key),
+            CmdType::MGet | CmdType::Exists | CmdType::SetOp(_) | CmdType::PfCount => {
+                match &self.key {
+                    // Arrays decoded from a command are never pushed to at the front, so the first
+                    // slice holds every key
+                    DataType::Array(BoopArray(keys)) => keys.as_slices().0,
+                    _ => &[],
+                }
+            }
+            _ => &[],
+        }
+    }
+
+    /// Runs a blocking command, which stops waiting once `hung_up` says that the client it runs
+    /// for has disconnected, see `Store::blocking_pop`. Any other command is simply executed.
+    pub fn execute_blocking(self, store: Store, hung_up: &dyn Fn() -> bool) -> Option<DataType> {
+        let CmdType::BPop { end, timeout_ms } = self.cmd_type else {
+            return self.execute(store);
+        };
+        let DataType::Array(BoopArray(keys)) = self.key else {
+            return None;
+        };
+        let keys = Vec::from(keys);
+        let timeout = (timeout_ms != 0).then(|| Duration::from_millis(timeout_ms));
+
+        Some(match store.blocking_pop(&keys, end, timeout, hung_up) {
+            Result::Ok(Some((key, element))) => BoopArray::new_wrapped(vec![key, element]),
+            Result::Ok(None) => BoopError::no_exist(),
+            Err(e) => e,
+        })
+    }
+
     #[inline(always)]
     /// Performs the operations specified by the command
     pub fn execute(self, store: Store) -> Option<DataType> {
+        if self.cmd_type.grows_memory() {
+            if let Err(e) = store.make_room() {
+                return Some(e);
+            }
+        }
+
         match self.cmd_type {
             CmdType::Get => store.get(&self.key),
             CmdType::GetSet => {
@@ -30,17 +438,534 @@ impl Command {
                 Some(store.get_set(&self.key, &self.val.unwrap()))
             }
             CmdType::GetDel => store.get_del(&self.key),
-            CmdType::Set => {
-                self.val.as_ref()?;
-                store.set(&self.key, &self.val.unwrap())
+            CmdType::GetVersioned => Some(store.get_versioned(&self.key).map_or_else(
+                BoopError::no_exist,
+                |(value, version, modified_at)| {
+                    BoopArray::new_wrapped(vec![
+                        value,
+                        Int::new_u64(version),
+                        Int::new_u64(modified_at),
+                    ])
+                },
+            )),
+            CmdType::Set(flags) => {
+                let val = self.val?;
+
+                // Each variant is a single atomic operation on the store, so there is no window
+                // between checking for the key and writing to it
+                let ttl = flags.ttl();
+                let (written, previous) = if let Some(version) = flags.version {
+                    store.set_if_version(&self.key, &val, version, ttl)
+                } else if flags.nx {
+                    let existing = store.set_nx(&self.key, &val, ttl);
+                    (existing.is_none(), existing)
+                } else if flags.xx {
+                    let replaced = store.set_xx(&self.key, &val, ttl);
+                    (replaced.is_some(), replaced)
+                } else {
+                    (true, store.set(&self.key, &val, ttl))
+                };
+
+                if flags.get {
+                    Some(previous.unwrap_or_else(BoopError::no_exist))
+                } else {
+                    Some(BoopBool::new_wrapped(written))
+                }
+            }
+            CmdType::MGet => {
+                let DataType::Array(BoopArray(keys)) = self.key else {
+                    return None;
+                };
+                let keys = Vec::from(keys);
+
+                let values = store
+                    .mget(&keys)
+                    .into_iter()
+                    .map(|v| v.unwrap_or_else(BoopError::no_exist))
+                    .collect();
+
+                Some(BoopArray::new_wrapped(values))
+            }
+            CmdType::MSet { atomic } => {
+                let DataType::Array(BoopArray(flat)) = self.key else {
+                    return None;
+                };
+                let flat = Vec::from(flat);
+
+                let pairs: Vec<(DataType, DataType)> = flat
+                    .chunks_exact(2)
+                    .map(|kv| (kv[0].to_owned(), kv[1].to_owned()))
+                    .collect();
+
+                if atomic {
+                    store.mset_atomic(&pairs);
+                } else {
+                    store.mset(&pairs);
+                }
+
+                Some(BoopBool::new_wrapped(true))
+            }
+            CmdType::Cas { expected } => {
+                let new_val = self.val?;
+                let (swapped, current) = store.cas(&self.key, &expected, &new_val);
+
+                Some(BoopArray::new_wrapped(vec![
+                    BoopBool::new_wrapped(swapped),
+                    current.unwrap_or_else(BoopError::no_exist),
+                ]))
+            }
+            CmdType::Expire { ms } => Some(BoopBool::new_wrapped(store.expire(&self.key, ms))),
+            CmdType::Ttl => match store.ttl(&self.key) {
+                Some(Some(ms)) => Some(Int::new_u64(ms)),
+                Some(None) => Some(BoopBool::new_wrapped(false)),
+                None => Some(BoopError::no_exist()),
+            },
+            CmdType::Persist => Some(BoopBool::new_wrapped(store.persist(&self.key))),
+            CmdType::Scan {
+                cursor,
+                count,
+                filter,
+            } => {
+                let (next, keys) = store.scan(cursor, count, &filter);
+                Some(BoopArray::new_wrapped(vec![
+                    Int::new_u64(next),
+                    BoopArray::new_wrapped(keys),
+                ]))
+            }
+            CmdType::Exists => {
+                let DataType::Array(BoopArray(keys)) = self.key else {
+                    return None;
+                };
+                let keys = Vec::from(keys);
+                Some(Int::new_u64(store.exists(&keys) as u64))
+            }
+            CmdType::Del => {
+                let DataType::Array(BoopArray(keys)) = self.key else {
+                    return None;
+                };
+                let keys = Vec::from(keys);
+                Some(Int::new_u64(store.del(&keys) as u64))
+            }
+            CmdType::Type => match store.value_type(&self.key) {
+                Some(meta) => Some(Int::new_u8(meta)),
+                None => Some(BoopError::no_exist()),
+            },
+            CmdType::DbSize => Some(Int::new_u64(store.len() as u64)),
+            CmdType::FlushDb { in_background } => {
+                store.flush(in_background);
+                Some(BoopBool::new_wrapped(true))
+            }
+            CmdType::Rename => {
+                let new_key = self.val?;
+                if store.rename(&self.key, &new_key) {
+                    Some(BoopBool::new_wrapped(true))
+                } else {
+                    Some(BoopError::no_exist())
+                }
+            }
+            CmdType::Push(end) => {
+                let Some(DataType::Array(BoopArray(elements))) = self.val else {
+                    return None;
+                };
+                let elements = Vec::from(elements);
+                Some(
+                    store
+                        .push(&self.key, elements, end)
+                        .map_or_else(|e| e, |len| Int::new_u64(len as u64)),
+                )
+            }
+            CmdType::Pop(end) => Some(
+                store
+                    .pop(&self.key, end)
+                    .map_or_else(|e| e, |v| v.unwrap_or_else(BoopError::no_exist)),
+            ),
+            CmdType::LIndex { index } => Some(
+                store
+                    .lindex(&self.key, index)
+                    .map_or_else(|e| e, |v| v.unwrap_or_else(BoopError::no_exist)),
+            ),
+            CmdType::LRange { start, stop } => Some(
+                store
+                    .lrange(&self.key, start, stop)
+                    .map_or_else(|e| e, BoopArray::new_wrapped),
+            ),
+            CmdType::LSet { index } => Some(
+                store
+                    .lset(&self.key, index, self.val?)
+                    .map_or_else(|e| e, |_| BoopBool::new_wrapped(true)),
+            ),
+            CmdType::LTrim { start, stop } => Some(
+                store
+                    .ltrim(&self.key, start, stop)
+                    .map_or_else(|e| e, |_| BoopBool::new_wrapped(true)),
+            ),
+            CmdType::BPop { .. } => self.execute_blocking(store, &|| false),
+            CmdType::Append => {
+                let Some(DataType::String(BoopString(suffix))) = self.val else {
+                    return None;
+                };
+                Some(
+                    store
+                        .append(&self.key, &suffix)
+                        .map_or_else(|e| e, |len| Int::new_u64(len as u64)),
+                )
+            }
+            CmdType::StrLen => Some(
+                store
+                    .strlen(&self.key)
+                    .map_or_else(|e| e, |len| Int::new_u64(len as u64)),
+            ),
+            CmdType::GetRange { start, stop } => Some(
+                store
+                    .get_range(&self.key, start, stop)
+                    .map_or_else(|e| e, BoopString::new_wrapped),
+            ),
+            CmdType::SetRange { offset } => {
+                let Some(DataType::String(BoopString(bytes))) = self.val else {
+                    return None;
+                };
+                Some(
+                    store
+                        .set_range(&self.key, offset, &bytes)
+                        .map_or_else(|e| e, |len| Int::new_u64(len as u64)),
+                )
             }
+            CmdType::SetBit { offset, bit } => Some(
+                store
+                    .set_bit(&self.key, offset, bit)
+                    .map_or_else(|e| e, BoopBool::new_wrapped),
+            ),
+            CmdType::GetBit { offset } => Some(
+                store
+                    .get_bit(&self.key, offset)
+                    .map_or_else(|e| e, BoopBool::new_wrapped),
+            ),
+            CmdType::BitCount { range } => Some(
+                store
+                    .bit_count(&self.key, range)
+                    .map_or_else(|e| e, Int::new_u64),
+            ),
+            CmdType::BitPos { bit, start, stop } => {
+                Some(store.bit_pos(&self.key, bit, start, stop).map_or_else(
+                    |e| e,
+                    |pos| pos.map_or_else(BoopError::no_exist, Int::new_u64),
+                ))
+            }
+            CmdType::BitOp(op) => {
+                let Some(DataType::Array(BoopArray(keys))) = self.val else {
+                    return None;
+                };
+                let keys = Vec::from(keys);
+                Some(
+                    store
+                        .bit_op(op, &self.key, &keys)
+                        .map_or_else(|e| e, |len| Int::new_u64(len as u64)),
+                )
+            }
+            CmdType::ZAdd { nx, xx } => {
+                let Some(DataType::Array(BoopArray(pairs))) = self.val else {
+                    return None;
+                };
+                let pairs = Vec::from(pairs);
+                // The pairs are validated when parsing, so every score is a number
+                let entries = pairs
+                    .chunks_exact(2)
+                    .map(|pair| (pair[0].as_f64().unwrap_or_default(), pair[1].to_owned()))
+                    .collect();
+
+                Some(
+                    store
+                        .zadd(&self.key, entries, nx, xx)
+                        .map_or_else(|e| e, |added| Int::new_u64(added as u64)),
+                )
+            }
+            CmdType::ZRem => {
+                let Some(DataType::Array(BoopArray(members))) = self.val else {
+                    return None;
+                };
+                let members = Vec::from(members);
+                Some(
+                    store
+                        .zrem(&self.key, &members)
+                        .map_or_else(|e| e, |removed| Int::new_u64(removed as u64)),
+                )
+            }
+            CmdType::ZScore => Some(
+                store
+                    .zscore(&self.key, &self.val?)
+                    .map_or_else(|e| e, |s| s.map_or_else(BoopError::no_exist, Int::new_f64)),
+            ),
+            CmdType::ZIncrBy { by } => Some(
+                store
+                    .zincrby(&self.key, by.0, self.val?)
+                    .map_or_else(|e| e, Int::new_f64),
+            ),
+            CmdType::ZRank { rev } => Some(store.zrank(&self.key, &self.val?, rev).map_or_else(
+                |e| e,
+                |rank| rank.map_or_else(BoopError::no_exist, |r| Int::new_u64(r as u64)),
+            )),
+            CmdType::ZRange {
+                by,
+                rev,
+                with_scores,
+            } => Some(store.zrange(&self.key, by, rev).map_or_else(
+                |e| e,
+                |entries| {
+                    let reply = if with_scores {
+                        entries
+                            .into_iter()
+                            .flat_map(|(member, score)| [member, Int::new_f64(score)])
+                            .collect()
+                    } else {
+                        entries.into_iter().map(|(member, _)| member).collect()
+                    };
+                    BoopArray::new_wrapped(reply)
+                },
+            )),
+            CmdType::SAdd => {
+                let Some(DataType::Array(BoopArray(members))) = self.val else {
+                    return None;
+                };
+                let members = Vec::from(members);
+                Some(
+                    store
+                        .sadd(&self.key, members)
+                        .map_or_else(|e| e, |added| Int::new_u64(added as u64)),
+                )
+            }
+            CmdType::SRem => {
+                let Some(DataType::Array(BoopArray(members))) = self.val else {
+                    return None;
+                };
+                let members = Vec::from(members);
+                Some(
+                    store
+                        .srem(&self.key, &members)
+                        .map_or_else(|e| e, |removed| Int::new_u64(removed as u64)),
+                )
+            }
+            CmdType::SIsMember => Some(
+                store
+                    .sismember(&self.key, &self.val?)
+                    .map_or_else(|e| e, BoopBool::new_wrapped),
+            ),
+            CmdType::SCard => Some(
+                store
+                    .scard(&self.key)
+                    .map_or_else(|e| e, |len| Int::new_u64(len as u64)),
+            ),
+            CmdType::SMembers => Some(
+                store
+                    .smembers(&self.key)
+                    .map_or_else(|e| e, BoopSet::new_wrapped),
+            ),
+            CmdType::SRandMember { count } => Some(
+                store
+                    .srandmember(&self.key, count)
+                    .map_or_else(|e| e, BoopArray::new_wrapped),
+            ),
+            CmdType::SetOp(op) => {
+                let DataType::Array(BoopArray(keys)) = self.key else {
+                    return None;
+                };
+                let keys = Vec::from(keys);
+                Some(
+                    store
+                        .set_op(op, &keys)
+                        .map_or_else(|e| e, BoopSet::new_wrapped),
+                )
+            }
+            CmdType::SetOpStore(op) => {
+                let Some(DataType::Array(BoopArray(keys))) = self.val else {
+                    return None;
+                };
+                let keys = Vec::from(keys);
+                Some(
+                    store
+                        .set_op_store(op, &self.key, &keys)
+                        .map_or_else(|e| e, |len| Int::new_u64(len as u64)),
+                )
+            }
+            CmdType::PfAdd => {
+                let Some(DataType::Array(BoopArray(elements))) = self.val else {
+                    return None;
+                };
+                let elements = Vec::from(elements);
+                Some(
+                    store
+                        .pfadd(&self.key, &elements)
+                        .map_or_else(|e| e, BoopBool::new_wrapped),
+                )
+            }
+            CmdType::PfCount => {
+                let DataType::Array(BoopArray(keys)) = self.key else {
+                    return None;
+                };
+                let keys = Vec::from(keys);
+                Some(store.pfcount(&keys).map_or_else(|e| e, Int::new_u64))
+            }
+            CmdType::PfMerge => {
+                let Some(DataType::Array(BoopArray(keys))) = self.val else {
+                    return None;
+                };
+                let keys = Vec::from(keys);
+                Some(
+                    store
+                        .pfmerge(&self.key, &keys)
+                        .map_or_else(|e| e, |_| BoopBool::new_wrapped(true)),
+                )
+            }
+            CmdType::XAdd { id, trim } => {
+                let Some(DataType::Array(BoopArray(flat))) = self.val else {
+                    return None;
+                };
+                let flat = Vec::from(flat);
+                let fields: Fields = flat
+                    .chunks_exact(2)
+                    .map(|fv| (fv[0].to_owned(), fv[1].to_owned()))
+                    .collect();
+
+                Some(
+                    store
+                        .xadd(&self.key, id, fields, trim)
+                        .map_or_else(|e| e, stream_id_reply),
+                )
+            }
+            CmdType::XRange {
+                start,
+                end,
+                count,
+                rev,
+            } => Some(store.xrange(&self.key, start, end, count, rev).map_or_else(
+                |e| e,
+                |entries| {
+                    BoopArray::new_wrapped(
+                        entries
+                            .into_iter()
+                            .map(|(id, fields)| stream_entry_reply(id, Some(fields)))
+                            .collect(),
+                    )
+                },
+            )),
+            CmdType::XLen => Some(
+                store
+                    .xlen(&self.key)
+                    .map_or_else(|e| e, |len| Int::new_u64(len as u64)),
+            ),
+            CmdType::XTrim(trim) => Some(
+                store
+                    .xtrim(&self.key, trim)
+                    .map_or_else(|e| e, |removed| Int::new_u64(removed as u64)),
+            ),
+            CmdType::XGroupCreate {
+                group,
+                start,
+                mkstream,
+            } => Some(
+                store
+                    .xgroup_create(&self.key, &group, start, mkstream)
+                    .map_or_else(|e| e, BoopBool::new_wrapped),
+            ),
+            CmdType::XReadGroup {
+                group,
+                consumer,
+                from,
+                count,
+                noack,
+            } => Some(
+                store
+                    .xreadgroup(&self.key, &group, &consumer, from, count, noack)
+                    .map_or_else(
+                        |e| e,
+                        |entries| {
+                            BoopArray::new_wrapped(
+                                entries
+                                    .into_iter()
+                                    .map(|(id, fields)| stream_entry_reply(id, fields))
+                                    .collect(),
+                            )
+                        },
+                    ),
+            ),
+            CmdType::XAck { group, ids } => Some(
+                store
+                    .xack(&self.key, &group, &ids)
+                    .map_or_else(|e| e, |acked| Int::new_u64(acked as u64)),
+            ),
+            CmdType::XPending { group } => Some(store.xpending(&self.key, &group).map_or_else(
+                |e| e,
+                |pending| {
+                    BoopArray::new_wrapped(
+                        pending
+                            .into_iter()
+                            .map(|p| {
+                                BoopArray::new_wrapped(vec![
+                                    stream_id_reply(p.id),
+                                    p.consumer,
+                                    Int::new_u64(p.idle),
+                                    Int::new_u64(p.deliveries),
+                                ])
+                            })
+                            .collect(),
+                    )
+                },
+            )),
+            // Transactions keep state across commands, so they can only be ran through a `Session`
+            CmdType::Multi
+            | CmdType::Exec
+            | CmdType::Discard
+            | CmdType::Watch
+            | CmdType::Unwatch => None,
+            // As do the commands which work across databases, which a single store knows nothing of
+            CmdType::FlushAll { .. }
+            | CmdType::Select(_)
+            | CmdType::Move { .. }
+            | CmdType::SwapDb(..)
+            | CmdType::On { .. }
+            | CmdType::Save { .. } => None,
+            // Subscriptions belong to the connection, whichever database it has selected
+            CmdType::Subscribe
+            | CmdType::PSubscribe
+            | CmdType::Unsubscribe
+            | CmdType::PUnsubscribe
+            | CmdType::Tracking { .. } => None,
+            CmdType::Publish => {
+                let message = self.val.as_ref()?;
+                Some(Int::new_u64(store.publish(&self.key, message) as u64))
+            }
+            CmdType::MemoryUsage { samples } => Some(
+                store
+                    .memory_usage(&self.key, samples)
+                    .map_or_else(BoopError::no_exist, |bytes| Int::new_u64(bytes as u64)),
+            ),
+            CmdType::Info => {
+                let field = |name: &'static str| {
+                    BoopString::new_wrapped(Bytes::from_static(name.as_bytes()))
+                };
+                let policy = store.eviction_policy().name();
+                Some(BoopArray::new_wrapped(vec![
+                    field("keys"),
+                    Int::new_u64(store.len() as u64),
+                    field("used_memory"),
+                    Int::new_u64(store.used_memory() as u64),
+                    field("max_memory"),
+                    Int::new_u64(store.max_memory() as u64),
+                    field("eviction_policy"),
+                    field(policy),
+                ]))
+            }
+            CmdType::LLen => Some(
+                store
+                    .llen(&self.key)
+                    .map_or_else(|e| e, |len| Int::new_u64(len as u64)),
+            ),
         }
     }
 }
 
 #[inline(always)]
-pub fn decode_command(buf: &mut bytes::BytesMut) -> anyhow::Result<Command> {
-    if buf.is_empty() {
+pub fn decode_command(buf: &mut impl Frame) -> anyhow::Result<Command> {
+    if !buf.has_remaining() {
         anyhow::bail!(DecodeError::BufTooShort("command"))
     }
 
@@ -48,13 +973,266 @@ pub fn decode_command(buf: &mut bytes::BytesMut) -> anyhow::Result<Command> {
         0x00 => parse_get(buf),
         0x01 => parse_get_set(buf),
         0x02 => parse_get_del(buf),
+        0x03 => parse_mget(buf),
+        0x04 => parse_get_versioned(buf),
         0x10 => parse_set(buf),
+        0x11 => parse_mset(buf),
+        0x12 => parse_cas(buf),
+        0x20 => parse_expire(buf, 1000),
+        0x21 => parse_expire(buf, 1),
+        0x22 => parse_key_only(buf, CmdType::Ttl),
+        0x23 => parse_key_only(buf, CmdType::Persist),
+        0x30 => parse_scan(buf),
+        0x31 => parse_keys(buf, CmdType::Exists),
+        0x32 => parse_keys(buf, CmdType::Del),
+        0x33 => parse_key_only(buf, CmdType::Type),
+        0x34 => Ok(Command {
+            cmd_type: CmdType::DbSize,
+            key: BoopArray::new_wrapped(vec![]),
+            val: None,
+        }),
+        0x35 => parse_flush(buf, true),
+        0x36 => parse_rename(buf),
+        0x37 => parse_flush(buf, false),
+        0x40 => parse_push(buf, End::Front),
+        0x41 => parse_push(buf, End::Back),
+        0x42 => parse_key_only(buf, CmdType::Pop(End::Front)),
+        0x43 => parse_key_only(buf, CmdType::Pop(End::Back)),
+        0x44 => parse_lindex(buf),
+        0x45 => parse_list_range(buf, |start, stop| CmdType::LRange { start, stop }),
+        0x46 => parse_lset(buf),
+        0x47 => parse_list_range(buf, |start, stop| CmdType::LTrim { start, stop }),
+        0x48 => parse_key_only(buf, CmdType::LLen),
+        0x49 => parse_blocking_pop(buf, End::Front),
+        0x4A => parse_blocking_pop(buf, End::Back),
+        0x50 => parse_append(buf),
+        0x51 => parse_key_only(buf, CmdType::StrLen),
+        0x52 => parse_list_range(buf, |start, stop| CmdType::GetRange { start, stop }),
+        0x53 => parse_set_range(buf),
+        0x54 => parse_set_bit(buf),
+        0x55 => parse_get_bit(buf),
+        0x56 => parse_bit_count(buf),
+        0x57 => parse_bit_pos(buf),
+        0x58 => parse_bit_op(buf),
+        0x60 => parse_zadd(buf),
+        0x61 => parse_key_and_array(buf, CmdType::ZRem, "ZREM expects an array of members"),
+        0x62 => parse_key_and_member(buf, CmdType::ZScore),
+        0x63 => parse_zincrby(buf),
+        0x64 => parse_zrank(buf),
+        0x65 => parse_zrange(buf),
+        0x70 => parse_key_and_array(buf, CmdType::SAdd, "SADD expects an array of members"),
+        0x71 => parse_key_and_array(buf, CmdType::SRem, "SREM expects an array of members"),
+        0x72 => parse_key_and_member(buf, CmdType::SIsMember),
+        0x73 => parse_key_only(buf, CmdType::SCard),
+        0x74 => parse_key_only(buf, CmdType::SMembers),
+        0x75 => parse_srandmember(buf),
+        0x76 => parse_keys(buf, CmdType::SetOp(SetOp::Union)),
+        0x77 => parse_keys(buf, CmdType::SetOp(SetOp::Inter)),
+        0x78 => parse_keys(buf, CmdType::SetOp(SetOp::Diff)),
+        0x79 => parse_key_and_array(
+            buf,
+            CmdType::SetOpStore(SetOp::Union),
+            "SUNIONSTORE expects an array of keys",
+        ),
+        0x7A => parse_key_and_array(
+            buf,
+            CmdType::SetOpStore(SetOp::Inter),
+            "SINTERSTORE expects an array of keys",
+        ),
+        0x7B => parse_key_and_array(
+            buf,
+            CmdType::SetOpStore(SetOp::Diff),
+            "SDIFFSTORE expects an array of keys",
+        ),
+        0x80 => parse_key_and_array(buf, CmdType::PfAdd, "PFADD expects an array of elements"),
+        0x81 => parse_keys(buf, CmdType::PfCount),
+        0x82 => parse_key_and_array(buf, CmdType::PfMerge, "PFMERGE expects an array of keys"),
+        0x90 => parse_xadd(buf),
+        0x91 => parse_xrange(buf),
+        0x92 => parse_key_only(buf, CmdType::XLen),
+        0x93 => parse_xtrim(buf),
+        0x94 => parse_xgroup_create(buf),
+        0x95 => parse_xreadgroup(buf),
+        0x96 => parse_xack(buf),
+        0x97 => {
+            let key = handle_decode(buf)?;
+            let group = handle_decode(buf)?;
+            Ok(Command {
+                cmd_type: CmdType::XPending { group },
+                key,
+                val: None,
+            })
+        }
+        0xA0 => Ok(Command {
+            cmd_type: CmdType::Multi,
+            key: BoopArray::new_wrapped(vec![]),
+            val: None,
+        }),
+        0xA1 => Ok(Command {
+            cmd_type: CmdType::Exec,
+            key: BoopArray::new_wrapped(vec![]),
+            val: None,
+        }),
+        0xA2 => Ok(Command {
+            cmd_type: CmdType::Discard,
+            key: BoopArray::new_wrapped(vec![]),
+            val: None,
+        }),
+        0xA3 => parse_keys(buf, CmdType::Watch),
+        0xA4 => Ok(Command {
+            cmd_type: CmdType::Unwatch,
+            key: BoopArray::new_wrapped(vec![]),
+            val: None,
+        }),
+        0xB0 => Ok(Command {
+            cmd_type: CmdType::Select(decode_db(buf, "SELECT expects a database index")?),
+            key: BoopArray::new_wrapped(vec![]),
+            val: None,
+        }),
+        0xB1 => {
+            let key = handle_decode(buf)?;
+            Ok(Command {
+                cmd_type: CmdType::Move {
+                    db: decode_db(buf, "MOVE expects a database index")?,
+                },
+                key,
+                val: None,
+            })
+        }
+        0xB2 => Ok(Command {
+            cmd_type: CmdType::SwapDb(
+                decode_db(buf, "SWAPDB expects two database indices")?,
+                decode_db(buf, "SWAPDB expects two database indices")?,
+            ),
+            key: BoopArray::new_wrapped(vec![]),
+            val: None,
+        }),
+        0xB3 => parse_on(buf),
+        0xC0 => {
+            let key = handle_decode(buf)?;
+            let samples = decode_u64(buf, "MEMORY USAGE expects an amount of samples")?;
+            Ok(Command {
+                cmd_type: CmdType::MemoryUsage {
+                    samples: samples as usize,
+                },
+                key,
+                val: None,
+            })
+        }
+        0xC1 => Ok(Command {
+            cmd_type: CmdType::Info,
+            key: BoopArray::new_wrapped(vec![]),
+            val: None,
+        }),
+        0xD0 => parse_key_and_member(buf, CmdType::Publish),
+        0xD1 => parse_keys(buf, CmdType::Subscribe),
+        0xD2 => parse_strings(buf, CmdType::PSubscribe),
+        0xD3 => parse_keys(buf, CmdType::Unsubscribe),
+        0xD4 => parse_strings(buf, CmdType::PUnsubscribe),
+        0xE0 => {
+            let on = decode_bool(buf, "TRACKING expects a bool")?;
+            parse_strings(buf, CmdType::Tracking { on })
+        }
+        0xF0 => Ok(Command {
+            cmd_type: CmdType::Save {
+                in_background: false,
+            },
+            key: BoopArray::new_wrapped(vec![]),
+            val: None,
+        }),
+        0xF1 => Ok(Command {
+            cmd_type: CmdType::Save {
+                in_background: true,
+            },
+            key: BoopArray::new_wrapped(vec![]),
+            val: None,
+        }),
 
         byte => anyhow::bail!(DecodeError::UnknownMetaByte(byte)),
     }
 }
 
-fn parse_get(buf: &mut bytes::BytesMut) -> anyhow::Result<Command> {
+/// Decodes the next data type from the buffer, which must be an unsigned integer of any width
+fn decode_u64(buf: &mut impl Frame, arg: &'static str) -> anyhow::Result<u64> {
+    match handle_decode(buf)?.as_u64() {
+        Some(v) => Ok(v),
+        None => anyhow::bail!(DecodeError::InvalidArgument(arg)),
+    }
+}
+
+/// Decodes the next data type from the buffer as a signed integer. See `DataType::as_i64`.
+fn decode_i64(buf: &mut impl Frame, arg: &'static str) -> anyhow::Result<i64> {
+    match handle_decode(buf)?.as_i64() {
+        Some(v) => Ok(v),
+        None => anyhow::bail!(DecodeError::InvalidArgument(arg)),
+    }
+}
+
+/// Decodes the next data type from the buffer, which must be a string
+fn decode_string(buf: &mut impl Frame, arg: &'static str) -> anyhow::Result<DataType> {
+    let val = handle_decode(buf)?;
+    if !matches!(val, DataType::String(_)) {
+        anyhow::bail!(DecodeError::InvalidArgument(arg))
+    }
+    Ok(val)
+}
+
+/// Decodes the next data type from the buffer as a float, which can't be NaN. See
+/// `DataType::as_f64`.
+fn decode_f64(buf: &mut impl Frame, arg: &'static str) -> anyhow::Result<f64> {
+    match handle_decode(buf)?.as_f64() {
+        Some(v) if !v.is_nan() => Ok(v),
+        _ => anyhow::bail!(DecodeError::InvalidArgument(arg)),
+    }
+}
+
+/// Decodes the next data type from the buffer as a bool
+fn decode_bool(buf: &mut impl Frame, arg: &'static str) -> anyhow::Result<bool> {
+    match handle_decode(buf)? {
+        DataType::Bool(BoopBool(v)) => Ok(v),
+        _ => anyhow::bail!(DecodeError::InvalidArgument(arg)),
+    }
+}
+
+/// Parses any command whose only argument is a single key
+fn parse_key_only(buf: &mut impl Frame, cmd_type: CmdType) -> anyhow::Result<Command> {
+    let key = handle_decode(buf)?;
+
+    Ok(Command {
+        cmd_type,
+        key,
+        val: None,
+    })
+}
+
+/// Parses any command whose only argument is an array of keys
+fn parse_keys(buf: &mut impl Frame, cmd_type: CmdType) -> anyhow::Result<Command> {
+    let keys = handle_decode(buf)?;
+    if !matches!(keys, DataType::Array(_)) {
+        anyhow::bail!(DecodeError::InvalidArgument("expected an array of keys"))
+    }
+
+    Ok(Command {
+        cmd_type,
+        key: keys,
+        val: None,
+    })
+}
+
+/// Parses any command whose last argument is an array of strings, such as glob style patterns or
+/// key prefixes
+fn parse_strings(buf: &mut impl Frame, cmd_type: CmdType) -> anyhow::Result<Command> {
+    let cmd = parse_keys(buf, cmd_type)?;
+    if let DataType::Array(BoopArray(strings)) = &cmd.key {
+        if !strings.iter().all(|s| matches!(s, DataType::String(_))) {
+            anyhow::bail!(DecodeError::InvalidArgument("expected an array of strings"))
+        }
+    }
+
+    Ok(cmd)
+}
+
+fn parse_get(buf: &mut impl Frame) -> anyhow::Result<Command> {
     let key = handle_decode(buf)?;
 
     Ok(Command {
@@ -64,7 +1242,17 @@ fn parse_get(buf: &mut bytes::BytesMut) -> anyhow::Result<Command> {
     })
 }
 
-fn parse_get_set(buf: &mut bytes::BytesMut) -> anyhow::Result<Command> {
+fn parse_get_versioned(buf: &mut impl Frame) -> anyhow::Result<Command> {
+    let key = handle_decode(buf)?;
+
+    Ok(Command {
+        cmd_type: CmdType::GetVersioned,
+        key,
+        val: None,
+    })
+}
+
+fn parse_get_set(buf: &mut impl Frame) -> anyhow::Result<Command> {
     let key = handle_decode(buf)?;
     let val = handle_decode(buf)?;
 
@@ -75,7 +1263,7 @@ fn parse_get_set(buf: &mut bytes::BytesMut) -> anyhow::Result<Command> {
     })
 }
 
-fn parse_get_del(buf: &mut bytes::BytesMut) -> anyhow::Result<Command> {
+fn parse_get_del(buf: &mut impl Frame) -> anyhow::Result<Command> {
     let key = handle_decode(buf)?;
 
     Ok(Command {
@@ -85,82 +1273,2591 @@ fn parse_get_del(buf: &mut bytes::BytesMut) -> anyhow::Result<Command> {
     })
 }
 
-fn parse_set(buf: &mut bytes::BytesMut) -> anyhow::Result<Command> {
+fn parse_set(buf: &mut impl Frame) -> anyhow::Result<Command> {
+    if !buf.has_remaining() {
+        anyhow::bail!(DecodeError::BufTooShort("SET flags"))
+    }
+    let flag_byte = buf.get_u8();
+    let mut flags = SetFlags::from_byte(flag_byte)?;
     let key = handle_decode(buf)?;
     let val = handle_decode(buf)?;
 
+    if flag_byte & SET_PX != 0 {
+        if flags.keep_ttl {
+            anyhow::bail!(DecodeError::InvalidArgument(
+                "SET flags PX and KEEPTTL are mutually exclusive"
+            ))
+        }
+        flags.expire_in = Some(decode_u64(buf, "SET expiry must be an unsigned integer")?);
+    }
+
+    if flag_byte & SET_VERSION != 0 {
+        if flags.nx {
+            anyhow::bail!(DecodeError::InvalidArgument(
+                "SET flags NX and VERSION are mutually exclusive"
+            ))
+        }
+        flags.version = Some(decode_u64(buf, "SET version must be an unsigned integer")?);
+    }
+
     Ok(Command {
-        cmd_type: CmdType::Set,
+        cmd_type: CmdType::Set(flags),
         key,
         val: Some(val),
     })
 }
 
-#[cfg(test)]
-mod tests {
-    use bytes::BufMut;
+fn parse_mget(buf: &mut impl Frame) -> anyhow::Result<Command> {
+    let keys = handle_decode(buf)?;
+    if !matches!(keys, DataType::Array(_)) {
+        anyhow::bail!(DecodeError::InvalidArgument(
+            "MGET expects an array of keys"
+        ))
+    }
 
-    use crate::{
-        command::{parse_get_set, parse_set, CmdType, Command},
-        data_type::Int,
-    };
+    Ok(Command {
+        cmd_type: CmdType::MGet,
+        key: keys,
+        val: None,
+    })
+}
 
-    use super::parse_get;
+fn parse_mset(buf: &mut impl Frame) -> anyhow::Result<Command> {
+    if !buf.has_remaining() {
+        anyhow::bail!(DecodeError::BufTooShort("MSET flags"))
+    }
+    let flags = buf.get_u8();
+    let pairs = handle_decode(buf)?;
 
-    #[test]
-    fn parse_get_valid() {
-        let mut buf = bytes::BytesMut::new();
-        buf.put_u8(0x00);
-        buf.put_u8(0xFF);
+    match &pairs {
+        DataType::Array(BoopArray(flat)) if flat.len() % 2 == 0 => {}
+        _ => anyhow::bail!(DecodeError::InvalidArgument(
+            "MSET expects an array of alternating keys and values"
+        )),
+    }
 
-        let result = parse_get(&mut buf);
-        assert_eq!(
-            result.unwrap(),
-            Command {
-                cmd_type: CmdType::Get,
-                key: Int::new_u8(0xFF),
-                val: None,
-            }
-        );
+    Ok(Command {
+        cmd_type: CmdType::MSet {
+            atomic: flags & MSET_ATOMIC != 0,
+        },
+        key: pairs,
+        val: None,
+    })
+}
+
+fn parse_cas(buf: &mut impl Frame) -> anyhow::Result<Command> {
+    let key = handle_decode(buf)?;
+    let expected = handle_decode(buf)?;
+    let val = handle_decode(buf)?;
+
+    Ok(Command {
+        cmd_type: CmdType::Cas { expected },
+        key,
+        val: Some(val),
+    })
+}
+
+/// Parses EXPIRE and PEXPIRE, which only differ in the unit of their timeout. `unit_ms` is the
+/// amount of milliseconds in one unit.
+fn parse_expire(buf: &mut impl Frame, unit_ms: u64) -> anyhow::Result<Command> {
+    let key = handle_decode(buf)?;
+    let timeout = decode_u64(buf, "expiry timeout must be an unsigned integer")?;
+
+    Ok(Command {
+        cmd_type: CmdType::Expire {
+            ms: timeout.saturating_mul(unit_ms),
+        },
+        key,
+        val: None,
+    })
+}
+
+fn parse_scan(buf: &mut impl Frame) -> anyhow::Result<Command> {
+    if !buf.has_remaining() {
+        anyhow::bail!(DecodeError::BufTooShort("SCAN flags"))
     }
+    let flags = buf.get_u8();
+    let cursor = decode_u64(buf, "SCAN cursor must be an unsigned integer")?;
+    let count = decode_u64(buf, "SCAN count must be an unsigned integer")? as usize;
 
-    #[test]
-    fn parse_get_set_valid() {
-        let mut buf = bytes::BytesMut::new();
-        buf.put_u8(0x00);
-        buf.put_u8(0xFF);
+    let mut filter = ScanFilter::default();
+    if flags & SCAN_MATCH != 0 {
+        match handle_decode(buf)? {
+            DataType::String(pattern) => filter.pattern = Some(pattern.0),
+            _ => anyhow::bail!(DecodeError::InvalidArgument(
+                "SCAN pattern must be a string"
+            )),
+        }
+    }
+    if flags & SCAN_TYPE != 0 {
+        if !buf.has_remaining() {
+            anyhow::bail!(DecodeError::BufTooShort("SCAN type"))
+        }
+        filter.variant = Some(buf.get_u8() & 0b_0000_0111);
+    }
 
-        buf.put_u8(0x00);
-        buf.put_u8(0xFF);
+    Ok(Command {
+        cmd_type: CmdType::Scan {
+            cursor,
+            count: if count == 0 {
+                SCAN_DEFAULT_COUNT
+            } else {
+                count
+            },
+            filter,
+        },
+        key: BoopArray::new_wrapped(vec![]),
+        val: None,
+    })
+}
 
-        let result = parse_get_set(&mut buf);
-        assert_eq!(
-            result.unwrap(),
-            Command {
-                cmd_type: CmdType::GetSet,
-                key: Int::new_u8(0xFF),
-                val: Some(Int::new_u8(0xff)),
-            }
-        );
+/// Parses FLUSHALL, which empties every database, or FLUSHDB, which only empties one
+fn parse_flush(buf: &mut impl Frame, all: bool) -> anyhow::Result<Command> {
+    if !buf.has_remaining() {
+        anyhow::bail!(DecodeError::BufTooShort("FLUSH flags"))
     }
+    let in_background = buf.get_u8() & FLUSH_ASYNC != 0;
 
-    #[test]
-    fn parse_set_valid() {
-        let mut buf = bytes::BytesMut::new();
-        buf.put_u8(0x00);
-        buf.put_u8(0xFF);
+    Ok(Command {
+        cmd_type: if all {
+            CmdType::FlushAll { in_background }
+        } else {
+            CmdType::FlushDb { in_background }
+        },
+        key: BoopArray::new_wrapped(vec![]),
+        val: None,
+    })
+}
 
-        buf.put_u8(0x00);
-        buf.put_u8(0xFF);
+fn parse_rename(buf: &mut impl Frame) -> anyhow::Result<Command> {
+    let key = handle_decode(buf)?;
+    let new_key = handle_decode(buf)?;
 
-        let result = parse_set(&mut buf);
-        assert_eq!(
-            result.unwrap(),
-            Command {
-                cmd_type: CmdType::Set,
-                key: Int::new_u8(0xFF),
-                val: Some(Int::new_u8(0xff)),
-            }
+    Ok(Command {
+        cmd_type: CmdType::Rename,
+        key,
+        val: Some(new_key),
+    })
+}
+
+/// Decodes the index of a database, which must be an unsigned integer of any width
+fn decode_db(buf: &mut impl Frame, arg: &'static str) -> anyhow::Result<usize> {
+    usize::try_from(decode_u64(buf, arg)?)
+        .map_err(|_| anyhow::anyhow!(DecodeError::InvalidArgument(arg)))
+}
+
+/// Parses ON, which runs the command that follows it against another database than the selected
+/// one
+fn parse_on(buf: &mut impl Frame) -> anyhow::Result<Command> {
+    let db = decode_db(buf, "ON expects a database index")?;
+    let cmd = decode_command(buf)?;
+    if cmd.cmd_type.needs_session() {
+        anyhow::bail!(DecodeError::InvalidArgument(
+            "ON can only run commands that work on a single database"
+        ))
+    }
+
+    Ok(Command {
+        cmd_type: CmdType::On {
+            db,
+            cmd: Box::new(cmd),
+        },
+        key: BoopArray::new_wrapped(vec![]),
+        val: None,
+    })
+}
+
+fn parse_push(buf: &mut impl Frame, end: End) -> anyhow::Result<Command> {
+    let key = handle_decode(buf)?;
+    let elements = handle_decode(buf)?;
+    if !matches!(elements, DataType::Array(_)) {
+        anyhow::bail!(DecodeError::InvalidArgument(
+            "push expects an array of elements"
+        ))
+    }
+
+    Ok(Command {
+        cmd_type: CmdType::Push(end),
+        key,
+        val: Some(elements),
+    })
+}
+
+fn parse_lindex(buf: &mut impl Frame) -> anyhow::Result<Command> {
+    let key = handle_decode(buf)?;
+    let index = decode_i64(buf, "LINDEX index must be an integer")?;
+
+    Ok(Command {
+        cmd_type: CmdType::LIndex { index },
+        key,
+        val: None,
+    })
+}
+
+/// Parses LRANGE, LTRIM and GETRANGE, which all take a key followed by a start and stop index
+fn parse_list_range(
+    buf: &mut impl Frame,
+    cmd_type: fn(i64, i64) -> CmdType,
+) -> anyhow::Result<Command> {
+    let key = handle_decode(buf)?;
+    let start = decode_i64(buf, "range start must be an integer")?;
+    let stop = decode_i64(buf, "range stop must be an integer")?;
+
+    Ok(Command {
+        cmd_type: cmd_type(start, stop),
+        key,
+        val: None,
+    })
+}
+
+fn parse_lset(buf: &mut impl Frame) -> anyhow::Result<Command> {
+    let key = handle_decode(buf)?;
+    let index = decode_i64(buf, "LSET index must be an integer")?;
+    let val = handle_decode(buf)?;
+
+    Ok(Command {
+        cmd_type: CmdType::LSet { index },
+        key,
+        val: Some(val),
+    })
+}
+
+/// Parses BLPOP and BRPOP, which take an array of keys followed by a timeout in milliseconds
+fn parse_blocking_pop(buf: &mut impl Frame, end: End) -> anyhow::Result<Command> {
+    let keys = handle_decode(buf)?;
+    if !matches!(&keys, DataType::Array(BoopArray(keys)) if !keys.is_empty()) {
+        anyhow::bail!(DecodeError::InvalidArgument(
+            "blocking pops expect a non-empty array of keys"
+        ))
+    }
+    let timeout_ms = decode_u64(buf, "blocking pop timeout must be an unsigned integer")?;
+
+    Ok(Command {
+        cmd_type: CmdType::BPop { end, timeout_ms },
+        key: keys,
+        val: None,
+    })
+}
+
+fn parse_append(buf: &mut impl Frame) -> anyhow::Result<Command> {
+    let key = handle_decode(buf)?;
+    let val = decode_string(buf, "APPEND value must be a string")?;
+
+    Ok(Command {
+        cmd_type: CmdType::Append,
+        key,
+        val: Some(val),
+    })
+}
+
+fn parse_set_range(buf: &mut impl Frame) -> anyhow::Result<Command> {
+    let key = handle_decode(buf)?;
+    let offset = decode_u64(buf, "SETRANGE offset must be an unsigned integer")?;
+    let val = decode_string(buf, "SETRANGE value must be a string")?;
+
+    Ok(Command {
+        cmd_type: CmdType::SetRange {
+            offset: usize::try_from(offset).unwrap_or(usize::MAX),
+        },
+        key,
+        val: Some(val),
+    })
+}
+
+fn parse_set_bit(buf: &mut impl Frame) -> anyhow::Result<Command> {
+    let key = handle_decode(buf)?;
+    let offset = decode_u64(buf, "SETBIT offset must be an unsigned integer")?;
+    let bit = decode_bool(buf, "SETBIT value must be a bool")?;
+
+    Ok(Command {
+        cmd_type: CmdType::SetBit { offset, bit },
+        key,
+        val: None,
+    })
+}
+
+fn parse_get_bit(buf: &mut impl Frame) -> anyhow::Result<Command> {
+    let key = handle_decode(buf)?;
+    let offset = decode_u64(buf, "GETBIT offset must be an unsigned integer")?;
+
+    Ok(Command {
+        cmd_type: CmdType::GetBit { offset },
+        key,
+        val: None,
+    })
+}
+
+fn parse_bit_count(buf: &mut impl Frame) -> anyhow::Result<Command> {
+    if !buf.has_remaining() {
+        anyhow::bail!(DecodeError::BufTooShort("BITCOUNT flags"))
+    }
+    let flags = buf.get_u8();
+    let key = handle_decode(buf)?;
+
+    let range = if flags & BITCOUNT_RANGE != 0 {
+        let start = decode_i64(buf, "range start must be an integer")?;
+        let stop = decode_i64(buf, "range stop must be an integer")?;
+        Some((start, stop))
+    } else {
+        None
+    };
+
+    Ok(Command {
+        cmd_type: CmdType::BitCount { range },
+        key,
+        val: None,
+    })
+}
+
+fn parse_bit_pos(buf: &mut impl Frame) -> anyhow::Result<Command> {
+    if !buf.has_remaining() {
+        anyhow::bail!(DecodeError::BufTooShort("BITPOS flags"))
+    }
+    let flags = buf.get_u8();
+    if flags & BITPOS_STOP != 0 && flags & BITPOS_START == 0 {
+        anyhow::bail!(DecodeError::InvalidArgument(
+            "BITPOS can't be given a stop without a start"
+        ))
+    }
+
+    let key = handle_decode(buf)?;
+    let bit = decode_bool(buf, "BITPOS bit must be a bool")?;
+    let start = if flags & BITPOS_START != 0 {
+        decode_i64(buf, "range start must be an integer")?
+    } else {
+        0
+    };
+    let stop = if flags & BITPOS_STOP != 0 {
+        Some(decode_i64(buf, "range stop must be an integer")?)
+    } else {
+        None
+    };
+
+    Ok(Command {
+        cmd_type: CmdType::BitPos { bit, start, stop },
+        key,
+        val: None,
+    })
+}
+
+fn parse_bit_op(buf: &mut impl Frame) -> anyhow::Result<Command> {
+    if !buf.has_remaining() {
+        anyhow::bail!(DecodeError::BufTooShort("BITOP operation"))
+    }
+    let op = match buf.get_u8() {
+        0x00 => BitOp::And,
+        0x01 => BitOp::Or,
+        0x02 => BitOp::Xor,
+        0x03 => BitOp::Not,
+        _ => anyhow::bail!(DecodeError::InvalidArgument("unknown BITOP operation")),
+    };
+
+    let dest = handle_decode(buf)?;
+    let keys = handle_decode(buf)?;
+    let valid = match (&keys, op) {
+        (DataType::Array(BoopArray(keys)), BitOp::Not) => keys.len() == 1,
+        (DataType::Array(BoopArray(keys)), _) => !keys.is_empty(),
+        _ => false,
+    };
+    if !valid {
+        anyhow::bail!(DecodeError::InvalidArgument(
+            "BITOP expects an array of keys, holding exactly one key for NOT"
+        ))
+    }
+
+    Ok(Command {
+        cmd_type: CmdType::BitOp(op),
+        key: dest,
+        val: Some(keys),
+    })
+}
+
+/// Parses any command which takes a key followed by a member, such as ZSCORE
+fn parse_key_and_member(buf: &mut impl Frame, cmd_type: CmdType) -> anyhow::Result<Command> {
+    let key = handle_decode(buf)?;
+    let member = handle_decode(buf)?;
+
+    Ok(Command {
+        cmd_type,
+        key,
+        val: Some(member),
+    })
+}
+
+fn parse_zadd(buf: &mut impl Frame) -> anyhow::Result<Command> {
+    if !buf.has_remaining() {
+        anyhow::bail!(DecodeError::BufTooShort("ZADD flags"))
+    }
+    let flags = buf.get_u8();
+    if flags & ZADD_NX != 0 && flags & ZADD_XX != 0 {
+        anyhow::bail!(DecodeError::InvalidArgument(
+            "ZADD can't be given both NX and XX"
+        ))
+    }
+
+    let key = handle_decode(buf)?;
+    let pairs = handle_decode(buf)?;
+    let valid = match &pairs {
+        DataType::Array(BoopArray(pairs)) => {
+            !pairs.is_empty()
+                && pairs.len() % 2 == 0
+                && pairs
+                    .iter()
+                    .step_by(2)
+                    .all(|score| score.as_f64().is_some_and(|s| !s.is_nan()))
+        }
+        _ => false,
+    };
+    if !valid {
+        anyhow::bail!(DecodeError::InvalidArgument(
+            "ZADD expects an array of score and member pairs"
+        ))
+    }
+
+    Ok(Command {
+        cmd_type: CmdType::ZAdd {
+            nx: flags & ZADD_NX != 0,
+            xx: flags & ZADD_XX != 0,
+        },
+        key,
+        val: Some(pairs),
+    })
+}
+
+/// Parses any command which takes a key followed by an array, such as ZREM or SADD
+fn parse_key_and_array(
+    buf: &mut impl Frame,
+    cmd_type: CmdType,
+    arg: &'static str,
+) -> anyhow::Result<Command> {
+    let key = handle_decode(buf)?;
+    let array = handle_decode(buf)?;
+    if !matches!(array, DataType::Array(_)) {
+        anyhow::bail!(DecodeError::InvalidArgument(arg))
+    }
+
+    Ok(Command {
+        cmd_type,
+        key,
+        val: Some(array),
+    })
+}
+
+fn parse_zincrby(buf: &mut impl Frame) -> anyhow::Result<Command> {
+    let key = handle_decode(buf)?;
+    let by = decode_f64(buf, "ZINCRBY increment must be a number")?;
+    let member = handle_decode(buf)?;
+
+    Ok(Command {
+        cmd_type: CmdType::ZIncrBy {
+            by: OrderedFloat(by),
+        },
+        key,
+        val: Some(member),
+    })
+}
+
+fn parse_zrank(buf: &mut impl Frame) -> anyhow::Result<Command> {
+    if !buf.has_remaining() {
+        anyhow::bail!(DecodeError::BufTooShort("ZRANK flags"))
+    }
+    let flags = buf.get_u8();
+
+    parse_key_and_member(
+        buf,
+        CmdType::ZRank {
+            rev: flags & Z_REV != 0,
+        },
+    )
+}
+
+fn parse_zrange(buf: &mut impl Frame) -> anyhow::Result<Command> {
+    if !buf.has_remaining() {
+        anyhow::bail!(DecodeError::BufTooShort("ZRANGE flags"))
+    }
+    let flags = buf.get_u8();
+    let by_score = flags & ZRANGE_BY_SCORE != 0;
+    if !by_score && flags & (ZRANGE_LIMIT | ZRANGE_MIN_EXCLUSIVE | ZRANGE_MAX_EXCLUSIVE) != 0 {
+        anyhow::bail!(DecodeError::InvalidArgument(
+            "ZRANGE limits and exclusive bounds require BYSCORE"
+        ))
+    }
+
+    let key = handle_decode(buf)?;
+    let by = if by_score {
+        let min = decode_f64(buf, "ZRANGE min must be a number")?;
+        let max = decode_f64(buf, "ZRANGE max must be a number")?;
+        let (offset, count) = if flags & ZRANGE_LIMIT != 0 {
+            let offset = decode_u64(buf, "ZRANGE offset must be an unsigned integer")?;
+            let count = decode_i64(buf, "ZRANGE count must be an integer")?;
+            (offset as usize, usize::try_from(count).ok())
+        } else {
+            (0, None)
+        };
+
+        ZRangeBy::Score {
+            min: OrderedFloat(min),
+            max: OrderedFloat(max),
+            min_exclusive: flags & ZRANGE_MIN_EXCLUSIVE != 0,
+            max_exclusive: flags & ZRANGE_MAX_EXCLUSIVE != 0,
+            offset,
+            count,
+        }
+    } else {
+        ZRangeBy::Rank {
+            start: decode_i64(buf, "range start must be an integer")?,
+            stop: decode_i64(buf, "range stop must be an integer")?,
+        }
+    };
+
+    Ok(Command {
+        cmd_type: CmdType::ZRange {
+            by,
+            rev: flags & Z_REV != 0,
+            with_scores: flags & ZRANGE_WITH_SCORES != 0,
+        },
+        key,
+        val: None,
+    })
+}
+
+fn parse_srandmember(buf: &mut impl Frame) -> anyhow::Result<Command> {
+    let key = handle_decode(buf)?;
+    let count = decode_i64(buf, "SRANDMEMBER count must be an integer")?;
+
+    Ok(Command {
+        cmd_type: CmdType::SRandMember { count },
+        key,
+        val: None,
+    })
+}
+
+/// Stream IDs are replied with as an array of their millisecond and sequence number
+fn stream_id_reply(id: StreamId) -> DataType {
+    BoopArray::new_wrapped(vec![Int::new_u64(id.ms), Int::new_u64(id.seq)])
+}
+
+/// Stream entries are replied with as an array of their ID and an array of their field/value
+/// pairs, or `no_exist` in place of the fields for an entry that no longer exists
+fn stream_entry_reply(id: StreamId, fields: Option<Fields>) -> DataType {
+    let fields = match fields {
+        Some(fields) => BoopArray::new_wrapped(
+            fields
+                .into_iter()
+                .flat_map(|(field, value)| [field, value])
+                .collect(),
+        ),
+        None => BoopError::no_exist(),
+    };
+    BoopArray::new_wrapped(vec![stream_id_reply(id), fields])
+}
+
+/// Decodes a stream ID, which is sent as two unsigned integers: the millisecond, then the sequence
+/// number
+fn decode_stream_id(buf: &mut impl Frame, arg: &'static str) -> anyhow::Result<StreamId> {
+    let ms = decode_u64(buf, arg)?;
+    let seq = decode_u64(buf, arg)?;
+    Ok(StreamId::new(ms, seq))
+}
+
+fn parse_xadd(buf: &mut impl Frame) -> anyhow::Result<Command> {
+    if !buf.has_remaining() {
+        anyhow::bail!(DecodeError::BufTooShort("XADD flags"))
+    }
+    let flags = buf.get_u8();
+    if flags & XADD_AUTO_ID != 0 && flags & XADD_AUTO_SEQ != 0 {
+        anyhow::bail!(DecodeError::InvalidArgument(
+            "XADD can't generate both the ID and the sequence number"
+        ))
+    }
+    if flags & XADD_MAXLEN != 0 && flags & XADD_MINID != 0 {
+        anyhow::bail!(DecodeError::InvalidArgument(
+            "XADD can't trim by both MAXLEN and MINID"
+        ))
+    }
+
+    let key = handle_decode(buf)?;
+    let id = if flags & XADD_AUTO_ID != 0 {
+        XAddId::Auto
+    } else if flags & XADD_AUTO_SEQ != 0 {
+        XAddId::AutoSeq(decode_u64(buf, "XADD ms must be an unsigned integer")?)
+    } else {
+        XAddId::Explicit(decode_stream_id(buf, "XADD ID must be unsigned integers")?)
+    };
+    let trim = parse_trim(buf, flags & XADD_MAXLEN != 0, flags & XADD_MINID != 0)?;
+
+    let fields = handle_decode(buf)?;
+    match &fields {
+        DataType::Array(BoopArray(flat)) if !flat.is_empty() && flat.len() % 2 == 0 => {}
+        _ => anyhow::bail!(DecodeError::InvalidArgument(
+            "XADD expects an array of field/value pairs"
+        )),
+    }
+
+    Ok(Command {
+        cmd_type: CmdType::XAdd { id, trim },
+        key,
+        val: Some(fields),
+    })
+}
+
+/// Parses the MAXLEN or MINID argument of XADD and XTRIM
+fn parse_trim(buf: &mut impl Frame, maxlen: bool, minid: bool) -> anyhow::Result<Option<XTrim>> {
+    Ok(if maxlen {
+        let len = decode_u64(buf, "MAXLEN must be an unsigned integer")?;
+        Some(XTrim::MaxLen(usize::try_from(len).unwrap_or(usize::MAX)))
+    } else if minid {
+        Some(XTrim::MinId(decode_stream_id(
+            buf,
+            "MINID must be unsigned integers",
+        )?))
+    } else {
+        None
+    })
+}
+
+fn parse_xrange(buf: &mut impl Frame) -> anyhow::Result<Command> {
+    if !buf.has_remaining() {
+        anyhow::bail!(DecodeError::BufTooShort("XRANGE flags"))
+    }
+    let flags = buf.get_u8();
+    let key = handle_decode(buf)?;
+    let start = decode_stream_id(buf, "XRANGE start must be unsigned integers")?;
+    let end = decode_stream_id(buf, "XRANGE end must be unsigned integers")?;
+    let count = if flags & XRANGE_COUNT != 0 {
+        Some(decode_u64(buf, "XRANGE count must be an unsigned integer")? as usize)
+    } else {
+        None
+    };
+
+    Ok(Command {
+        cmd_type: CmdType::XRange {
+            start,
+            end,
+            count,
+            rev: flags & XRANGE_REV != 0,
+        },
+        key,
+        val: None,
+    })
+}
+
+fn parse_xtrim(buf: &mut impl Frame) -> anyhow::Result<Command> {
+    if !buf.has_remaining() {
+        anyhow::bail!(DecodeError::BufTooShort("XTRIM flags"))
+    }
+    let flags = buf.get_u8();
+    let key = handle_decode(buf)?;
+    let minid = flags & XTRIM_MINID != 0;
+    let trim = parse_trim(buf, !minid, minid)?.expect("XTRIM always trims");
+
+    Ok(Command {
+        cmd_type: CmdType::XTrim(trim),
+        key,
+        val: None,
+    })
+}
+
+fn parse_xgroup_create(buf: &mut impl Frame) -> anyhow::Result<Command> {
+    if !buf.has_remaining() {
+        anyhow::bail!(DecodeError::BufTooShort("XGROUPCREATE flags"))
+    }
+    let flags = buf.get_u8();
+    let key = handle_decode(buf)?;
+    let group = handle_decode(buf)?;
+    let start = if flags & XGROUP_LAST != 0 {
+        None
+    } else {
+        Some(decode_stream_id(
+            buf,
+            "XGROUPCREATE ID must be unsigned integers",
+        )?)
+    };
+
+    Ok(Command {
+        cmd_type: CmdType::XGroupCreate {
+            group,
+            start,
+            mkstream: flags & XGROUP_MKSTREAM != 0,
+        },
+        key,
+        val: None,
+    })
+}
+
+fn parse_xreadgroup(buf: &mut impl Frame) -> anyhow::Result<Command> {
+    if !buf.has_remaining() {
+        anyhow::bail!(DecodeError::BufTooShort("XREADGROUP flags"))
+    }
+    let flags = buf.get_u8();
+    let key = handle_decode(buf)?;
+    let group = handle_decode(buf)?;
+    let consumer = handle_decode(buf)?;
+    let from = if flags & XREADGROUP_PENDING != 0 {
+        Some(decode_stream_id(
+            buf,
+            "XREADGROUP ID must be unsigned integers",
+        )?)
+    } else {
+        None
+    };
+    let count = if flags & XREADGROUP_COUNT != 0 {
+        Some(decode_u64(buf, "XREADGROUP count must be an unsigned integer")? as usize)
+    } else {
+        None
+    };
+
+    Ok(Command {
+        cmd_type: CmdType::XReadGroup {
+            group,
+            consumer,
+            from,
+            count,
+            noack: flags & XREADGROUP_NOACK != 0,
+        },
+        key,
+        val: None,
+    })
+}
+
+fn parse_xack(buf: &mut impl Frame) -> anyhow::Result<Command> {
+    const ARG: &str = "XACK expects an array of IDs, as pairs of unsigned integers";
+
+    let key = handle_decode(buf)?;
+    let group = handle_decode(buf)?;
+    let DataType::Array(BoopArray(flat)) = handle_decode(buf)? else {
+        anyhow::bail!(DecodeError::InvalidArgument(ARG))
+    };
+    let flat = Vec::from(flat);
+    if flat.len() % 2 != 0 {
+        anyhow::bail!(DecodeError::InvalidArgument(ARG))
+    }
+
+    let mut ids = Vec::with_capacity(flat.len() / 2);
+    for pair in flat.chunks_exact(2) {
+        match (pair[0].as_u64(), pair[1].as_u64()) {
+            (Some(ms), Some(seq)) => ids.push(StreamId::new(ms, seq)),
+            _ => anyhow::bail!(DecodeError::InvalidArgument(ARG)),
+        }
+    }
+
+    Ok(Command {
+        cmd_type: CmdType::XAck { group, ids },
+        key,
+        val: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::{BufMut, Bytes};
+
+    use crate::{
+        command::{
+            decode_command, parse_cas, parse_get_set, parse_mget, parse_mset, parse_set, CmdType,
+            Command, SetFlags,
+        },
+        data_type::{BoopArray, BoopBool, BoopError, BoopSet, BoopString, DataType, Int, StreamId},
+        store::{
+            bits::BitOp,
+            list::End,
+            scan::ScanFilter,
+            set::SetOp,
+            sorted_set::ZRangeBy,
+            stream::{XAddId, XTrim},
+            SetTtl, Store,
+        },
+    };
+    use ordered_float::OrderedFloat;
+    use std::time::Duration;
+
+    use super::parse_get;
+
+    #[test]
+    fn parse_get_valid() {
+        let mut buf = bytes::BytesMut::new();
+        buf.put_u8(0x00);
+        buf.put_u8(0xFF);
+
+        let result = parse_get(&mut buf);
+        assert_eq!(
+            result.unwrap(),
+            Command {
+                cmd_type: CmdType::Get,
+                key: Int::new_u8(0xFF),
+                val: None,
+            }
+        );
+    }
+
+    #[test]
+    fn parse_get_set_valid() {
+        let mut buf = bytes::BytesMut::new();
+        buf.put_u8(0x00);
+        buf.put_u8(0xFF);
+
+        buf.put_u8(0x00);
+        buf.put_u8(0xFF);
+
+        let result = parse_get_set(&mut buf);
+        assert_eq!(
+            result.unwrap(),
+            Command {
+                cmd_type: CmdType::GetSet,
+                key: Int::new_u8(0xFF),
+                val: Some(Int::new_u8(0xff)),
+            }
+        );
+    }
+
+    #[test]
+    fn parse_set_valid() {
+        let mut buf = bytes::BytesMut::new();
+        buf.put_u8(0x00); // flags
+        buf.put_u8(0x00);
+        buf.put_u8(0xFF);
+
+        buf.put_u8(0x00);
+        buf.put_u8(0xFF);
+
+        let result = parse_set(&mut buf);
+        assert_eq!(
+            result.unwrap(),
+            Command {
+                cmd_type: CmdType::Set(SetFlags::default()),
+                key: Int::new_u8(0xFF),
+                val: Some(Int::new_u8(0xff)),
+            }
+        );
+    }
+
+    #[test]
+    fn parse_mget_valid() {
+        let mut buf = bytes::BytesMut::new();
+        buf.put_u8(0x03);
+        buf.put_u16(2);
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        buf.put_u8(0x00);
+        buf.put_u8(0x02);
+
+        let result = parse_mget(&mut buf);
+        assert_eq!(
+            result.unwrap(),
+            Command {
+                cmd_type: CmdType::MGet,
+                key: BoopArray::new_wrapped(vec![Int::new_u8(1), Int::new_u8(2)]),
+                val: None,
+            }
+        );
+    }
+
+    #[test]
+    fn parse_mget_rejects_non_array() {
+        let mut buf = bytes::BytesMut::new();
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+
+        assert!(parse_mget(&mut buf).is_err());
+    }
+
+    #[test]
+    fn parse_mset_valid() {
+        let mut buf = bytes::BytesMut::new();
+        buf.put_u8(0x01); // atomic
+        buf.put_u8(0x03);
+        buf.put_u16(2);
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        buf.put_u8(0b_1_0000_100);
+
+        let result = parse_mset(&mut buf);
+        assert_eq!(
+            result.unwrap(),
+            Command {
+                cmd_type: CmdType::MSet { atomic: true },
+                key: BoopArray::new_wrapped(vec![Int::new_u8(1), BoopBool::new_wrapped(true)]),
+                val: None,
+            }
+        );
+    }
+
+    #[test]
+    fn parse_mset_rejects_odd_length() {
+        let mut buf = bytes::BytesMut::new();
+        buf.put_u8(0x00);
+        buf.put_u8(0x03);
+        buf.put_u16(1);
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+
+        assert!(parse_mset(&mut buf).is_err());
+    }
+
+    #[test]
+    fn mget_placeholders_for_missing_keys() {
+        let store = Store::new();
+        Command {
+            cmd_type: CmdType::MSet { atomic: false },
+            key: BoopArray::new_wrapped(vec![Int::new_u8(1), Int::new_u8(10)]),
+            val: None,
+        }
+        .execute(store.clone());
+
+        let result = Command {
+            cmd_type: CmdType::MGet,
+            key: BoopArray::new_wrapped(vec![Int::new_u8(1), Int::new_u8(2)]),
+            val: None,
+        }
+        .execute(store);
+
+        assert_eq!(
+            result.unwrap(),
+            BoopArray::new_wrapped(vec![Int::new_u8(10), BoopError::no_exist()])
+        );
+    }
+
+    #[test]
+    fn parse_set_flags() {
+        let mut buf = bytes::BytesMut::new();
+        buf.put_u8(0b_0000_1101); // NX | GET | KEEPTTL
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        buf.put_u8(0x00);
+        buf.put_u8(0x02);
+
+        let result = parse_set(&mut buf).unwrap();
+        assert_eq!(
+            result.cmd_type,
+            CmdType::Set(SetFlags {
+                nx: true,
+                xx: false,
+                get: true,
+                keep_ttl: true,
+                expire_in: None,
+                version: None,
+            })
+        );
+    }
+
+    #[test]
+    fn parse_set_rejects_nx_and_xx() {
+        let mut buf = bytes::BytesMut::new();
+        buf.put_u8(0b_0000_0011);
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        buf.put_u8(0x00);
+        buf.put_u8(0x02);
+
+        assert!(parse_set(&mut buf).is_err());
+    }
+
+    fn set(store: &Store, flags: SetFlags, val: u8) -> DataType {
+        Command {
+            cmd_type: CmdType::Set(flags),
+            key: Int::new_u8(1),
+            val: Some(Int::new_u8(val)),
+        }
+        .execute(store.clone())
+        .unwrap()
+    }
+
+    #[test]
+    fn set_nx_and_xx_replies() {
+        let store = Store::new();
+        let nx = SetFlags {
+            nx: true,
+            ..Default::default()
+        };
+        let xx = SetFlags {
+            xx: true,
+            ..Default::default()
+        };
+
+        assert_eq!(set(&store, xx, 1), BoopBool::new_wrapped(false));
+        assert_eq!(set(&store, nx, 2), BoopBool::new_wrapped(true));
+        assert_eq!(set(&store, nx, 3), BoopBool::new_wrapped(false));
+        assert_eq!(set(&store, xx, 4), BoopBool::new_wrapped(true));
+        assert_eq!(store.get(&Int::new_u8(1)).unwrap(), Int::new_u8(4));
+    }
+
+    #[test]
+    fn set_get_returns_previous_value() {
+        let store = Store::new();
+        let get = SetFlags {
+            get: true,
+            ..Default::default()
+        };
+        let nx_get = SetFlags {
+            nx: true,
+            get: true,
+            ..Default::default()
+        };
+
+        assert_eq!(set(&store, get, 1), BoopError::no_exist());
+        assert_eq!(set(&store, get, 2), Int::new_u8(1));
+        assert_eq!(set(&store, nx_get, 3), Int::new_u8(2));
+        assert_eq!(store.get(&Int::new_u8(1)).unwrap(), Int::new_u8(2));
+    }
+
+    #[test]
+    fn parse_cas_valid() {
+        let mut buf = bytes::BytesMut::new();
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        buf.put_u8(0x00);
+        buf.put_u8(0x02);
+        buf.put_u8(0x00);
+        buf.put_u8(0x03);
+
+        assert_eq!(
+            parse_cas(&mut buf).unwrap(),
+            Command {
+                cmd_type: CmdType::Cas {
+                    expected: Int::new_u8(2)
+                },
+                key: Int::new_u8(1),
+                val: Some(Int::new_u8(3)),
+            }
+        );
+    }
+
+    #[test]
+    fn cas_replies_with_outcome_and_current_value() {
+        let store = Store::new();
+        store.set(
+            &Int::new_u8(1),
+            &BoopArray::new_wrapped(vec![Int::new_u8(1)]),
+            SetTtl::Clear,
+        );
+
+        let cas = |expected: DataType, new_val: DataType| {
+            Command {
+                cmd_type: CmdType::Cas { expected },
+                key: Int::new_u8(1),
+                val: Some(new_val),
+            }
+            .execute(store.clone())
+            .unwrap()
+        };
+
+        assert_eq!(
+            cas(BoopBool::new_wrapped(true), Int::new_u8(2)),
+            BoopArray::new_wrapped(vec![
+                BoopBool::new_wrapped(false),
+                BoopArray::new_wrapped(vec![Int::new_u8(1)]),
+            ])
+        );
+        assert_eq!(
+            cas(BoopArray::new_wrapped(vec![Int::new_u8(1)]), Int::new_u8(2)),
+            BoopArray::new_wrapped(vec![BoopBool::new_wrapped(true), Int::new_u8(2)])
+        );
+    }
+
+    #[test]
+    fn parse_set_with_expiry() {
+        let mut buf = bytes::BytesMut::new();
+        buf.put_u8(0b_0001_0000); // PX
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        buf.put_u8(0x00);
+        buf.put_u8(0x02);
+        buf.put_u8(0b_00_001_000);
+        buf.put_u16(1500);
+
+        let result = parse_set(&mut buf).unwrap();
+        assert_eq!(
+            result.cmd_type,
+            CmdType::Set(SetFlags {
+                expire_in: Some(1500),
+                ..Default::default()
+            })
+        );
+    }
+
+    #[test]
+    fn parse_set_rejects_px_and_keep_ttl() {
+        let mut buf = bytes::BytesMut::new();
+        buf.put_u8(0b_0001_1000);
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        buf.put_u8(0x00);
+        buf.put_u8(0x02);
+        buf.put_u8(0x00);
+        buf.put_u8(0x10);
+
+        assert!(parse_set(&mut buf).is_err());
+    }
+
+    #[test]
+    fn parse_set_with_version() {
+        let mut buf = bytes::BytesMut::new();
+        buf.put_u8(0b_0011_0000); // PX | VERSION
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        buf.put_u8(0x00);
+        buf.put_u8(0x02);
+        buf.put_u8(0x00);
+        buf.put_u8(0x10);
+        buf.put_u8(0b_00_010_000);
+        buf.put_u32(7);
+
+        let result = parse_set(&mut buf).unwrap();
+        assert_eq!(
+            result.cmd_type,
+            CmdType::Set(SetFlags {
+                expire_in: Some(0x10),
+                version: Some(7),
+                ..Default::default()
+            })
+        );
+
+        let mut buf = bytes::BytesMut::new();
+        buf.put_u8(0b_0010_0001); // NX | VERSION
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        buf.put_u8(0x00);
+        buf.put_u8(0x02);
+        buf.put_u8(0x00);
+        buf.put_u8(0x07);
+
+        assert!(parse_set(&mut buf).is_err());
+    }
+
+    #[test]
+    fn versioned_get_and_set_replies() {
+        let store = Store::new();
+        let get_versioned = || {
+            Command {
+                cmd_type: CmdType::GetVersioned,
+                key: Int::new_u8(1),
+                val: None,
+            }
+            .execute(store.clone())
+            .unwrap()
+        };
+        let set_if_version = |version: u64, get: bool| {
+            let flags = SetFlags {
+                get,
+                version: Some(version),
+                ..Default::default()
+            };
+            set(&store, flags, 2)
+        };
+
+        assert_eq!(get_versioned(), BoopError::no_exist());
+        assert_eq!(set_if_version(1, false), BoopBool::new_wrapped(false));
+
+        set(&store, SetFlags::default(), 1);
+        let DataType::Array(BoopArray(reply)) = get_versioned() else {
+            panic!("expected an array")
+        };
+        assert_eq!(reply.len(), 3);
+        assert_eq!(reply[0], Int::new_u8(1));
+        let DataType::Num(Int::Large(version)) = reply[1] else {
+            panic!("expected a u64 version")
+        };
+
+        assert_eq!(set_if_version(version + 1, true), Int::new_u8(1));
+        assert_eq!(store.get(&Int::new_u8(1)), Some(Int::new_u8(1)));
+        assert_eq!(set_if_version(version, false), BoopBool::new_wrapped(true));
+        assert_eq!(store.get(&Int::new_u8(1)), Some(Int::new_u8(2)));
+    }
+
+    #[test]
+    fn parse_expire_commands() {
+        let mut buf = bytes::BytesMut::new();
+        buf.put_u8(0x20); // EXPIRE
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        buf.put_u8(0x00);
+        buf.put_u8(0x02);
+        buf.put_u8(0x21); // PEXPIRE
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        buf.put_u8(0x00);
+        buf.put_u8(0x02);
+        buf.put_u8(0x22); // TTL
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        buf.put_u8(0x23); // PERSIST
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+
+        let cmd = decode_command(&mut buf).unwrap();
+        assert_eq!(cmd.cmd_type, CmdType::Expire { ms: 2000 });
+        let cmd = decode_command(&mut buf).unwrap();
+        assert_eq!(cmd.cmd_type, CmdType::Expire { ms: 2 });
+        let cmd = decode_command(&mut buf).unwrap();
+        assert_eq!(cmd.cmd_type, CmdType::Ttl);
+        let cmd = decode_command(&mut buf).unwrap();
+        assert_eq!(cmd.cmd_type, CmdType::Persist);
+    }
+
+    #[test]
+    fn parse_expire_rejects_non_integer_timeout() {
+        let mut buf = bytes::BytesMut::new();
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        buf.put_u8(0b_1_0000_100);
+
+        assert!(super::parse_expire(&mut buf, 1).is_err());
+    }
+
+    #[test]
+    fn ttl_replies() {
+        let store = Store::new();
+        let run = |cmd_type: CmdType| {
+            Command {
+                cmd_type,
+                key: Int::new_u8(1),
+                val: None,
+            }
+            .execute(store.clone())
+            .unwrap()
+        };
+
+        assert_eq!(run(CmdType::Ttl), BoopError::no_exist());
+        assert_eq!(
+            run(CmdType::Expire { ms: 1000 }),
+            BoopBool::new_wrapped(false)
+        );
+
+        set(&store, SetFlags::default(), 1);
+        assert_eq!(run(CmdType::Ttl), BoopBool::new_wrapped(false));
+        assert_eq!(
+            run(CmdType::Expire { ms: 60_000 }),
+            BoopBool::new_wrapped(true)
+        );
+        assert!(matches!(run(CmdType::Ttl), DataType::Num(Int::Large(ms)) if ms <= 60_000));
+        assert_eq!(run(CmdType::Persist), BoopBool::new_wrapped(true));
+        assert_eq!(run(CmdType::Ttl), BoopBool::new_wrapped(false));
+    }
+
+    #[test]
+    fn parse_scan_with_filters() {
+        let mut buf = bytes::BytesMut::new();
+        buf.put_u8(0x30);
+        buf.put_u8(0b_0000_0011); // MATCH | TYPE
+        buf.put_u8(0x00);
+        buf.put_u8(0x05); // cursor
+        buf.put_u8(0x00);
+        buf.put_u8(0x00); // count
+        buf.put_u8(0x02);
+        buf.put_u16(2);
+        buf.put_slice(b"a*");
+        buf.put_u8(0b_00_100_000); // any int type
+
+        let cmd = decode_command(&mut buf).unwrap();
+        assert_eq!(
+            cmd.cmd_type,
+            CmdType::Scan {
+                cursor: 5,
+                count: 10,
+                filter: ScanFilter {
+                    pattern: Some(bytes::Bytes::from_static(b"a*")),
+                    variant: Some(0),
+                },
+            }
+        );
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn scan_reply() {
+        let store = Store::new();
+        set(&store, SetFlags::default(), 1);
+
+        let reply = Command {
+            cmd_type: CmdType::Scan {
+                cursor: 0,
+                count: 10,
+                filter: ScanFilter::default(),
+            },
+            key: BoopArray::new_wrapped(vec![]),
+            val: None,
+        }
+        .execute(store);
+
+        assert_eq!(
+            reply.unwrap(),
+            BoopArray::new_wrapped(vec![
+                Int::new_u64(0),
+                BoopArray::new_wrapped(vec![Int::new_u8(1)]),
+            ])
+        );
+    }
+
+    #[test]
+    fn parse_keyspace_commands() {
+        let mut buf = bytes::BytesMut::new();
+        buf.put_u8(0x31); // EXISTS
+        buf.put_u8(0x03);
+        buf.put_u16(1);
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        buf.put_u8(0x32); // DEL
+        buf.put_u8(0x03);
+        buf.put_u16(0);
+        buf.put_u8(0x33); // TYPE
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        buf.put_u8(0x34); // DBSIZE
+        buf.put_u8(0x35); // FLUSHALL ASYNC
+        buf.put_u8(0x01);
+        buf.put_u8(0x36); // RENAME
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        buf.put_u8(0x00);
+        buf.put_u8(0x02);
+
+        let expected = [
+            CmdType::Exists,
+            CmdType::Del,
+            CmdType::Type,
+            CmdType::DbSize,
+            CmdType::FlushAll {
+                in_background: true,
+            },
+            CmdType::Rename,
+        ];
+        for cmd_type in expected {
+            assert_eq!(decode_command(&mut buf).unwrap().cmd_type, cmd_type);
+        }
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn keyspace_replies() {
+        let store = Store::new();
+        let run = |cmd_type: CmdType, key: DataType, val: Option<DataType>| {
+            Command { cmd_type, key, val }
+                .execute(store.clone())
+                .unwrap()
+        };
+        let keys = || BoopArray::new_wrapped(vec![Int::new_u8(1), Int::new_u8(2)]);
+        let none = || BoopArray::new_wrapped(vec![]);
+
+        set(&store, SetFlags::default(), 1);
+        assert_eq!(run(CmdType::Exists, keys(), None), Int::new_u64(1));
+        assert_eq!(run(CmdType::Type, Int::new_u8(1), None), Int::new_u8(0));
+        assert_eq!(
+            run(CmdType::Type, Int::new_u8(2), None),
+            BoopError::no_exist()
+        );
+        assert_eq!(
+            run(CmdType::Rename, Int::new_u8(1), Some(Int::new_u8(2))),
+            BoopBool::new_wrapped(true)
+        );
+        assert_eq!(
+            run(CmdType::Rename, Int::new_u8(1), Some(Int::new_u8(2))),
+            BoopError::no_exist()
+        );
+        assert_eq!(run(CmdType::DbSize, none(), None), Int::new_u64(1));
+        assert_eq!(run(CmdType::Del, keys(), None), Int::new_u64(1));
+
+        set(&store, SetFlags::default(), 1);
+        assert_eq!(
+            run(
+                CmdType::FlushDb {
+                    in_background: false
+                },
+                none(),
+                None
+            ),
+            BoopBool::new_wrapped(true)
+        );
+        assert_eq!(run(CmdType::DbSize, none(), None), Int::new_u64(0));
+    }
+
+    #[test]
+    fn memory_replies() {
+        let store = Store::new();
+        let mut buf = bytes::BytesMut::new();
+        buf.put_u8(0xC0); // MEMORY USAGE 1 0
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        buf.put_u8(0x00);
+        buf.put_u8(0x00);
+        buf.put_u8(0xC1); // INFO
+
+        let usage = decode_command(&mut buf).unwrap();
+        let info = decode_command(&mut buf).unwrap();
+        assert!(buf.is_empty());
+        assert_eq!(usage.cmd_type, CmdType::MemoryUsage { samples: 0 });
+        assert_eq!(usage.key, Int::new_u8(1));
+        assert_eq!(info.cmd_type, CmdType::Info);
+
+        let usage = || Command {
+            cmd_type: CmdType::MemoryUsage { samples: 0 },
+            key: Int::new_u8(1),
+            val: None,
+        };
+        assert_eq!(usage().execute(store.clone()), Some(BoopError::no_exist()));
+
+        set(&store, SetFlags::default(), 1);
+        let used = store.used_memory() as u64;
+        assert_eq!(usage().execute(store.clone()), Some(Int::new_u64(used)));
+
+        let field =
+            |name: &'static str| BoopString::new_wrapped(Bytes::from_static(name.as_bytes()));
+        assert_eq!(
+            info.execute(store.clone()),
+            Some(BoopArray::new_wrapped(vec![
+                field("keys"),
+                Int::new_u64(1),
+                field("used_memory"),
+                Int::new_u64(used),
+                field("max_memory"),
+                Int::new_u64(0),
+                field("eviction_policy"),
+                field("noeviction"),
+            ]))
+        );
+    }
+
+    #[test]
+    fn parse_pubsub_commands() {
+        let mut buf = bytes::BytesMut::new();
+        buf.put_u8(0xD0); // PUB "news" 1
+        buf.put_u8(0x02);
+        buf.put_u16(4);
+        buf.put_slice(b"news");
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        buf.put_u8(0xD1); // SUB ["news", 1]
+        buf.put_u8(0x03);
+        buf.put_u16(2);
+        buf.put_u8(0x02);
+        buf.put_u16(4);
+        buf.put_slice(b"news");
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        buf.put_u8(0xD2); // PSUB ["n*"]
+        buf.put_u8(0x03);
+        buf.put_u16(1);
+        buf.put_u8(0x02);
+        buf.put_u16(2);
+        buf.put_slice(b"n*");
+        buf.put_u8(0xD3); // UNSUB []
+        buf.put_u8(0x03);
+        buf.put_u16(0);
+        buf.put_u8(0xD4); // PUNSUB []
+        buf.put_u8(0x03);
+        buf.put_u16(0);
+
+        let news = BoopString::new_wrapped(Bytes::from_static(b"news"));
+        let publish = decode_command(&mut buf).unwrap();
+        assert_eq!(publish.cmd_type, CmdType::Publish);
+        assert_eq!(publish.key, news);
+        assert_eq!(publish.val, Some(Int::new_u8(1)));
+        let cmd = decode_command(&mut buf).unwrap();
+        assert_eq!(cmd.cmd_type, CmdType::Subscribe);
+        assert_eq!(
+            cmd.key,
+            BoopArray::new_wrapped(vec![news.clone(), Int::new_u8(1)])
+        );
+        let cmd = decode_command(&mut buf).unwrap();
+        assert_eq!(cmd.cmd_type, CmdType::PSubscribe);
+        assert_eq!(
+            cmd.key,
+            BoopArray::new_wrapped(vec![BoopString::new_wrapped(Bytes::from_static(b"n*"))])
+        );
+        let cmd = decode_command(&mut buf).unwrap();
+        assert_eq!(cmd.cmd_type, CmdType::Unsubscribe);
+        let cmd = decode_command(&mut buf).unwrap();
+        assert_eq!(cmd.cmd_type, CmdType::PUnsubscribe);
+        assert!(buf.is_empty());
+
+        // Patterns must be strings
+        let mut buf = bytes::BytesMut::new();
+        buf.put_u8(0xD2); // PSUB [1]
+        buf.put_u8(0x03);
+        buf.put_u16(1);
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        assert!(decode_command(&mut buf).is_err());
+
+        // Publishing replies with how many subscriptions the message was sent to
+        let store = Store::new();
+        let mut subscriber = store.pubsub().subscriber();
+        subscriber.subscribe(vec![news.clone()]);
+        assert_eq!(publish.execute(store.clone()), Some(Int::new_u64(1)));
+        assert_eq!(
+            subscriber.next_message(Duration::ZERO),
+            Some(BoopArray::new_wrapped(vec![
+                BoopString::new_wrapped(Bytes::from_static(b"message")),
+                news,
+                Int::new_u8(1),
+            ]))
+        );
+    }
+
+    #[test]
+    fn parse_tracking() {
+        let mut buf = bytes::BytesMut::new();
+        buf.put_u8(0xE0); // TRACKING true ["user:"]
+        buf.put_u8(0x84);
+        buf.put_u8(0x03);
+        buf.put_u16(1);
+        buf.put_u8(0x02);
+        buf.put_u16(5);
+        buf.put_slice(b"user:");
+        buf.put_u8(0xE0); // TRACKING false []
+        buf.put_u8(0x04);
+        buf.put_u8(0x03);
+        buf.put_u16(0);
+
+        let cmd = decode_command(&mut buf).unwrap();
+        assert_eq!(cmd.cmd_type, CmdType::Tracking { on: true });
+        assert_eq!(
+            cmd.key,
+            BoopArray::new_wrapped(vec![BoopString::new_wrapped(Bytes::from_static(b"user:"))])
+        );
+        let cmd = decode_command(&mut buf).unwrap();
+        assert_eq!(cmd.cmd_type, CmdType::Tracking { on: false });
+        assert_eq!(cmd.key, BoopArray::new_wrapped(vec![]));
+        assert!(buf.is_empty());
+
+        // Tracking is run by the session
+        assert_eq!(cmd.execute(Store::new()), None);
+
+        // The switch must be a bool
+        let mut buf = bytes::BytesMut::new();
+        buf.put_u8(0xE0); // TRACKING 1 []
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        buf.put_u8(0x03);
+        buf.put_u16(0);
+        assert!(decode_command(&mut buf).is_err());
+    }
+
+    #[test]
+    fn parse_save() {
+        let mut buf = bytes::BytesMut::new();
+        buf.put_u8(0xF0); // SAVE
+        buf.put_u8(0xF1); // BGSAVE
+
+        for in_background in [false, true] {
+            let cmd = decode_command(&mut buf).unwrap();
+            assert_eq!(cmd.cmd_type, CmdType::Save { in_background });
+            assert_eq!(cmd.key, BoopArray::new_wrapped(vec![]));
+            // Snapshots cover every database, so are taken by the session
+            assert_eq!(cmd.execute(Store::new()), None);
+        }
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn read_keys_are_the_keys_read() {
+        let key = || Int::new_u8(1);
+        let cmd = |cmd_type, key| Command {
+            cmd_type,
+            key,
+            val: None,
+        };
+        assert_eq!(cmd(CmdType::Get, key()).read_keys(), &[key()]);
+        assert_eq!(
+            cmd(
+                CmdType::MGet,
+                BoopArray::new_wrapped(vec![key(), Int::new_u8(2)])
+            )
+            .read_keys(),
+            &[key(), Int::new_u8(2)]
+        );
+        assert!(cmd(CmdType::Del, BoopArray::new_wrapped(vec![key()]))
+            .read_keys()
+            .is_empty());
+    }
+
+    #[test]
+    fn parse_database_commands() {
+        let mut buf = bytes::BytesMut::new();
+        buf.put_u8(0xB0); // SELECT 3
+        buf.put_u8(0x00);
+        buf.put_u8(0x03);
+        buf.put_u8(0xB1); // MOVE 1 2
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        buf.put_u8(0x00);
+        buf.put_u8(0x02);
+        buf.put_u8(0xB2); // SWAPDB 0 1
+        buf.put_u8(0x00);
+        buf.put_u8(0x00);
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        buf.put_u8(0xB3); // ON 2 GET 1
+        buf.put_u8(0x00);
+        buf.put_u8(0x02);
+        buf.put_u8(0x00);
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        buf.put_u8(0x37); // FLUSHDB ASYNC
+        buf.put_u8(0x01);
+
+        let cmd = decode_command(&mut buf).unwrap();
+        assert_eq!(cmd.cmd_type, CmdType::Select(3));
+        let cmd = decode_command(&mut buf).unwrap();
+        assert_eq!(cmd.cmd_type, CmdType::Move { db: 2 });
+        assert_eq!(cmd.key, Int::new_u8(1));
+        let cmd = decode_command(&mut buf).unwrap();
+        assert_eq!(cmd.cmd_type, CmdType::SwapDb(0, 1));
+        let cmd = decode_command(&mut buf).unwrap();
+        assert_eq!(
+            cmd.cmd_type,
+            CmdType::On {
+                db: 2,
+                cmd: Box::new(Command {
+                    cmd_type: CmdType::Get,
+                    key: Int::new_u8(1),
+                    val: None,
+                })
+            }
+        );
+        let cmd = decode_command(&mut buf).unwrap();
+        assert_eq!(
+            cmd.cmd_type,
+            CmdType::FlushDb {
+                in_background: true
+            }
+        );
+        assert!(buf.is_empty());
+
+        // ON can't wrap commands that work across databases
+        let mut buf = bytes::BytesMut::new();
+        buf.put_u8(0xB3); // ON 2 SELECT 1
+        buf.put_u8(0x00);
+        buf.put_u8(0x02);
+        buf.put_u8(0xB0);
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        assert!(decode_command(&mut buf).is_err());
+
+        let mut buf = bytes::BytesMut::new();
+        buf.put_u8(0xB0); // SELECT "a"
+        buf.put_u8(0x02);
+        buf.put_u16(1);
+        buf.put_u8(b'a');
+        assert!(decode_command(&mut buf).is_err());
+    }
+
+    #[test]
+    fn parse_list_commands() {
+        let mut buf = bytes::BytesMut::new();
+        buf.put_u8(0x40); // LPUSH
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        buf.put_u8(0x03);
+        buf.put_u16(1);
+        buf.put_u8(0x00);
+        buf.put_u8(0x02);
+        buf.put_u8(0x43); // RPOP
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        buf.put_u8(0x44); // LINDEX -1
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        buf.put_u8(0b_00_111_000);
+        buf.put_f64(-1.0);
+        buf.put_u8(0x45); // LRANGE 0 -2
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        buf.put_u8(0x00);
+        buf.put_u8(0x00);
+        buf.put_u8(0b_00_110_000);
+        buf.put_f32(-2.0);
+        buf.put_u8(0x46); // LSET 1
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        buf.put_u8(0x00);
+        buf.put_u8(0x03);
+
+        let cmd = decode_command(&mut buf).unwrap();
+        assert_eq!(cmd.cmd_type, CmdType::Push(End::Front));
+        assert_eq!(cmd.val, Some(BoopArray::new_wrapped(vec![Int::new_u8(2)])));
+        let cmd = decode_command(&mut buf).unwrap();
+        assert_eq!(cmd.cmd_type, CmdType::Pop(End::Back));
+        let cmd = decode_command(&mut buf).unwrap();
+        assert_eq!(cmd.cmd_type, CmdType::LIndex { index: -1 });
+        let cmd = decode_command(&mut buf).unwrap();
+        assert_eq!(cmd.cmd_type, CmdType::LRange { start: 0, stop: -2 });
+        let cmd = decode_command(&mut buf).unwrap();
+        assert_eq!(cmd.cmd_type, CmdType::LSet { index: 1 });
+        assert_eq!(cmd.val, Some(Int::new_u8(3)));
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn list_replies() {
+        let store = Store::new();
+        let run = |cmd_type: CmdType, val: Option<DataType>| {
+            Command {
+                cmd_type,
+                key: Int::new_u8(1),
+                val,
+            }
+            .execute(store.clone())
+            .unwrap()
+        };
+
+        assert_eq!(
+            run(
+                CmdType::Push(End::Back),
+                Some(BoopArray::new_wrapped(vec![Int::new_u8(1), Int::new_u8(2)]))
+            ),
+            Int::new_u64(2)
+        );
+        assert_eq!(run(CmdType::LLen, None), Int::new_u64(2));
+        assert_eq!(
+            run(CmdType::LRange { start: 0, stop: -1 }, None),
+            BoopArray::new_wrapped(vec![Int::new_u8(1), Int::new_u8(2)])
+        );
+        assert_eq!(
+            run(CmdType::LSet { index: 0 }, Some(Int::new_u8(3))),
+            BoopBool::new_wrapped(true)
+        );
+        assert_eq!(
+            run(CmdType::LIndex { index: 2 }, None),
+            BoopError::out_of_range()
+        );
+        assert_eq!(run(CmdType::Pop(End::Front), None), Int::new_u8(3));
+        assert_eq!(
+            run(CmdType::LTrim { start: 1, stop: 0 }, None),
+            BoopBool::new_wrapped(true)
+        );
+        assert_eq!(run(CmdType::Pop(End::Front), None), BoopError::no_exist());
+
+        set(&store, SetFlags::default(), 1);
+        assert_eq!(run(CmdType::LLen, None), BoopError::wrong_type());
+    }
+
+    #[test]
+    fn parse_blocking_pops() {
+        let mut buf = bytes::BytesMut::new();
+        buf.put_u8(0x4A); // BRPOP
+        buf.put_u8(0x03);
+        buf.put_u16(2);
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        buf.put_u8(0x00);
+        buf.put_u8(0x02);
+        buf.put_u8(0b_00_001_000);
+        buf.put_u16(500);
+
+        let cmd = decode_command(&mut buf).unwrap();
+        assert_eq!(
+            cmd.cmd_type,
+            CmdType::BPop {
+                end: End::Back,
+                timeout_ms: 500
+            }
+        );
+        assert_eq!(
+            cmd.key,
+            BoopArray::new_wrapped(vec![Int::new_u8(1), Int::new_u8(2)])
+        );
+
+        let mut buf = bytes::BytesMut::new();
+        buf.put_u8(0x49); // BLPOP without any keys
+        buf.put_u8(0x03);
+        buf.put_u16(0);
+        buf.put_u8(0x00);
+        buf.put_u8(0x00);
+        assert!(decode_command(&mut buf).is_err());
+    }
+
+    #[test]
+    fn blocking_pop_replies() {
+        let store = Store::new();
+        let run = |timeout_ms| {
+            Command {
+                cmd_type: CmdType::BPop {
+                    end: End::Front,
+                    timeout_ms,
+                },
+                key: BoopArray::new_wrapped(vec![Int::new_u8(1), Int::new_u8(2)]),
+                val: None,
+            }
+            .execute(store.clone())
+            .unwrap()
+        };
+
+        assert_eq!(run(10), BoopError::no_exist());
+
+        store
+            .push(&Int::new_u8(2), vec![Int::new_u8(5)], End::Back)
+            .unwrap();
+        assert_eq!(
+            run(0),
+            BoopArray::new_wrapped(vec![Int::new_u8(2), Int::new_u8(5)])
+        );
+    }
+
+    #[test]
+    fn parse_string_commands() {
+        let mut buf = bytes::BytesMut::new();
+        buf.put_u8(0x50); // APPEND
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        buf.put_u8(0x02);
+        buf.put_u16(2);
+        buf.put_slice(b"ab");
+        buf.put_u8(0x51); // STRLEN
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        buf.put_u8(0x52); // GETRANGE 0 -1
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        buf.put_u8(0x00);
+        buf.put_u8(0x00);
+        buf.put_u8(0b_00_111_000);
+        buf.put_f64(-1.0);
+        buf.put_u8(0x53); // SETRANGE 4
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        buf.put_u8(0x00);
+        buf.put_u8(0x04);
+        buf.put_u8(0x02);
+        buf.put_u16(1);
+        buf.put_slice(b"c");
+
+        let cmd = decode_command(&mut buf).unwrap();
+        assert_eq!(cmd.cmd_type, CmdType::Append);
+        assert_eq!(
+            cmd.val,
+            Some(BoopString::new_wrapped(Bytes::from_static(b"ab")))
+        );
+        let cmd = decode_command(&mut buf).unwrap();
+        assert_eq!(cmd.cmd_type, CmdType::StrLen);
+        let cmd = decode_command(&mut buf).unwrap();
+        assert_eq!(cmd.cmd_type, CmdType::GetRange { start: 0, stop: -1 });
+        let cmd = decode_command(&mut buf).unwrap();
+        assert_eq!(cmd.cmd_type, CmdType::SetRange { offset: 4 });
+        assert!(buf.is_empty());
+
+        // Only strings can be appended
+        let mut buf = bytes::BytesMut::new();
+        buf.put_u8(0x50);
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        assert!(decode_command(&mut buf).is_err());
+    }
+
+    #[test]
+    fn string_replies() {
+        let store = Store::new();
+        let run = |cmd_type: CmdType, val: Option<&'static [u8]>| {
+            Command {
+                cmd_type,
+                key: Int::new_u8(1),
+                val: val.map(|v| BoopString::new_wrapped(Bytes::from_static(v))),
+            }
+            .execute(store.clone())
+            .unwrap()
+        };
+
+        assert_eq!(run(CmdType::Append, Some(b"abc")), Int::new_u64(3));
+        assert_eq!(
+            run(CmdType::SetRange { offset: 5 }, Some(b"f")),
+            Int::new_u64(6)
+        );
+        assert_eq!(run(CmdType::StrLen, None), Int::new_u64(6));
+        assert_eq!(
+            run(CmdType::GetRange { start: 2, stop: -1 }, None),
+            BoopString::new_wrapped(Bytes::from_static(b"c\0\0f"))
+        );
+
+        set(&store, SetFlags::default(), 1);
+        assert_eq!(run(CmdType::StrLen, None), BoopError::wrong_type());
+    }
+
+    #[test]
+    fn parse_bit_commands() {
+        let mut buf = bytes::BytesMut::new();
+        buf.put_u8(0x54); // SETBIT 9 true
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        buf.put_u8(0x00);
+        buf.put_u8(0x09);
+        buf.put_u8(0b_1_0000_100);
+        buf.put_u8(0x55); // GETBIT 9
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        buf.put_u8(0x00);
+        buf.put_u8(0x09);
+        buf.put_u8(0x56); // BITCOUNT 1 -1
+        buf.put_u8(0x01);
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        buf.put_u8(0b_00_111_000);
+        buf.put_f64(-1.0);
+        buf.put_u8(0x57); // BITPOS false 2
+        buf.put_u8(0x01);
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        buf.put_u8(0b_0_0000_100);
+        buf.put_u8(0x00);
+        buf.put_u8(0x02);
+        buf.put_u8(0x58); // BITOP XOR
+        buf.put_u8(0x02);
+        buf.put_u8(0x00);
+        buf.put_u8(0x03);
+        buf.put_u8(0x03);
+        buf.put_u16(2);
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        buf.put_u8(0x00);
+        buf.put_u8(0x02);
+
+        let expected = [
+            CmdType::SetBit {
+                offset: 9,
+                bit: true,
+            },
+            CmdType::GetBit { offset: 9 },
+            CmdType::BitCount {
+                range: Some((1, -1)),
+            },
+            CmdType::BitPos {
+                bit: false,
+                start: 2,
+                stop: None,
+            },
+            CmdType::BitOp(BitOp::Xor),
+        ];
+        for cmd_type in expected {
+            assert_eq!(decode_command(&mut buf).unwrap().cmd_type, cmd_type);
+        }
+        assert!(buf.is_empty());
+
+        // NOT only works on a single key
+        let mut buf = bytes::BytesMut::new();
+        buf.put_u8(0x58);
+        buf.put_u8(0x03);
+        buf.put_u8(0x00);
+        buf.put_u8(0x03);
+        buf.put_u8(0x03);
+        buf.put_u16(2);
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        buf.put_u8(0x00);
+        buf.put_u8(0x02);
+        assert!(decode_command(&mut buf).is_err());
+    }
+
+    #[test]
+    fn bit_replies() {
+        let store = Store::new();
+        let run = |cmd_type: CmdType| {
+            Command {
+                cmd_type,
+                key: Int::new_u8(1),
+                val: None,
+            }
+            .execute(store.clone())
+            .unwrap()
+        };
+
+        assert_eq!(
+            run(CmdType::SetBit {
+                offset: 7,
+                bit: true
+            }),
+            BoopBool::new_wrapped(false)
+        );
+        assert_eq!(
+            run(CmdType::GetBit { offset: 7 }),
+            BoopBool::new_wrapped(true)
+        );
+        assert_eq!(run(CmdType::BitCount { range: None }), Int::new_u64(1));
+        assert_eq!(
+            run(CmdType::BitPos {
+                bit: true,
+                start: 0,
+                stop: None
+            }),
+            Int::new_u64(7)
+        );
+        assert_eq!(
+            run(CmdType::BitPos {
+                bit: true,
+                start: 1,
+                stop: None
+            }),
+            BoopError::no_exist()
+        );
+
+        let reply = Command {
+            cmd_type: CmdType::BitOp(BitOp::Not),
+            key: Int::new_u8(2),
+            val: Some(BoopArray::new_wrapped(vec![Int::new_u8(1)])),
+        }
+        .execute(store.clone())
+        .unwrap();
+        assert_eq!(reply, Int::new_u64(1));
+        assert_eq!(
+            store.get(&Int::new_u8(2)),
+            Some(BoopString::new_wrapped(Bytes::from_static(&[0xFE])))
+        );
+    }
+
+    #[test]
+    fn parse_sorted_set_commands() {
+        let mut buf = bytes::BytesMut::new();
+        buf.put_u8(0x60); // ZADD NX
+        buf.put_u8(0x01);
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        buf.put_u8(0x03);
+        buf.put_u16(2);
+        buf.put_u8(0b_00_111_000);
+        buf.put_f64(-1.5);
+        buf.put_u8(0x00);
+        buf.put_u8(0x02);
+        buf.put_u8(0x63); // ZINCRBY 2
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        buf.put_u8(0x00);
+        buf.put_u8(0x02);
+        buf.put_u8(0x00);
+        buf.put_u8(0x02);
+        buf.put_u8(0x64); // ZRANK REV
+        buf.put_u8(0x01);
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        buf.put_u8(0x00);
+        buf.put_u8(0x02);
+        buf.put_u8(0x65); // ZRANGE BYSCORE LIMIT WITHSCORES (0 100 LIMIT 1 -1
+        buf.put_u8(0b_0001_1110);
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        buf.put_u8(0x00);
+        buf.put_u8(0x00);
+        buf.put_u8(0x00);
+        buf.put_u8(100);
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        buf.put_u8(0b_00_111_000);
+        buf.put_f64(-1.0);
+
+        let cmd = decode_command(&mut buf).unwrap();
+        assert_eq!(
+            cmd.cmd_type,
+            CmdType::ZAdd {
+                nx: true,
+                xx: false
+            }
+        );
+        let cmd = decode_command(&mut buf).unwrap();
+        assert_eq!(
+            cmd.cmd_type,
+            CmdType::ZIncrBy {
+                by: OrderedFloat(2.0)
+            }
+        );
+        let cmd = decode_command(&mut buf).unwrap();
+        assert_eq!(cmd.cmd_type, CmdType::ZRank { rev: true });
+        assert_eq!(cmd.val, Some(Int::new_u8(2)));
+        let cmd = decode_command(&mut buf).unwrap();
+        assert_eq!(
+            cmd.cmd_type,
+            CmdType::ZRange {
+                by: ZRangeBy::Score {
+                    min: OrderedFloat(0.0),
+                    max: OrderedFloat(100.0),
+                    min_exclusive: true,
+                    max_exclusive: false,
+                    offset: 1,
+                    count: None,
+                },
+                rev: false,
+                with_scores: true,
+            }
+        );
+        assert!(buf.is_empty());
+
+        // Scores must be numbers
+        let mut buf = bytes::BytesMut::new();
+        buf.put_u8(0x60);
+        buf.put_u8(0x00);
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        buf.put_u8(0x03);
+        buf.put_u16(2);
+        buf.put_u8(0b_1_0000_100);
+        buf.put_u8(0x00);
+        buf.put_u8(0x02);
+        assert!(decode_command(&mut buf).is_err());
+
+        // Limits only apply to score ranges
+        let mut buf = bytes::BytesMut::new();
+        buf.put_u8(0x65);
+        buf.put_u8(0b_0000_0100);
+        assert!(decode_command(&mut buf).is_err());
+    }
+
+    #[test]
+    fn sorted_set_replies() {
+        let store = Store::new();
+        let run = |cmd_type: CmdType, val: Option<DataType>| {
+            Command {
+                cmd_type,
+                key: Int::new_u8(1),
+                val,
+            }
+            .execute(store.clone())
+            .unwrap()
+        };
+
+        let pairs = BoopArray::new_wrapped(vec![
+            Int::new_u8(1),
+            Int::new_u8(10),
+            Int::new_f64(2.5),
+            Int::new_u8(20),
+        ]);
+        assert_eq!(
+            run(
+                CmdType::ZAdd {
+                    nx: false,
+                    xx: false
+                },
+                Some(pairs)
+            ),
+            Int::new_u64(2)
+        );
+        assert_eq!(
+            run(CmdType::ZScore, Some(Int::new_u8(20))),
+            Int::new_f64(2.5)
+        );
+        assert_eq!(
+            run(CmdType::ZScore, Some(Int::new_u8(30))),
+            BoopError::no_exist()
+        );
+        assert_eq!(
+            run(
+                CmdType::ZIncrBy {
+                    by: OrderedFloat(2.0)
+                },
+                Some(Int::new_u8(10))
+            ),
+            Int::new_f64(3.0)
+        );
+        assert_eq!(
+            run(CmdType::ZRank { rev: false }, Some(Int::new_u8(10))),
+            Int::new_u64(1)
+        );
+        assert_eq!(
+            run(
+                CmdType::ZRange {
+                    by: ZRangeBy::Rank { start: 0, stop: -1 },
+                    rev: true,
+                    with_scores: true,
+                },
+                None
+            ),
+            BoopArray::new_wrapped(vec![
+                Int::new_u8(10),
+                Int::new_f64(3.0),
+                Int::new_u8(20),
+                Int::new_f64(2.5),
+            ])
+        );
+        assert_eq!(
+            run(
+                CmdType::ZRem,
+                Some(BoopArray::new_wrapped(vec![Int::new_u8(10)]))
+            ),
+            Int::new_u64(1)
+        );
+        assert_eq!(run(CmdType::Type, None), Int::new_u8(0b_0_0000_101));
+    }
+
+    #[test]
+    fn parse_set_commands() {
+        let mut buf = bytes::BytesMut::new();
+        buf.put_u8(0x70); // SADD
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        buf.put_u8(0x03);
+        buf.put_u16(1);
+        buf.put_u8(0x00);
+        buf.put_u8(0x02);
+        buf.put_u8(0x75); // SRANDMEMBER -2
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        buf.put_u8(0b_00_111_000);
+        buf.put_f64(-2.0);
+        buf.put_u8(0x77); // SINTER
+        buf.put_u8(0x03);
+        buf.put_u16(1);
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        buf.put_u8(0x7B); // SDIFFSTORE
+        buf.put_u8(0x00);
+        buf.put_u8(0x03);
+        buf.put_u8(0x03);
+        buf.put_u16(1);
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+
+        let cmd = decode_command(&mut buf).unwrap();
+        assert_eq!(cmd.cmd_type, CmdType::SAdd);
+        assert_eq!(cmd.val, Some(BoopArray::new_wrapped(vec![Int::new_u8(2)])));
+        let cmd = decode_command(&mut buf).unwrap();
+        assert_eq!(cmd.cmd_type, CmdType::SRandMember { count: -2 });
+        let cmd = decode_command(&mut buf).unwrap();
+        assert_eq!(cmd.cmd_type, CmdType::SetOp(SetOp::Inter));
+        assert_eq!(cmd.key, BoopArray::new_wrapped(vec![Int::new_u8(1)]));
+        let cmd = decode_command(&mut buf).unwrap();
+        assert_eq!(cmd.cmd_type, CmdType::SetOpStore(SetOp::Diff));
+        assert_eq!(cmd.key, Int::new_u8(3));
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn set_replies() {
+        let store = Store::new();
+        let run = |cmd_type: CmdType, key: DataType, val: Option<DataType>| {
+            Command { cmd_type, key, val }
+                .execute(store.clone())
+                .unwrap()
+        };
+        let members = |values: &[u8]| values.iter().map(|v| Int::new_u8(*v)).collect::<Vec<_>>();
+
+        assert_eq!(
+            run(
+                CmdType::SAdd,
+                Int::new_u8(1),
+                Some(BoopArray::new_wrapped(members(&[1, 2, 3])))
+            ),
+            Int::new_u64(3)
+        );
+        run(
+            CmdType::SAdd,
+            Int::new_u8(2),
+            Some(BoopArray::new_wrapped(members(&[3, 4]))),
+        );
+
+        assert_eq!(
+            run(CmdType::SIsMember, Int::new_u8(1), Some(Int::new_u8(2))),
+            BoopBool::new_wrapped(true)
+        );
+        assert_eq!(run(CmdType::SCard, Int::new_u8(1), None), Int::new_u64(3));
+        assert_eq!(
+            run(CmdType::SMembers, Int::new_u8(2), None),
+            BoopSet::new_wrapped(members(&[3, 4]))
+        );
+        assert_eq!(
+            run(
+                CmdType::SetOp(SetOp::Union),
+                BoopArray::new_wrapped(members(&[1, 2])),
+                None
+            ),
+            BoopSet::new_wrapped(members(&[1, 2, 3, 4]))
+        );
+        assert_eq!(
+            run(
+                CmdType::SetOpStore(SetOp::Inter),
+                Int::new_u8(3),
+                Some(BoopArray::new_wrapped(members(&[1, 2])))
+            ),
+            Int::new_u64(1)
+        );
+        assert_eq!(
+            run(CmdType::Type, Int::new_u8(3), None),
+            Int::new_u8(0b_0_0000_111)
+        );
+        assert_eq!(
+            run(
+                CmdType::SRem,
+                Int::new_u8(3),
+                Some(BoopArray::new_wrapped(members(&[3])))
+            ),
+            Int::new_u64(1)
+        );
+        assert_eq!(
+            run(CmdType::SRandMember { count: 1 }, Int::new_u8(3), None),
+            BoopArray::new_wrapped(vec![])
+        );
+    }
+
+    #[test]
+    fn hyperloglog_replies() {
+        let store = Store::new();
+        let run = |cmd_type: CmdType, key: DataType, val: Option<DataType>| {
+            Command { cmd_type, key, val }
+                .execute(store.clone())
+                .unwrap()
+        };
+        let elements = |values: &[u8]| values.iter().map(|v| Int::new_u8(*v)).collect::<Vec<_>>();
+
+        let mut buf = bytes::BytesMut::new();
+        buf.put_u8(0x80); // PFADD
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        buf.put_u8(0x03);
+        buf.put_u16(2);
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        buf.put_u8(0x00);
+        buf.put_u8(0x02);
+        let cmd = decode_command(&mut buf).unwrap();
+        assert_eq!(cmd.cmd_type, CmdType::PfAdd);
+        assert_eq!(
+            cmd.execute(store.clone()).unwrap(),
+            BoopBool::new_wrapped(true)
+        );
+
+        assert_eq!(
+            run(
+                CmdType::PfAdd,
+                Int::new_u8(1),
+                Some(BoopArray::new_wrapped(elements(&[2])))
+            ),
+            BoopBool::new_wrapped(false)
+        );
+        run(
+            CmdType::PfAdd,
+            Int::new_u8(2),
+            Some(BoopArray::new_wrapped(elements(&[2, 3]))),
+        );
+        assert_eq!(
+            run(
+                CmdType::PfCount,
+                BoopArray::new_wrapped(elements(&[1, 2])),
+                None
+            ),
+            Int::new_u64(3)
+        );
+        assert_eq!(
+            run(
+                CmdType::PfMerge,
+                Int::new_u8(3),
+                Some(BoopArray::new_wrapped(elements(&[1, 2])))
+            ),
+            BoopBool::new_wrapped(true)
+        );
+        assert_eq!(
+            run(
+                CmdType::PfCount,
+                BoopArray::new_wrapped(elements(&[3])),
+                None
+            ),
+            Int::new_u64(3)
+        );
+    }
+
+    #[test]
+    fn parse_stream_commands() {
+        let mut buf = bytes::BytesMut::new();
+        buf.put_u8(0x90); // XADD with an automatic sequence number and MAXLEN
+        buf.put_u8(0b_0000_0110);
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        buf.put_u8(0x00); // ms
+        buf.put_u8(0x05);
+        buf.put_u8(0x00); // MAXLEN
+        buf.put_u8(0x0A);
+        buf.put_u8(0x03);
+        buf.put_u16(2);
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        buf.put_u8(0x00);
+        buf.put_u8(0x02);
+        buf.put_u8(0x93); // XTRIM MINID
+        buf.put_u8(0b_0000_0001);
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        buf.put_u8(0x00);
+        buf.put_u8(0x05);
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        buf.put_u8(0x95); // XREADGROUP with a count, from pending entries
+        buf.put_u8(0b_0000_0101);
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        buf.put_u8(0x00);
+        buf.put_u8(0x02);
+        buf.put_u8(0x00);
+        buf.put_u8(0x03);
+        buf.put_u8(0x00);
+        buf.put_u8(0x00);
+        buf.put_u8(0x00);
+        buf.put_u8(0x00);
+        buf.put_u8(0x00);
+        buf.put_u8(0x04);
+        buf.put_u8(0x96); // XACK
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        buf.put_u8(0x00);
+        buf.put_u8(0x02);
+        buf.put_u8(0x03);
+        buf.put_u16(2);
+        buf.put_u8(0x00);
+        buf.put_u8(0x05);
+        buf.put_u8(0x00);
+        buf.put_u8(0x00);
+
+        let cmd = decode_command(&mut buf).unwrap();
+        assert_eq!(
+            cmd.cmd_type,
+            CmdType::XAdd {
+                id: XAddId::AutoSeq(5),
+                trim: Some(XTrim::MaxLen(10)),
+            }
+        );
+        let cmd = decode_command(&mut buf).unwrap();
+        assert_eq!(
+            cmd.cmd_type,
+            CmdType::XTrim(XTrim::MinId(StreamId::new(5, 1)))
+        );
+        let cmd = decode_command(&mut buf).unwrap();
+        assert_eq!(
+            cmd.cmd_type,
+            CmdType::XReadGroup {
+                group: Int::new_u8(2),
+                consumer: Int::new_u8(3),
+                from: Some(StreamId::MIN),
+                count: Some(4),
+                noack: false,
+            }
+        );
+        let cmd = decode_command(&mut buf).unwrap();
+        assert_eq!(
+            cmd.cmd_type,
+            CmdType::XAck {
+                group: Int::new_u8(2),
+                ids: vec![StreamId::new(5, 0)],
+            }
+        );
+        assert!(buf.is_empty());
+
+        // Fields have to be pairs
+        let mut buf = bytes::BytesMut::new();
+        buf.put_u8(0x90);
+        buf.put_u8(0b_0000_0001);
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        buf.put_u8(0x03);
+        buf.put_u16(1);
+        buf.put_u8(0x00);
+        buf.put_u8(0x01);
+        assert!(decode_command(&mut buf).is_err());
+    }
+
+    #[test]
+    fn stream_replies() {
+        let store = Store::new();
+        let run = |cmd_type: CmdType, val: Option<DataType>| {
+            Command {
+                cmd_type,
+                key: Int::new_u8(1),
+                val,
+            }
+            .execute(store.clone())
+            .unwrap()
+        };
+        let id = |ms, seq| BoopArray::new_wrapped(vec![Int::new_u64(ms), Int::new_u64(seq)]);
+        let pair = || BoopArray::new_wrapped(vec![Int::new_u8(1), Int::new_u8(2)]);
+
+        assert_eq!(
+            run(
+                CmdType::XAdd {
+                    id: XAddId::Explicit(StreamId::new(5, 0)),
+                    trim: None,
+                },
+                Some(pair())
+            ),
+            id(5, 0)
+        );
+        assert_eq!(
+            run(
+                CmdType::XRange {
+                    start: StreamId::MIN,
+                    end: StreamId::new(u64::MAX, u64::MAX),
+                    count: None,
+                    rev: false,
+                },
+                None
+            ),
+            BoopArray::new_wrapped(vec![BoopArray::new_wrapped(vec![id(5, 0), pair()])])
+        );
+        assert_eq!(run(CmdType::XLen, None), Int::new_u64(1));
+        assert_eq!(run(CmdType::Type, None), Int::new_u8(0b_0_0000_001));
+
+        let group = Int::new_u8(2);
+        assert_eq!(
+            run(
+                CmdType::XGroupCreate {
+                    group: group.to_owned(),
+                    start: Some(StreamId::MIN),
+                    mkstream: false,
+                },
+                None
+            ),
+            BoopBool::new_wrapped(true)
+        );
+        run(
+            CmdType::XReadGroup {
+                group: group.to_owned(),
+                consumer: Int::new_u8(3),
+                from: None,
+                count: None,
+                noack: false,
+            },
+            None,
+        );
+        run(CmdType::XTrim(XTrim::MaxLen(0)), None);
+
+        // The entry is still pending, but has been trimmed
+        assert_eq!(
+            run(
+                CmdType::XReadGroup {
+                    group: group.to_owned(),
+                    consumer: Int::new_u8(3),
+                    from: Some(StreamId::MIN),
+                    count: None,
+                    noack: false,
+                },
+                None
+            ),
+            BoopArray::new_wrapped(vec![BoopArray::new_wrapped(vec![
+                id(5, 0),
+                BoopError::no_exist()
+            ])])
+        );
+        // Idle time depends on the wall clock, so only the other parts of the entry are compared
+        let DataType::Array(BoopArray(pending)) = run(
+            CmdType::XPending {
+                group: group.to_owned(),
+            },
+            None,
+        ) else {
+            panic!("XPENDING should reply with an array");
+        };
+        let DataType::Array(BoopArray(entry)) = &pending[0] else {
+            panic!("pending entries should be arrays");
+        };
+        assert_eq!(pending.len(), 1);
+        assert_eq!(
+            (&entry[0], &entry[1], &entry[3]),
+            (&id(5, 0), &Int::new_u8(3), &Int::new_u64(2))
+        );
+        assert_eq!(
+            run(
+                CmdType::XAck {
+                    group,
+                    ids: vec![StreamId::new(5, 0)],
+                },
+                None
+            ),
+            Int::new_u64(1)
         );
     }
 }
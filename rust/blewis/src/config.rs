@@ -0,0 +1,325 @@
+use crate::{
+    data_type::BoopString,
+    databases::Databases,
+    network::tcp::DEFAULT_MAX_COMMAND_BYTES,
+    pubsub::{PubSub, DEFAULT_MAX_TRACKED_KEYS},
+    snapshot::{Snapshots, DEFAULT_SNAPSHOT_FILE},
+    store::{
+        backend::BackendKind, eviction::EvictionPolicy, hasher::HashAlgorithm, notify::KeyEvents,
+        Store,
+    },
+};
+use anyhow::Context;
+use std::{collections::BTreeMap, path::PathBuf};
+
+/// The amount of databases a server holds unless told otherwise
+const DEFAULT_DATABASES: usize = 16;
+
+/// Config holds the server's settings, which are read from the command line at startup, e.g.
+/// `blewis --max-bitmap-bytes 1024`. Anything that isn't given keeps its default.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Config {
+    /// The most bytes a bitmap can grow to through SETBIT
+    pub max_bitmap_bytes: usize,
+    /// The amount of databases
+    pub databases: usize,
+    /// The settings of individual databases, by index. Any database without settings of its own
+    /// uses the defaults.
+    pub db_settings: BTreeMap<usize, DbSettings>,
+    /// The most bytes each database may hold on its own, or 0 for no limit. The limit is not
+    /// shared, so every database can use up to this much.
+    pub max_memory_per_db: usize,
+    /// What a database does once it has used up its memory
+    pub eviction_policy: EvictionPolicy,
+    /// What every database keeps its entries in
+    pub backend: BackendKind,
+    /// What every database hashes its keys with
+    pub hasher: HashAlgorithm,
+    /// The changes to keys that every database publishes
+    pub keyspace_events: KeyEvents,
+    /// The most keys that are remembered for tracking clients across every database, or 0 for
+    /// no limit
+    pub tracking_max_keys: usize,
+    /// The file that snapshots are written to by SAVE and BGSAVE, and loaded from at startup
+    pub snapshot_file: PathBuf,
+    /// The most bytes a single command may take up, past which its client is disconnected
+    pub max_command_bytes: usize,
+}
+
+/// The settings of the store behind a single database
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct DbSettings {
+    /// The amount of entries the store has room for before it first grows
+    pub capacity: usize,
+    /// The amount of shards the store is split into, which must be a power of two greater than 1.
+    /// `None` leaves it to the backend. `DashMap` picks an amount based on the amount of CPUs.
+    pub shards: Option<usize>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            max_bitmap_bytes: BoopString::MAX_LEN,
+            databases: DEFAULT_DATABASES,
+            db_settings: BTreeMap::new(),
+            max_memory_per_db: 0,
+            eviction_policy: EvictionPolicy::NoEviction,
+            backend: BackendKind::DashMap,
+            hasher: HashAlgorithm::SipHash,
+            keyspace_events: KeyEvents::NONE,
+            tracking_max_keys: DEFAULT_MAX_TRACKED_KEYS,
+            snapshot_file: PathBuf::from(DEFAULT_SNAPSHOT_FILE),
+            max_command_bytes: DEFAULT_MAX_COMMAND_BYTES,
+        }
+    }
+}
+
+impl Config {
+    /// Parses the config from command line arguments, not including the program name
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> anyhow::Result<Self> {
+        let mut config = Config::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .with_context(|| format!("{arg} should be followed by a value"))
+            };
+
+            match arg.as_str() {
+                "--max-bitmap-bytes" => {
+                    config.max_bitmap_bytes = value()?
+                        .parse()
+                        .context("--max-bitmap-bytes should be an unsigned integer")?;
+                }
+                "--databases" => {
+                    config.databases = value()?
+                        .parse()
+                        .context("--databases should be an unsigned integer")?;
+                    if config.databases == 0 {
+                        anyhow::bail!("--databases should be at least 1");
+                    }
+                }
+                "--db-capacity" => {
+                    let (db, capacity) = parse_db_setting(&arg, &value()?)?;
+                    config.db_settings.entry(db).or_default().capacity = capacity;
+                }
+                "--db-shards" => {
+                    let (db, shards) = parse_db_setting(&arg, &value()?)?;
+                    if shards < 2 || !shards.is_power_of_two() {
+                        anyhow::bail!("{arg} should be a power of two greater than 1");
+                    }
+                    config.db_settings.entry(db).or_default().shards = Some(shards);
+                }
+                "--max-memory-per-db" => {
+                    config.max_memory_per_db = value()?
+                        .parse()
+                        .context("--max-memory-per-db should be an unsigned integer")?;
+                }
+                "--eviction-policy" => {
+                    config.eviction_policy = value()?.parse()?;
+                }
+                "--backend" => {
+                    config.backend = value()?.parse()?;
+                }
+                "--hasher" => {
+                    config.hasher = value()?.parse()?;
+                }
+                "--keyspace-events" => {
+                    config.keyspace_events = value()?.parse()?;
+                }
+                "--tracking-max-keys" => {
+                    config.tracking_max_keys = value()?
+                        .parse()
+                        .context("--tracking-max-keys should be an unsigned integer")?;
+                }
+                "--snapshot-file" => {
+                    config.snapshot_file = PathBuf::from(value()?);
+                }
+                "--max-command-bytes" => {
+                    config.max_command_bytes = value()?
+                        .parse()
+                        .context("--max-command-bytes should be an unsigned integer")?;
+                }
+                unknown => anyhow::bail!("unknown argument: {unknown}"),
+            }
+        }
+
+        if let Some(db) = config
+            .db_settings
+            .keys()
+            .find(|db| **db >= config.databases)
+        {
+            anyhow::bail!(
+                "database {db} is configured, but there are only {} databases",
+                config.databases
+            );
+        }
+
+        Ok(config)
+    }
+
+    /// Creates the store of every database that the server runs with, all publishing through one
+    /// hub, which also tracks keys for every database. The databases are empty until their
+    /// snapshot is loaded, see `Snapshots::load`.
+    pub fn build_databases(&self) -> Databases {
+        let pubsub = PubSub::with_max_tracked_keys(self.tracking_max_keys);
+        Databases::new(
+            (0..self.databases)
+                .map(|db| self.build_store(db, &pubsub))
+                .collect(),
+        )
+        .with_snapshots(Snapshots::new(&self.snapshot_file))
+    }
+
+    /// Creates the store of a single database
+    fn build_store(&self, db: usize, pubsub: &PubSub) -> Store {
+        let settings = self.db_settings.get(&db).copied().unwrap_or_default();
+
+        Store::with_backend(
+            self.backend
+                .build(self.hasher, settings.capacity, settings.shards),
+        )
+        .with_max_bitmap_len(self.max_bitmap_bytes)
+        .with_max_memory(self.max_memory_per_db, self.eviction_policy)
+        .with_notifications(pubsub, self.keyspace_events)
+    }
+}
+
+/// Parses the value of a per database setting, given as `$db=$amount`
+fn parse_db_setting(arg: &str, value: &str) -> anyhow::Result<(usize, usize)> {
+    let (db, amount) = value
+        .split_once('=')
+        .with_context(|| format!("{arg} should be given as $db=$amount"))?;
+
+    Ok((
+        db.parse()
+            .with_context(|| format!("{arg} database should be an unsigned integer"))?,
+        amount
+            .parse()
+            .with_context(|| format!("{arg} amount should be an unsigned integer"))?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Config, DbSettings};
+    use crate::store::{
+        backend::BackendKind, eviction::EvictionPolicy, hasher::HashAlgorithm, notify::KeyEvent,
+    };
+
+    fn parse(args: &[&str]) -> anyhow::Result<Config> {
+        Config::from_args(args.iter().map(|a| a.to_string()))
+    }
+
+    #[test]
+    fn parses_args() {
+        assert_eq!(parse(&[]).unwrap(), Config::default());
+        assert_eq!(
+            parse(&["--max-bitmap-bytes", "128"])
+                .unwrap()
+                .max_bitmap_bytes,
+            128
+        );
+
+        assert!(parse(&["--max-bitmap-bytes"]).is_err());
+        assert!(parse(&["--max-bitmap-bytes", "-1"]).is_err());
+        assert!(parse(&["--unknown"]).is_err());
+
+        let events = parse(&["--keyspace-events", "set,evicted"])
+            .unwrap()
+            .keyspace_events;
+        assert!(events.contains(KeyEvent::Set) && events.contains(KeyEvent::Evicted));
+        assert!(!events.contains(KeyEvent::Del));
+        assert!(parse(&["--keyspace-events", "renamed"]).is_err());
+
+        assert_eq!(
+            parse(&["--tracking-max-keys", "0"])
+                .unwrap()
+                .tracking_max_keys,
+            0
+        );
+        assert!(parse(&["--tracking-max-keys", "many"]).is_err());
+
+        assert_eq!(
+            parse(&["--snapshot-file", "/tmp/blewis.boop"])
+                .unwrap()
+                .snapshot_file,
+            std::path::Path::new("/tmp/blewis.boop")
+        );
+
+        assert_eq!(
+            parse(&["--max-command-bytes", "4096"])
+                .unwrap()
+                .max_command_bytes,
+            4096
+        );
+        assert!(parse(&["--max-command-bytes", "4kb"]).is_err());
+    }
+
+    #[test]
+    fn parses_memory_settings() {
+        let config = parse(&[
+            "--max-memory-per-db",
+            "1048576",
+            "--eviction-policy",
+            "allkeys-lfu",
+        ])
+        .unwrap();
+        assert_eq!(config.max_memory_per_db, 1_048_576);
+        assert_eq!(config.eviction_policy, EvictionPolicy::AllKeysLfu);
+
+        assert!(parse(&["--eviction-policy", "allkeys-random"]).is_err());
+
+        let config = parse(&[
+            "--backend",
+            "lock-free",
+            "--hasher",
+            "fxhash",
+            "--db-shards",
+            "0=4",
+        ])
+        .unwrap();
+        assert_eq!(config.backend, BackendKind::LockFree);
+        assert_eq!(config.hasher, HashAlgorithm::FxHash);
+        assert_eq!(config.build_databases().get(0).unwrap().shard_amount(), 4);
+        assert!(parse(&["--backend", "btree"]).is_err());
+        assert!(parse(&["--hasher", "md5"]).is_err());
+        assert!(parse(&["--max-memory-per-db", "1mb"]).is_err());
+    }
+
+    #[test]
+    fn parses_database_settings() {
+        let config = parse(&[
+            "--databases",
+            "4",
+            "--db-capacity",
+            "1=1000",
+            "--db-shards",
+            "1=8",
+            "--db-shards",
+            "3=2",
+        ])
+        .unwrap();
+        assert_eq!(config.databases, 4);
+        assert_eq!(
+            config.db_settings.get(&1),
+            Some(&DbSettings {
+                capacity: 1000,
+                shards: Some(8)
+            })
+        );
+        assert_eq!(config.db_settings.get(&3).unwrap().capacity, 0);
+
+        let databases = config.build_databases();
+        assert_eq!(databases.len(), 4);
+        assert_eq!(databases.get(1).unwrap().shard_amount(), 8);
+        assert_eq!(databases.get(3).unwrap().shard_amount(), 2);
+
+        assert!(parse(&["--databases", "0"]).is_err());
+        assert!(parse(&["--db-shards", "1=3"]).is_err());
+        assert!(parse(&["--db-shards", "1=1"]).is_err());
+        assert!(parse(&["--db-capacity", "1"]).is_err());
+        assert!(parse(&["--databases", "2", "--db-capacity", "2=10"]).is_err());
+    }
+}
@@ -1,17 +1,28 @@
 #![allow(clippy::unusual_byte_groupings)]
 
-use std::fmt::Display;
+use std::{collections::VecDeque, fmt::Display};
 
 use bytes::{BufMut, Bytes};
 use ordered_float::OrderedFloat;
 
-#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+mod memory;
+mod set;
+mod sorted_set;
+mod stream;
+pub(crate) use set::BoopSet;
+pub(crate) use sorted_set::BoopSortedSet;
+pub(crate) use stream::{BoopStream, ConsumerGroup, PendingEntry, StreamId};
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
 pub(crate) enum DataType {
     Num(Int),
     Bool(BoopBool),
     String(BoopString),
     Error(BoopError),
     Array(BoopArray),
+    SortedSet(BoopSortedSet),
+    Set(BoopSet),
+    Stream(BoopStream),
 }
 
 impl Display for DataType {
@@ -29,6 +40,76 @@ impl Display for DataType {
             DataType::String(x) => Ok(write!(f, "{:?}", x)?),
             DataType::Array(x) => Ok(write!(f, "{:?}", x)?),
             DataType::Error(x) => Ok(write!(f, "{:?}", x)?),
+            DataType::SortedSet(x) => Ok(write!(f, "{:?}", x)?),
+            DataType::Set(x) => Ok(write!(f, "{:?}", x)?),
+            DataType::Stream(x) => Ok(write!(f, "{:?}", x)?),
+        }
+    }
+}
+
+impl DataType {
+    /// The meta byte that the value is prefixed with when it is encoded, ignoring the value bit of
+    /// bools. The lowest 3 bits identify the variant, see `DataType::variant_bits`, and for integers
+    /// the next 3 bits identify the width.
+    pub fn meta_byte(&self) -> u8 {
+        match self {
+            DataType::Num(Int::Tiny(_)) => 0b_00_000_000,
+            DataType::Num(Int::Small(_)) => 0b_00_001_000,
+            DataType::Num(Int::Medium(_)) => 0b_00_010_000,
+            DataType::Num(Int::Large(_)) => 0b_00_100_000,
+            DataType::Num(Int::FloatS(_)) => 0b_00_110_000,
+            DataType::Num(Int::FloatL(_)) => 0b_00_111_000,
+            DataType::Bool(_) => 0b_0_0000_100,
+            DataType::String(_) => 0b_00000_010,
+            DataType::Error(_) => 0b_0_0000_110,
+            DataType::Array(_) => 0b_0_0000_011,
+            DataType::SortedSet(_) => 0b_0_0000_101,
+            DataType::Set(_) => 0b_0_0000_111,
+            DataType::Stream(_) => 0b_0_0000_001,
+        }
+    }
+
+    /// The bits of the meta byte which identify the variant, regardless of integer width
+    #[inline(always)]
+    pub fn variant_bits(&self) -> u8 {
+        self.meta_byte() & 0b_0000_0111
+    }
+
+    /// Returns the value of an unsigned integer of any width. Floats and every other data type
+    /// return `None`.
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            DataType::Num(Int::Tiny(v)) => Some(*v as u64),
+            DataType::Num(Int::Small(v)) => Some(*v as u64),
+            DataType::Num(Int::Medium(v)) => Some(*v as u64),
+            DataType::Num(Int::Large(v)) => Some(*v),
+            _ => None,
+        }
+    }
+
+    /// Returns the value of any number as a float. Integers are read as unsigned, so negative
+    /// values have to be sent as floats. Every other data type returns `None`.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            DataType::Num(Int::FloatS(v)) => Some(v.0 as f64),
+            DataType::Num(Int::FloatL(v)) => Some(v.0),
+            other => other.as_u64().map(|v| v as f64),
+        }
+    }
+
+    /// Returns the value of any number as a signed integer. Integers are read as unsigned, as they
+    /// are by `DataType::as_f64`, so negative values, such as indices counted from the end of a
+    /// list, have to be sent as floats that hold a whole number, like -1.0. Integers too large for
+    /// an i64, floats with a fraction and every other data type return `None`.
+    pub fn as_i64(&self) -> Option<i64> {
+        let whole = |v: f64| {
+            let in_range = v >= i64::MIN as f64 && v < i64::MAX as f64;
+            (v.fract() == 0.0 && in_range).then_some(v as i64)
+        };
+        match self {
+            DataType::Num(Int::FloatS(v)) => whole(v.0 as f64),
+            DataType::Num(Int::FloatL(v)) => whole(v.0),
+            other => other.as_u64().and_then(|v| i64::try_from(v).ok()),
         }
     }
 }
@@ -52,9 +133,83 @@ mod tests {
         let dt = DataType::Bool(BoopBool(false));
         println!("{dt}");
     }
+
+    #[test]
+    fn test_meta_byte_matches_decoder() {
+        use crate::data_type::{
+            BoopArray, BoopError, BoopSet, BoopSortedSet, BoopStream, BoopString,
+        };
+        use crate::decoder::handle_decode;
+        use bytes::{BufMut, Bytes};
+
+        let values = vec![
+            Int::new_u8(1),
+            Int::new_u16(1),
+            Int::new_u32(1),
+            Int::new_u64(1),
+            Int::new_f32(1.0),
+            Int::new_f64(1.0),
+            BoopString::new_wrapped(Bytes::from_static(b"")),
+            BoopError::new_wrapped(false, 0, Bytes::from_static(b"")),
+            BoopArray::new_wrapped(vec![]),
+            BoopSortedSet::new_wrapped([]),
+            BoopSet::new_wrapped([]),
+            DataType::Stream(BoopStream::default()),
+        ];
+
+        for value in values {
+            let mut buf = bytes::BytesMut::new();
+            buf.put_u8(value.meta_byte());
+            buf.put_bytes(0, 32);
+            assert_eq!(
+                handle_decode(&mut buf).unwrap().meta_byte(),
+                value.meta_byte()
+            );
+        }
+
+        assert_eq!(DataType::Bool(BoopBool(true)).variant_bits(), 0b100);
+        assert_eq!(Int::new_f64(1.0).variant_bits(), 0);
+    }
+
+    #[test]
+    fn test_as_i64() {
+        // Integers are unsigned whatever their width, as they are when read as floats
+        assert_eq!(Int::new_u8(200).as_i64(), Some(200));
+        assert_eq!(Int::new_u8(200).as_f64(), Some(200.0));
+        assert_eq!(Int::new_u16(0xFFFE).as_i64(), Some(0xFFFE));
+        assert_eq!(Int::new_u32(0xFFFF_FFFD).as_i64(), Some(0xFFFF_FFFD));
+        assert_eq!(Int::new_u64(i64::MAX as u64).as_i64(), Some(i64::MAX));
+        assert_eq!(Int::new_u64(u64::MAX).as_i64(), None);
+
+        // Negative values are sent as floats holding a whole number
+        assert_eq!(Int::new_f64(-1.0).as_i64(), Some(-1));
+        assert_eq!(Int::new_f32(-2.0).as_i64(), Some(-2));
+        assert_eq!(Int::new_f64(-1.5).as_i64(), None);
+        assert_eq!(Int::new_f64(f64::NAN).as_i64(), None);
+        assert_eq!(Int::new_f64(f64::INFINITY).as_i64(), None);
+        assert_eq!(Int::new_f64(1e19).as_i64(), None);
+    }
+
+    #[test]
+    fn test_as_f64() {
+        assert_eq!(Int::new_u8(10).as_f64(), Some(10.0));
+        assert_eq!(Int::new_f32(-1.5).as_f64(), Some(-1.5));
+        assert_eq!(Int::new_f64(f64::INFINITY).as_f64(), Some(f64::INFINITY));
+        assert_eq!(DataType::Bool(BoopBool(true)).as_f64(), None);
+    }
+
+    #[test]
+    fn test_as_u64() {
+        assert_eq!(Int::new_u8(10).as_u64(), Some(10));
+        assert_eq!(Int::new_u16(10).as_u64(), Some(10));
+        assert_eq!(Int::new_u32(10).as_u64(), Some(10));
+        assert_eq!(Int::new_u64(u64::MAX).as_u64(), Some(u64::MAX));
+        assert_eq!(Int::new_f32(10.0).as_u64(), None);
+        assert_eq!(DataType::Bool(BoopBool(true)).as_u64(), None);
+    }
 }
 
-#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
 pub(crate) enum Int {
     Tiny(u8),
     Medium(u32),
@@ -85,7 +240,7 @@ impl Int {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
 pub(crate) struct BoopBool(pub bool);
 
 impl BoopBool {
@@ -94,16 +249,19 @@ impl BoopBool {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
 pub(crate) struct BoopString(pub Bytes);
 
 impl BoopString {
+    /// The most bytes a string can hold, as its length is encoded as a u16
+    pub const MAX_LEN: usize = u16::MAX as usize;
+
     pub fn new_wrapped(v: Bytes) -> DataType {
         DataType::String(BoopString(v))
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Hash)]
 pub(crate) struct BoopError {
     pub is_server_err: bool,
     pub err_code: u8,
@@ -111,6 +269,87 @@ pub(crate) struct BoopError {
 }
 
 impl BoopError {
+    /// Error code used when a key has no entry in the store
+    pub const NO_EXIST: u8 = 0x10;
+    /// Error code used when a command is ran against a key holding the wrong data type
+    pub const WRONG_TYPE: u8 = 0x11;
+    /// Error code used when an index is outside of the bounds of a value
+    pub const OUT_OF_RANGE: u8 = 0x12;
+    /// Error code used when a command would grow a value past what BOOP can encode
+    pub const TOO_LARGE: u8 = 0x13;
+    /// Error code used when a command would produce a float that is not a number
+    pub const NOT_A_NUMBER: u8 = 0x14;
+    /// Error code used when a command can't be ran in the connection's current state, such as
+    /// EXEC without MULTI
+    pub const INVALID_STATE: u8 = 0x15;
+    /// Error code used when a write is refused as the store has used up its memory limit
+    pub const OUT_OF_MEMORY: u8 = 0x16;
+    /// Error code used when a snapshot couldn't be written
+    pub const SAVE_FAILED: u8 = 0x17;
+
+    /// Creates the wrapped error that is returned in place of a value when a key does not exist
+    pub fn no_exist() -> DataType {
+        BoopError::new_wrapped(true, Self::NO_EXIST, Bytes::from_static(b"no_exist"))
+    }
+
+    /// Creates the wrapped error that is returned when a key holds the wrong data type
+    pub fn wrong_type() -> DataType {
+        BoopError::new_wrapped(false, Self::WRONG_TYPE, Bytes::from_static(b"wrong_type"))
+    }
+
+    /// Creates the wrapped error that is returned when an index is out of range
+    pub fn out_of_range() -> DataType {
+        BoopError::new_wrapped(
+            false,
+            Self::OUT_OF_RANGE,
+            Bytes::from_static(b"out_of_range"),
+        )
+    }
+
+    /// Creates the wrapped error that is returned when a value would grow too large to encode
+    pub fn too_large() -> DataType {
+        BoopError::new_wrapped(false, Self::TOO_LARGE, Bytes::from_static(b"too_large"))
+    }
+
+    /// Creates the wrapped error that is returned when a float would be NaN
+    pub fn not_a_number() -> DataType {
+        BoopError::new_wrapped(
+            false,
+            Self::NOT_A_NUMBER,
+            Bytes::from_static(b"not_a_number"),
+        )
+    }
+
+    /// Creates the wrapped error that is returned when a command can't be ran in the
+    /// connection's current state
+    pub fn invalid_state() -> DataType {
+        BoopError::new_wrapped(
+            false,
+            Self::INVALID_STATE,
+            Bytes::from_static(b"invalid_state"),
+        )
+    }
+
+    /// Creates the wrapped error that is returned when a write is refused as the store has used
+    /// up its memory limit
+    pub fn out_of_memory() -> DataType {
+        BoopError::new_wrapped(
+            true,
+            Self::OUT_OF_MEMORY,
+            Bytes::from_static(b"out_of_memory"),
+        )
+    }
+
+    /// Creates the wrapped error that is returned when a snapshot couldn't be written, with why it
+    /// couldn't be after the usual message
+    pub fn save_failed(cause: &anyhow::Error) -> DataType {
+        BoopError::new_wrapped(
+            true,
+            Self::SAVE_FAILED,
+            Bytes::from(format!("save_failed: {cause:#}")),
+        )
+    }
+
     pub fn new_unwrapped(is_server_err: bool, err_code: u8, err_msg: Bytes) -> Self {
         Self {
             is_server_err,
@@ -146,11 +385,16 @@ impl BoopError {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Hash, Clone)]
-pub(crate) struct BoopArray(pub Vec<DataType>);
+/// An array of values. Arrays double as lists, so they are kept in a ring buffer that can be
+/// pushed to and popped from at either end in constant time.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+pub(crate) struct BoopArray(pub VecDeque<DataType>);
 
 impl BoopArray {
+    /// The most elements an array can hold, as its length is encoded as a u16
+    pub const MAX_LEN: usize = u16::MAX as usize;
+
     pub fn new_wrapped(data: Vec<DataType>) -> DataType {
-        DataType::Array(Self(data))
+        DataType::Array(Self(data.into()))
     }
 }
@@ -0,0 +1,175 @@
+//! Estimates of the memory held by values. Every value is a tree of `DataType`s, and its estimate
+//! is whatever the tree holds on the heap: the bytes of strings, the spare capacity of vectors and
+//! hash tables, the nodes of trees, and the members that collections hold, recursively.
+//!
+//! Measuring every member of a large collection would take as long as the collection is large, so
+//! only the first few members of each collection are measured and scaled up to its length.
+
+use super::{
+    BoopArray, BoopSet, BoopStream, BoopString, ConsumerGroup, DataType, PendingEntry, StreamId,
+};
+use std::mem::size_of;
+
+/// How many members of each collection are measured by `DataType::heap_size`
+pub const DEFAULT_SAMPLES: usize = 5;
+
+/// Estimates the heap held by `len` members from the first `samples` of them, or all of them if
+/// `samples` is 0
+pub(super) fn sampled<T>(
+    members: impl Iterator<Item = T>,
+    len: usize,
+    samples: usize,
+    size: impl Fn(T) -> usize,
+) -> usize {
+    let samples = if samples == 0 { len.max(1) } else { samples };
+    let measured = members.take(samples).map(size).sum::<usize>();
+    measured * len / len.clamp(1, samples)
+}
+
+/// The bytes of a hash table holding `capacity` items of type `T`. Tables have a power of two
+/// amount of buckets, at most 7/8 of which are used, each with a control byte next to the item.
+pub(super) fn hash_table<T>(capacity: usize) -> usize {
+    if capacity == 0 {
+        return 0;
+    }
+
+    let buckets = if capacity < 8 {
+        if capacity < 4 {
+            4
+        } else {
+            8
+        }
+    } else {
+        (capacity * 8 / 7).next_power_of_two()
+    };
+    buckets * (size_of::<T>() + 1)
+}
+
+/// The bytes of the nodes of a B-tree holding `len` items of type `(K, V)`. Nodes are around two
+/// thirds full on average, and hold a length and a parent pointer each.
+fn b_tree<K, V>(len: usize) -> usize {
+    len * (size_of::<(K, V)>() + 2) * 3 / 2
+}
+
+impl DataType {
+    /// A rough estimate of the bytes that the value holds on the heap, not counting the
+    /// `DataType` itself. It measures `DEFAULT_SAMPLES` members of every collection, so it takes
+    /// the same time however large the value is.
+    #[inline]
+    pub fn heap_size(&self) -> usize {
+        self.heap_size_sampled(DEFAULT_SAMPLES)
+    }
+
+    /// Like `heap_size`, but measuring the first `samples` members of every collection, or every
+    /// member when `samples` is 0
+    pub fn heap_size_sampled(&self, samples: usize) -> usize {
+        let member = |member: &DataType| member.heap_size_sampled(samples);
+
+        match self {
+            DataType::Num(_) | DataType::Bool(_) => 0,
+            DataType::String(BoopString(bytes)) => bytes.len(),
+            DataType::Error(err) => err.err_msg.len(),
+            DataType::Array(BoopArray(items)) => {
+                items.capacity() * size_of::<DataType>()
+                    + sampled(items.iter(), items.len(), samples, member)
+            }
+            DataType::Set(BoopSet(set)) => {
+                hash_table::<DataType>(set.capacity())
+                    + sampled(set.iter(), set.len(), samples, member)
+            }
+            DataType::SortedSet(set) => set.heap_size_sampled(samples),
+            DataType::Stream(stream) => stream.heap_size_sampled(samples),
+        }
+    }
+}
+
+impl BoopStream {
+    fn heap_size_sampled(&self, samples: usize) -> usize {
+        let member = |member: &DataType| member.heap_size_sampled(samples);
+
+        let entries = b_tree::<StreamId, Vec<(DataType, DataType)>>(self.entries.len())
+            + sampled(
+                self.entries.values(),
+                self.entries.len(),
+                samples,
+                |fields| {
+                    fields.capacity() * size_of::<(DataType, DataType)>()
+                        + fields
+                            .iter()
+                            .map(|(f, v)| member(f) + member(v))
+                            .sum::<usize>()
+                },
+            );
+
+        let groups = b_tree::<DataType, ConsumerGroup>(self.groups.len())
+            + sampled(
+                self.groups.iter(),
+                self.groups.len(),
+                samples,
+                |(name, group)| {
+                    member(name)
+                        + b_tree::<StreamId, PendingEntry>(group.pending.len())
+                        + sampled(
+                            group.pending.values(),
+                            group.pending.len(),
+                            samples,
+                            |pending| member(&pending.consumer),
+                        )
+                },
+            );
+
+        entries + groups
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data_type::{BoopArray, BoopSet, BoopSortedSet, BoopString, DataType, Int};
+    use bytes::Bytes;
+    use std::mem::size_of;
+
+    fn string(len: usize) -> DataType {
+        BoopString::new_wrapped(Bytes::from(vec![0; len]))
+    }
+
+    #[test]
+    fn nested_values_count_what_they_hold() {
+        assert_eq!(Int::new_u64(1).heap_size(), 0);
+        assert_eq!(string(100).heap_size(), 100);
+
+        // Arrays count their capacity, whether it is used or not
+        let array = || {
+            let mut items = Vec::with_capacity(4);
+            items.push(string(10));
+            BoopArray::new_wrapped(items)
+        };
+        assert_eq!(array().heap_size(), 4 * size_of::<DataType>() + 10);
+
+        let nested = BoopArray::new_wrapped(vec![array(), array()]);
+        assert_eq!(
+            nested.heap_size(),
+            2 * size_of::<DataType>() + 2 * array().heap_size()
+        );
+
+        let set = BoopSet::new_wrapped([string(10), string(20)]);
+        assert!(set.heap_size() > 30 + 2 * size_of::<DataType>());
+        let sorted = BoopSortedSet::new_wrapped([(string(10), 1.0), (string(20), 2.0)]);
+        assert!(sorted.heap_size() > 2 * 30);
+    }
+
+    #[test]
+    fn large_collections_are_sampled() {
+        let array = BoopArray::new_wrapped((0..1_000).map(|_| string(10)).collect());
+        assert_eq!(array.heap_size(), array.heap_size_sampled(0));
+
+        // The first members stand in for the rest, however different they are
+        let mut items = vec![string(10); 5];
+        items.extend((0..995).map(|_| string(1_000)));
+        let array = BoopArray::new_wrapped(items);
+        assert!(array.heap_size() < array.heap_size_sampled(0));
+        assert_eq!(
+            array.heap_size_sampled(0),
+            1_000 * size_of::<DataType>() + 5 * 10 + 995 * 1_000
+        );
+    }
+}
@@ -0,0 +1,87 @@
+use super::DataType;
+use std::{
+    cmp::Ordering,
+    collections::{hash_map::DefaultHasher, HashSet},
+    hash::{Hash, Hasher},
+};
+
+/// BoopSet is an unordered set of unique members, which can be any `DataType`. Members are compared
+/// with the same `Hash` and `Eq` implementations that keys in the store use.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub(crate) struct BoopSet(pub HashSet<DataType>);
+
+impl BoopSet {
+    /// The most members a set can hold, as its length is encoded as a u16
+    pub const MAX_LEN: usize = u16::MAX as usize;
+
+    pub fn new_wrapped(members: impl IntoIterator<Item = DataType>) -> DataType {
+        DataType::Set(BoopSet(members.into_iter().collect()))
+    }
+
+    /// Returns the members in a stable order, which doesn't depend on the order they were added in
+    fn sorted(&self) -> Vec<&DataType> {
+        let mut members: Vec<_> = self.0.iter().collect();
+        members.sort_unstable();
+        members
+    }
+}
+
+impl PartialOrd for BoopSet {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for BoopSet {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.sorted().cmp(&other.sorted())
+    }
+}
+
+impl Hash for BoopSet {
+    /// Sets are hashed by combining the hashes of their members with a commutative operation, so
+    /// that equal sets hash the same no matter what order their members are iterated in
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let combined = self
+            .0
+            .iter()
+            .map(|member| {
+                let mut hasher = DefaultHasher::new();
+                member.hash(&mut hasher);
+                hasher.finish()
+            })
+            .fold(0u64, u64::wrapping_add);
+
+        state.write_usize(self.0.len());
+        state.write_u64(combined);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BoopSet;
+    use crate::data_type::{DataType, Int};
+    use std::{
+        collections::hash_map::DefaultHasher,
+        hash::{Hash, Hasher},
+    };
+
+    fn hash(value: &DataType) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn equal_sets_hash_and_order_the_same() {
+        let a = BoopSet::new_wrapped((0..100).map(Int::new_u8));
+        let b = BoopSet::new_wrapped((0..100).rev().map(Int::new_u8));
+        let c = BoopSet::new_wrapped((1..101).map(Int::new_u8));
+
+        assert_eq!(a, b);
+        assert_eq!(hash(&a), hash(&b));
+        assert_eq!(a.cmp(&b), std::cmp::Ordering::Equal);
+        assert_ne!(a, c);
+        assert!(a < c);
+    }
+}
@@ -0,0 +1,433 @@
+use super::{
+    memory::{hash_table, sampled},
+    DataType,
+};
+use ordered_float::OrderedFloat;
+use std::{
+    cmp::Ordering,
+    collections::{hash_map::RandomState, HashMap},
+    fmt::Debug,
+    hash::{BuildHasher, Hash, Hasher},
+    mem::size_of,
+};
+
+type Score = OrderedFloat<f64>;
+type Link = Option<Box<Node>>;
+
+/// Node of the treap that orders a sorted set's members. Nodes are ordered by score, then by
+/// member, and every node keeps the size of its subtree so that ranks can be found in O(log n).
+#[derive(Clone)]
+struct Node {
+    score: Score,
+    member: DataType,
+    priority: u64,
+    size: usize,
+    left: Link,
+    right: Link,
+}
+
+impl Node {
+    fn new(score: Score, member: DataType, priority: u64) -> Box<Self> {
+        Box::new(Node {
+            score,
+            member,
+            priority,
+            size: 1,
+            left: None,
+            right: None,
+        })
+    }
+
+    #[inline(always)]
+    fn cmp_to(&self, score: Score, member: &DataType) -> Ordering {
+        self.score.cmp(&score).then_with(|| self.member.cmp(member))
+    }
+
+    #[inline(always)]
+    fn update_size(&mut self) {
+        self.size = 1 + size(&self.left) + size(&self.right);
+    }
+}
+
+#[inline(always)]
+fn size(link: &Link) -> usize {
+    link.as_ref().map_or(0, |node| node.size)
+}
+
+/// Splits the tree into the nodes that `before` holds for, and the rest. `before` must hold for a
+/// prefix of the tree's order.
+fn split(link: Link, before: &impl Fn(&Node) -> bool) -> (Link, Link) {
+    let Some(mut node) = link else {
+        return (None, None);
+    };
+
+    if before(&node) {
+        let (left, right) = split(node.right.take(), before);
+        node.right = left;
+        node.update_size();
+        (Some(node), right)
+    } else {
+        let (left, right) = split(node.left.take(), before);
+        node.left = right;
+        node.update_size();
+        (left, Some(node))
+    }
+}
+
+/// Joins two trees, where every node of `left` is ordered before every node of `right`
+fn merge(left: Link, right: Link) -> Link {
+    match (left, right) {
+        (None, right) => right,
+        (left, None) => left,
+        (Some(mut l), Some(mut r)) => {
+            if l.priority > r.priority {
+                l.right = merge(l.right.take(), Some(r));
+                l.update_size();
+                Some(l)
+            } else {
+                r.left = merge(Some(l), r.left.take());
+                r.update_size();
+                Some(r)
+            }
+        }
+    }
+}
+
+/// BoopSortedSet is a set of unique members, each with a score, which is kept ordered by score and
+/// then by member. Members can be any `DataType`.
+///
+/// Members are indexed twice: a hash map from member to score gives O(1) score lookups, and a treap
+/// (a randomised binary search tree) ordered by score and member, where every node knows the size
+/// of its subtree, gives O(log n) inserts, removals and rank lookups, and O(log n + k) range
+/// queries by rank or by score. Each member is therefore held twice, once in each index.
+#[derive(Clone)]
+pub(crate) struct BoopSortedSet {
+    scores: HashMap<DataType, Score>,
+    root: Link,
+    /// State of the xorshift generator that gives treap nodes their priority
+    seed: u64,
+}
+
+impl BoopSortedSet {
+    /// The most members a sorted set can hold, as its length is encoded as a u16
+    pub const MAX_LEN: usize = u16::MAX as usize;
+
+    pub fn new() -> Self {
+        BoopSortedSet {
+            scores: HashMap::new(),
+            root: None,
+            seed: RandomState::new().build_hasher().finish() | 1,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn new_wrapped(entries: impl IntoIterator<Item = (DataType, f64)>) -> DataType {
+        let mut set = Self::new();
+        for (member, score) in entries {
+            set.insert(member, score);
+        }
+        DataType::SortedSet(set)
+    }
+
+    fn next_priority(&mut self) -> u64 {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 7;
+        self.seed ^= self.seed << 17;
+        self.seed
+    }
+
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    /// Returns the score of `member`
+    pub fn score(&self, member: &DataType) -> Option<f64> {
+        self.scores.get(member).map(|score| score.0)
+    }
+
+    /// Adds `member` with `score`, or updates its score if it is already a member. Returns the
+    /// member's previous score. Scores must not be NaN.
+    pub fn insert(&mut self, member: DataType, score: f64) -> Option<f64> {
+        debug_assert!(!score.is_nan(), "sorted set scores can't be NaN");
+        let score = OrderedFloat(score);
+
+        let previous = self.scores.insert(member.to_owned(), score);
+        if let Some(previous) = previous {
+            if previous == score {
+                return Some(previous.0);
+            }
+            self.remove_node(previous, &member);
+        }
+
+        let (left, right) = split(self.root.take(), &|node| {
+            node.cmp_to(score, &member).is_lt()
+        });
+        let node = Node::new(score, member, self.next_priority());
+        self.root = merge(merge(left, Some(node)), right);
+
+        previous.map(|score| score.0)
+    }
+
+    /// Removes `member`, returning its score
+    pub fn remove(&mut self, member: &DataType) -> Option<f64> {
+        let score = self.scores.remove(member)?;
+        self.remove_node(score, member);
+        Some(score.0)
+    }
+
+    fn remove_node(&mut self, score: Score, member: &DataType) {
+        let (left, rest) = split(self.root.take(), &|node| node.cmp_to(score, member).is_lt());
+        let (_, right) = split(rest, &|node| node.cmp_to(score, member).is_le());
+        self.root = merge(left, right);
+    }
+
+    /// Returns how many members are ordered before `member`
+    pub fn rank(&self, member: &DataType) -> Option<usize> {
+        let score = *self.scores.get(member)?;
+        Some(self.count_before(|node| node.cmp_to(score, member).is_lt()))
+    }
+
+    /// Counts the members that `before` holds for. `before` must hold for a prefix of the order.
+    fn count_before(&self, before: impl Fn(&Node) -> bool) -> usize {
+        let mut count = 0;
+        let mut link = self.root.as_deref();
+        while let Some(node) = link {
+            if before(node) {
+                count += size(&node.left) + 1;
+                link = node.right.as_deref();
+            } else {
+                link = node.left.as_deref();
+            }
+        }
+        count
+    }
+
+    /// Returns the rank of the first member with a score of at least `score`, or above `score` if
+    /// `exclusive` is set
+    pub fn rank_of_score(&self, score: f64, exclusive: bool) -> usize {
+        let score = OrderedFloat(score);
+        self.count_before(|node| node.score < score || (exclusive && node.score == score))
+    }
+
+    /// Iterates over the members and their scores in order, starting from the member at `rank`.
+    /// When `rev` is set, iterates backwards from that member instead.
+    pub fn iter_from(&self, rank: usize, rev: bool) -> Iter<'_> {
+        let mut iter = Iter {
+            stack: Vec::new(),
+            rev,
+        };
+        if rank >= self.len() {
+            return iter;
+        }
+
+        let mut rank = rank;
+        let mut link = self.root.as_deref();
+        while let Some(node) = link {
+            let left = size(&node.left);
+            match rank.cmp(&left) {
+                Ordering::Less => {
+                    if !rev {
+                        iter.stack.push(node);
+                    }
+                    link = node.left.as_deref();
+                }
+                Ordering::Equal => {
+                    iter.stack.push(node);
+                    break;
+                }
+                Ordering::Greater => {
+                    if rev {
+                        iter.stack.push(node);
+                    }
+                    rank -= left + 1;
+                    link = node.right.as_deref();
+                }
+            }
+        }
+
+        iter
+    }
+
+    /// Iterates over the members and their scores in order
+    pub fn iter(&self) -> Iter<'_> {
+        self.iter_from(0, false)
+    }
+
+    /// An estimate of the bytes the set holds on the heap, see `DataType::heap_size_sampled`.
+    /// Every member is held by both the hash map and a boxed treap node.
+    pub(super) fn heap_size_sampled(&self, samples: usize) -> usize {
+        hash_table::<(DataType, Score)>(self.scores.capacity())
+            + self.len() * size_of::<Node>()
+            + 2 * sampled(self.scores.keys(), self.len(), samples, |member| {
+                member.heap_size_sampled(samples)
+            })
+    }
+}
+
+impl Default for BoopSortedSet {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// In order iterator over the members of a sorted set, and their scores
+pub(crate) struct Iter<'a> {
+    stack: Vec<&'a Node>,
+    rev: bool,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a DataType, f64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.stack.pop()?;
+
+        let (mut link, rev) = if self.rev {
+            (node.left.as_deref(), true)
+        } else {
+            (node.right.as_deref(), false)
+        };
+        while let Some(next) = link {
+            self.stack.push(next);
+            link = if rev {
+                next.right.as_deref()
+            } else {
+                next.left.as_deref()
+            };
+        }
+
+        Some((&node.member, node.score.0))
+    }
+}
+
+impl PartialEq for BoopSortedSet {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().eq(other.iter())
+    }
+}
+
+impl Eq for BoopSortedSet {}
+
+impl PartialOrd for BoopSortedSet {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for BoopSortedSet {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let entries = |set: &'_ Self| {
+            set.iter()
+                .map(|(member, score)| (OrderedFloat(score), member.to_owned()))
+                .collect::<Vec<_>>()
+        };
+        entries(self).cmp(&entries(other))
+    }
+}
+
+impl Hash for BoopSortedSet {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_usize(self.len());
+        for (member, score) in self.iter() {
+            OrderedFloat(score).hash(state);
+            member.hash(state);
+        }
+    }
+}
+
+impl Debug for BoopSortedSet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BoopSortedSet;
+    use crate::data_type::{DataType, Int};
+
+    fn members(set: &BoopSortedSet, rank: usize, rev: bool) -> Vec<u16> {
+        set.iter_from(rank, rev)
+            .map(|(member, _)| match member {
+                DataType::Num(crate::data_type::Int::Small(v)) => *v,
+                _ => unreachable!(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn keeps_members_ordered_by_score_then_member() {
+        let mut set = BoopSortedSet::new();
+        set.insert(Int::new_u16(3), 1.0);
+        set.insert(Int::new_u16(1), 2.0);
+        set.insert(Int::new_u16(2), 1.0);
+        set.insert(Int::new_u16(4), f64::NEG_INFINITY);
+
+        assert_eq!(members(&set, 0, false), vec![4, 2, 3, 1]);
+        assert_eq!(members(&set, 2, false), vec![3, 1]);
+        assert_eq!(members(&set, 2, true), vec![3, 2, 4]);
+        assert_eq!(members(&set, 4, false), Vec::<u16>::new());
+
+        assert_eq!(set.rank(&Int::new_u16(4)), Some(0));
+        assert_eq!(set.rank(&Int::new_u16(1)), Some(3));
+        assert_eq!(set.rank(&Int::new_u16(5)), None);
+
+        assert_eq!(set.rank_of_score(1.0, false), 1);
+        assert_eq!(set.rank_of_score(1.0, true), 3);
+        assert_eq!(set.rank_of_score(10.0, false), 4);
+    }
+
+    #[test]
+    fn updates_and_removes_members() {
+        let mut set = BoopSortedSet::new();
+        assert_eq!(set.insert(Int::new_u16(1), 1.0), None);
+        assert_eq!(set.insert(Int::new_u16(2), 2.0), None);
+        assert_eq!(set.insert(Int::new_u16(1), 3.0), Some(1.0));
+
+        assert_eq!(set.len(), 2);
+        assert_eq!(set.score(&Int::new_u16(1)), Some(3.0));
+        assert_eq!(members(&set, 0, false), vec![2, 1]);
+
+        assert_eq!(set.remove(&Int::new_u16(2)), Some(2.0));
+        assert_eq!(set.remove(&Int::new_u16(2)), None);
+        assert_eq!(members(&set, 0, false), vec![1]);
+    }
+
+    #[test]
+    fn ranks_stay_consistent_with_many_members() {
+        let mut set = BoopSortedSet::new();
+        // Insert out of order, then remove every third member
+        for i in 0..1_000u16 {
+            let member = (i * 7) % 1_000;
+            set.insert(Int::new_u16(member), member as f64);
+        }
+        for i in (0..1_000u16).step_by(3) {
+            set.remove(&Int::new_u16(i));
+        }
+
+        let expected: Vec<u16> = (0..1_000).filter(|i| i % 3 != 0).collect();
+        assert_eq!(members(&set, 0, false), expected);
+        for (rank, member) in expected.iter().enumerate() {
+            assert_eq!(set.rank(&Int::new_u16(*member)), Some(rank));
+            assert_eq!(
+                members(&set, rank, false).first(),
+                Some(member),
+                "select rank {rank}"
+            );
+        }
+    }
+
+    #[test]
+    fn equality_ignores_insertion_order() {
+        let a = BoopSortedSet::new_wrapped([(Int::new_u8(1), 1.0), (Int::new_u8(2), 2.0)]);
+        let b = BoopSortedSet::new_wrapped([(Int::new_u8(2), 2.0), (Int::new_u8(1), 1.0)]);
+        let c = BoopSortedSet::new_wrapped([(Int::new_u8(2), 2.0), (Int::new_u8(1), 3.0)]);
+
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+    }
+}
@@ -0,0 +1,75 @@
+use super::DataType;
+use std::collections::BTreeMap;
+
+/// The ID of a stream entry: the millisecond it was added in, and a sequence number that orders
+/// entries added within the same millisecond
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Default)]
+pub(crate) struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl StreamId {
+    pub const MIN: StreamId = StreamId { ms: 0, seq: 0 };
+
+    pub fn new(ms: u64, seq: u64) -> Self {
+        StreamId { ms, seq }
+    }
+
+    /// The ID straight after this one, or `None` if this is the largest ID
+    pub fn next(self) -> Option<StreamId> {
+        match self.seq.checked_add(1) {
+            Some(seq) => Some(StreamId::new(self.ms, seq)),
+            None => Some(StreamId::new(self.ms.checked_add(1)?, 0)),
+        }
+    }
+}
+
+/// An entry of a consumer group's pending entries list: an entry that was delivered to a consumer
+/// and hasn't been acknowledged yet
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+pub(crate) struct PendingEntry {
+    pub consumer: DataType,
+    /// When the entry was last delivered, in milliseconds since the UNIX epoch
+    pub delivered_at: u64,
+    pub deliveries: u64,
+}
+
+/// A consumer group shares the entries of a stream between its consumers, tracking which entries
+/// have been delivered and which of those have been acknowledged
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Default)]
+pub(crate) struct ConsumerGroup {
+    /// The ID of the last entry delivered to any consumer of the group
+    pub last_delivered: StreamId,
+    pub pending: BTreeMap<StreamId, PendingEntry>,
+}
+
+/// BoopStream is an append-only log of entries, each of which holds field/value pairs of any
+/// `DataType`, ordered by strictly increasing IDs
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Default)]
+pub(crate) struct BoopStream {
+    pub entries: BTreeMap<StreamId, Vec<(DataType, DataType)>>,
+    /// The largest ID ever added, which is kept when entries are trimmed so that IDs never go
+    /// backwards
+    pub last_id: StreamId,
+    pub groups: BTreeMap<DataType, ConsumerGroup>,
+}
+
+impl BoopStream {
+    /// The most entries a stream can hold, as its length is encoded as a u16. The same limit
+    /// applies to the fields of an entry, the groups of a stream and the pending entries of a group.
+    pub const MAX_LEN: usize = u16::MAX as usize;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StreamId;
+
+    #[test]
+    fn ids_order_by_ms_then_seq() {
+        assert!(StreamId::new(1, 5) < StreamId::new(2, 0));
+        assert!(StreamId::new(2, 0) < StreamId::new(2, 1));
+        assert_eq!(StreamId::new(1, u64::MAX).next(), Some(StreamId::new(2, 0)));
+        assert_eq!(StreamId::new(u64::MAX, u64::MAX).next(), None);
+    }
+}
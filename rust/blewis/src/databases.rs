@@ -0,0 +1,134 @@
+use crate::{pubsub::PubSub, snapshot::Snapshots, store::Store};
+use std::sync::{Arc, PoisonError, RwLock};
+
+/// Databases are the isolated keyspaces that a server holds, each of which is its own `Store` with
+/// its own settings. They are identified by their index, starting from 0. Every connection starts
+/// out using database 0 and can switch to another with SELECT.
+///
+/// The amount of databases is fixed once the server has started, but the store behind an index
+/// can change, as SWAPDB swaps the stores of two indices.
+pub(crate) struct Databases {
+    stores: Arc<RwLock<Vec<Store>>>,
+    pubsub: PubSub,
+    snapshots: Snapshots,
+}
+
+impl Databases {
+    /// Creates the databases from their stores, in index order. There must be at least one. The
+    /// stores should all publish through the same hub, see `Store::with_notifications`, which is
+    /// taken from the first.
+    pub fn new(stores: Vec<Store>) -> Self {
+        assert!(!stores.is_empty(), "there must be at least one database");
+        for (db, store) in stores.iter().enumerate() {
+            store.set_db(db);
+        }
+
+        Databases {
+            pubsub: stores[0].pubsub(),
+            stores: Arc::new(RwLock::new(stores)),
+            snapshots: Snapshots::default(),
+        }
+    }
+
+    /// Writes snapshots of the databases to, and loads them from, `snapshots` rather than the
+    /// default file
+    pub fn with_snapshots(mut self, snapshots: Snapshots) -> Self {
+        self.snapshots = snapshots;
+        self
+    }
+
+    #[inline]
+    pub fn clone(&self) -> Self {
+        Databases {
+            stores: self.stores.clone(),
+            pubsub: self.pubsub.clone(),
+            snapshots: self.snapshots.clone(),
+        }
+    }
+
+    /// The hub that every database publishes through
+    pub fn pubsub(&self) -> &PubSub {
+        &self.pubsub
+    }
+
+    /// Where snapshots of the databases are written
+    pub fn snapshots(&self) -> &Snapshots {
+        &self.snapshots
+    }
+
+    /// The amount of databases
+    pub fn len(&self) -> usize {
+        self.stores
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .len()
+    }
+
+    /// Returns the store of the database at `index`, or `None` if there is no such database
+    pub fn get(&self, index: usize) -> Option<Store> {
+        self.stores
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(index)
+            .map(Store::clone)
+    }
+
+    /// Returns the store of every database, in index order
+    pub fn all(&self) -> Vec<Store> {
+        self.stores
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .map(Store::clone)
+            .collect()
+    }
+
+    /// Swaps the stores of two databases, so that every connection using one of them sees the
+    /// other's keys from then on. Returns false, swapping nothing, if either database doesn't
+    /// exist.
+    pub fn swap(&self, a: usize, b: usize) -> bool {
+        let mut stores = self.stores.write().unwrap_or_else(PoisonError::into_inner);
+        if a >= stores.len() || b >= stores.len() {
+            return false;
+        }
+
+        stores.swap(a, b);
+        stores[a].set_db(a);
+        stores[b].set_db(b);
+        // Keys that were read from either database now hold the other one's values
+        if a != b {
+            stores[a].invalidate_all();
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Databases;
+    use crate::{
+        data_type::Int,
+        store::{SetTtl, Store},
+    };
+
+    #[test]
+    fn databases_are_isolated_and_swappable() {
+        let databases = Databases::new(vec![Store::new(), Store::new()]);
+        let key = Int::new_u8(1);
+        assert_eq!(databases.len(), 2);
+        assert!(databases.get(2).is_none());
+
+        databases
+            .get(0)
+            .unwrap()
+            .set(&key, &Int::new_u8(0), SetTtl::Clear);
+        assert_eq!(databases.get(1).unwrap().get(&key), None);
+
+        assert!(databases.swap(0, 1));
+        assert_eq!(databases.get(0).unwrap().get(&key), None);
+        assert_eq!(databases.get(1).unwrap().get(&key), Some(Int::new_u8(0)));
+
+        assert!(!databases.swap(0, 2));
+        assert_eq!(databases.all().len(), 2);
+    }
+}
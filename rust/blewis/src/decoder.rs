@@ -1,25 +1,40 @@
+#![allow(clippy::unusual_byte_groupings)]
+
 use crate::{
-    data_type::{BoopArray, BoopBool, BoopError, BoopString, DataType, Int},
+    data_type::{
+        BoopArray, BoopBool, BoopError, BoopSet, BoopSortedSet, BoopStream, BoopString,
+        ConsumerGroup, DataType, Int, PendingEntry, StreamId,
+    },
     errors::DecodeError,
 };
 use anyhow::{Ok, Result};
-use bytes::{Buf, BufMut, BytesMut};
+use bytes::Buf;
+use std::collections::HashSet;
+
+/// Frame is a buffer that values are decoded from the front of. Cloning it must leave the clone
+/// reading from the same place, so that a value that isn't all there yet can put the frame back
+/// as it was. Cloning `Bytes` only bumps a reference count, so connections decode from it, while
+/// cloning `BytesMut` copies every byte.
+pub trait Frame: Buf + Clone {}
+
+impl<B: Buf + Clone> Frame for B {}
 
-/// Check the buffer's length contains at least `n` bytes and if it doesn't, put the `meta`
-/// byte into the buffer and return a DecodeError::BufTooShort, with the given `buf_msg`
+/// Check the buffer's length contains at least `n` bytes and if it doesn't, put the buffer back
+/// to `start`, where the value began, and return a DecodeError::BufTooShort, with the given `msg`
 #[inline(always)]
-fn check_header(buf: &mut BytesMut, n: usize, meta: u8, msg: &'static str) -> Result<()> {
-    if buf.len() < n {
-        buf.put_u8(meta);
-        anyhow::bail!(DecodeError::BufTooShort(msg))
+fn check_header<B: Frame>(buf: &mut B, n: usize, start: &mut B, msg: &'static str) -> Result<()> {
+    if buf.remaining() < n {
+        let missing = n - buf.remaining();
+        std::mem::swap(buf, start);
+        return Err(DecodeError::too_short(msg, missing));
     }
     Ok(())
 }
 
-pub fn handle_decode(buf: &mut BytesMut) -> anyhow::Result<DataType> {
-    // NOTE: Length checks are required before all get calls, as bytes::BufMut will panic if insufficient bytes
-    if buf.is_empty() {
-        anyhow::bail!(DecodeError::BufTooShort("meta data byte"));
+pub fn handle_decode(buf: &mut impl Frame) -> anyhow::Result<DataType> {
+    // NOTE: Length checks are required before all get calls, as bytes::Buf will panic if insufficient bytes
+    if !buf.has_remaining() {
+        return Err(DecodeError::too_short("meta data byte", 1));
     }
 
     let pre_decode_start = &mut buf.clone();
@@ -28,27 +43,27 @@ pub fn handle_decode(buf: &mut BytesMut) -> anyhow::Result<DataType> {
     match meta_byte {
         // NOTE: All the get_N functions read in BIG ENDIAN order
         0 => {
-            check_header(buf, 1, meta_byte, "u8")?;
+            check_header(buf, 1, pre_decode_start, "u8")?;
             Ok(Int::new_u8(buf.get_u8()))
         }
         8 => {
-            check_header(buf, 2, meta_byte, "u16")?;
+            check_header(buf, 2, pre_decode_start, "u16")?;
             Ok(Int::new_u16(buf.get_u16()))
         }
         16 => {
-            check_header(buf, 4, meta_byte, "u32")?;
+            check_header(buf, 4, pre_decode_start, "u32")?;
             Ok(Int::new_u32(buf.get_u32()))
         }
         32 => {
-            check_header(buf, 8, meta_byte, "u64")?;
+            check_header(buf, 8, pre_decode_start, "u64")?;
             Ok(Int::new_u64(buf.get_u64()))
         }
         48 => {
-            check_header(buf, 4, meta_byte, "f32")?;
+            check_header(buf, 4, pre_decode_start, "f32")?;
             Ok(Int::new_f32(buf.get_f32()))
         }
         56 => {
-            check_header(buf, 8, meta_byte, "f64")?;
+            check_header(buf, 8, pre_decode_start, "f64")?;
             Ok(Int::new_f64(buf.get_f64()))
         }
 
@@ -59,13 +74,14 @@ pub fn handle_decode(buf: &mut BytesMut) -> anyhow::Result<DataType> {
         // Strings are length prepended byte arrays. We use the `copy_to_bytes` function to
         // leverage the Bytes package's shallow copy mechanism, as opposed to making a full copy.
         2 => {
-            check_header(buf, 2, meta_byte, "string header")?;
+            check_header(buf, 2, pre_decode_start, "string header")?;
 
             let str_len = buf.get_u16();
 
             // If not enough bytes, clear out the buffer and refil it with any bytes already
             // consumed, so that next handle_decode can pick up where this left off
-            if str_len as usize > buf.len() {
+            if str_len as usize > buf.remaining() {
+                let missing = str_len as usize - buf.remaining();
                 // Reset buffer to what it was before we started decoding. Use a ptr swap instead
                 // of a memcpy to reduce wasted clock cycles.
                 // # Safety
@@ -75,7 +91,7 @@ pub fn handle_decode(buf: &mut BytesMut) -> anyhow::Result<DataType> {
                 unsafe {
                     std::ptr::swap(buf, pre_decode_start);
                 }
-                anyhow::bail!(DecodeError::BufTooShort("string body"));
+                return Err(DecodeError::too_short("string body", missing));
             }
 
             Ok(BoopString::new_wrapped(buf.copy_to_bytes(str_len as usize)))
@@ -83,12 +99,13 @@ pub fn handle_decode(buf: &mut BytesMut) -> anyhow::Result<DataType> {
 
         // Error
         6 => {
-            check_header(buf, 4, meta_byte, "error header")?;
+            check_header(buf, 4, pre_decode_start, "error header")?;
             let is_server_err = buf.get_u8();
             let err_code = buf.get_u8();
             let err_len = buf.get_u16();
 
-            if err_len as usize > buf.len() {
+            if err_len as usize > buf.remaining() {
+                let missing = err_len as usize - buf.remaining();
                 // Reset buffer to what it was before we started decoding. Use a ptr swap instead
                 // of a memcpy to reduce wasted clock cycles.
                 // # Safety
@@ -99,7 +116,7 @@ pub fn handle_decode(buf: &mut BytesMut) -> anyhow::Result<DataType> {
                     std::ptr::swap(buf, pre_decode_start);
                 }
 
-                anyhow::bail!(DecodeError::BufTooShort("error value"))
+                return Err(DecodeError::too_short("error value", missing));
             }
 
             let err_msg = buf.copy_to_bytes(err_len as usize);
@@ -113,7 +130,7 @@ pub fn handle_decode(buf: &mut BytesMut) -> anyhow::Result<DataType> {
 
         // Array
         3 => {
-            check_header(buf, 2, meta_byte, "array header")?;
+            check_header(buf, 2, pre_decode_start, "array header")?;
             let element_length = buf.get_u16();
 
             let mut data: Vec<DataType> = Vec::with_capacity(element_length as usize);
@@ -121,7 +138,7 @@ pub fn handle_decode(buf: &mut BytesMut) -> anyhow::Result<DataType> {
 
             while index < element_length {
                 let result = handle_decode(buf);
-                if result.is_err() {
+                if let Err(e) = result {
                     // Reset buffer to what it was before we started decoding. Use a ptr swap instead
                     // of a memcpy to reduce wasted clock cycles.
                     // # Safety
@@ -132,7 +149,9 @@ pub fn handle_decode(buf: &mut BytesMut) -> anyhow::Result<DataType> {
                         std::ptr::swap(buf, pre_decode_start);
                     }
 
-                    anyhow::bail!("array decode failed at index: {index}");
+                    let remaining = (element_length - index - 1) as usize;
+                    return Err(missing_members(e, remaining, 1)
+                        .context(format!("array decode failed at index: {index}")));
                 }
 
                 // TODO: Recursion check
@@ -145,19 +164,194 @@ pub fn handle_decode(buf: &mut BytesMut) -> anyhow::Result<DataType> {
             Ok(BoopArray::new_wrapped(data))
         }
 
+        // Sorted set. Each entry is a f64 score followed by the member, which can be any type
+        5 => {
+            check_header(buf, 2, pre_decode_start, "sorted set header")?;
+            let entry_length = buf.get_u16();
+
+            let mut set = BoopSortedSet::new();
+            for index in 0..entry_length {
+                let member = if buf.remaining() < 8 {
+                    Err(DecodeError::too_short(
+                        "sorted set score",
+                        8 - buf.remaining(),
+                    ))
+                } else {
+                    let score = buf.get_f64();
+                    handle_decode(buf).map(|member| (score, member))
+                };
+
+                match member {
+                    Result::Ok((score, _)) if score.is_nan() => {
+                        anyhow::bail!("sorted set score at index: {index} is NaN")
+                    }
+                    Result::Ok((score, member)) => {
+                        set.insert(member, score);
+                    }
+                    Err(e) => {
+                        // Reset buffer to what it was before we started decoding. Use a ptr swap
+                        // instead of a memcpy to reduce wasted clock cycles.
+                        // # Safety
+                        // This is safe because both buf and pre_decode_start point to the same
+                        // underlying chunk of memory, so the lifetime of both pre_decode_start and
+                        // buf will be identical.
+                        unsafe {
+                            std::ptr::swap(buf, pre_decode_start);
+                        }
+
+                        let remaining = (entry_length - index - 1) as usize;
+                        return Err(missing_members(e, remaining, 9)
+                            .context(format!("sorted set decode failed at index: {index}")));
+                    }
+                }
+            }
+
+            Ok(DataType::SortedSet(set))
+        }
+
+        // Set. Encoded in the same way as an array, but a member that appears more than once is
+        // only held once
+        7 => {
+            check_header(buf, 2, pre_decode_start, "set header")?;
+            let member_length = buf.get_u16();
+
+            let mut members = HashSet::with_capacity(member_length as usize);
+            for index in 0..member_length {
+                match handle_decode(buf) {
+                    Result::Ok(member) => {
+                        members.insert(member);
+                    }
+                    Err(e) => {
+                        // Reset buffer to what it was before we started decoding. Use a ptr swap
+                        // instead of a memcpy to reduce wasted clock cycles.
+                        // # Safety
+                        // This is safe because both buf and pre_decode_start point to the same
+                        // underlying chunk of memory, so the lifetime of both pre_decode_start and
+                        // buf will be identical.
+                        unsafe {
+                            std::ptr::swap(buf, pre_decode_start);
+                        }
+
+                        let remaining = (member_length - index - 1) as usize;
+                        return Err(missing_members(e, remaining, 1)
+                            .context(format!("set decode failed at index: {index}")));
+                    }
+                }
+            }
+
+            Ok(DataType::Set(BoopSet(members)))
+        }
+
+        // Stream. Holds its entries, and its consumer groups along with their pending entries
+        1 => match decode_stream(buf) {
+            Result::Ok(stream) => Ok(DataType::Stream(stream)),
+            Err(e) => {
+                // Reset buffer to what it was before we started decoding. Use a ptr swap
+                // instead of a memcpy to reduce wasted clock cycles.
+                // # Safety
+                // This is safe because both buf and pre_decode_start point to the same
+                // underlying chunk of memory, so the lifetime of both pre_decode_start and
+                // buf will be identical.
+                unsafe {
+                    std::ptr::swap(buf, pre_decode_start);
+                }
+
+                Err(e.context("stream decode failed"))
+            }
+        },
+
         unknown => Err(anyhow::anyhow!(DecodeError::UnknownMetaByte(unknown))),
     }
 }
 
+/// Adds what the `remaining` members of a collection after an incomplete one take up, at `least`
+/// bytes each, to how many bytes the incomplete member was missing. A connection then doesn't try
+/// to decode a large collection again after every member that arrives.
+fn missing_members(e: anyhow::Error, remaining: usize, least: usize) -> anyhow::Error {
+    if !DecodeError::is_incomplete(&e) {
+        return e;
+    }
+    let missing = DecodeError::shortfall(&e) + remaining * least;
+    DecodeError::too_short("collection member", missing)
+}
+
+/// Check the buffer's length contains at least `n` bytes, without putting anything back into it
+#[inline(always)]
+fn ensure_len(buf: &impl Frame, n: usize, msg: &'static str) -> Result<()> {
+    if buf.remaining() < n {
+        return Err(DecodeError::too_short(msg, n - buf.remaining()));
+    }
+    Ok(())
+}
+
+fn decode_stream_id(buf: &mut impl Frame) -> Result<StreamId> {
+    ensure_len(buf, 16, "stream id")?;
+    Ok(StreamId::new(buf.get_u64(), buf.get_u64()))
+}
+
+/// Decodes the body of a stream, after its meta data byte. The buffer is left part way through the
+/// stream if this fails, so the caller has to reset it.
+fn decode_stream(buf: &mut impl Frame) -> Result<BoopStream> {
+    let mut stream = BoopStream {
+        last_id: decode_stream_id(buf)?,
+        ..Default::default()
+    };
+
+    ensure_len(buf, 2, "stream header")?;
+    for index in 0..buf.get_u16() {
+        let id = decode_stream_id(buf)?;
+        if id > stream.last_id
+            || stream
+                .entries
+                .last_key_value()
+                .is_some_and(|(l, _)| *l >= id)
+        {
+            anyhow::bail!("stream entry at index: {index} is out of order");
+        }
+
+        ensure_len(buf, 2, "stream entry header")?;
+        let field_length = buf.get_u16();
+        let mut fields = Vec::with_capacity(field_length as usize);
+        for _ in 0..field_length {
+            fields.push((handle_decode(buf)?, handle_decode(buf)?));
+        }
+        stream.entries.insert(id, fields);
+    }
+
+    ensure_len(buf, 2, "stream group header")?;
+    for _ in 0..buf.get_u16() {
+        let name = handle_decode(buf)?;
+        let mut group = ConsumerGroup {
+            last_delivered: decode_stream_id(buf)?,
+            ..Default::default()
+        };
+
+        ensure_len(buf, 2, "stream pending entries header")?;
+        for _ in 0..buf.get_u16() {
+            let id = decode_stream_id(buf)?;
+            let consumer = handle_decode(buf)?;
+            ensure_len(buf, 16, "stream pending entry")?;
+            let pending = PendingEntry {
+                consumer,
+                delivered_at: buf.get_u64(),
+                deliveries: buf.get_u64(),
+            };
+            group.pending.insert(id, pending);
+        }
+
+        stream.groups.insert(name, group);
+    }
+
+    Ok(stream)
+}
+
 #[cfg(test)]
 mod test {
     #![allow(unused_imports)]
 
-    use std::u16;
-
     use super::*;
     use anyhow::Context;
-    use bytes::{BufMut, Bytes};
+    use bytes::{BufMut, Bytes, BytesMut};
 
     fn _run_test(buf: &mut bytes::BytesMut, expected: DataType, ctxt: &'static str) {
         let received = handle_decode(buf).context(ctxt).unwrap();
@@ -349,17 +543,18 @@ mod test {
 
     #[test]
     /// The insufficient_bytes_for_N tests each validate that the length validation function works,
-    /// and that it leaves any unprocessed bytes on the FIFO stack to be popped off later.
+    /// and that it leaves the buffer as it was, to be decoded again once more bytes arrive.
     fn insufficient_bytes_for_uint8() {
         let mut buf = BytesMut::new();
         buf.put_u8(0x00); // u8
+        let cloned = buf.clone();
         let err = handle_decode(&mut buf);
         assert!(err.is_err());
         assert_eq!(
             err.unwrap_err().to_string(),
             DecodeError::BufTooShort("u8").to_string()
         );
-        assert!(buf.get_u8() == 0x00);
+        assert_eq!(buf, cloned);
     }
 
     #[test]
@@ -367,14 +562,14 @@ mod test {
         let mut buf = BytesMut::new();
         buf.put_u8(0x8); // u16
         buf.put_u8(0x00);
+        let cloned = buf.clone();
         let err = handle_decode(&mut buf);
         assert!(err.is_err());
         assert_eq!(
             err.unwrap_err().to_string(),
             DecodeError::BufTooShort("u16").to_string()
         );
-        assert!(buf.get_u8() == 0x00);
-        assert!(buf.get_u8() == 0x8);
+        assert_eq!(buf, cloned);
     }
 
     #[test]
@@ -384,16 +579,14 @@ mod test {
         buf.put_u8(0x00);
         buf.put_u8(0x01);
         buf.put_u8(0x02);
+        let cloned = buf.clone();
         let err = handle_decode(&mut buf);
         assert!(err.is_err());
         assert_eq!(
             err.unwrap_err().to_string(),
             DecodeError::BufTooShort("u32").to_string()
         );
-        assert!(buf.get_u8() == 0x00);
-        assert!(buf.get_u8() == 0x01);
-        assert!(buf.get_u8() == 0x02);
-        assert!(buf.get_u8() == 0x10);
+        assert_eq!(buf, cloned);
     }
 
     #[test]
@@ -408,6 +601,7 @@ mod test {
         buf.put_u8(0x05);
         buf.put_u8(0x06);
 
+        let cloned = buf.clone();
         let err = handle_decode(&mut buf);
         assert!(err.is_err());
         assert_eq!(
@@ -415,14 +609,7 @@ mod test {
             DecodeError::BufTooShort("u64").to_string()
         );
 
-        assert_eq!(buf.get_u8(), 0x00);
-        assert_eq!(buf.get_u8(), 0x01);
-        assert_eq!(buf.get_u8(), 0x02);
-        assert_eq!(buf.get_u8(), 0x03);
-        assert_eq!(buf.get_u8(), 0x04);
-        assert_eq!(buf.get_u8(), 0x05);
-        assert_eq!(buf.get_u8(), 0x06);
-        assert_eq!(buf.get_u8(), 0x20);
+        assert_eq!(buf, cloned);
     }
 
     #[test]
@@ -432,16 +619,14 @@ mod test {
         buf.put_u8(0x00);
         buf.put_u8(0x01);
         buf.put_u8(0x02);
+        let cloned = buf.clone();
         let err = handle_decode(&mut buf);
         assert!(err.is_err());
         assert_eq!(
             err.unwrap_err().to_string(),
             DecodeError::BufTooShort("f32").to_string()
         );
-        assert!(buf.get_u8() == 0x00);
-        assert!(buf.get_u8() == 0x01);
-        assert!(buf.get_u8() == 0x02);
-        assert!(buf.get_u8() == 0x30);
+        assert_eq!(buf, cloned);
     }
 
     #[test]
@@ -456,6 +641,7 @@ mod test {
         buf.put_u8(0x05);
         buf.put_u8(0x06);
 
+        let cloned = buf.clone();
         let err = handle_decode(&mut buf);
         assert!(err.is_err());
         assert_eq!(
@@ -463,14 +649,7 @@ mod test {
             DecodeError::BufTooShort("f64").to_string()
         );
 
-        assert_eq!(buf.get_u8(), 0x00);
-        assert_eq!(buf.get_u8(), 0x01);
-        assert_eq!(buf.get_u8(), 0x02);
-        assert_eq!(buf.get_u8(), 0x03);
-        assert_eq!(buf.get_u8(), 0x04);
-        assert_eq!(buf.get_u8(), 0x05);
-        assert_eq!(buf.get_u8(), 0x06);
-        assert_eq!(buf.get_u8(), 0x38);
+        assert_eq!(buf, cloned);
     }
 
     #[test]
@@ -478,14 +657,14 @@ mod test {
         let mut buf = BytesMut::new();
         buf.put_u8(0x02); // string header
         buf.put_u8(0x00);
+        let cloned = buf.clone();
         let err = handle_decode(&mut buf);
         assert!(err.is_err());
         assert_eq!(
             err.unwrap_err().to_string(),
             DecodeError::BufTooShort("string header").to_string()
         );
-        assert!(buf.get_u8() == 0x00);
-        assert!(buf.get_u8() == 0x02);
+        assert_eq!(buf, cloned);
     }
 
     #[test]
@@ -507,20 +686,44 @@ mod test {
         assert_eq!(cloned, buf);
     }
 
+    #[test]
+    fn incomplete_values_know_how_many_bytes_are_missing() {
+        let mut buf = BytesMut::new();
+        buf.put_u8(0x02);
+        buf.put_u16(0x04);
+        buf.put_slice(b"t");
+        let err = handle_decode(&mut buf).unwrap_err();
+        assert!(DecodeError::is_incomplete(&err));
+        assert_eq!(DecodeError::shortfall(&err), 3);
+
+        // The members of an array after the incomplete one take up a byte at the least
+        let mut buf = BytesMut::new();
+        buf.put_u8(0x03);
+        buf.put_u16(10);
+        buf.put_u8(0x00); // u8, missing its value
+        let err = handle_decode(&mut buf).unwrap_err();
+        assert!(DecodeError::is_incomplete(&err));
+        assert_eq!(DecodeError::shortfall(&err), 1 + 9);
+
+        // Bytes that can never be decoded aren't incomplete
+        let err = handle_decode(&mut Bytes::from_static(&[0xFF])).unwrap_err();
+        assert!(!DecodeError::is_incomplete(&err));
+    }
+
     #[test]
     fn insufficient_bytes_for_error_header() {
         let mut buf = BytesMut::new();
         buf.put_u8(0x06); // Error type
         buf.put_u8(0x00); //    is_server_err,
 
+        let cloned = buf.clone();
         let err = handle_decode(&mut buf);
         assert!(err.is_err());
         assert_eq!(
             err.unwrap_err().to_string(),
             DecodeError::BufTooShort("error header").to_string()
         );
-        assert_eq!(buf.get_u8(), 0x00);
-        assert_eq!(buf.get_u8(), 0x06);
+        assert_eq!(buf, cloned);
     }
 
     #[test]
@@ -548,14 +751,14 @@ mod test {
         let mut buf = BytesMut::new();
         buf.put_u8(0x03);
         buf.put_u8(0x00);
+        let cloned = buf.clone();
         let err = handle_decode(&mut buf);
         assert!(err.is_err());
         assert_eq!(
             err.unwrap_err().to_string(),
             DecodeError::BufTooShort("array header").to_string()
         );
-        assert_eq!(buf.get_u8(), 0x00);
-        assert_eq!(buf.get_u8(), 0x03);
+        assert_eq!(buf, cloned);
     }
 
     #[test]
@@ -624,4 +827,137 @@ mod test {
             "array decode failed at index: 1"
         );
     }
+
+    #[test]
+    fn sorted_set_decode() {
+        let mut buf = bytes::BytesMut::new();
+        buf.put_u8(0b_0_0000_101);
+        buf.put_u16(2);
+        buf.put_f64(2.5);
+        buf.put_u8(0);
+        buf.put_u8(0x01);
+        buf.put_f64(-1.0);
+        buf.put_u8(0b_00000_010);
+        buf.put_u16(1);
+        buf.put_slice(b"a");
+
+        _run_test(
+            &mut buf,
+            BoopSortedSet::new_wrapped([
+                (Int::new_u8(1), 2.5),
+                (BoopString::new_wrapped(Bytes::from_static(b"a")), -1.0),
+            ]),
+            "decode a sorted set",
+        );
+
+        // Too short to hold the second member, so the buffer is left as it was
+        buf.put_u8(0b_0_0000_101);
+        buf.put_u16(2);
+        buf.put_f64(2.5);
+        buf.put_u8(0);
+        buf.put_u8(0x01);
+        buf.put_f64(-1.0);
+        let len = buf.len();
+        assert!(handle_decode(&mut buf).is_err());
+        assert_eq!(buf.len(), len);
+
+        let mut buf = bytes::BytesMut::new();
+        buf.put_u8(0b_0_0000_101);
+        buf.put_u16(1);
+        buf.put_f64(f64::NAN);
+        buf.put_u8(0);
+        buf.put_u8(0x01);
+        assert!(handle_decode(&mut buf).is_err());
+    }
+
+    #[test]
+    fn set_decode() {
+        let mut buf = bytes::BytesMut::new();
+        buf.put_u8(0b_0_0000_111);
+        buf.put_u16(3);
+        buf.put_u8(0);
+        buf.put_u8(0x01);
+        buf.put_u8(0b_1_0000_100);
+        buf.put_u8(0);
+        buf.put_u8(0x01);
+
+        _run_test(
+            &mut buf,
+            BoopSet::new_wrapped([Int::new_u8(1), BoopBool::new_wrapped(true)]),
+            "decode a set",
+        );
+
+        buf.put_u8(0b_0_0000_111);
+        buf.put_u16(2);
+        buf.put_u8(0);
+        buf.put_u8(0x01);
+        let len = buf.len();
+        assert!(handle_decode(&mut buf).is_err());
+        assert_eq!(buf.len(), len);
+    }
+
+    #[test]
+    fn stream_decode() {
+        let mut buf = bytes::BytesMut::new();
+        buf.put_u8(0b_0_0000_001); // stream
+        buf.put_u64(5); // last id
+        buf.put_u64(1);
+        buf.put_u16(1); // entries
+        buf.put_u64(5);
+        buf.put_u64(0);
+        buf.put_u16(1); // fields
+        buf.put_u8(0);
+        buf.put_u8(1);
+        buf.put_u8(0);
+        buf.put_u8(2);
+        buf.put_u16(1); // groups
+        buf.put_u8(0);
+        buf.put_u8(9);
+        buf.put_u64(5); // last delivered
+        buf.put_u64(0);
+        buf.put_u16(1); // pending entries
+        buf.put_u64(5);
+        buf.put_u64(0);
+        buf.put_u8(0);
+        buf.put_u8(7);
+        buf.put_u64(1_000);
+        buf.put_u64(2);
+
+        let mut stream = BoopStream {
+            last_id: StreamId::new(5, 1),
+            ..Default::default()
+        };
+        stream
+            .entries
+            .insert(StreamId::new(5, 0), vec![(Int::new_u8(1), Int::new_u8(2))]);
+        let mut group = ConsumerGroup {
+            last_delivered: StreamId::new(5, 0),
+            ..Default::default()
+        };
+        group.pending.insert(
+            StreamId::new(5, 0),
+            PendingEntry {
+                consumer: Int::new_u8(7),
+                delivered_at: 1_000,
+                deliveries: 2,
+            },
+        );
+        stream.groups.insert(Int::new_u8(9), group);
+
+        _run_test(&mut buf, DataType::Stream(stream), "decode a stream");
+        assert!(buf.is_empty());
+
+        // An entry newer than the last ID is refused, and the buffer is left as it was
+        buf.put_u8(0b_0_0000_001);
+        buf.put_u64(1);
+        buf.put_u64(0);
+        buf.put_u16(1);
+        buf.put_u64(2);
+        buf.put_u64(0);
+        buf.put_u16(0);
+        buf.put_u16(0);
+        let len = buf.len();
+        assert!(handle_decode(&mut buf).is_err());
+        assert_eq!(buf.len(), len);
+    }
 }
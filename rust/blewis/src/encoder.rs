@@ -1 +1,205 @@
+#![allow(clippy::unusual_byte_groupings)]
 
+use crate::{
+    data_type::{BoopStream, DataType, Int, StreamId},
+    errors::EncodeError,
+};
+use anyhow::Result;
+use bytes::{BufMut, BytesMut};
+
+/// Writes the length of a string or collection as the u16 it is encoded as, failing if it is too
+/// long to fit
+#[inline(always)]
+fn put_len(buf: &mut BytesMut, len: usize, what: &'static str) -> Result<()> {
+    let Ok(len) = u16::try_from(len) else {
+        anyhow::bail!(EncodeError::TooLong(what))
+    };
+    buf.put_u16(len);
+    Ok(())
+}
+
+/// Encodes the value onto the end of the buffer, in the same format that `handle_decode` reads.
+/// Fails if any string or collection in the value is too long for its length to be encoded, in
+/// which case the buffer may hold part of the value.
+pub fn handle_encode(value: &DataType, buf: &mut BytesMut) -> Result<()> {
+    match value {
+        // NOTE: All the put_N functions write in BIG ENDIAN order
+        DataType::Num(int) => {
+            buf.put_u8(value.meta_byte());
+            match int {
+                Int::Tiny(v) => buf.put_u8(*v),
+                Int::Small(v) => buf.put_u16(*v),
+                Int::Medium(v) => buf.put_u32(*v),
+                Int::Large(v) => buf.put_u64(*v),
+                Int::FloatS(v) => buf.put_f32(v.0),
+                Int::FloatL(v) => buf.put_f64(v.0),
+            }
+        }
+
+        // The value of a bool is held in the top bit of its meta byte
+        DataType::Bool(v) => buf.put_u8(value.meta_byte() | ((v.0 as u8) << 7)),
+
+        DataType::String(v) => {
+            buf.put_u8(value.meta_byte());
+            put_len(buf, v.0.len(), "string")?;
+            buf.put_slice(&v.0);
+        }
+
+        DataType::Error(err) => {
+            if err.err_msg.len() > u16::MAX as usize {
+                anyhow::bail!(EncodeError::TooLong("error value"));
+            }
+            buf.put(err.encode());
+        }
+
+        DataType::Array(array) => {
+            buf.put_u8(value.meta_byte());
+            put_len(buf, array.0.len(), "array")?;
+            for element in &array.0 {
+                handle_encode(element, buf)?;
+            }
+        }
+
+        // Sorted set. Each entry is a f64 score followed by the member
+        DataType::SortedSet(set) => {
+            buf.put_u8(value.meta_byte());
+            put_len(buf, set.len(), "sorted set")?;
+            for (member, score) in set.iter() {
+                buf.put_f64(score);
+                handle_encode(member, buf)?;
+            }
+        }
+
+        DataType::Set(set) => {
+            buf.put_u8(value.meta_byte());
+            put_len(buf, set.0.len(), "set")?;
+            for member in &set.0 {
+                handle_encode(member, buf)?;
+            }
+        }
+
+        DataType::Stream(stream) => {
+            buf.put_u8(value.meta_byte());
+            encode_stream(stream, buf)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn encode_stream_id(id: StreamId, buf: &mut BytesMut) {
+    buf.put_u64(id.ms);
+    buf.put_u64(id.seq);
+}
+
+/// Encodes the body of a stream, after its meta data byte
+fn encode_stream(stream: &BoopStream, buf: &mut BytesMut) -> Result<()> {
+    encode_stream_id(stream.last_id, buf);
+
+    put_len(buf, stream.entries.len(), "stream entries")?;
+    for (id, fields) in &stream.entries {
+        encode_stream_id(*id, buf);
+        put_len(buf, fields.len(), "stream entry fields")?;
+        for (field, value) in fields {
+            handle_encode(field, buf)?;
+            handle_encode(value, buf)?;
+        }
+    }
+
+    put_len(buf, stream.groups.len(), "stream groups")?;
+    for (name, group) in &stream.groups {
+        handle_encode(name, buf)?;
+        encode_stream_id(group.last_delivered, buf);
+
+        put_len(buf, group.pending.len(), "stream pending entries")?;
+        for (id, pending) in &group.pending {
+            encode_stream_id(*id, buf);
+            handle_encode(&pending.consumer, buf)?;
+            buf.put_u64(pending.delivered_at);
+            buf.put_u64(pending.deliveries);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::handle_encode;
+    use crate::{
+        data_type::{
+            BoopArray, BoopBool, BoopError, BoopSet, BoopSortedSet, BoopStream, BoopString,
+            ConsumerGroup, DataType, Int, PendingEntry, StreamId,
+        },
+        decoder::handle_decode,
+    };
+    use bytes::{Bytes, BytesMut};
+
+    fn round_trip(value: DataType) {
+        let mut buf = BytesMut::new();
+        handle_encode(&value, &mut buf).unwrap();
+        assert_eq!(handle_decode(&mut buf).unwrap(), value);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn every_data_type_round_trips() {
+        round_trip(Int::new_u8(0xFF));
+        round_trip(Int::new_u16(0xFF00));
+        round_trip(Int::new_u32(0xDEADBEEF));
+        round_trip(Int::new_u64(0xFEEDFACEDEADBEEF));
+        round_trip(Int::new_f32(-0.1234));
+        round_trip(Int::new_f64(0.1234));
+        round_trip(BoopBool::new_wrapped(true));
+        round_trip(BoopBool::new_wrapped(false));
+        round_trip(BoopString::new_wrapped(Bytes::from_static(b"null\0bytes")));
+        round_trip(BoopError::new_wrapped(
+            true,
+            0x10,
+            Bytes::from_static(b"err"),
+        ));
+        round_trip(BoopArray::new_wrapped(vec![
+            Int::new_u8(1),
+            BoopArray::new_wrapped(vec![]),
+            BoopString::new_wrapped(Bytes::from_static(b"nested")),
+        ]));
+        round_trip(BoopSortedSet::new_wrapped([
+            (Int::new_u8(1), -1.5),
+            (BoopString::new_wrapped(Bytes::from_static(b"a")), 2.0),
+        ]));
+        round_trip(BoopSet::new_wrapped([Int::new_u8(1), Int::new_u16(1)]));
+
+        let mut stream = BoopStream {
+            last_id: StreamId::new(5, 1),
+            ..Default::default()
+        };
+        stream
+            .entries
+            .insert(StreamId::new(5, 0), vec![(Int::new_u8(1), Int::new_u8(2))]);
+        stream.entries.insert(StreamId::new(5, 1), vec![]);
+        let mut group = ConsumerGroup {
+            last_delivered: StreamId::new(5, 0),
+            ..Default::default()
+        };
+        group.pending.insert(
+            StreamId::new(5, 0),
+            PendingEntry {
+                consumer: Int::new_u8(7),
+                delivered_at: 1_000,
+                deliveries: 2,
+            },
+        );
+        stream.groups.insert(Int::new_u8(9), group);
+        round_trip(DataType::Stream(stream));
+    }
+
+    #[test]
+    fn lengths_must_fit_a_u16() {
+        let mut buf = BytesMut::new();
+        let long = BoopString::new_wrapped(Bytes::from(vec![0; BoopString::MAX_LEN + 1]));
+        assert!(handle_encode(&long, &mut buf).is_err());
+
+        let wide = BoopArray::new_wrapped(vec![Int::new_u8(0); BoopArray::MAX_LEN + 1]);
+        assert!(handle_encode(&wide, &mut buf).is_err());
+    }
+}
@@ -7,4 +7,42 @@ pub enum DecodeError {
 
     #[error("Unable to decode buffer due to unknown meta byte: {0}")]
     UnknownMetaByte(u8),
+
+    #[error("Invalid argument for command: {0}")]
+    InvalidArgument(&'static str),
+
+    #[error("At least {0} more bytes are needed to decode the buffer")]
+    Missing(usize),
+}
+
+impl DecodeError {
+    /// The error for a buffer that ends at least `missing` bytes short of the `what` being
+    /// decoded. It reads as `BufTooShort`, with how many bytes are missing as its root cause.
+    pub fn too_short(what: &'static str, missing: usize) -> anyhow::Error {
+        anyhow::Error::new(DecodeError::Missing(missing)).context(DecodeError::BufTooShort(what))
+    }
+
+    /// Whether decoding failed only because the buffer ended part way through, so could succeed
+    /// once more bytes have arrived
+    pub fn is_incomplete(e: &anyhow::Error) -> bool {
+        matches!(
+            e.root_cause().downcast_ref(),
+            Some(DecodeError::BufTooShort(_) | DecodeError::Missing(_))
+        )
+    }
+
+    /// The least amount of bytes that have to arrive before decoding that was incomplete can
+    /// succeed, which is 1 unless the decoder knew how many were missing
+    pub fn shortfall(e: &anyhow::Error) -> usize {
+        match e.root_cause().downcast_ref() {
+            Some(DecodeError::Missing(missing)) => (*missing).max(1),
+            _ => 1,
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum EncodeError {
+    #[error("Unable to encode {0} as its length doesn't fit in a u16")]
+    TooLong(&'static str),
 }
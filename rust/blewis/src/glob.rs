@@ -0,0 +1,147 @@
+/// Matches `subject` against a glob style `pattern`, byte by byte. Supported syntax:
+///  - `*` matches any amount of bytes, including none
+///  - `?` matches exactly one byte
+///  - `[abc]` matches any one of the bytes in the brackets, `[a-z]` matches a range and `[^abc]`
+///    matches any byte that is not in the brackets
+///  - `\` escapes the byte after it, so that it is matched literally
+///
+/// Uses the iterative backtracking approach, where only the position of the last `*` is
+/// remembered, so the worst case is O(pattern * subject) with no recursion.
+pub fn glob_match(pattern: &[u8], subject: &[u8]) -> bool {
+    let (mut p, mut s) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+
+    while s < subject.len() {
+        if p < pattern.len() {
+            match pattern[p] {
+                b'*' => {
+                    star = Some((p, s));
+                    p += 1;
+                    continue;
+                }
+                b'?' => {
+                    p += 1;
+                    s += 1;
+                    continue;
+                }
+                b'[' => {
+                    if let Some((matched, len)) = match_class(&pattern[p..], subject[s]) {
+                        if matched {
+                            p += len;
+                            s += 1;
+                            continue;
+                        }
+                    } else if subject[s] == b'[' {
+                        // An unterminated class is treated as a literal '['
+                        p += 1;
+                        s += 1;
+                        continue;
+                    }
+                }
+                b'\\' if p + 1 < pattern.len() => {
+                    if pattern[p + 1] == subject[s] {
+                        p += 2;
+                        s += 1;
+                        continue;
+                    }
+                }
+                literal => {
+                    if literal == subject[s] {
+                        p += 1;
+                        s += 1;
+                        continue;
+                    }
+                }
+            }
+        }
+
+        // Mismatch, so let the last star swallow one more byte and try again from there
+        match star {
+            Some((star_p, star_s)) => {
+                star = Some((star_p, star_s + 1));
+                p = star_p + 1;
+                s = star_s + 1;
+            }
+            None => return false,
+        }
+    }
+
+    pattern[p..].iter().all(|b| *b == b'*')
+}
+
+/// Matches a single byte against the character class at the start of `class`. Returns whether it
+/// matched and the length of the class in the pattern, or `None` if the class is never closed.
+fn match_class(class: &[u8], byte: u8) -> Option<(bool, usize)> {
+    let mut i = 1;
+    let negate = class.get(i) == Some(&b'^');
+    if negate {
+        i += 1;
+    }
+
+    let mut matched = false;
+    let mut first = true;
+
+    while i < class.len() {
+        let mut c = class[i];
+
+        if c == b']' && !first {
+            return Some((matched != negate, i + 1));
+        }
+        first = false;
+
+        if c == b'\\' && i + 1 < class.len() {
+            i += 1;
+            c = class[i];
+        }
+
+        if i + 2 < class.len() && class[i + 1] == b'-' && class[i + 2] != b']' {
+            let (lo, hi) = (c.min(class[i + 2]), c.max(class[i + 2]));
+            matched |= (lo..=hi).contains(&byte);
+            i += 3;
+        } else {
+            matched |= c == byte;
+            i += 1;
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::glob_match;
+
+    #[test]
+    fn literals_and_wildcards() {
+        assert!(glob_match(b"user:1", b"user:1"));
+        assert!(!glob_match(b"user:1", b"user:12"));
+        assert!(glob_match(b"user:*", b"user:12"));
+        assert!(glob_match(b"user:*", b"user:"));
+        assert!(glob_match(b"*", b""));
+        assert!(glob_match(b"*:*:name", b"user:12:name"));
+        assert!(!glob_match(b"*:*:name", b"user:12:age"));
+        assert!(glob_match(b"user:?", b"user:1"));
+        assert!(!glob_match(b"user:?", b"user:"));
+        assert!(glob_match(b"a*b*c", b"aXXbYYbZZc"));
+    }
+
+    #[test]
+    fn classes() {
+        assert!(glob_match(b"h[ae]llo", b"hello"));
+        assert!(glob_match(b"h[ae]llo", b"hallo"));
+        assert!(!glob_match(b"h[ae]llo", b"hillo"));
+        assert!(glob_match(b"h[^e]llo", b"hallo"));
+        assert!(!glob_match(b"h[^e]llo", b"hello"));
+        assert!(glob_match(b"h[a-c]llo", b"hbllo"));
+        assert!(!glob_match(b"h[a-c]llo", b"hdllo"));
+        assert!(glob_match(b"[]]", b"]"));
+        assert!(glob_match(b"h[llo", b"h[llo"));
+    }
+
+    #[test]
+    fn escapes() {
+        assert!(glob_match(b"h\\*llo", b"h*llo"));
+        assert!(!glob_match(b"h\\*llo", b"hello"));
+        assert!(glob_match(b"[\\]]", b"]"));
+    }
+}
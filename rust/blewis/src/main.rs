@@ -1,17 +1,44 @@
+use config::Config;
 use network::tcp;
-use store::Store;
+use std::time::Duration;
+use store::expiry;
 mod command;
+mod config;
 mod data_type;
+mod databases;
 mod decoder;
 mod encoder;
 mod errors;
+mod glob;
 mod network;
+mod pubsub;
+mod session;
+mod snapshot;
 mod store;
 
+/// How often the background sweeper looks for expired keys
+const SWEEP_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Reports something the server did that no client is waiting on a reply for. Everything the
+/// server reports goes through here.
+pub(crate) fn log(message: std::fmt::Arguments) {
+    println!("{message}");
+}
+
 fn main() -> anyhow::Result<()> {
-    let store = Store::new();
+    let config = Config::from_args(std::env::args().skip(1))?;
+    let databases = config.build_databases();
+    let snapshots = databases.snapshots();
+    let loaded = snapshots.load(&databases.all())?;
+    log(format_args!(
+        "loaded {loaded} entries from {}",
+        snapshots.path().display()
+    ));
+
+    expiry::spawn_sweeper(databases.all(), SWEEP_INTERVAL);
 
-    let mut tcp_server = tcp::TCPServer::new("127.0.0.1:1523", store.clone())?;
+    let mut tcp_server = tcp::TCPServer::new("127.0.0.1:1523", databases.clone())?
+        .with_max_command_bytes(config.max_command_bytes);
     tcp_server.run()?;
 
     Ok(())
@@ -1,58 +1,137 @@
-use super::tcp_cnx::TcpCnx;
-use crate::{command::decode_command, store::Store};
+use super::tcp_cnx::{Incoming, TcpCnx};
+use crate::{command::Command, databases::Databases, session::Session};
 use anyhow::{Context, Ok};
-use std::{io::Read, net::TcpListener, thread};
+use std::{
+    net::{TcpListener, TcpStream},
+    sync::mpsc::{self, SyncSender},
+    thread,
+    time::Duration,
+};
+
+/// What a connection waits for: the next command read from its client, or being woken as messages
+/// were pushed to it
+enum Event {
+    Command(Box<Command>),
+    Pushed,
+    Closed,
+    /// The client sent something that can't be read as a command, or couldn't be read from
+    Failed(anyhow::Error),
+}
+
+/// The most bytes a single command may take up unless told otherwise
+pub const DEFAULT_MAX_COMMAND_BYTES: usize = 512 * 1024 * 1024;
 
 pub(crate) struct TCPServer {
     listener: TcpListener,
-    store: Store,
+    databases: Databases,
+    max_command_bytes: usize,
 }
 
 impl TCPServer {
-    pub fn new(port: &str, store: Store) -> anyhow::Result<Self> {
+    pub fn new(port: &str, databases: Databases) -> anyhow::Result<Self> {
         Ok(TCPServer {
             listener: TcpListener::bind(port)
                 .with_context(|| format!("Should bind to port {port}"))?,
-            store,
+            databases,
+            max_command_bytes: DEFAULT_MAX_COMMAND_BYTES,
         })
     }
+
+    /// Sets the most bytes a single command may take up. A client that sends a larger one is
+    /// disconnected, rather than the server holding on to its bytes until it is all there.
+    pub fn with_max_command_bytes(mut self, bytes: usize) -> Self {
+        self.max_command_bytes = bytes;
+        self
+    }
 }
 
 impl TCPServer {
     pub fn run(&mut self) -> anyhow::Result<()> {
-        println!("starting TCP Server");
+        crate::log(format_args!("starting TCP Server"));
         loop {
             for stream in self.listener.incoming() {
-                let store = self.store.clone();
+                let cnx = match stream {
+                    Result::Ok(cnx) => cnx,
+                    Err(e) => {
+                        crate::log(format_args!("error: {e:?} while accepting client"));
+                        continue;
+                    }
+                };
+                let databases = self.databases.clone();
+                let max_command_bytes = self.max_command_bytes;
 
                 // TODO: Beter thread management
                 thread::spawn(move || {
-                    let cnx = stream.unwrap();
-                    let mut s = TcpCnx::new(cnx);
-
-                    if let Err(e) = s.cnx.read(&mut s.buf) {
-                        println!("error: {:?} while reading from client", e);
-                        return;
+                    if let Err(e) = serve(cnx, databases, max_command_bytes) {
+                        crate::log(format_args!("error: {e:#} while serving client"));
                     }
+                });
+            }
+        }
+    }
+}
 
-                    let cmd = decode_command(&mut s.buf);
-                    if cmd.is_err() {
-                        println!("{}", cmd.unwrap_err());
-                        return;
-                    }
-                    let cmd = cmd.unwrap();
-                    println!("valid command parsed");
+/// Serves a client until it disconnects, replying to each of its commands in the order they were
+/// sent. Messages pushed to the client are written as they arrive, between replies. Fails if a
+/// command can't be decoded or isn't well formed, or the client can't be written to, which closes
+/// the connection.
+///
+/// Commands are read on a thread of their own, and the hub wakes the connection when messages are
+/// pushed to it, so that it can wait for both at once rather than checking for messages.
+fn serve(cnx: TcpStream, databases: Databases, max_command_bytes: usize) -> anyhow::Result<()> {
+    let mut cnx = TcpCnx::new(cnx, max_command_bytes);
+    // Holding a single event is enough, as the connection passes on every pushed message after
+    // each event, and the reader waits for a command to be taken before reading the next one
+    let (events, received) = mpsc::sync_channel(1);
+    let pushed = events.clone();
+    let mut session = Session::new(databases)
+        .with_hangup_check(cnx.hangup_check()?)
+        .with_waker(move || {
+            // A full channel already holds an event that will pass the message on
+            let _ = pushed.try_send(Event::Pushed);
+        });
 
-                    let result = cmd.execute(store);
-                    if result.is_none() {
-                        println!("no result");
-                        return;
-                    }
+    let reader = cnx.try_clone()?;
+    thread::spawn(move || read_commands(reader, events));
 
-                    let result = result.unwrap();
-                    println!("valid result: {result}");
-                });
+    let served = (|| loop {
+        match received.recv()? {
+            Event::Command(cmd) => {
+                let Some(reply) = session.handle(*cmd) else {
+                    anyhow::bail!("command isn't well formed")
+                };
+                cnx.send(&reply)?;
             }
+            Event::Pushed => {}
+            Event::Closed => return Ok(()),
+            Event::Failed(e) => return Err(e),
+        }
+
+        if session.is_subscribed() {
+            while let Some(message) = session.next_message(Duration::ZERO) {
+                cnx.send(&message)?;
+            }
+        }
+    })();
+
+    // The reader stops once the connection is closed, if it hasn't already
+    cnx.shutdown();
+    served
+}
+
+/// Reads commands from the client and hands them to its connection, until the client disconnects
+/// or sends something that can't be read as a command
+fn read_commands(mut cnx: TcpCnx, events: SyncSender<Event>) {
+    loop {
+        let event = match cnx.read_command() {
+            Result::Ok(Incoming::Command(cmd)) => Event::Command(cmd),
+            Result::Ok(Incoming::Idle) => continue,
+            Result::Ok(Incoming::Closed) => Event::Closed,
+            Err(e) => Event::Failed(e),
+        };
+        let last = !matches!(event, Event::Command(_));
+        if events.send(event).is_err() || last {
+            return;
         }
     }
 }
@@ -77,3 +156,301 @@ impl TCPServer {
 //         }
 //     }
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::{serve, DEFAULT_MAX_COMMAND_BYTES};
+    use crate::{
+        data_type::{BoopArray, BoopBool, BoopString, DataType, Int},
+        databases::Databases,
+        decoder::handle_decode,
+        encoder::handle_encode,
+        errors::DecodeError,
+        store::{list::End, Store},
+    };
+    use bytes::{BufMut, Bytes, BytesMut};
+    use std::{
+        io::{Read, Write},
+        net::{TcpListener, TcpStream},
+        thread::{self, JoinHandle},
+        time::Duration,
+    };
+
+    /// The client end of a connection, keeping whatever it has read past the last value
+    struct Client {
+        cnx: TcpStream,
+        buf: BytesMut,
+    }
+
+    impl Client {
+        fn send(&mut self, bytes: &[u8]) {
+            self.cnx.write_all(bytes).unwrap();
+        }
+
+        /// Reads the next value sent to the client
+        fn receive(&mut self) -> DataType {
+            loop {
+                let mut rest = self.buf.clone();
+                match handle_decode(&mut rest) {
+                    Result::Ok(value) => {
+                        self.buf = rest;
+                        return value;
+                    }
+                    Err(e) => assert!(DecodeError::is_incomplete(&e), "{e:#}"),
+                }
+
+                let mut read = [0; 256];
+                let len = self.cnx.read(&mut read).unwrap();
+                assert!(len > 0, "server closed the connection");
+                self.buf.put_slice(&read[..len]);
+            }
+        }
+    }
+
+    /// Accepts a single client, serving it on its own thread
+    fn server(databases: &Databases) -> (Client, JoinHandle<anyhow::Result<()>>) {
+        limited_server(databases, DEFAULT_MAX_COMMAND_BYTES)
+    }
+
+    /// Accepts a single client, serving it on its own thread with a limit on how large its
+    /// commands can be
+    fn limited_server(
+        databases: &Databases,
+        max_command_bytes: usize,
+    ) -> (Client, JoinHandle<anyhow::Result<()>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let cnx = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        cnx.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let (accepted, _) = listener.accept().unwrap();
+        let databases = databases.clone();
+        let handle = thread::spawn(move || serve(accepted, databases, max_command_bytes));
+
+        let client = Client {
+            cnx,
+            buf: BytesMut::new(),
+        };
+        (client, handle)
+    }
+
+    fn key(key: &'static [u8]) -> DataType {
+        BoopString::new_wrapped(Bytes::from_static(key))
+    }
+
+    /// Encodes a command from its opcode and the values after it
+    fn frame(opcode: &[u8], args: &[DataType]) -> BytesMut {
+        let mut buf = BytesMut::new();
+        buf.put_slice(opcode);
+        for arg in args {
+            handle_encode(arg, &mut buf).unwrap();
+        }
+        buf
+    }
+
+    fn set(k: &'static [u8], val: u8) -> BytesMut {
+        frame(&[0x10, 0x00], &[key(k), Int::new_u8(val)])
+    }
+
+    fn get(k: &'static [u8]) -> BytesMut {
+        frame(&[0x00], &[key(k)])
+    }
+
+    #[test]
+    fn commands_are_served_until_the_client_disconnects() {
+        let databases = Databases::new(vec![Store::new()]);
+        let (mut client, handle) = server(&databases);
+
+        // Several commands in one write
+        let mut buf = set(b"a", 1);
+        buf.put(get(b"a"));
+        client.send(&buf);
+        assert_eq!(client.receive(), BoopBool::new_wrapped(true));
+        assert_eq!(client.receive(), Int::new_u8(1));
+
+        // A command split across writes, with the start of the next one after it
+        let mut buf = set(b"b", 2);
+        buf.put(get(b"b"));
+        let rest = buf.split_off(5);
+        client.send(&buf);
+        thread::sleep(Duration::from_millis(20));
+        client.send(&rest);
+        assert_eq!(client.receive(), BoopBool::new_wrapped(true));
+        assert_eq!(client.receive(), Int::new_u8(2));
+
+        // A transaction takes several commands on the same connection
+        client.send(&frame(&[0xA0], &[]));
+        assert_eq!(client.receive(), BoopBool::new_wrapped(true));
+        client.send(&set(b"a", 3));
+        assert_eq!(client.receive(), key(b"QUEUED"));
+        client.send(&frame(&[0xA1], &[]));
+        assert_eq!(
+            client.receive(),
+            BoopArray::new_wrapped(vec![BoopBool::new_wrapped(true)])
+        );
+        assert_eq!(
+            databases.get(0).unwrap().get(&key(b"a")),
+            Some(Int::new_u8(3))
+        );
+
+        drop(client);
+        handle.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn selected_database_lasts_for_the_connection() {
+        let databases = Databases::new(vec![Store::new(), Store::new()]);
+        let (mut client, handle) = server(&databases);
+
+        client.send(&frame(&[0xB0], &[Int::new_u8(1)]));
+        assert_eq!(client.receive(), BoopBool::new_wrapped(true));
+        client.send(&set(b"a", 1));
+        assert_eq!(client.receive(), BoopBool::new_wrapped(true));
+        client.send(&get(b"a"));
+        assert_eq!(client.receive(), Int::new_u8(1));
+
+        assert_eq!(databases.get(0).unwrap().get(&key(b"a")), None);
+        assert_eq!(
+            databases.get(1).unwrap().get(&key(b"a")),
+            Some(Int::new_u8(1))
+        );
+
+        drop(client);
+        handle.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn messages_are_pushed_to_subscribed_clients() {
+        let databases = Databases::new(vec![Store::new()]);
+        let (mut subscriber, subscribed) = server(&databases);
+        let (mut publisher, published) = server(&databases);
+        let publish = || frame(&[0xD0], &[key(b"news"), Int::new_u8(1)]);
+
+        subscriber.send(&frame(
+            &[0xD1],
+            &[BoopArray::new_wrapped(vec![key(b"news")])],
+        ));
+        assert_eq!(subscriber.receive(), Int::new_u64(1));
+
+        publisher.send(&publish());
+        assert_eq!(publisher.receive(), Int::new_u64(1));
+        assert_eq!(
+            subscriber.receive(),
+            BoopArray::new_wrapped(vec![key(b"message"), key(b"news"), Int::new_u8(1)])
+        );
+
+        // Commands are still read while subscribed
+        subscriber.send(&frame(
+            &[0xD3],
+            &[BoopArray::new_wrapped(vec![key(b"news")])],
+        ));
+        assert_eq!(subscriber.receive(), Int::new_u64(0));
+        publisher.send(&publish());
+        assert_eq!(publisher.receive(), Int::new_u64(0));
+
+        drop(subscriber);
+        drop(publisher);
+        subscribed.join().unwrap().unwrap();
+        published.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn subscribed_clients_can_disconnect() {
+        let databases = Databases::new(vec![Store::new()]);
+        let (mut client, handle) = server(&databases);
+
+        client.send(&frame(
+            &[0xD1],
+            &[BoopArray::new_wrapped(vec![key(b"news")])],
+        ));
+        assert_eq!(client.receive(), Int::new_u64(1));
+        drop(client);
+        handle.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn invalidations_are_pushed_to_tracking_clients() {
+        let databases = Databases::new(vec![Store::new()]);
+        let (mut reader, read) = server(&databases);
+        let (mut writer, written) = server(&databases);
+
+        let tracking = frame(
+            &[0xE0],
+            &[BoopBool::new_wrapped(true), BoopArray::new_wrapped(vec![])],
+        );
+        reader.send(&tracking);
+        assert_eq!(reader.receive(), BoopBool::new_wrapped(true));
+        writer.send(&set(b"a", 1));
+        assert_eq!(writer.receive(), BoopBool::new_wrapped(true));
+        reader.send(&get(b"a"));
+        assert_eq!(reader.receive(), Int::new_u8(1));
+
+        writer.send(&set(b"a", 2));
+        assert_eq!(writer.receive(), BoopBool::new_wrapped(true));
+        assert_eq!(
+            reader.receive(),
+            BoopArray::new_wrapped(vec![key(b"invalidate"), key(b"a")])
+        );
+
+        drop(reader);
+        drop(writer);
+        read.join().unwrap().unwrap();
+        written.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn blocked_clients_can_disconnect() {
+        let databases = Databases::new(vec![Store::new()]);
+        let (mut client, handle) = server(&databases);
+        let store = databases.get(0).unwrap();
+
+        client.send(&frame(
+            &[0x49],
+            &[BoopArray::new_wrapped(vec![key(b"list")]), Int::new_u8(0)],
+        ));
+        thread::sleep(Duration::from_millis(50));
+        drop(client);
+        handle.join().unwrap().unwrap();
+
+        // The element isn't handed to the client that has gone
+        store
+            .push(&key(b"list"), vec![Int::new_u8(1)], End::Back)
+            .unwrap();
+        assert_eq!(store.llen(&key(b"list")), Ok(1));
+    }
+
+    #[test]
+    fn large_commands_are_read_as_they_arrive() {
+        let databases = Databases::new(vec![Store::new()]);
+        let (mut client, handle) = limited_server(&databases, 64 * 1024);
+
+        // RPUSH with enough elements to take many reads, sent in pieces
+        let elements: Vec<DataType> = (0..10_000u16).map(Int::new_u16).collect();
+        let buf = frame(
+            &[0x41],
+            &[key(b"list"), BoopArray::new_wrapped(elements.clone())],
+        );
+        for piece in buf.chunks(1_000) {
+            client.send(piece);
+        }
+        assert_eq!(client.receive(), Int::new_u64(10_000));
+        assert_eq!(
+            databases.get(0).unwrap().lrange(&key(b"list"), 0, -1),
+            Ok(elements)
+        );
+
+        // One that is larger than the limit closes the connection before it is all sent
+        let elements: Vec<DataType> = (0..30_000u16).map(Int::new_u16).collect();
+        let buf = frame(&[0x41], &[key(b"list"), BoopArray::new_wrapped(elements)]);
+        let _ = client.cnx.write_all(&buf);
+        assert!(handle.join().unwrap().is_err());
+    }
+
+    #[test]
+    fn malformed_commands_close_the_connection() {
+        let databases = Databases::new(vec![Store::new()]);
+        let (mut client, handle) = server(&databases);
+
+        client.send(&[0xFF]);
+        assert!(handle.join().unwrap().is_err());
+        assert_eq!(client.cnx.read(&mut [0; 1]).unwrap(), 0);
+    }
+}
@@ -1,8 +1,41 @@
-use std::{io::BufReader, net::TcpStream};
+use crate::{
+    command::{decode_command, Command},
+    data_type::DataType,
+    encoder::handle_encode,
+    errors::DecodeError,
+};
+use bytes::{Bytes, BytesMut};
+use std::{
+    io::{ErrorKind, Read, Write},
+    net::{Shutdown, TcpStream},
+};
 
+/// The most bytes read from the socket at once
+const READ_CHUNK: usize = 4 * 1024;
+
+/// What was read from a connection
+pub enum Incoming {
+    Command(Box<Command>),
+    /// The read started during a hangup check, while the connection doesn't wait, see
+    /// `TcpCnx::hangup_check`
+    Idle,
+    /// The client closed the connection
+    Closed,
+}
+
+/// TcpCnx is a client's connection, which commands are read from and replies are written to.
+/// Commands are framed by nothing but their own encoding, so the bytes read so far are kept until
+/// they hold a whole command, and whatever follows it is kept for the next one.
 pub struct TcpCnx {
-    pub cnx: BufReader<TcpStream>,
-    pub buf: bytes::BytesMut,
+    cnx: TcpStream,
+    /// The bytes read that haven't been decoded yet
+    buf: Bytes,
+    /// How many bytes `buf` has to hold before it is worth decoding again, as the last attempt
+    /// found a command that wasn't all there yet
+    needed: usize,
+    /// The most bytes a single command may take up
+    max_command_bytes: usize,
+    out: BytesMut,
     // TODO: TIMEOUT
     // time_connected: Instant,
     // read_timeout: time::Duration,
@@ -10,13 +43,100 @@ pub struct TcpCnx {
 }
 
 impl TcpCnx {
-    pub fn new(cnx: TcpStream) -> Self {
+    pub fn new(cnx: TcpStream, max_command_bytes: usize) -> Self {
         TcpCnx {
-            cnx: BufReader::new(cnx),
-            buf: bytes::BytesMut::with_capacity(1024),
+            cnx,
+            buf: Bytes::new(),
+            needed: 1,
+            max_command_bytes,
+            out: BytesMut::with_capacity(READ_CHUNK),
             // time_connected: Instant::now(),
             // read_timeout: time::Duration::new(2, 0),
             // write_timeout: time::Duration::new(2, 0)
         }
     }
+
+    /// Creates another handle to the same connection, with buffers of its own, so that one
+    /// thread can read commands while another writes replies
+    pub fn try_clone(&self) -> anyhow::Result<Self> {
+        Ok(TcpCnx::new(self.cnx.try_clone()?, self.max_command_bytes))
+    }
+
+    /// Closes the connection for every handle to it, so that a thread reading from another handle
+    /// stops too
+    pub fn shutdown(&self) {
+        let _ = self.cnx.shutdown(Shutdown::Both);
+    }
+
+    /// Reads the next command, waiting for as long as it takes for more bytes if the ones read so
+    /// far don't hold a whole command yet. Fails if the bytes can never be decoded as a command,
+    /// as the connection can't find where the next command starts after them, or if the command
+    /// takes up more than the most bytes allowed.
+    pub fn read_command(&mut self) -> anyhow::Result<Incoming> {
+        loop {
+            if self.buf.len() >= self.needed {
+                // Decoding takes from the front of a clone of the bytes, which shares them, so a
+                // command that isn't all there yet leaves them as they were without a copy
+                let mut frame = self.buf.clone();
+                match decode_command(&mut frame) {
+                    Ok(cmd) => {
+                        self.buf = frame;
+                        self.needed = 1;
+                        return Ok(Incoming::Command(Box::new(cmd)));
+                    }
+                    Err(e) if !DecodeError::is_incomplete(&e) => return Err(e),
+                    Err(e) => self.needed = self.buf.len() + DecodeError::shortfall(&e),
+                }
+            }
+
+            if self.needed > self.max_command_bytes {
+                anyhow::bail!(
+                    "command takes up more than the most bytes allowed, {}",
+                    self.max_command_bytes
+                );
+            }
+
+            // The bytes are only copied if a command decoded earlier still shares them
+            let mut buf = BytesMut::from(std::mem::take(&mut self.buf));
+            let len = buf.len();
+            buf.resize(len + READ_CHUNK, 0);
+            let read = self.cnx.read(&mut buf[len..]);
+            buf.truncate(len + *read.as_ref().unwrap_or(&0));
+            self.buf = buf.freeze();
+
+            match read {
+                Ok(0) => return Ok(Incoming::Closed),
+                Ok(_) => {}
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(Incoming::Idle),
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// Returns a check of whether the client has disconnected, which peeks at the connection
+    /// without waiting. Peeking stops the connection waiting for a moment, so a read that starts
+    /// meanwhile from another handle to it returns `Incoming::Idle` and should be retried.
+    pub fn hangup_check(&self) -> anyhow::Result<impl Fn() -> bool> {
+        let cnx = self.cnx.try_clone()?;
+        Ok(move || {
+            if cnx.set_nonblocking(true).is_err() {
+                return true;
+            }
+            let peeked = cnx.peek(&mut [0; 1]);
+            let _ = cnx.set_nonblocking(false);
+            match peeked {
+                Ok(read) => read == 0,
+                Err(e) => e.kind() != ErrorKind::WouldBlock,
+            }
+        })
+    }
+
+    /// Writes a reply, or a message pushed to the client
+    pub fn send(&mut self, value: &DataType) -> anyhow::Result<()> {
+        self.out.clear();
+        handle_encode(value, &mut self.out)?;
+        self.cnx.write_all(&self.out)?;
+        Ok(())
+    }
 }
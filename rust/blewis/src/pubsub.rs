@@ -0,0 +1,504 @@
+use crate::{
+    data_type::{BoopArray, BoopString, DataType},
+    glob::glob_match,
+};
+use bytes::Bytes;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        mpsc::{self, Receiver, SyncSender},
+        Arc, PoisonError, RwLock,
+    },
+    time::Duration,
+};
+use tracking::{invalidation, Tracking};
+
+mod tracking;
+
+/// The most messages that can wait for a subscriber to take them. Anything published to a
+/// subscriber that has fallen this far behind is dropped, so that a client that stops reading
+/// can't make the server hold on to everything published to it.
+const MAX_PENDING: usize = 1_024;
+
+/// The most keys that are tracked for clients that cache them, unless told otherwise
+pub(crate) const DEFAULT_MAX_TRACKED_KEYS: usize = 1_000_000;
+
+/// Waker is called whenever a message is pushed to a subscriber, so that whatever is passing its
+/// messages on doesn't have to keep checking for them. It is called by the publishing thread, so
+/// it must not wait on anything.
+pub(crate) type Waker = Arc<dyn Fn() + Send + Sync>;
+
+/// PubSub is the hub that messages are published through. Every subscriber to a channel is sent
+/// every message published to it from then on, and every subscriber to a pattern is sent every
+/// message published to a channel that the pattern matches. Channels can be any data type, but
+/// patterns only ever match channels that are strings, see `glob_match`.
+///
+/// A subscriber to both a channel and a pattern that matches it is sent a message twice, once for
+/// each subscription. Messages are arrays, either `["message", channel, message]` for channel
+/// subscriptions or `["pmessage", pattern, channel, message]` for pattern subscriptions.
+///
+/// The hub also pushes invalidations to clients that cache the keys they read, see `Tracking`.
+pub(crate) struct PubSub {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    subscriptions: RwLock<Subscriptions>,
+    /// The amount of channels and patterns subscribed to by every subscriber combined, so that
+    /// publishing when nobody is subscribed to anything doesn't need to take the lock
+    count: AtomicUsize,
+    next_id: AtomicU64,
+    tracking: Tracking,
+}
+
+#[derive(Default)]
+struct Subscriptions {
+    channels: HashMap<DataType, Vec<Mailbox>>,
+    patterns: HashMap<Bytes, Vec<Mailbox>>,
+}
+
+/// Mailbox is where the messages for a single subscriber are sent
+#[derive(Clone)]
+struct Mailbox {
+    id: u64,
+    sender: SyncSender<DataType>,
+    /// Set when an invalidation couldn't be sent, as the subscriber was too far behind
+    lost: Arc<AtomicBool>,
+    wake: Option<Waker>,
+}
+
+impl Mailbox {
+    /// Sends the message, returning false if it was dropped as the subscriber is too far behind.
+    /// The subscriber is woken either way, as one that is behind has messages waiting.
+    #[inline(always)]
+    fn deliver(&self, message: DataType) -> bool {
+        let delivered = self.sender.try_send(message).is_ok();
+        if let Some(wake) = &self.wake {
+            wake();
+        }
+        delivered
+    }
+
+    /// Sends an invalidation. Unlike other messages, an invalidation that is dropped is
+    /// remembered, as the subscriber would otherwise keep a key it cached that has changed.
+    fn invalidate(&self, message: DataType) {
+        if !self.deliver(message) {
+            self.lost.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Default for PubSub {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PubSub {
+    pub fn new() -> Self {
+        Self::with_max_tracked_keys(DEFAULT_MAX_TRACKED_KEYS)
+    }
+
+    /// Creates a hub that tracks up to `max_keys` keys for clients that cache them, or any amount
+    /// if it is 0. Once the limit is reached, every key that is tracked makes room for itself by
+    /// invalidating another.
+    pub fn with_max_tracked_keys(max_keys: usize) -> Self {
+        PubSub {
+            inner: Arc::new(Inner {
+                subscriptions: RwLock::default(),
+                count: AtomicUsize::new(0),
+                next_id: AtomicU64::new(0),
+                tracking: Tracking::new(max_keys),
+            }),
+        }
+    }
+
+    #[inline]
+    pub fn clone(&self) -> Self {
+        PubSub {
+            inner: self.inner.clone(),
+        }
+    }
+
+    /// Whether anything is subscribed to at all. Publishers can skip building a message that
+    /// nobody would be sent.
+    #[inline(always)]
+    pub fn has_subscribers(&self) -> bool {
+        self.inner.count.load(Ordering::Relaxed) > 0
+    }
+
+    /// Creates a subscriber that isn't subscribed to anything yet
+    pub fn subscriber(&self) -> Subscriber {
+        let (sender, receiver) = mpsc::sync_channel(MAX_PENDING);
+        Subscriber {
+            pubsub: self.clone(),
+            mailbox: Mailbox {
+                id: self.inner.next_id.fetch_add(1, Ordering::Relaxed),
+                sender,
+                lost: Arc::default(),
+                wake: None,
+            },
+            receiver,
+            channels: HashSet::new(),
+            patterns: HashSet::new(),
+            tracking: None,
+        }
+    }
+
+    /// Whether any client is tracking keys, see `Tracking`
+    #[inline(always)]
+    pub fn is_tracking(&self) -> bool {
+        self.inner.tracking.is_active()
+    }
+
+    /// Tells every client that may have cached `key` from database `db` that it has changed
+    pub fn invalidate(&self, db: usize, key: &DataType) {
+        self.inner.tracking.invalidate(db, key);
+    }
+
+    /// Tells every tracking client that every key it cached may have changed
+    pub fn invalidate_all(&self) {
+        self.inner.tracking.invalidate_all();
+    }
+
+    /// Publishes a message to a channel, returning the amount of subscriptions it was sent to
+    pub fn publish(&self, channel: &DataType, message: &DataType) -> usize {
+        if !self.has_subscribers() {
+            return 0;
+        }
+
+        let subscriptions = self
+            .inner
+            .subscriptions
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        let mut sent = 0;
+
+        if let Some(mailboxes) = subscriptions.channels.get(channel) {
+            let delivered = BoopArray::new_wrapped(vec![
+                string(b"message"),
+                channel.to_owned(),
+                message.to_owned(),
+            ]);
+            sent += mailboxes
+                .iter()
+                .filter(|mailbox| mailbox.deliver(delivered.to_owned()))
+                .count();
+        }
+
+        if let DataType::String(BoopString(name)) = channel {
+            for (pattern, mailboxes) in &subscriptions.patterns {
+                if !glob_match(pattern, name) {
+                    continue;
+                }
+
+                let delivered = BoopArray::new_wrapped(vec![
+                    string(b"pmessage"),
+                    BoopString::new_wrapped(pattern.clone()),
+                    channel.to_owned(),
+                    message.to_owned(),
+                ]);
+                sent += mailboxes
+                    .iter()
+                    .filter(|mailbox| mailbox.deliver(delivered.to_owned()))
+                    .count();
+            }
+        }
+
+        sent
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, Subscriptions> {
+        self.inner
+            .subscriptions
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+fn string(s: &'static [u8]) -> DataType {
+    BoopString::new_wrapped(Bytes::from_static(s))
+}
+
+/// Removes a subscriber's mailbox from the subscribers of a channel or pattern, forgetting the
+/// channel or pattern altogether once nobody is subscribed to it
+fn remove_mailbox<K: std::hash::Hash + Eq>(map: &mut HashMap<K, Vec<Mailbox>>, key: &K, id: u64) {
+    if let Some(mailboxes) = map.get_mut(key) {
+        mailboxes.retain(|mailbox| mailbox.id != id);
+        if mailboxes.is_empty() {
+            map.remove(key);
+        }
+    }
+}
+
+/// Subscriber is a single client's subscriptions and tracking, along with the messages that have
+/// been pushed to it and not yet taken. Dropping it unsubscribes from everything and stops
+/// tracking.
+pub(crate) struct Subscriber {
+    pubsub: PubSub,
+    mailbox: Mailbox,
+    receiver: Receiver<DataType>,
+    channels: HashSet<DataType>,
+    patterns: HashSet<Bytes>,
+    /// The prefixes the client registered while tracking, which are empty if the keys it reads
+    /// are tracked instead, or `None` if it isn't tracking
+    tracking: Option<Vec<Bytes>>,
+}
+
+impl Subscriber {
+    /// Calls `wake` whenever a message is pushed to the subscriber. It has to be set before
+    /// subscribing or tracking, which hand out the subscriber's mailbox.
+    pub fn with_waker(mut self, wake: Waker) -> Self {
+        self.mailbox.wake = Some(wake);
+        self
+    }
+
+    /// The amount of channels and patterns subscribed to
+    pub fn subscriptions(&self) -> usize {
+        self.channels.len() + self.patterns.len()
+    }
+
+    /// Whether the subscriber is subscribed to anything, or tracking
+    pub fn is_active(&self) -> bool {
+        self.subscriptions() > 0 || self.tracking.is_some()
+    }
+
+    /// Starts tracking, either of every key the client reads from then on if `prefixes` is empty,
+    /// or of every key that starts with one of the prefixes. Tracking that was already started is
+    /// replaced.
+    pub fn track(&mut self, prefixes: Vec<Bytes>) {
+        self.pubsub
+            .inner
+            .tracking
+            .start(&self.mailbox, prefixes.clone());
+        self.tracking = Some(prefixes);
+    }
+
+    /// Stops tracking
+    pub fn untrack(&mut self) {
+        if self.tracking.take().is_some() {
+            self.pubsub.inner.tracking.stop(self.mailbox.id);
+        }
+    }
+
+    /// Tracks keys that the client is about to read from database `db`, if it is tracking the keys
+    /// it reads
+    pub fn reading(&self, db: usize, keys: &[DataType]) {
+        if self.tracking.as_ref().is_some_and(Vec::is_empty) && !keys.is_empty() {
+            self.pubsub.inner.tracking.read(self.mailbox.id, db, keys);
+        }
+    }
+
+    /// Subscribes to every one of the channels, returning the amount of subscriptions afterwards.
+    /// Channels that are already subscribed to are left as they are.
+    pub fn subscribe(&mut self, channels: Vec<DataType>) -> usize {
+        let mut subscriptions = self.pubsub.write();
+        for channel in channels {
+            if self.channels.insert(channel.to_owned()) {
+                subscriptions
+                    .channels
+                    .entry(channel)
+                    .or_default()
+                    .push(self.mailbox.clone());
+                self.pubsub.inner.count.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.subscriptions()
+    }
+
+    /// Subscribes to every one of the patterns, returning the amount of subscriptions afterwards
+    pub fn psubscribe(&mut self, patterns: Vec<Bytes>) -> usize {
+        let mut subscriptions = self.pubsub.write();
+        for pattern in patterns {
+            if self.patterns.insert(pattern.clone()) {
+                subscriptions
+                    .patterns
+                    .entry(pattern)
+                    .or_default()
+                    .push(self.mailbox.clone());
+                self.pubsub.inner.count.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.subscriptions()
+    }
+
+    /// Unsubscribes from every one of the channels, or from every channel if none are given,
+    /// returning the amount of subscriptions afterwards
+    pub fn unsubscribe(&mut self, channels: Vec<DataType>) -> usize {
+        let channels = if channels.is_empty() {
+            self.channels.drain().collect()
+        } else {
+            channels
+                .into_iter()
+                .filter(|channel| self.channels.remove(channel))
+                .collect::<Vec<_>>()
+        };
+
+        let mut subscriptions = self.pubsub.write();
+        for channel in &channels {
+            remove_mailbox(&mut subscriptions.channels, channel, self.mailbox.id);
+        }
+        self.pubsub
+            .inner
+            .count
+            .fetch_sub(channels.len(), Ordering::Relaxed);
+        self.subscriptions()
+    }
+
+    /// Unsubscribes from every one of the patterns, or from every pattern if none are given,
+    /// returning the amount of subscriptions afterwards
+    pub fn punsubscribe(&mut self, patterns: Vec<Bytes>) -> usize {
+        let patterns = if patterns.is_empty() {
+            self.patterns.drain().collect()
+        } else {
+            patterns
+                .into_iter()
+                .filter(|pattern| self.patterns.remove(pattern))
+                .collect::<Vec<_>>()
+        };
+
+        let mut subscriptions = self.pubsub.write();
+        for pattern in &patterns {
+            remove_mailbox(&mut subscriptions.patterns, pattern, self.mailbox.id);
+        }
+        self.pubsub
+            .inner
+            .count
+            .fetch_sub(patterns.len(), Ordering::Relaxed);
+        self.subscriptions()
+    }
+
+    /// Takes the next message that was pushed to the subscriber, waiting up to `timeout` for one
+    /// if there are none yet. If an invalidation was dropped as the subscriber fell too far
+    /// behind, an invalidation of every key comes first.
+    pub fn next_message(&self, timeout: Duration) -> Option<DataType> {
+        if self.mailbox.lost.swap(false, Ordering::Relaxed) {
+            return Some(invalidation(None));
+        }
+        self.receiver.recv_timeout(timeout).ok()
+    }
+}
+
+impl Drop for Subscriber {
+    fn drop(&mut self) {
+        self.unsubscribe(Vec::new());
+        self.punsubscribe(Vec::new());
+        self.untrack();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PubSub, MAX_PENDING};
+    use crate::data_type::{BoopArray, BoopString, DataType, Int};
+    use bytes::Bytes;
+    use std::time::Duration;
+
+    fn string(s: &'static str) -> DataType {
+        BoopString::new_wrapped(Bytes::from_static(s.as_bytes()))
+    }
+
+    fn message(channel: DataType, message: DataType) -> Option<DataType> {
+        Some(BoopArray::new_wrapped(vec![
+            string("message"),
+            channel,
+            message,
+        ]))
+    }
+
+    const NO_WAIT: Duration = Duration::ZERO;
+
+    #[test]
+    fn channel_subscribers_get_every_message() {
+        let pubsub = PubSub::new();
+        let mut a = pubsub.subscriber();
+        let mut b = pubsub.subscriber();
+
+        assert_eq!(pubsub.publish(&string("news"), &Int::new_u8(0)), 0);
+        assert!(!pubsub.has_subscribers());
+
+        assert_eq!(a.subscribe(vec![string("news"), Int::new_u8(1)]), 2);
+        assert_eq!(a.subscribe(vec![string("news")]), 2);
+        assert_eq!(b.subscribe(vec![string("news")]), 1);
+
+        assert_eq!(pubsub.publish(&string("news"), &Int::new_u8(1)), 2);
+        assert_eq!(pubsub.publish(&Int::new_u8(1), &Int::new_u8(2)), 1);
+        assert_eq!(pubsub.publish(&string("other"), &Int::new_u8(3)), 0);
+
+        assert_eq!(
+            a.next_message(NO_WAIT),
+            message(string("news"), Int::new_u8(1))
+        );
+        assert_eq!(
+            a.next_message(NO_WAIT),
+            message(Int::new_u8(1), Int::new_u8(2))
+        );
+        assert_eq!(a.next_message(NO_WAIT), None);
+        assert_eq!(
+            b.next_message(NO_WAIT),
+            message(string("news"), Int::new_u8(1))
+        );
+
+        // Unsubscribing from nothing in particular unsubscribes from everything
+        assert_eq!(a.unsubscribe(vec![string("news")]), 1);
+        assert_eq!(a.unsubscribe(vec![string("news")]), 1);
+        assert_eq!(a.unsubscribe(vec![]), 0);
+        assert_eq!(pubsub.publish(&string("news"), &Int::new_u8(4)), 1);
+        assert_eq!(a.next_message(NO_WAIT), None);
+
+        drop(b);
+        assert!(!pubsub.has_subscribers());
+    }
+
+    #[test]
+    fn patterns_match_string_channels() {
+        let pubsub = PubSub::new();
+        let mut subscriber = pubsub.subscriber();
+        subscriber.psubscribe(vec![Bytes::from_static(b"user:*")]);
+        subscriber.subscribe(vec![string("user:1")]);
+
+        // A channel matching both subscriptions is sent twice
+        assert_eq!(pubsub.publish(&string("user:1"), &Int::new_u8(1)), 2);
+        assert_eq!(
+            subscriber.next_message(NO_WAIT),
+            message(string("user:1"), Int::new_u8(1))
+        );
+        assert_eq!(
+            subscriber.next_message(NO_WAIT),
+            Some(BoopArray::new_wrapped(vec![
+                string("pmessage"),
+                string("user:*"),
+                string("user:1"),
+                Int::new_u8(1),
+            ]))
+        );
+
+        assert_eq!(pubsub.publish(&string("users"), &Int::new_u8(2)), 0);
+        assert_eq!(pubsub.publish(&Int::new_u8(1), &Int::new_u8(3)), 0);
+
+        assert_eq!(
+            subscriber.punsubscribe(vec![Bytes::from_static(b"user:*")]),
+            1
+        );
+        assert_eq!(pubsub.publish(&string("user:2"), &Int::new_u8(4)), 0);
+    }
+
+    #[test]
+    fn slow_subscribers_drop_messages() {
+        let pubsub = PubSub::new();
+        let mut subscriber = pubsub.subscriber();
+        subscriber.subscribe(vec![string("news")]);
+
+        for i in 0..MAX_PENDING {
+            assert_eq!(pubsub.publish(&string("news"), &Int::new_u64(i as u64)), 1);
+        }
+        assert_eq!(pubsub.publish(&string("news"), &Int::new_u8(0)), 0);
+
+        assert_eq!(
+            subscriber.next_message(NO_WAIT),
+            message(string("news"), Int::new_u64(0))
+        );
+        assert_eq!(pubsub.publish(&string("news"), &Int::new_u8(0)), 1);
+    }
+}
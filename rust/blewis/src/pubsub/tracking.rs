@@ -0,0 +1,315 @@
+//! Tracking for clients that cache what they read. A tracking client is pushed an invalidation
+//! message whenever a key it may have cached changes, so it can drop the key from its cache. It
+//! either has every key it reads remembered for it, or registers prefixes and is told about every
+//! key that starts with one of them, which costs the server nothing per key.
+//!
+//! Keys are tracked along with the database they were read from, so a write only invalidates the
+//! key for clients that read it from the same database. Prefixes match keys in every database, as
+//! a client may cache keys from whichever database it selects. A key is only tracked until it is next invalidated, after which
+//! a client has to read it again to be told about it. Invalidations are sent once the write has
+//! been made, and a read is tracked before it runs, so a client never misses a write that it
+//! could have read the result of, though it may be told about one it read the result of already.
+
+use super::{string, Mailbox};
+use crate::data_type::{BoopArray, BoopString, DataType};
+use bytes::Bytes;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        PoisonError, RwLock,
+    },
+};
+
+/// Tracking is the table of which clients may have cached which keys
+pub(super) struct Tracking {
+    /// The most keys the table holds, or 0 for no limit
+    max_keys: usize,
+    table: RwLock<Table>,
+    /// The amount of tracking clients, so that writes don't need to take the lock while nobody
+    /// is tracking anything
+    clients: AtomicUsize,
+}
+
+#[derive(Default)]
+struct Table {
+    /// Every tracked key, along with the database and id of each client that read it since it was
+    /// last invalidated there
+    keys: HashMap<DataType, Vec<(usize, u64)>>,
+    /// The prefixes registered by clients, each alongside the id of the client that registered it
+    prefixes: Vec<(Bytes, u64)>,
+    /// The mailbox of every tracking client, by id
+    clients: HashMap<u64, Mailbox>,
+}
+
+impl Table {
+    /// Sends an invalidation for `key` to every client that has it tracked in database `db`, or in
+    /// any database if `db` is `None`, untracking it
+    fn invalidate_tracked(&mut self, db: Option<usize>, key: &DataType) {
+        let Some(readers) = self.keys.get_mut(key) else {
+            return;
+        };
+        let mut invalidated = Vec::new();
+        readers.retain(|&(read_in, id)| {
+            let matches = db.is_none_or(|db| db == read_in);
+            if matches {
+                invalidated.push(id);
+            }
+            !matches
+        });
+        if readers.is_empty() {
+            self.keys.remove(key);
+        }
+
+        for id in invalidated {
+            if let Some(mailbox) = self.clients.get(&id) {
+                mailbox.invalidate(invalidation(Some(key)));
+            }
+        }
+    }
+
+    /// Sends an invalidation for `key` to every client that registered a prefix of it. Only keys
+    /// that are strings have prefixes.
+    fn invalidate_prefixed(&self, key: &DataType) {
+        let DataType::String(BoopString(name)) = key else {
+            return;
+        };
+        for (prefix, id) in &self.prefixes {
+            if let Some(mailbox) = self.clients.get(id).filter(|_| name.starts_with(prefix)) {
+                mailbox.invalidate(invalidation(Some(key)));
+            }
+        }
+    }
+}
+
+/// An invalidation message for a key, or for every key if there is none
+pub(super) fn invalidation(key: Option<&DataType>) -> DataType {
+    let mut message = vec![string(b"invalidate")];
+    message.extend(key.cloned());
+    BoopArray::new_wrapped(message)
+}
+
+impl Tracking {
+    pub fn new(max_keys: usize) -> Self {
+        Tracking {
+            max_keys,
+            table: RwLock::default(),
+            clients: AtomicUsize::new(0),
+        }
+    }
+
+    /// Whether any client is tracking anything
+    #[inline(always)]
+    pub fn is_active(&self) -> bool {
+        self.clients.load(Ordering::Relaxed) > 0
+    }
+
+    /// The amount of keys tracked
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.table
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .keys
+            .len()
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, Table> {
+        self.table.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Starts tracking for a client, replacing any prefixes it registered before. With no
+    /// prefixes, the keys the client reads are tracked from then on, see `Tracking::read`.
+    pub fn start(&self, mailbox: &Mailbox, prefixes: Vec<Bytes>) {
+        let mut table = self.write();
+        table.prefixes.retain(|(_, id)| *id != mailbox.id);
+        table
+            .prefixes
+            .extend(prefixes.into_iter().map(|prefix| (prefix, mailbox.id)));
+        if table.clients.insert(mailbox.id, mailbox.clone()).is_none() {
+            self.clients.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Stops tracking for a client. The keys it read are left in the table until they are next
+    /// invalidated, rather than searching the whole table for them.
+    pub fn stop(&self, id: u64) {
+        let mut table = self.write();
+        table.prefixes.retain(|(_, client)| *client != id);
+        if table.clients.remove(&id).is_some() {
+            self.clients.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Tracks the keys for a client that is about to read them from database `db`. If the table is
+    /// full, an arbitrary tracked key is invalidated to make room for each key that isn't tracked
+    /// yet.
+    pub fn read(&self, id: u64, db: usize, keys: &[DataType]) {
+        let mut table = self.write();
+        for key in keys {
+            if let Some(readers) = table.keys.get_mut(key) {
+                if !readers.contains(&(db, id)) {
+                    readers.push((db, id));
+                }
+                continue;
+            }
+
+            if self.max_keys > 0 && table.keys.len() >= self.max_keys {
+                let evicted = table.keys.keys().next().cloned();
+                if let Some(evicted) = evicted {
+                    table.invalidate_tracked(None, &evicted);
+                }
+            }
+            table.keys.insert(key.to_owned(), vec![(db, id)]);
+        }
+    }
+
+    /// Sends an invalidation for `key` in database `db` to every client that may have cached it
+    pub fn invalidate(&self, db: usize, key: &DataType) {
+        // Most writes are to keys that nobody has cached, which only need the shared lock
+        {
+            let table = self.table.read().unwrap_or_else(PoisonError::into_inner);
+            if !table.keys.contains_key(key) {
+                table.invalidate_prefixed(key);
+                return;
+            }
+        }
+
+        let mut table = self.write();
+        table.invalidate_tracked(Some(db), key);
+        table.invalidate_prefixed(key);
+    }
+
+    /// Tells every tracking client that every key it cached may have changed, untracking every key
+    pub fn invalidate_all(&self) {
+        let mut table = self.write();
+        table.keys.clear();
+        for mailbox in table.clients.values() {
+            mailbox.invalidate(invalidation(None));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::invalidation;
+    use crate::{
+        data_type::{BoopString, DataType, Int},
+        pubsub::{PubSub, MAX_PENDING},
+        store::{notify::KeyEvents, SetTtl, Store},
+    };
+    use bytes::Bytes;
+    use std::time::Duration;
+
+    fn string(s: &str) -> DataType {
+        BoopString::new_wrapped(Bytes::copy_from_slice(s.as_bytes()))
+    }
+
+    fn store(pubsub: &PubSub) -> Store {
+        Store::new().with_notifications(pubsub, KeyEvents::NONE)
+    }
+
+    const NO_WAIT: Duration = Duration::ZERO;
+
+    #[test]
+    fn keys_that_were_read_are_invalidated_once() {
+        let pubsub = PubSub::new();
+        let store = store(&pubsub);
+        let mut client = pubsub.subscriber();
+        client.track(Vec::new());
+        assert!(pubsub.is_tracking());
+
+        client.reading(0, &[string("a"), Int::new_u8(1)]);
+        store.set(&string("b"), &Int::new_u8(0), SetTtl::Clear);
+        assert_eq!(client.next_message(NO_WAIT), None);
+
+        store.set(&string("a"), &Int::new_u8(0), SetTtl::Clear);
+        store.set(&string("a"), &Int::new_u8(1), SetTtl::Clear);
+        assert_eq!(
+            client.next_message(NO_WAIT),
+            Some(invalidation(Some(&string("a"))))
+        );
+        assert_eq!(client.next_message(NO_WAIT), None);
+
+        // Deleting a key that doesn't exist changes nothing
+        store.del(&[Int::new_u8(1)]);
+        assert_eq!(client.next_message(NO_WAIT), None);
+        store.set(&Int::new_u8(1), &Int::new_u8(0), SetTtl::Clear);
+        assert_eq!(
+            client.next_message(NO_WAIT),
+            Some(invalidation(Some(&Int::new_u8(1))))
+        );
+
+        client.reading(0, &[string("a")]);
+        store.flush(false);
+        assert_eq!(client.next_message(NO_WAIT), Some(invalidation(None)));
+        assert_eq!(pubsub.inner.tracking.len(), 0);
+
+        client.untrack();
+        assert!(!pubsub.is_tracking());
+        client.reading(0, &[string("a")]);
+        store.set(&string("a"), &Int::new_u8(2), SetTtl::Clear);
+        assert_eq!(client.next_message(NO_WAIT), None);
+    }
+
+    #[test]
+    fn prefixes_match_string_keys() {
+        let pubsub = PubSub::new();
+        let store = store(&pubsub);
+        let mut client = pubsub.subscriber();
+        client.track(vec![Bytes::from_static(b"user:")]);
+
+        // Reads aren't tracked for clients that registered prefixes
+        client.reading(0, &[string("order:1")]);
+        assert_eq!(pubsub.inner.tracking.len(), 0);
+
+        store.set(&string("order:1"), &Int::new_u8(0), SetTtl::Clear);
+        store.set(&Int::new_u8(1), &Int::new_u8(0), SetTtl::Clear);
+        assert_eq!(client.next_message(NO_WAIT), None);
+
+        store.set(&string("user:1"), &Int::new_u8(0), SetTtl::Clear);
+        store.set(&string("user:1"), &Int::new_u8(1), SetTtl::Clear);
+        for _ in 0..2 {
+            assert_eq!(
+                client.next_message(NO_WAIT),
+                Some(invalidation(Some(&string("user:1"))))
+            );
+        }
+        assert_eq!(client.next_message(NO_WAIT), None);
+    }
+
+    #[test]
+    fn full_tables_invalidate_keys_to_make_room() {
+        let pubsub = PubSub::with_max_tracked_keys(2);
+        let mut client = pubsub.subscriber();
+        client.track(Vec::new());
+
+        client.reading(0, &[Int::new_u8(1), Int::new_u8(2)]);
+        assert_eq!(client.next_message(NO_WAIT), None);
+        client.reading(0, &[Int::new_u8(2), Int::new_u8(3)]);
+        assert_eq!(pubsub.inner.tracking.len(), 2);
+
+        let Some(DataType::Array(evicted)) = client.next_message(NO_WAIT) else {
+            panic!("expected an invalidation");
+        };
+        assert_eq!(evicted.0.len(), 2);
+        assert!([Int::new_u8(1), Int::new_u8(2)].contains(&evicted.0[1]));
+        assert_eq!(client.next_message(NO_WAIT), None);
+    }
+
+    #[test]
+    fn lost_invalidations_invalidate_everything() {
+        let pubsub = PubSub::new();
+        let mut client = pubsub.subscriber();
+        client.track(vec![Bytes::new()]);
+
+        for n in 0..=MAX_PENDING {
+            pubsub.invalidate(0, &string(&n.to_string()));
+        }
+        assert_eq!(client.next_message(NO_WAIT), Some(invalidation(None)));
+        assert_eq!(
+            client.next_message(NO_WAIT),
+            Some(invalidation(Some(&string("0"))))
+        );
+    }
+}
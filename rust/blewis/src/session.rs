@@ -0,0 +1,834 @@
+use crate::{
+    command::{CmdType, Command},
+    data_type::{BoopArray, BoopBool, BoopError, BoopString, DataType, Int},
+    databases::Databases,
+    pubsub::{Subscriber, Waker},
+    store::Store,
+};
+use bytes::Bytes;
+use std::time::Duration;
+
+/// Session is the state that a connection keeps between its commands. Every command a client
+/// sends goes through its session, which runs it against the selected database, or queues it
+/// while a transaction is open.
+///
+/// Every session starts out using database 0, until SELECT picks another. A single command can be
+/// ran against another database with ON, without changing the selected one.
+///
+/// A transaction is opened with MULTI, which queues every following command until EXEC runs them
+/// all as one isolated unit, see `Store::exclusive`, or DISCARD throws them away. WATCH makes the
+/// next EXEC abort, without running anything, if any of the watched keys have been written to
+/// since they were watched. A transaction only ever runs against the database that is selected
+/// when EXEC runs, so commands that work across databases can't be queued.
+///
+/// Subscriptions also belong to the session, so they last until the client unsubscribes or
+/// disconnects, whichever database it selects in the meantime. So does tracking, which has the
+/// keys the client reads remembered, or the prefixes it registers, so that it is pushed an
+/// invalidation whenever one of them changes, see `Tracking`.
+pub(crate) struct Session {
+    databases: Databases,
+    /// The index of the selected database
+    db: usize,
+    /// The commands queued since MULTI, or `None` if there is no open transaction
+    queued: Option<Vec<Command>>,
+    /// Every watched key, along with the database it was watched in and its version at the time
+    watched: Vec<(usize, DataType, Option<u64>)>,
+    /// The session's subscriptions and tracking, or `None` if it isn't subscribed to anything and
+    /// isn't tracking
+    subscriber: Option<Subscriber>,
+    /// Tells whether the client has disconnected, so that blocking commands stop waiting for it
+    hung_up: Box<dyn Fn() -> bool>,
+    /// Called whenever a message is pushed to the session, see `Session::with_waker`
+    wake: Option<Waker>,
+}
+
+impl Session {
+    pub fn new(databases: Databases) -> Self {
+        Session {
+            databases,
+            db: 0,
+            queued: None,
+            watched: Vec::new(),
+            subscriber: None,
+            hung_up: Box::new(|| false),
+            wake: None,
+        }
+    }
+
+    /// Has blocking commands check `hung_up` while they wait, and stop once it says the client
+    /// has disconnected
+    pub fn with_hangup_check(mut self, hung_up: impl Fn() -> bool + 'static) -> Self {
+        self.hung_up = Box::new(hung_up);
+        self
+    }
+
+    /// Calls `wake` whenever a message is pushed to the session, from the thread that pushed it,
+    /// so that the connection can wait for messages and commands at once
+    pub fn with_waker(mut self, wake: impl Fn() + Send + Sync + 'static) -> Self {
+        self.wake = Some(std::sync::Arc::new(wake));
+        self
+    }
+
+    /// The store of the selected database
+    fn store(&self) -> Store {
+        self.databases
+            .get(self.db)
+            .expect("selected database should exist")
+    }
+
+    /// Handles a command sent by the client, returning the reply, or `None` if the command isn't
+    /// well formed
+    pub fn handle(&mut self, cmd: Command) -> Option<DataType> {
+        match (&cmd.cmd_type, &mut self.queued) {
+            (CmdType::Multi, None) => {
+                self.queued = Some(Vec::new());
+                Some(BoopBool::new_wrapped(true))
+            }
+            (CmdType::Exec, Some(_)) => Some(self.exec()),
+            (CmdType::Discard, Some(_)) => {
+                self.queued = None;
+                self.watched.clear();
+                Some(BoopBool::new_wrapped(true))
+            }
+            (CmdType::Watch, None) => {
+                let DataType::Array(BoopArray(keys)) = cmd.key else {
+                    return None;
+                };
+                let store = self.store();
+                self.watched.extend(keys.into_iter().map(|key| {
+                    let version = store.version(&key);
+                    (self.db, key, version)
+                }));
+                Some(BoopBool::new_wrapped(true))
+            }
+            (CmdType::Unwatch, _) => {
+                self.watched.clear();
+                Some(BoopBool::new_wrapped(true))
+            }
+            // Transactions can't be nested, watched inside of, or finished without being opened,
+            // and only run against a single database
+            (CmdType::Multi | CmdType::Watch, Some(_))
+            | (CmdType::Exec | CmdType::Discard, None) => Some(BoopError::invalid_state()),
+            (cmd_type, Some(_)) if cmd_type.needs_session() => Some(BoopError::invalid_state()),
+            (_, Some(queued)) => {
+                queued.push(cmd);
+                Some(BoopString::new_wrapped(Bytes::from_static(b"QUEUED")))
+            }
+            (CmdType::Select(db), None) => Some(if *db < self.databases.len() {
+                self.db = *db;
+                BoopBool::new_wrapped(true)
+            } else {
+                BoopError::out_of_range()
+            }),
+            (CmdType::SwapDb(a, b), None) => Some(if self.databases.swap(*a, *b) {
+                BoopBool::new_wrapped(true)
+            } else {
+                BoopError::out_of_range()
+            }),
+            (CmdType::Move { db }, None) => {
+                let Some(dest) = self.databases.get(*db) else {
+                    return Some(BoopError::out_of_range());
+                };
+                if let Err(e) = dest.make_room() {
+                    return Some(e);
+                }
+                let store = self.store();
+                let moved = store.shared_with(&dest, || store.move_to(&cmd.key, &dest));
+                Some(BoopBool::new_wrapped(moved))
+            }
+            (CmdType::FlushAll { in_background }, None) => {
+                for store in self.databases.all() {
+                    store.shared(|| store.flush(*in_background));
+                }
+                Some(BoopBool::new_wrapped(true))
+            }
+            (CmdType::Save { in_background }, None) => {
+                let snapshots = self.databases.snapshots();
+                let stores = self.databases.all();
+                if *in_background {
+                    return Some(if snapshots.save_in_background(stores) {
+                        BoopBool::new_wrapped(true)
+                    } else {
+                        BoopError::invalid_state()
+                    });
+                }
+
+                Some(match snapshots.save(&stores) {
+                    Some(Ok(_)) => BoopBool::new_wrapped(true),
+                    Some(Err(e)) => BoopError::save_failed(&e),
+                    None => BoopError::invalid_state(),
+                })
+            }
+            (CmdType::On { db, .. }, None) => {
+                let db = *db;
+                let store = self.databases.get(db);
+                let CmdType::On { cmd, .. } = cmd.cmd_type else {
+                    unreachable!()
+                };
+                match store {
+                    Some(store) => {
+                        self.reading(db, &cmd);
+                        self.run(*cmd, store)
+                    }
+                    None => Some(BoopError::out_of_range()),
+                }
+            }
+            (
+                CmdType::Subscribe
+                | CmdType::PSubscribe
+                | CmdType::Unsubscribe
+                | CmdType::PUnsubscribe,
+                None,
+            ) => self.subscription(cmd),
+            (CmdType::Tracking { on }, None) => {
+                let DataType::Array(BoopArray(prefixes)) = &cmd.key else {
+                    return None;
+                };
+                let prefixes = prefixes
+                    .iter()
+                    .filter_map(|prefix| match prefix {
+                        DataType::String(BoopString(prefix)) => Some(prefix.clone()),
+                        _ => None,
+                    })
+                    .collect();
+
+                if *on {
+                    self.subscriber().track(prefixes);
+                } else if let Some(subscriber) = &mut self.subscriber {
+                    subscriber.untrack();
+                    if !subscriber.is_active() {
+                        self.subscriber = None;
+                    }
+                }
+                Some(BoopBool::new_wrapped(true))
+            }
+            (_, None) => {
+                self.reading(self.db, &cmd);
+                self.run(cmd, self.store())
+            }
+        }
+    }
+
+    /// The session's subscriber, which is created if the session doesn't have one yet
+    fn subscriber(&mut self) -> &mut Subscriber {
+        self.subscriber.get_or_insert_with(|| {
+            let subscriber = self.databases.pubsub().subscriber();
+            match &self.wake {
+                Some(wake) => subscriber.with_waker(wake.clone()),
+                None => subscriber,
+            }
+        })
+    }
+
+    /// Tracks the keys the command is about to read from database `db`, if the session is
+    /// tracking its reads. Keys are tracked before they are read, so that no write after the read
+    /// goes unnoticed.
+    fn reading(&self, db: usize, cmd: &Command) {
+        if let Some(subscriber) = &self.subscriber {
+            subscriber.reading(db, cmd.read_keys());
+        }
+    }
+
+    /// Subscribes to or unsubscribes from channels or patterns, replying with the amount of
+    /// subscriptions the session has afterwards
+    fn subscription(&mut self, cmd: Command) -> Option<DataType> {
+        let DataType::Array(BoopArray(keys)) = cmd.key else {
+            return None;
+        };
+        let patterns = || {
+            keys.iter()
+                .filter_map(|key| match key {
+                    DataType::String(BoopString(pattern)) => Some(pattern.clone()),
+                    _ => None,
+                })
+                .collect()
+        };
+
+        let subscriber = self.subscriber();
+        let subscriptions = match cmd.cmd_type {
+            CmdType::Subscribe => subscriber.subscribe(keys.iter().cloned().collect()),
+            CmdType::PSubscribe => subscriber.psubscribe(patterns()),
+            CmdType::Unsubscribe => subscriber.unsubscribe(keys.iter().cloned().collect()),
+            CmdType::PUnsubscribe => subscriber.punsubscribe(patterns()),
+            _ => unreachable!(),
+        };
+
+        if !subscriber.is_active() {
+            self.subscriber = None;
+        }
+        Some(Int::new_u64(subscriptions as u64))
+    }
+
+    /// Whether the session is subscribed to any channel or pattern, or is tracking, so could be
+    /// pushed messages
+    pub fn is_subscribed(&self) -> bool {
+        self.subscriber.is_some()
+    }
+
+    /// Takes the next message pushed to the session, waiting up to `timeout` for one. Returns
+    /// `None` straight away if the session isn't subscribed to anything and isn't tracking.
+    pub fn next_message(&self, timeout: Duration) -> Option<DataType> {
+        self.subscriber.as_ref()?.next_message(timeout)
+    }
+
+    /// Runs a command that works on a single database against its store
+    fn run(&self, cmd: Command, store: Store) -> Option<DataType> {
+        match cmd.cmd_type {
+            // A blocking command takes the gate itself, as it must not hold it while it waits
+            CmdType::BPop { .. } => cmd.execute_blocking(store, &self.hung_up),
+            _ => store.shared(|| cmd.execute(store.clone())),
+        }
+    }
+
+    /// Runs the queued commands as one transaction, replying with an array of their replies, or
+    /// with `no_exist` if a watched key has changed. Either way, the transaction is closed and
+    /// every key is unwatched. Every database with a watched key is held exclusively as well, so
+    /// none of the keys can change between being checked and the commands running.
+    fn exec(&mut self) -> DataType {
+        let queued = self.queued.take().unwrap_or_default();
+        let watched = std::mem::take(&mut self.watched);
+        let store = &self.store();
+        let others: Vec<_> = watched
+            .iter()
+            .filter_map(|(db, _, _)| self.databases.get(*db))
+            .collect();
+
+        store.exclusive(&others, || {
+            if watched.iter().any(|(db, key, version)| {
+                self.databases.get(*db).and_then(|store| store.version(key)) != *version
+            }) {
+                return BoopError::no_exist();
+            }
+
+            // Blocking commands don't wait inside a transaction, as nothing else can run until it
+            // finishes, so they time out straight away if there is nothing to pop
+            BoopArray::new_wrapped(
+                queued
+                    .into_iter()
+                    .map(|cmd| {
+                        self.reading(self.db, &cmd);
+                        cmd.execute(store.clone())
+                            .unwrap_or_else(BoopError::no_exist)
+                    })
+                    .collect(),
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Session;
+    use crate::{
+        command::{CmdType, Command},
+        data_type::{BoopArray, BoopBool, BoopError, BoopString, DataType, Int},
+        databases::Databases,
+        pubsub::PubSub,
+        snapshot::Snapshots,
+        store::{list::End, notify::KeyEvents, SetTtl, Store},
+    };
+    use bytes::Bytes;
+    use std::time::Duration;
+
+    /// A session against a server with `store` as its only database
+    fn session(store: &Store) -> Session {
+        Session::new(Databases::new(vec![store.clone()]))
+    }
+
+    fn command(cmd_type: CmdType, key: DataType, val: Option<DataType>) -> Command {
+        Command { cmd_type, key, val }
+    }
+
+    fn no_key(cmd_type: CmdType) -> Command {
+        command(cmd_type, BoopArray::new_wrapped(vec![]), None)
+    }
+
+    fn set(key: u8, val: u8) -> Command {
+        command(
+            CmdType::Set(Default::default()),
+            Int::new_u8(key),
+            Some(Int::new_u8(val)),
+        )
+    }
+
+    fn queued() -> Option<DataType> {
+        Some(BoopString::new_wrapped(Bytes::from_static(b"QUEUED")))
+    }
+
+    #[test]
+    fn exec_runs_queued_commands_in_order() {
+        let store = Store::new();
+        let mut session = session(&store);
+
+        assert_eq!(
+            session.handle(no_key(CmdType::Multi)),
+            Some(BoopBool::new_wrapped(true))
+        );
+        assert_eq!(session.handle(set(1, 1)), queued());
+        assert_eq!(
+            session.handle(command(CmdType::Get, Int::new_u8(1), None)),
+            queued()
+        );
+
+        // Nothing runs until EXEC
+        assert_eq!(store.get(&Int::new_u8(1)), None);
+        assert_eq!(
+            session.handle(no_key(CmdType::Exec)),
+            Some(BoopArray::new_wrapped(vec![
+                BoopBool::new_wrapped(true),
+                Int::new_u8(1)
+            ]))
+        );
+
+        // Commands run straight away again once the transaction has finished
+        assert_eq!(
+            session.handle(command(CmdType::Get, Int::new_u8(1), None)),
+            Some(Int::new_u8(1))
+        );
+    }
+
+    #[test]
+    fn discard_and_invalid_states() {
+        let store = Store::new();
+        let mut session = session(&store);
+        let invalid = Some(BoopError::invalid_state());
+
+        assert_eq!(session.handle(no_key(CmdType::Exec)), invalid);
+        assert_eq!(session.handle(no_key(CmdType::Discard)), invalid);
+
+        session.handle(no_key(CmdType::Multi));
+        assert_eq!(session.handle(no_key(CmdType::Multi)), invalid);
+        assert_eq!(
+            session.handle(command(
+                CmdType::Watch,
+                BoopArray::new_wrapped(vec![Int::new_u8(1)]),
+                None
+            )),
+            invalid
+        );
+        session.handle(set(1, 1));
+        assert_eq!(
+            session.handle(no_key(CmdType::Discard)),
+            Some(BoopBool::new_wrapped(true))
+        );
+        assert_eq!(store.get(&Int::new_u8(1)), None);
+        assert_eq!(session.handle(no_key(CmdType::Exec)), invalid);
+    }
+
+    #[test]
+    fn watched_keys_abort_exec() {
+        let store = Store::new();
+        let mut session = session(&store);
+        let watch =
+            |keys: Vec<DataType>| command(CmdType::Watch, BoopArray::new_wrapped(keys), None);
+        store.set(&Int::new_u8(1), &Int::new_u8(1), SetTtl::Clear);
+
+        // Untouched keys, including keys that don't exist, let EXEC run
+        session.handle(watch(vec![Int::new_u8(1), Int::new_u8(2)]));
+        session.handle(no_key(CmdType::Multi));
+        session.handle(set(3, 3));
+        assert_eq!(
+            session.handle(no_key(CmdType::Exec)),
+            Some(BoopArray::new_wrapped(vec![BoopBool::new_wrapped(true)]))
+        );
+
+        // Another client changing a watched key aborts it
+        session.handle(watch(vec![Int::new_u8(1)]));
+        store.set(&Int::new_u8(1), &Int::new_u8(1), SetTtl::Clear);
+        session.handle(no_key(CmdType::Multi));
+        session.handle(set(4, 4));
+        assert_eq!(
+            session.handle(no_key(CmdType::Exec)),
+            Some(BoopError::no_exist())
+        );
+        assert_eq!(store.get(&Int::new_u8(4)), None);
+
+        // A key that didn't exist being created aborts it, and EXEC always unwatches
+        session.handle(watch(vec![Int::new_u8(5)]));
+        store.set(&Int::new_u8(5), &Int::new_u8(5), SetTtl::Clear);
+        session.handle(no_key(CmdType::Multi));
+        assert_eq!(
+            session.handle(no_key(CmdType::Exec)),
+            Some(BoopError::no_exist())
+        );
+        session.handle(no_key(CmdType::Multi));
+        assert_eq!(
+            session.handle(no_key(CmdType::Exec)),
+            Some(BoopArray::new_wrapped(vec![]))
+        );
+
+        // UNWATCH forgets every key
+        session.handle(watch(vec![Int::new_u8(1)]));
+        store.del(&[Int::new_u8(1)]);
+        session.handle(no_key(CmdType::Unwatch));
+        session.handle(no_key(CmdType::Multi));
+        assert_eq!(
+            session.handle(no_key(CmdType::Exec)),
+            Some(BoopArray::new_wrapped(vec![]))
+        );
+    }
+
+    #[test]
+    fn failed_writes_dont_abort_exec() {
+        let store = Store::new();
+        let mut session = session(&store);
+        let key = Int::new_u8(1);
+        store.set(&key, &Int::new_u8(1), SetTtl::Clear);
+
+        // Another client's write that fails with WRONGTYPE leaves the watched key as it was
+        session.handle(command(
+            CmdType::Watch,
+            BoopArray::new_wrapped(vec![key.clone()]),
+            None,
+        ));
+        assert_eq!(
+            store.push(&key, vec![Int::new_u8(2)], End::Front),
+            Err(BoopError::wrong_type())
+        );
+        session.handle(no_key(CmdType::Multi));
+        session.handle(set(2, 2));
+        assert_eq!(
+            session.handle(no_key(CmdType::Exec)),
+            Some(BoopArray::new_wrapped(vec![BoopBool::new_wrapped(true)]))
+        );
+    }
+
+    #[test]
+    fn blocking_pops_dont_wait_inside_exec() {
+        let store = Store::new();
+        let mut session = session(&store);
+        let bpop = || {
+            command(
+                CmdType::BPop {
+                    end: End::Front,
+                    timeout_ms: 0,
+                },
+                BoopArray::new_wrapped(vec![Int::new_u8(1)]),
+                None,
+            )
+        };
+
+        session.handle(no_key(CmdType::Multi));
+        session.handle(bpop());
+        assert_eq!(
+            session.handle(no_key(CmdType::Exec)),
+            Some(BoopArray::new_wrapped(vec![BoopError::no_exist()]))
+        );
+
+        store
+            .push(&Int::new_u8(1), vec![Int::new_u8(9)], End::Back)
+            .unwrap();
+        assert_eq!(
+            session.handle(bpop()),
+            Some(BoopArray::new_wrapped(vec![Int::new_u8(1), Int::new_u8(9)]))
+        );
+    }
+
+    #[test]
+    fn select_move_and_swap_databases() {
+        let databases = Databases::new(vec![Store::new(), Store::new()]);
+        let mut session = Session::new(databases.clone());
+        let get = |key: u8| command(CmdType::Get, Int::new_u8(key), None);
+        let ok = Some(BoopBool::new_wrapped(true));
+
+        session.handle(set(1, 1));
+        assert_eq!(session.handle(no_key(CmdType::Select(1))), ok);
+        assert_eq!(session.handle(get(1)), None);
+        assert_eq!(
+            session.handle(no_key(CmdType::Select(2))),
+            Some(BoopError::out_of_range())
+        );
+
+        // ON runs a single command against another database, leaving database 1 selected
+        let on = |db: usize, cmd: Command| {
+            no_key(CmdType::On {
+                db,
+                cmd: Box::new(cmd),
+            })
+        };
+        assert_eq!(session.handle(on(0, get(1))), Some(Int::new_u8(1)));
+        assert_eq!(session.handle(on(0, set(2, 2))), ok);
+        assert_eq!(session.handle(get(2)), None);
+        assert_eq!(
+            session.handle(on(5, get(1))),
+            Some(BoopError::out_of_range())
+        );
+
+        // MOVE never replaces a key the other database already has
+        session.handle(set(2, 20));
+        let move_to = |key: u8, db: usize| command(CmdType::Move { db }, Int::new_u8(key), None);
+        assert_eq!(
+            session.handle(move_to(2, 0)),
+            Some(BoopBool::new_wrapped(false))
+        );
+        session.handle(no_key(CmdType::Select(0)));
+        assert_eq!(session.handle(move_to(1, 1)), ok);
+        assert_eq!(session.handle(get(1)), None);
+        assert_eq!(
+            session.handle(move_to(2, 0)),
+            Some(BoopBool::new_wrapped(false))
+        );
+        assert_eq!(
+            session.handle(move_to(2, 9)),
+            Some(BoopError::out_of_range())
+        );
+
+        // Other sessions see the swap straight away
+        assert_eq!(session.handle(no_key(CmdType::SwapDb(0, 1))), ok);
+        assert_eq!(session.handle(get(2)), Some(Int::new_u8(20)));
+        assert_eq!(
+            databases.get(1).unwrap().get(&Int::new_u8(2)),
+            Some(Int::new_u8(2))
+        );
+        assert_eq!(
+            session.handle(no_key(CmdType::SwapDb(0, 2))),
+            Some(BoopError::out_of_range())
+        );
+
+        assert_eq!(
+            session.handle(no_key(CmdType::FlushAll {
+                in_background: false
+            })),
+            ok
+        );
+        assert!(databases.all().iter().all(|store| store.len() == 0));
+    }
+
+    #[test]
+    fn transactions_stay_on_one_database() {
+        let databases = Databases::new(vec![Store::new(), Store::new()]);
+        let mut session = Session::new(databases.clone());
+
+        session.handle(no_key(CmdType::Multi));
+        for cmd_type in [
+            CmdType::Select(1),
+            CmdType::SwapDb(0, 1),
+            CmdType::FlushAll {
+                in_background: false,
+            },
+        ] {
+            assert_eq!(
+                session.handle(no_key(cmd_type)),
+                Some(BoopError::invalid_state())
+            );
+        }
+        session.handle(set(1, 1));
+        session.handle(no_key(CmdType::Exec));
+        assert_eq!(
+            databases.get(0).unwrap().get(&Int::new_u8(1)),
+            Some(Int::new_u8(1))
+        );
+
+        // Keys watched in one database are still checked once another is selected
+        session.handle(command(
+            CmdType::Watch,
+            BoopArray::new_wrapped(vec![Int::new_u8(1)]),
+            None,
+        ));
+        session.handle(no_key(CmdType::Select(1)));
+        databases
+            .get(0)
+            .unwrap()
+            .set(&Int::new_u8(1), &Int::new_u8(2), SetTtl::Clear);
+        session.handle(no_key(CmdType::Multi));
+        assert_eq!(
+            session.handle(no_key(CmdType::Exec)),
+            Some(BoopError::no_exist())
+        );
+    }
+
+    #[test]
+    fn subscriptions_belong_to_the_session() {
+        let pubsub = PubSub::new();
+        let store = || Store::new().with_notifications(&pubsub, KeyEvents::NONE);
+        let databases = Databases::new(vec![store(), store()]);
+        let mut session = Session::new(databases.clone());
+        let mut publisher = Session::new(databases.clone());
+        let news = || BoopString::new_wrapped(Bytes::from_static(b"news"));
+        let channels = |cmd_type: CmdType, channels: Vec<DataType>| {
+            command(cmd_type, BoopArray::new_wrapped(channels), None)
+        };
+        let publish = |message: u8| command(CmdType::Publish, news(), Some(Int::new_u8(message)));
+        let wait = Duration::from_millis(100);
+
+        assert!(!session.is_subscribed());
+        assert_eq!(
+            session.handle(channels(CmdType::Subscribe, vec![news(), Int::new_u8(1)])),
+            Some(Int::new_u64(2))
+        );
+        assert_eq!(
+            session.handle(channels(
+                CmdType::PSubscribe,
+                vec![BoopString::new_wrapped(Bytes::from_static(b"n*"))]
+            )),
+            Some(Int::new_u64(3))
+        );
+
+        // Messages published from any database reach the subscriptions
+        publisher.handle(no_key(CmdType::Select(1)));
+        assert_eq!(publisher.handle(publish(7)), Some(Int::new_u64(2)));
+        assert_eq!(
+            session.next_message(wait),
+            Some(BoopArray::new_wrapped(vec![
+                BoopString::new_wrapped(Bytes::from_static(b"message")),
+                news(),
+                Int::new_u8(7),
+            ]))
+        );
+        assert!(session.next_message(wait).is_some());
+        assert_eq!(session.next_message(Duration::ZERO), None);
+
+        // Subscribing can't be queued in a transaction, but publishing can
+        session.handle(no_key(CmdType::Multi));
+        assert_eq!(
+            session.handle(channels(CmdType::Unsubscribe, vec![])),
+            Some(BoopError::invalid_state())
+        );
+        assert_eq!(session.handle(publish(8)), queued());
+        assert_eq!(
+            session.handle(no_key(CmdType::Exec)),
+            Some(BoopArray::new_wrapped(vec![Int::new_u64(2)]))
+        );
+
+        assert_eq!(
+            session.handle(channels(CmdType::Unsubscribe, vec![])),
+            Some(Int::new_u64(1))
+        );
+        assert_eq!(
+            session.handle(channels(CmdType::PUnsubscribe, vec![])),
+            Some(Int::new_u64(0))
+        );
+        assert!(!session.is_subscribed());
+        assert_eq!(session.next_message(Duration::ZERO), None);
+        assert_eq!(publisher.handle(publish(9)), Some(Int::new_u64(0)));
+    }
+
+    #[test]
+    fn save_snapshots_every_database() {
+        let path = std::env::temp_dir().join(format!("blewis-{}-session.boop", std::process::id()));
+        let snapshots = || Snapshots::new(&path);
+        let databases =
+            Databases::new(vec![Store::new(), Store::new()]).with_snapshots(snapshots());
+        let mut session = Session::new(databases.clone());
+
+        session.handle(no_key(CmdType::Select(1)));
+        session.handle(set(1, 1));
+        let save = |in_background| no_key(CmdType::Save { in_background });
+        assert_eq!(
+            session.handle(save(false)),
+            Some(BoopBool::new_wrapped(true))
+        );
+
+        let loaded = Databases::new(vec![Store::new(), Store::new()]);
+        assert_eq!(snapshots().load(&loaded.all()).unwrap(), 1);
+        assert_eq!(
+            loaded.get(1).unwrap().get(&Int::new_u8(1)),
+            Some(Int::new_u8(1))
+        );
+
+        // Saving can't be queued in a transaction
+        session.handle(no_key(CmdType::Multi));
+        assert_eq!(session.handle(save(true)), Some(BoopError::invalid_state()));
+        session.handle(no_key(CmdType::Discard));
+
+        // A server error is replied when the snapshot can't be written
+        let mut broken = Session::new(
+            Databases::new(vec![Store::new()])
+                .with_snapshots(Snapshots::new(path.join("not-a-directory"))),
+        );
+        let Some(DataType::Error(e)) = broken.handle(save(false)) else {
+            panic!("expected an error");
+        };
+        assert_eq!(e.err_code, BoopError::SAVE_FAILED);
+        assert!(e.err_msg.starts_with(b"save_failed: "));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn tracking_invalidates_what_the_session_read() {
+        let pubsub = PubSub::new();
+        let store = || Store::new().with_notifications(&pubsub, KeyEvents::NONE);
+        let databases = Databases::new(vec![store(), store()]);
+        let mut session = Session::new(databases.clone());
+        let mut writer = Session::new(databases.clone());
+        let tracking = |on: bool| {
+            command(
+                CmdType::Tracking { on },
+                BoopArray::new_wrapped(vec![]),
+                None,
+            )
+        };
+        let get = |key: u8| command(CmdType::Get, Int::new_u8(key), None);
+        let invalidate = |key: u8| {
+            Some(BoopArray::new_wrapped(vec![
+                BoopString::new_wrapped(Bytes::from_static(b"invalidate")),
+                Int::new_u8(key),
+            ]))
+        };
+
+        assert_eq!(
+            session.handle(tracking(true)),
+            Some(BoopBool::new_wrapped(true))
+        );
+        assert!(session.is_subscribed());
+
+        // Reads are tracked whether they run straight away, in a transaction or on another
+        // database
+        session.handle(get(1));
+        session.handle(no_key(CmdType::Multi));
+        session.handle(get(2));
+        session.handle(no_key(CmdType::Exec));
+        session.handle(command(
+            CmdType::On {
+                db: 1,
+                cmd: Box::new(get(3)),
+            },
+            BoopArray::new_wrapped(vec![]),
+            None,
+        ));
+
+        // Only writes to the database a key was read from invalidate it
+        writer.handle(no_key(CmdType::Select(1)));
+        writer.handle(set(1, 1));
+        assert_eq!(session.next_message(Duration::ZERO), None);
+
+        writer.handle(no_key(CmdType::Select(0)));
+        writer.handle(set(1, 1));
+        writer.handle(set(2, 1));
+        writer.handle(no_key(CmdType::Select(1)));
+        writer.handle(set(3, 1));
+        for key in 1..=3 {
+            assert_eq!(session.next_message(Duration::ZERO), invalidate(key));
+        }
+        assert_eq!(session.next_message(Duration::ZERO), None);
+
+        // Swapping databases changes every key that was read from them
+        session.handle(get(1));
+        writer.handle(no_key(CmdType::SwapDb(0, 1)));
+        assert_eq!(
+            session.next_message(Duration::ZERO),
+            Some(BoopArray::new_wrapped(vec![BoopString::new_wrapped(
+                Bytes::from_static(b"invalidate")
+            )]))
+        );
+        assert_eq!(session.next_message(Duration::ZERO), None);
+
+        // Tracking can't be switched inside a transaction
+        session.handle(no_key(CmdType::Multi));
+        assert_eq!(
+            session.handle(tracking(false)),
+            Some(BoopError::invalid_state())
+        );
+        session.handle(no_key(CmdType::Discard));
+
+        session.handle(get(1));
+        assert_eq!(
+            session.handle(tracking(false)),
+            Some(BoopBool::new_wrapped(true))
+        );
+        assert!(!session.is_subscribed());
+        writer.handle(no_key(CmdType::Select(0)));
+        writer.handle(set(1, 2));
+        assert_eq!(session.next_message(Duration::ZERO), None);
+    }
+}
@@ -0,0 +1,388 @@
+//! Snapshots of every database, which SAVE and BGSAVE write to a file, and which the server loads
+//! back at startup. A snapshot file is a header followed by a body of BOOP values:
+//!
+//! | Bytes | Field                                                   |
+//! |-------|---------------------------------------------------------|
+//! | 4     | `BLWS`, which marks the file as a snapshot              |
+//! | 2     | the version of the format, as a u16                     |
+//! | 4     | the CRC-32 checksum of the body, as a u32               |
+//! | 8     | the length of the body in bytes, as a u64               |
+//!
+//! The body is an integer holding the index of a database, followed by the records of its entries,
+//! see `Store::dump`, for every database that has entries. Entries that have expired are left out.
+//!
+//! Each database is dumped a segment at a time, so the snapshot is consistent per segment but not
+//! as a whole; writes made while it is taken may or may not be in it. The snapshot is written to a
+//! temporary file next to the real one, which is only renamed into place once it is complete, so
+//! the file at the real path is always a whole snapshot.
+
+use crate::{
+    data_type::{DataType, Int},
+    decoder::handle_decode,
+    encoder::handle_encode,
+    store::Store,
+};
+use anyhow::Context;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+};
+
+/// The file that snapshots are written to and loaded from, unless told otherwise
+pub(crate) const DEFAULT_SNAPSHOT_FILE: &str = "dump.boop";
+
+/// The bytes every snapshot file starts with
+const MAGIC: &[u8; 4] = b"BLWS";
+
+/// The version of the format that snapshots are written in. Snapshots in any other version are
+/// refused rather than misread.
+const VERSION: u16 = 1;
+
+/// The length of the header, see the module docs
+const HEADER_LEN: usize = 18;
+
+/// The CRC-32 (IEEE) lookup table, built at compile time
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// Continues a CRC-32 checksum over more bytes. A checksum starts from 0.
+fn crc32(crc: u32, bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!crc, |crc, byte| {
+        CRC_TABLE[((crc ^ *byte as u32) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
+
+/// Snapshots is where a server's snapshots are written, shared by every connection so that only
+/// one snapshot is taken at once
+pub(crate) struct Snapshots {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    path: PathBuf,
+    /// Set while a snapshot is being taken
+    saving: AtomicBool,
+}
+
+/// Clears the saving flag once the snapshot it was set for is finished, however it finishes
+struct Saving(Arc<Inner>);
+
+impl Drop for Saving {
+    fn drop(&mut self) {
+        self.0.saving.store(false, Ordering::Release);
+    }
+}
+
+impl Default for Snapshots {
+    fn default() -> Self {
+        Self::new(DEFAULT_SNAPSHOT_FILE)
+    }
+}
+
+impl Snapshots {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Snapshots {
+            inner: Arc::new(Inner {
+                path: path.into(),
+                saving: AtomicBool::new(false),
+            }),
+        }
+    }
+
+    #[inline]
+    pub fn clone(&self) -> Self {
+        Snapshots {
+            inner: self.inner.clone(),
+        }
+    }
+
+    /// The file that snapshots are written to
+    pub fn path(&self) -> &Path {
+        &self.inner.path
+    }
+
+    /// Sets the saving flag, unless another snapshot is being taken
+    fn start(&self) -> Option<Saving> {
+        (!self.inner.saving.swap(true, Ordering::Acquire)).then(|| Saving(self.inner.clone()))
+    }
+
+    /// Writes a snapshot of the stores, in database order, returning how many entries it holds.
+    /// Returns `None` without writing anything if another snapshot is being taken.
+    pub fn save(&self, stores: &[Store]) -> Option<anyhow::Result<usize>> {
+        let _saving = self.start()?;
+        Some(write(self.path(), stores))
+    }
+
+    /// Writes a snapshot of the stores on a background thread, returning false without starting
+    /// one if another snapshot is being taken. Nobody is waiting on the result, so it is logged.
+    pub fn save_in_background(&self, stores: Vec<Store>) -> bool {
+        let Some(saving) = self.start() else {
+            return false;
+        };
+
+        thread::spawn(move || {
+            let path = &saving.0.path;
+            match write(path, &stores) {
+                Ok(entries) => crate::log(format_args!("background save wrote {entries} entries")),
+                Err(e) => crate::log(format_args!("background save failed: {e:#}")),
+            }
+        });
+        true
+    }
+
+    /// Loads the snapshot into the stores, in database order, returning how many entries were
+    /// restored. A missing file restores nothing, but a file that isn't a whole snapshot of this
+    /// version, or that holds a database the server doesn't have, is an error.
+    pub fn load(&self, stores: &[Store]) -> anyhow::Result<usize> {
+        let path = self.path();
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e).with_context(|| format!("couldn't read {}", path.display())),
+        };
+        read(&bytes, stores).with_context(|| format!("couldn't load {}", path.display()))
+    }
+}
+
+/// Writes a snapshot of the stores to a temporary file, then renames it to `path`
+fn write(path: &Path, stores: &[Store]) -> anyhow::Result<usize> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    let temp = PathBuf::from(temp);
+
+    let written = write_file(&temp, stores).and_then(|entries| {
+        fs::rename(&temp, path)
+            .with_context(|| format!("couldn't rename {} into place", temp.display()))?;
+        Ok(entries)
+    });
+    if written.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    written
+}
+
+fn write_file(path: &Path, stores: &[Store]) -> anyhow::Result<usize> {
+    let file = File::create(path).with_context(|| format!("couldn't create {}", path.display()))?;
+    let mut out = BufWriter::new(file);
+    let (mut crc, mut len) = (0, 0);
+    let mut entries = 0;
+
+    // The header is written again once the checksum of the body is known
+    out.write_all(&[0; HEADER_LEN])?;
+    let mut body = |bytes: &[u8]| -> anyhow::Result<()> {
+        crc = crc32(crc, bytes);
+        len += bytes.len() as u64;
+        out.write_all(bytes)?;
+        Ok(())
+    };
+
+    for (db, store) in stores.iter().enumerate() {
+        if store.len() == 0 {
+            continue;
+        }
+        let mut marker = BytesMut::new();
+        handle_encode(&Int::new_u64(db as u64), &mut marker)?;
+        body(&marker)?;
+        entries += store.dump(&mut body)?;
+    }
+
+    let mut header = BytesMut::with_capacity(HEADER_LEN);
+    header.put_slice(MAGIC);
+    header.put_u16(VERSION);
+    header.put_u32(crc);
+    header.put_u64(len);
+
+    let mut file = out.into_inner().map_err(|e| e.into_error())?;
+    file.seek(SeekFrom::Start(0))?;
+    file.write_all(&header)?;
+    file.sync_all()?;
+    Ok(entries)
+}
+
+/// Restores the entries of a snapshot into the stores
+fn read(bytes: &[u8], stores: &[Store]) -> anyhow::Result<usize> {
+    if bytes.len() < HEADER_LEN || &bytes[..MAGIC.len()] != MAGIC {
+        anyhow::bail!("not a snapshot");
+    }
+    let mut header = &bytes[MAGIC.len()..HEADER_LEN];
+    let version = header.get_u16();
+    if version != VERSION {
+        anyhow::bail!("snapshot is in version {version}, but only version {VERSION} can be read");
+    }
+    let (crc, len) = (header.get_u32(), header.get_u64());
+
+    let body = &bytes[HEADER_LEN..];
+    if body.len() as u64 != len {
+        anyhow::bail!("snapshot body is {} bytes, but should be {len}", body.len());
+    }
+    if crc32(0, body) != crc {
+        anyhow::bail!("snapshot checksum doesn't match");
+    }
+
+    // Decoding clones the buffer at the start of every value, which only bumps a count for `Bytes`
+    let mut buf = Bytes::copy_from_slice(body);
+    let mut store: Option<&Store> = None;
+    let mut restored = 0;
+    while !buf.is_empty() {
+        match handle_decode(&mut buf)? {
+            DataType::Array(record) => {
+                let store = store.context("snapshot has an entry before any database")?;
+                let mut record = record.0.into_iter();
+                let (Some(key), Some(value), expires_at, None) =
+                    (record.next(), record.next(), record.next(), record.next())
+                else {
+                    anyhow::bail!("snapshot has a malformed entry");
+                };
+                let expires_at = match expires_at {
+                    Some(at) => Some(at.as_u64().context("snapshot has a malformed expiry")?),
+                    None => None,
+                };
+                if store.restore(key, value, expires_at) {
+                    restored += 1;
+                }
+            }
+            db => {
+                let db = db.as_u64().context("snapshot has a malformed database")? as usize;
+                store = Some(stores.get(db).with_context(|| {
+                    format!(
+                        "snapshot holds database {db}, but there are only {}",
+                        stores.len()
+                    )
+                })?);
+            }
+        }
+    }
+
+    Ok(restored)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{crc32, Snapshots, HEADER_LEN};
+    use crate::{
+        data_type::{BoopArray, BoopString, Int},
+        store::{list::End, SetTtl, Store},
+    };
+    use bytes::Bytes;
+    use std::{fs, path::PathBuf, sync::atomic::Ordering, thread, time::Duration};
+
+    /// A snapshot file of its own for each test, in the system's temporary directory
+    fn snapshots(name: &str) -> (Snapshots, PathBuf) {
+        let path = std::env::temp_dir().join(format!("blewis-{}-{name}.boop", std::process::id()));
+        let _ = fs::remove_file(&path);
+        (Snapshots::new(&path), path)
+    }
+
+    #[test]
+    fn checksums_are_crc32() {
+        assert_eq!(crc32(0, b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(crc32(0, b"1234"), b"56789"), 0xCBF4_3926);
+        assert_eq!(crc32(0, b""), 0);
+    }
+
+    #[test]
+    fn snapshots_restore_every_database() {
+        let (snapshots, path) = snapshots("restore");
+        let stores = [Store::new(), Store::new(), Store::new()];
+        let name = BoopString::new_wrapped(Bytes::from_static(b"name"));
+        stores[0].set(&name, &Int::new_u8(0), SetTtl::Clear);
+        stores[2].set(&name, &Int::new_u8(2), SetTtl::ExpireIn(60_000));
+        stores[2]
+            .push(&Int::new_u8(1), vec![Int::new_u8(1)], End::Back)
+            .unwrap();
+
+        // Nothing to load yet
+        assert_eq!(snapshots.load(&stores).unwrap(), 0);
+        assert_eq!(snapshots.save(&stores).unwrap().unwrap(), 3);
+        assert!(!path.with_extension("boop.tmp").exists());
+
+        let loaded = [Store::new(), Store::new(), Store::new()];
+        assert_eq!(snapshots.load(&loaded).unwrap(), 3);
+        assert_eq!(loaded[0].get(&name), Some(Int::new_u8(0)));
+        assert_eq!(loaded[1].len(), 0);
+        assert_eq!(loaded[2].get(&name), Some(Int::new_u8(2)));
+        assert!(loaded[2].ttl(&name).unwrap().is_some());
+        assert_eq!(
+            loaded[2].get(&Int::new_u8(1)),
+            Some(BoopArray::new_wrapped(vec![Int::new_u8(1)]))
+        );
+
+        // A server with fewer databases than the snapshot refuses it
+        assert!(snapshots.load(&[Store::new()]).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn damaged_snapshots_are_refused() {
+        let (snapshots, path) = snapshots("damaged");
+        let stores = [Store::new()];
+        stores[0].set(&Int::new_u8(1), &Int::new_u8(1), SetTtl::Clear);
+        snapshots.save(&stores).unwrap().unwrap();
+        let bytes = fs::read(&path).unwrap();
+
+        let mut flipped = bytes.clone();
+        *flipped.last_mut().unwrap() ^= 1;
+        fs::write(&path, &flipped).unwrap();
+        assert!(snapshots.load(&[Store::new()]).is_err());
+
+        fs::write(&path, &bytes[..bytes.len() - 1]).unwrap();
+        assert!(snapshots.load(&[Store::new()]).is_err());
+
+        let mut newer = bytes.clone();
+        newer[5] += 1;
+        fs::write(&path, &newer).unwrap();
+        assert!(snapshots.load(&[Store::new()]).is_err());
+
+        fs::write(&path, &bytes[..HEADER_LEN - 1]).unwrap();
+        assert!(snapshots.load(&[Store::new()]).is_err());
+
+        fs::write(&path, &bytes).unwrap();
+        assert_eq!(snapshots.load(&[Store::new()]).unwrap(), 1);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn one_snapshot_is_taken_at_once() {
+        let (snapshots, path) = snapshots("once");
+        let saving = snapshots.start().unwrap();
+        assert!(snapshots.save(&[Store::new()]).is_none());
+        assert!(!snapshots.save_in_background(vec![Store::new()]));
+        drop(saving);
+
+        assert_eq!(snapshots.save(&[Store::new()]).unwrap().unwrap(), 0);
+
+        // A background snapshot holds off others until it has been renamed into place
+        let store = Store::new();
+        store.set(&Int::new_u8(1), &Int::new_u8(1), SetTtl::Clear);
+        assert!(snapshots.save_in_background(vec![store]));
+        while snapshots.inner.saving.load(Ordering::Acquire) {
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(snapshots.load(&[Store::new()]).unwrap(), 1);
+        fs::remove_file(&path).unwrap();
+    }
+}
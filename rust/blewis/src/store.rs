@@ -1,59 +1,286 @@
-use crate::data_type::{BoopError, DataType};
-use dashmap::DashMap;
-use std::sync::Arc;
-
-/// Store is the concurrent hashmap that is the core of `Blewis`. It is a concurrent hashmap based
-/// on `DashMap` which itself is based on Google's SwissTable. It is a highly performant,
-/// concurrent HashMap that uses shards of RWLocks. The store does allow for weird data structures
-/// that perhaps might seem counter intuitive at first. This is to cater for weird and wonderful
-/// use cases. It is, for example, possible to store a Boolean as a key and an array of Errors for
-/// the value. In fact, any data type that can be encoded via BOOP can be used as both a key and a value.
-pub struct Store(Arc<DashMap<DataType, DataType>>);
+use crate::data_type::{BoopBool, BoopError, BoopString, DataType};
+use backend::{StorageBackend, Write};
+use blocking::Waiters;
+use clock::{Clock, SystemClock};
+use eviction::EvictionPolicy;
+use memory::Memory;
+use notify::{KeyEvent, Notifier};
+use std::{
+    cell::Cell,
+    collections::{hash_map::RandomState, HashMap},
+    hash::{BuildHasher, Hasher},
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+};
+
+pub mod backend;
+pub mod bits;
+pub mod blocking;
+pub mod clock;
+pub mod eviction;
+pub mod expiry;
+pub mod hasher;
+pub mod hyperloglog;
+pub mod keyspace;
+pub mod list;
+pub mod memory;
+pub mod notify;
+pub mod scan;
+pub mod set;
+pub mod snapshot;
+pub mod sorted_set;
+pub mod stream;
+pub mod string;
+pub mod transaction;
+
+/// The source of entry versions. It is shared by every store, so a version is never reused, not
+/// even for a key that is deleted and then written again.
+static NEXT_VERSION: AtomicU64 = AtomicU64::new(1);
+
+thread_local! {
+    /// State of this thread's xorshift generator, see `random`
+    static RANDOM: Cell<u64> = Cell::new(RandomState::new().build_hasher().finish() | 1);
+}
+
+/// Returns a number from this thread's xorshift generator, which is seeded differently on every
+/// thread. It is cheap, but far from unpredictable, so must only be used for sampling.
+fn random() -> u64 {
+    RANDOM.with(|state| {
+        let mut x = state.get();
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        state.set(x);
+        x
+    })
+}
+
+/// Entry is what the store holds for every key; the value itself alongside any meta data that the
+/// store needs to keep about it.
+///
+/// The version, modification time, value size and access tracking cost 24 bytes per key on top of
+/// the value and its expiry. All but the access tracking are plain integers rather than atomics,
+/// as they are only ever written while holding the key's shard write lock.
+#[derive(Debug)]
+pub(crate) struct Entry {
+    pub value: DataType,
+    /// The time at which the entry expires, in milliseconds since the UNIX epoch
+    pub expires_at: Option<u64>,
+    /// Changes every time the entry is written to, see `Store::version`
+    pub version: u64,
+    /// The time at which the entry was last written to, in milliseconds since the UNIX epoch
+    pub modified_at: u64,
+    /// The estimated bytes the value holds on the heap, see `DataType::heap_size`, kept so that
+    /// removing the entry doesn't need to walk the value
+    pub value_size: u32,
+    /// When, or how often, the entry is accessed, depending on the store's eviction policy. It is
+    /// atomic so that readers can update it while only holding the shard's read lock. See
+    /// `Store::accessed`.
+    pub access: AtomicU32,
+}
+
+impl Entry {
+    pub fn new(value: DataType, expires_at: Option<u64>, now: u64) -> Self {
+        Entry {
+            value_size: Self::size_of(&value),
+            value,
+            expires_at,
+            version: NEXT_VERSION.fetch_add(1, Ordering::Relaxed),
+            modified_at: now,
+            access: AtomicU32::new(0),
+        }
+    }
+
+    #[inline(always)]
+    fn size_of(value: &DataType) -> u32 {
+        value.heap_size().try_into().unwrap_or(u32::MAX)
+    }
+
+    /// Replaces the value in place, keeping the entry's expiry and access tracking. Returns the
+    /// estimated size of the value that was replaced.
+    #[inline(always)]
+    pub fn set_value(&mut self, value: DataType, now: u64) -> u32 {
+        self.value = value;
+        self.touch(now);
+        std::mem::replace(&mut self.value_size, Self::size_of(&self.value))
+    }
+
+    /// Gives the entry a new version, after it has been written to in place
+    #[inline(always)]
+    pub fn touch(&mut self, now: u64) {
+        self.version = NEXT_VERSION.fetch_add(1, Ordering::Relaxed);
+        self.modified_at = now;
+    }
+
+    #[inline(always)]
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|at| at <= now)
+    }
+}
+
+/// SetTtl decides what happens to the expiry of a key when a new value is written to it
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SetTtl {
+    /// Remove any existing expiry, so the new value lives until it is deleted
+    Clear,
+    /// Keep the expiry of the value being replaced, if it had one
+    Keep,
+    /// Expire the new value after the given amount of milliseconds
+    ExpireIn(u64),
+}
+
+/// Store is the concurrent hashmap that is the core of `Blewis`. The store does allow for weird
+/// data structures that perhaps might seem counter intuitive at first. This is to cater for weird
+/// and wonderful use cases. It is, for example, possible to store a Boolean as a key and an array
+/// of Errors for the value. In fact, any data type that can be encoded via BOOP can be used as
+/// both a key and a value.
+///
+/// Entries are kept in a `StorageBackend`, which by default is `DashMapBackend`, a concurrent
+/// hashmap based on Google's SwissTable that uses shards of RWLocks, or `LockFreeBackend`, whose
+/// reads never wait but whose writes spin on a lock per bucket.
+///
+/// Keys can be given an expiry. Expired entries are treated as though they don't exist by every
+/// operation, and are removed either lazily when they are next accessed or by the background
+/// `expiry::Sweeper`.
+pub struct Store {
+    map: Arc<dyn StorageBackend>,
+    clock: Arc<dyn Clock>,
+    waiters: Arc<Waiters>,
+    /// The most bytes a bitmap can grow to through SETBIT
+    max_bitmap_len: usize,
+    /// Held shared by every command, and exclusively by a transaction, see `Store::exclusive`
+    gate: Arc<RwLock<()>>,
+    memory: Arc<Memory>,
+    notifier: Arc<Notifier>,
+}
 
 impl Store {
+    /// Creates a new Store on the default backend
+    #[cfg(test)]
     pub fn new() -> Self {
-        Store(Arc::new(DashMap::new()))
+        Self::with_backend(backend::BackendKind::default().build(
+            hasher::HashAlgorithm::default(),
+            0,
+            None,
+        ))
+    }
+
+    /// Creates a new Store which keeps its entries in the given backend
+    pub(crate) fn with_backend(backend: Arc<dyn StorageBackend>) -> Self {
+        Store {
+            map: backend,
+            clock: Arc::new(SystemClock),
+            waiters: Arc::default(),
+            max_bitmap_len: BoopString::MAX_LEN,
+            gate: Arc::default(),
+            memory: Arc::default(),
+            notifier: Arc::default(),
+        }
     }
 
     #[inline]
     pub fn clone(&self) -> Self {
-        Store(self.0.clone())
+        Store {
+            map: self.map.clone(),
+            clock: self.clock.clone(),
+            waiters: self.waiters.clone(),
+            max_bitmap_len: self.max_bitmap_len,
+            gate: self.gate.clone(),
+            memory: self.memory.clone(),
+            notifier: self.notifier.clone(),
+        }
+    }
+
+    /// Sets the most bytes a bitmap can grow to through SETBIT. It can never be more than
+    /// `BoopString::MAX_LEN`, as the bitmap would no longer be encodable.
+    pub fn with_max_bitmap_len(mut self, len: usize) -> Self {
+        self.max_bitmap_len = len.min(BoopString::MAX_LEN);
+        self
+    }
+
+    /// Limits the estimated memory the store holds to `bytes`, see `Store::used_memory`, making
+    /// room for writes past the limit as `policy` decides. A limit of 0 means there is no limit.
+    pub fn with_max_memory(mut self, bytes: usize, policy: EvictionPolicy) -> Self {
+        self.memory = Arc::new(Memory::new(bytes, policy));
+        self
+    }
+
+    /// Creates a new Store which uses the given clock to decide when entries expire
+    #[cfg(test)]
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
+        let mut store = Self::new();
+        store.clock = clock;
+        store
+    }
+
+    /// The amount of shards the store is split into
+    #[cfg(test)]
+    pub fn shard_amount(&self) -> usize {
+        self.map.segments()
+    }
+
+    #[inline(always)]
+    fn now(&self) -> u64 {
+        self.clock.now_ms()
     }
 
-    /// Creates a new Store with a preset capacity
-    pub fn with_capacity(cap: usize) -> Self {
-        Store(Arc::new(DashMap::with_capacity(cap)))
+    /// Works out the expiry of a value that is about to be written, given the expiry of the live
+    /// entry it is replacing (if any)
+    #[inline(always)]
+    fn expiry_for(ttl: SetTtl, replacing: Option<&Entry>, now: u64) -> Option<u64> {
+        match ttl {
+            SetTtl::Clear => None,
+            SetTtl::Keep => replacing.and_then(|e| e.expires_at),
+            SetTtl::ExpireIn(ms) => Some(now.saturating_add(ms)),
+        }
     }
 
-    /// Creates a new Store with a preset capacity and shard amount. The shard amount must be a
-    /// power of two. If a none power of two is selected, the program will panic.
-    pub fn with_capacity_and_shard_amount(cap: usize, shard_amount: usize) -> Self {
-        Store(Arc::new(DashMap::with_capacity_and_shard_amount(
-            cap,
-            shard_amount,
-        )))
+    /// Removes the entry at `key`, but only if it has expired. The check is done atomically with
+    /// the removal, so a value written in the meantime is never removed.
+    #[inline(always)]
+    fn remove_expired(&self, key: &DataType, now: u64) {
+        self.remove_entry_if(key, |entry| entry.is_expired(now));
+    }
+
+    /// Reads something out of the live entry held by `key`, counting it as an access. An expired
+    /// entry is removed, and read as `None`.
+    #[inline(always)]
+    fn read_live<R>(&self, key: &DataType, f: impl FnOnce(&Entry) -> R) -> Option<R> {
+        let now = self.now();
+        let mut expired = false;
+        let result = self.read_entry(key, |entry| {
+            let entry = entry?;
+            expired = entry.is_expired(now);
+            if expired {
+                return None;
+            }
+
+            self.accessed(entry, now);
+            Some(f(entry))
+        });
+
+        if expired {
+            self.remove_expired(key, now);
+        }
+        result
     }
 
     /// Retrieves a value from the store
     #[inline(always)]
     pub fn get(&self, key: &DataType) -> Option<DataType> {
-        let res = self.0.get(key);
-
-        res.map(|v| v.to_owned())
+        self.read_live(key, |entry| entry.value.to_owned())
     }
 
     /// Simillar to set but instead of returning an Option<DataType>, it returns an error code if
     /// a previous value was not set
     #[inline(always)]
     pub fn get_set(&self, key: &DataType, new_val: &DataType) -> DataType {
-        let existing_val = self.0.insert(key.to_owned(), new_val.to_owned());
+        let existing_val = self.set(key, new_val, SetTtl::Clear);
 
         if existing_val.is_none() {
-            return DataType::Error(BoopError {
-                is_server_err: true,
-                err_code: 0x10,
-                err_msg: bytes::Bytes::from_static(b"no_exist"),
-            });
+            return BoopError::no_exist();
         }
 
         existing_val.unwrap().to_owned()
@@ -61,24 +288,362 @@ impl Store {
 
     #[inline(always)]
     pub fn get_del(&self, key: &DataType) -> Option<DataType> {
-        self.0.remove(key).map(|(_, entry)| entry)
+        let now = self.now();
+        let (key, entry) = self.map.remove(key)?;
+        self.refund(&key, &entry);
+        self.invalidate(&key);
+
+        if entry.is_expired(now) {
+            self.notify(KeyEvent::Expired, &key);
+            return None;
+        }
+        self.notify(KeyEvent::Del, &key);
+        Some(entry.value)
     }
 
     /// When the set command is ran, if a value with the key already exists, it replaces it and returns
     /// the old value
     #[inline(always)]
-    pub fn set(&self, key: &DataType, value: &DataType) -> Option<DataType> {
-        self.0.insert(key.to_owned(), value.to_owned())
+    pub fn set(&self, key: &DataType, value: &DataType, ttl: SetTtl) -> Option<DataType> {
+        let now = self.now();
+
+        let (live, old) = self.update_entry(key, |current| {
+            let live = current.filter(|entry| !entry.is_expired(now));
+            let expires_at = Self::expiry_for(ttl, live.as_deref(), now);
+            let new = self.new_entry(value.to_owned(), expires_at, now);
+            (Write::Insert(new), live.is_some())
+        });
+
+        self.notify(KeyEvent::Set, key);
+        old.filter(|_| live).map(|old| old.value)
+    }
+
+    /// Sets the value only if the key does not exist yet. Returns the existing value if there was
+    /// one, in which case nothing was written. The check and the write are one atomic operation
+    /// on the backend.
+    #[inline(always)]
+    pub fn set_nx(&self, key: &DataType, value: &DataType, ttl: SetTtl) -> Option<DataType> {
+        let now = self.now();
+
+        let (existing, _) = self.update_entry(key, |current| match current {
+            Some(entry) if !entry.is_expired(now) => (Write::Keep, Some(entry.value.to_owned())),
+            _ => {
+                let expires_at = Self::expiry_for(ttl, None, now);
+                let new = self.new_entry(value.to_owned(), expires_at, now);
+                (Write::Insert(new), None)
+            }
+        });
+
+        if existing.is_none() {
+            self.notify(KeyEvent::Set, key);
+        }
+        existing
+    }
+
+    /// Sets the value only if the key already exists, returning the replaced value. Returns `None`
+    /// if the key did not exist, in which case nothing was written.
+    #[inline(always)]
+    pub fn set_xx(&self, key: &DataType, value: &DataType, ttl: SetTtl) -> Option<DataType> {
+        let now = self.now();
+
+        let (written, old) = self.update_entry(key, |current| match current {
+            Some(entry) if entry.is_expired(now) => (Write::Remove, false),
+            Some(entry) => {
+                let expires_at = Self::expiry_for(ttl, Some(entry), now);
+                let new = self.new_entry(value.to_owned(), expires_at, now);
+                (Write::Insert(new), true)
+            }
+            None => (Write::Keep, false),
+        });
+
+        if written {
+            self.notify(KeyEvent::Set, key);
+        }
+        old.filter(|_| written).map(|old| old.value)
+    }
+
+    /// Compare and swap. Replaces the value at `key` with `new_val` only if the current value is
+    /// equal to `expected`. Returns whether the swap happened, alongside the value held by the key
+    /// once the operation is complete. The comparison and the write are one atomic operation on
+    /// the backend, so nothing can change the value in between. A successful swap keeps the key's
+    /// expiry.
+    pub fn cas(
+        &self,
+        key: &DataType,
+        expected: &DataType,
+        new_val: &DataType,
+    ) -> (bool, Option<DataType>) {
+        let now = self.now();
+
+        let (swapped, _) = self.update_entry(key, |current| match current {
+            Some(current) if current.is_expired(now) => (Write::Keep, (false, None)),
+            Some(current) if current.value == *expected => {
+                let replaced = current.set_value(new_val.to_owned(), now);
+                self.resized(replaced, current);
+                self.accessed(current, now);
+                (Write::Keep, (true, Some(new_val.to_owned())))
+            }
+            Some(current) => (Write::Keep, (false, Some(current.value.to_owned()))),
+            None => (Write::Keep, (false, None)),
+        });
+
+        if swapped.0 {
+            self.notify(KeyEvent::Set, key);
+        }
+        swapped
+    }
+
+    /// Retrieves a value alongside its version and the time it was last written to, in
+    /// milliseconds since the UNIX epoch. All three are read atomically, so the version always
+    /// belongs to the returned value.
+    pub fn get_versioned(&self, key: &DataType) -> Option<(DataType, u64, u64)> {
+        self.read_live(key, |entry| {
+            (entry.value.to_owned(), entry.version, entry.modified_at)
+        })
+    }
+
+    /// Sets the value only if the key exists and its version is still `version`, see
+    /// `Store::version`. Returns whether the value was written, alongside the value the key held
+    /// beforehand. The version check and the write are one atomic operation on the backend.
+    pub fn set_if_version(
+        &self,
+        key: &DataType,
+        value: &DataType,
+        version: u64,
+        ttl: SetTtl,
+    ) -> (bool, Option<DataType>) {
+        let now = self.now();
+
+        let ((written, current), old) = self.update_entry(key, |current| match current {
+            Some(entry) if entry.is_expired(now) => (Write::Remove, (false, None)),
+            Some(entry) if entry.version != version => {
+                (Write::Keep, (false, Some(entry.value.to_owned())))
+            }
+            Some(entry) => {
+                let expires_at = Self::expiry_for(ttl, Some(entry), now);
+                let new = self.new_entry(value.to_owned(), expires_at, now);
+                (Write::Insert(new), (true, None))
+            }
+            None => (Write::Keep, (false, None)),
+        });
+
+        if written {
+            self.notify(KeyEvent::Set, key);
+            (true, old.map(|old| old.value))
+        } else {
+            (false, current)
+        }
+    }
+
+    /// Runs `f` against the value held by `key`, while nothing else can write to it. The value is
+    /// `None` if the key does not exist (or has expired).
+    pub fn view<R>(&self, key: &DataType, f: impl FnOnce(Option<&DataType>) -> R) -> R {
+        let now = self.now();
+        self.read_entry(key, |entry| {
+            let entry = entry.filter(|entry| !entry.is_expired(now));
+            if let Some(entry) = entry {
+                self.accessed(entry, now);
+            }
+
+            f(entry.map(|entry| &entry.value))
+        })
+    }
+
+    /// Runs `f` against the value held by `key` as one atomic operation on the backend, so that
+    /// reading and modifying the value can't be interleaved with any other write. The value is
+    /// `None` if the key does not exist (or has expired). Leaving a value in the option writes it
+    /// back, keeping the key's expiry, while leaving `None` removes the key.
+    ///
+    /// `f` returns whether it changed the value alongside its result. A value that wasn't changed
+    /// must be left in the option as it was handed over, and its entry is left untouched, so that
+    /// a failed or empty write doesn't abort a WATCH, invalidate tracking clients or charge for
+    /// memory again.
+    pub fn update<R>(
+        &self,
+        key: &DataType,
+        f: impl FnOnce(&mut Option<DataType>) -> (R, bool),
+    ) -> R {
+        let now = self.now();
+
+        self.update_entry(key, |current| match current {
+            Some(stored) => {
+                let expired = stored.is_expired(now);
+
+                // Swap a cheap placeholder in, so the value can be handed to `f` without a clone
+                let placeholder = DataType::Bool(BoopBool(false));
+                let current = std::mem::replace(&mut stored.value, placeholder);
+                let mut value = (!expired).then_some(current);
+
+                let (result, changed) = f(&mut value);
+
+                match value {
+                    Some(value) if !changed && !expired => {
+                        stored.value = value;
+                        self.accessed(stored, now);
+                        (Write::Keep, result)
+                    }
+                    Some(value) => {
+                        let replaced = stored.set_value(value, now);
+                        self.resized(replaced, stored);
+                        self.accessed(stored, now);
+                        if expired {
+                            stored.expires_at = None;
+                        }
+                        (Write::Keep, result)
+                    }
+                    None => (Write::Remove, result),
+                }
+            }
+            None => {
+                let mut value = None;
+                let (result, _) = f(&mut value);
+
+                match value {
+                    Some(value) => (Write::Insert(self.new_entry(value, None, now)), result),
+                    None => (Write::Keep, result),
+                }
+            }
+        })
+        .0
+    }
+
+    /// Sets the expiry of an existing key to `ms` milliseconds from now. Returns false if the key
+    /// does not exist.
+    pub fn expire(&self, key: &DataType, ms: u64) -> bool {
+        let now = self.now();
+
+        self.update_entry(key, |entry| match entry {
+            Some(entry) if !entry.is_expired(now) => {
+                entry.expires_at = Some(now.saturating_add(ms));
+                entry.touch(now);
+                (Write::Keep, true)
+            }
+            _ => (Write::Keep, false),
+        })
+        .0
+    }
+
+    /// Returns the amount of milliseconds until the key expires. The outer option is `None` if
+    /// the key does not exist, and the inner option is `None` if the key exists but never expires.
+    pub fn ttl(&self, key: &DataType) -> Option<Option<u64>> {
+        let now = self.now();
+        self.read_entry(key, |entry| {
+            let entry = entry.filter(|entry| !entry.is_expired(now))?;
+            Some(entry.expires_at.map(|at| at - now))
+        })
+    }
+
+    /// Removes the expiry from a key. Returns true only if the key existed and had an expiry.
+    pub fn persist(&self, key: &DataType) -> bool {
+        let now = self.now();
+
+        self.update_entry(key, |entry| match entry {
+            Some(entry) if !entry.is_expired(now) => {
+                let had_expiry = entry.expires_at.take().is_some();
+                if had_expiry {
+                    entry.touch(now);
+                }
+                (Write::Keep, had_expiry)
+            }
+            _ => (Write::Keep, false),
+        })
+        .0
+    }
+
+    /// Retrieves the values for many keys at once, in the same order as the keys were given. The
+    /// values are read as one consistent snapshot, so the result can never observe half of an
+    /// atomic `mset`.
+    pub fn mget(&self, keys: &[DataType]) -> Vec<Option<DataType>> {
+        self.view_many(keys, |values| {
+            values
+                .iter()
+                .map(|value| value.map(|v| v.to_owned()))
+                .collect()
+        })
+    }
+
+    /// Runs `f` against the values of many keys at once, in the same order as `keys`, without
+    /// copying them. Nothing can write to any of the keys until `f` returns, so the values are one
+    /// consistent snapshot.
+    pub fn view_many<R>(&self, keys: &[DataType], f: impl FnOnce(&[Option<&DataType>]) -> R) -> R {
+        let now = self.now();
+        self.read_entries(keys, |entries| {
+            let values: Vec<Option<&DataType>> = entries
+                .iter()
+                .map(|entry| {
+                    let entry = entry.filter(|entry| !entry.is_expired(now))?;
+                    self.accessed(entry, now);
+                    Some(&entry.value)
+                })
+                .collect();
+
+            f(&values)
+        })
+    }
+
+    /// Sets many key/value pairs. Each pair is written on its own, so concurrent readers may
+    /// observe some of the batch before the rest of it has been written.
+    pub fn mset(&self, pairs: &[(DataType, DataType)]) {
+        let now = self.now();
+        for (key, value) in pairs {
+            let entry = self.new_entry(value.to_owned(), None, now);
+            self.charge(key, &entry);
+            if let Some(old) = self.map.insert(key.to_owned(), entry) {
+                self.refund(key, &old);
+            }
+            self.invalidate(key);
+            self.notify(KeyEvent::Set, key);
+        }
+    }
+
+    /// Sets many key/value pairs as one atomic batch, so other readers either see the whole batch
+    /// or none of it. If a key is given more than once, the last value wins.
+    pub fn mset_atomic(&self, pairs: &[(DataType, DataType)]) {
+        let now = self.now();
+
+        // Later pairs replace earlier ones with the same key, as the backend needs distinct keys
+        let mut indices: HashMap<&DataType, usize> = HashMap::with_capacity(pairs.len());
+        let mut keys: Vec<DataType> = Vec::with_capacity(pairs.len());
+        let mut values: Vec<&DataType> = Vec::with_capacity(pairs.len());
+        for (key, value) in pairs {
+            match indices.get(key) {
+                Some(&i) => values[i] = value,
+                None => {
+                    indices.insert(key, keys.len());
+                    keys.push(key.to_owned());
+                    values.push(value);
+                }
+            }
+        }
+
+        self.update_entries(&keys, |entries| {
+            for (entry, value) in entries.iter_mut().zip(values) {
+                *entry = Some(self.new_entry(value.to_owned(), None, now));
+            }
+        });
+
+        for key in &keys {
+            self.notify(KeyEvent::Set, key);
+        }
+    }
+}
+
+/// Splits the outcome of a write to a value into what `Store::update` expects. A write that
+/// failed never changed the value, so it must leave it as it found it.
+fn written<R>(outcome: Result<(R, bool), DataType>) -> (Result<R, DataType>, bool) {
+    match outcome {
+        Ok((result, changed)) => (Ok(result), changed),
+        Err(err) => (Err(err), false),
     }
 }
 
+#[cfg(test)]
 mod test {
     #![allow(unused_imports)]
 
-    use super::Store;
+    use super::{clock::ManualClock, SetTtl, Store};
     use crate::data_type::{BoopBool, BoopError, BoopString, DataType, Int};
     use bytes::Bytes;
-    use dashmap::DashMap;
     use std::sync::Arc;
 
     #[test]
@@ -87,7 +652,7 @@ mod test {
         let key = Int::new_u8(0x00);
         let val = Int::new_u8(0x01);
 
-        let set_res = store.set(&key, &val);
+        let set_res = store.set(&key, &val, SetTtl::Clear);
         assert!(set_res.is_none());
         assert_eq!(store.get(&key).unwrap(), val);
     }
@@ -99,7 +664,7 @@ mod test {
         let key = BoopString::new_wrapped(Bytes::from_static(b"getset"));
         let val = BoopBool::new_wrapped(true);
 
-        store.0.insert(key.clone(), val.clone());
+        store.set(&key, &val, SetTtl::Clear);
         assert_eq!(store.get_set(&key, &BoopBool::new_wrapped(false)), val);
     }
 
@@ -125,10 +690,357 @@ mod test {
         let key = BoopString::new_wrapped(Bytes::from_static(b"getset"));
         let val = BoopBool::new_wrapped(true);
 
-        store.0.insert(key.clone(), val.clone());
+        store.set(&key, &val, SetTtl::Clear);
 
         assert_eq!(store.get_del(&key.clone()).unwrap(), val);
         assert!(store.get_del(&key.clone()).is_none());
         assert!(store.get(&key).is_none())
     }
+
+    #[test]
+    fn set_nx_only_writes_absent_keys() {
+        let store: Store = Store::new();
+        let key = Int::new_u8(1);
+
+        assert!(store
+            .set_nx(&key, &Int::new_u8(10), SetTtl::Clear)
+            .is_none());
+        assert_eq!(
+            store.set_nx(&key, &Int::new_u8(20), SetTtl::Clear),
+            Some(Int::new_u8(10))
+        );
+        assert_eq!(store.get(&key).unwrap(), Int::new_u8(10));
+    }
+
+    #[test]
+    fn set_xx_only_writes_present_keys() {
+        let store: Store = Store::new();
+        let key = Int::new_u8(1);
+
+        assert!(store
+            .set_xx(&key, &Int::new_u8(10), SetTtl::Clear)
+            .is_none());
+        assert!(store.get(&key).is_none());
+
+        store.set(&key, &Int::new_u8(10), SetTtl::Clear);
+        assert_eq!(
+            store.set_xx(&key, &Int::new_u8(20), SetTtl::Clear),
+            Some(Int::new_u8(10))
+        );
+        assert_eq!(store.get(&key).unwrap(), Int::new_u8(20));
+    }
+
+    #[test]
+    fn cas_swaps_only_on_match() {
+        let store: Store = Store::new();
+        let key = Int::new_u8(1);
+
+        assert_eq!(
+            store.cas(&key, &Int::new_u8(1), &Int::new_u8(2)),
+            (false, None)
+        );
+
+        store.set(&key, &Int::new_u8(1), SetTtl::Clear);
+        assert_eq!(
+            store.cas(&key, &Int::new_u16(1), &Int::new_u8(2)),
+            (false, Some(Int::new_u8(1)))
+        );
+        assert_eq!(
+            store.cas(&key, &Int::new_u8(1), &Int::new_u8(2)),
+            (true, Some(Int::new_u8(2)))
+        );
+        assert_eq!(store.get(&key).unwrap(), Int::new_u8(2));
+    }
+
+    #[test]
+    fn cas_concurrent_increments_never_lose_updates() {
+        let store: Store = Store::new();
+        let key = Int::new_u8(1);
+        store.set(&key, &Int::new_u64(0), SetTtl::Clear);
+
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let store = store.clone();
+                let key = key.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..250 {
+                        loop {
+                            let DataType::Num(Int::Large(n)) = store.get(&key).unwrap() else {
+                                unreachable!()
+                            };
+                            let (swapped, _) =
+                                store.cas(&key, &Int::new_u64(n), &Int::new_u64(n + 1));
+                            if swapped {
+                                break;
+                            }
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        for h in handles {
+            h.join().unwrap();
+        }
+
+        assert_eq!(store.get(&key).unwrap(), Int::new_u64(1000));
+    }
+
+    #[test]
+    fn versioned_get_and_set() {
+        let clock = Arc::new(ManualClock::new(1_000));
+        let store = Store::with_clock(clock.clone());
+        let key = Int::new_u8(1);
+
+        assert_eq!(store.get_versioned(&key), None);
+        assert_eq!(
+            store.set_if_version(&key, &Int::new_u8(1), 0, SetTtl::Clear),
+            (false, None)
+        );
+
+        store.set(&key, &Int::new_u8(1), SetTtl::ExpireIn(5_000));
+        let (value, version, modified_at) = store.get_versioned(&key).unwrap();
+        assert_eq!((value, modified_at), (Int::new_u8(1), 1_000));
+
+        // A stale version writes nothing
+        clock.advance(10);
+        assert_eq!(
+            store.set_if_version(&key, &Int::new_u8(2), version + 1, SetTtl::Keep),
+            (false, Some(Int::new_u8(1)))
+        );
+        assert_eq!(store.get_versioned(&key).unwrap().1, version);
+
+        assert_eq!(
+            store.set_if_version(&key, &Int::new_u8(2), version, SetTtl::Keep),
+            (true, Some(Int::new_u8(1)))
+        );
+        let (value, new_version, modified_at) = store.get_versioned(&key).unwrap();
+        assert_eq!((value, modified_at), (Int::new_u8(2), 1_010));
+        assert!(new_version > version);
+        assert_eq!(store.ttl(&key), Some(Some(4_990)));
+
+        // The version that was just used can't be used again
+        assert_eq!(
+            store.set_if_version(&key, &Int::new_u8(3), version, SetTtl::Clear),
+            (false, Some(Int::new_u8(2)))
+        );
+
+        clock.advance(5_000);
+        assert_eq!(store.get_versioned(&key), None);
+        assert_eq!(
+            store.set_if_version(&key, &Int::new_u8(3), new_version, SetTtl::Clear),
+            (false, None)
+        );
+    }
+
+    #[test]
+    fn entry_meta_data_overhead() {
+        use super::Entry;
+        use std::mem::size_of;
+
+        // The version, modification time, value size and access tracking add 24 bytes per entry,
+        // as documented on `Entry`
+        assert_eq!(
+            size_of::<Entry>(),
+            size_of::<DataType>() + size_of::<Option<u64>>() + 24
+        );
+    }
+
+    #[test]
+    fn mset_and_mget() {
+        let store: Store = Store::new();
+        let pairs: Vec<(DataType, DataType)> = (0..50)
+            .map(|i| (Int::new_u8(i), Int::new_u16(i as u16)))
+            .collect();
+
+        store.mset(&pairs);
+
+        let keys: Vec<DataType> = (0..52).map(Int::new_u8).collect();
+        let values = store.mget(&keys);
+
+        assert_eq!(values.len(), 52);
+        for (i, v) in values.iter().enumerate().take(50) {
+            assert_eq!(v.to_owned().unwrap(), Int::new_u16(i as u16));
+        }
+        assert!(values[50].is_none());
+        assert!(values[51].is_none());
+    }
+
+    #[test]
+    fn mset_atomic_last_value_wins() {
+        let store: Store = Store::new();
+        let key = Int::new_u8(1);
+
+        store.mset_atomic(&[
+            (key.clone(), BoopBool::new_wrapped(false)),
+            (Int::new_u8(2), BoopBool::new_wrapped(false)),
+            (key.clone(), BoopBool::new_wrapped(true)),
+        ]);
+
+        assert_eq!(store.get(&key).unwrap(), BoopBool::new_wrapped(true));
+        assert_eq!(
+            store.get(&Int::new_u8(2)).unwrap(),
+            BoopBool::new_wrapped(false)
+        );
+    }
+
+    #[test]
+    fn mset_atomic_is_never_partially_visible() {
+        let store: Store = Store::new();
+        let keys: Vec<DataType> = (0..64).map(Int::new_u8).collect();
+
+        let writer = {
+            let store = store.clone();
+            let keys = keys.clone();
+            std::thread::spawn(move || {
+                for round in 0..200_u16 {
+                    let pairs: Vec<(DataType, DataType)> = keys
+                        .iter()
+                        .map(|k| (k.to_owned(), Int::new_u16(round)))
+                        .collect();
+                    store.mset_atomic(&pairs);
+                }
+            })
+        };
+
+        for _ in 0..200 {
+            let values = store.mget(&keys);
+            let first = values[0].clone();
+            assert!(values.iter().all(|v| *v == first));
+        }
+
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn expired_keys_disappear_from_get() {
+        let clock = Arc::new(ManualClock::new(1_000));
+        let store = Store::with_clock(clock.clone());
+        let key = Int::new_u8(1);
+
+        store.set(&key, &Int::new_u8(1), SetTtl::ExpireIn(100));
+        assert_eq!(store.ttl(&key), Some(Some(100)));
+
+        clock.advance(99);
+        assert_eq!(store.get(&key), Some(Int::new_u8(1)));
+        assert_eq!(store.ttl(&key), Some(Some(1)));
+
+        clock.advance(1);
+        assert!(store.get(&key).is_none());
+        assert!(store.ttl(&key).is_none());
+        assert_eq!(store.len(), 0);
+    }
+
+    #[test]
+    fn expired_keys_are_treated_as_absent_by_writes() {
+        let clock = Arc::new(ManualClock::new(1_000));
+        let store = Store::with_clock(clock.clone());
+        let key = Int::new_u8(1);
+
+        store.set(&key, &Int::new_u8(1), SetTtl::ExpireIn(10));
+        clock.advance(10);
+
+        assert!(store.set_xx(&key, &Int::new_u8(2), SetTtl::Clear).is_none());
+        assert!(store.get(&key).is_none());
+
+        store.set(&key, &Int::new_u8(1), SetTtl::ExpireIn(10));
+        clock.advance(10);
+        assert!(store.set_nx(&key, &Int::new_u8(3), SetTtl::Keep).is_none());
+        assert_eq!(store.ttl(&key), Some(None));
+
+        store.set(&key, &Int::new_u8(1), SetTtl::ExpireIn(10));
+        clock.advance(10);
+        assert!(store.set(&key, &Int::new_u8(4), SetTtl::Clear).is_none());
+        assert_eq!(
+            store.mget(std::slice::from_ref(&key)),
+            vec![Some(Int::new_u8(4))]
+        );
+    }
+
+    #[test]
+    fn set_ttl_modes() {
+        let clock = Arc::new(ManualClock::new(1_000));
+        let store = Store::with_clock(clock.clone());
+        let key = Int::new_u8(1);
+
+        store.set(&key, &Int::new_u8(1), SetTtl::ExpireIn(100));
+        store.set(&key, &Int::new_u8(2), SetTtl::Keep);
+        assert_eq!(store.ttl(&key), Some(Some(100)));
+
+        store.set_xx(&key, &Int::new_u8(3), SetTtl::ExpireIn(50));
+        assert_eq!(store.ttl(&key), Some(Some(50)));
+
+        store.set(&key, &Int::new_u8(4), SetTtl::Clear);
+        assert_eq!(store.ttl(&key), Some(None));
+    }
+
+    #[test]
+    fn expire_and_persist() {
+        let clock = Arc::new(ManualClock::new(1_000));
+        let store = Store::with_clock(clock.clone());
+        let key = Int::new_u8(1);
+
+        assert!(!store.expire(&key, 10));
+        assert!(!store.persist(&key));
+
+        store.set(&key, &Int::new_u8(1), SetTtl::Clear);
+        assert!(!store.persist(&key));
+        assert!(store.expire(&key, 10));
+        assert!(store.persist(&key));
+        assert_eq!(store.ttl(&key), Some(None));
+
+        clock.advance(10);
+        assert_eq!(store.get(&key), Some(Int::new_u8(1)));
+
+        assert!(store.expire(&key, 0));
+        assert!(store.get(&key).is_none());
+    }
+
+    #[test]
+    fn update_creates_modifies_and_removes() {
+        let clock = Arc::new(ManualClock::new(1_000));
+        let store = Store::with_clock(clock.clone());
+        let key = Int::new_u8(1);
+
+        store.update(&key, |v| {
+            assert!(v.is_none());
+            *v = Some(Int::new_u8(1));
+            ((), true)
+        });
+        assert_eq!(store.ttl(&key), Some(None));
+
+        store.expire(&key, 100);
+        let seen = store.update(&key, |v| (v.replace(Int::new_u8(2)), true));
+        assert_eq!(seen, Some(Int::new_u8(1)));
+        assert_eq!(store.ttl(&key), Some(Some(100)));
+
+        store.update(&key, |v| (*v = None, true));
+        assert!(store.get(&key).is_none());
+        assert_eq!(store.len(), 0);
+
+        store.set(&key, &Int::new_u8(3), SetTtl::ExpireIn(10));
+        clock.advance(10);
+        store.update(&key, |v| {
+            assert!(v.is_none());
+            *v = Some(Int::new_u8(4));
+            ((), true)
+        });
+        assert_eq!(store.ttl(&key), Some(None));
+    }
+
+    #[test]
+    fn update_leaves_unchanged_entries_untouched() {
+        let store = Store::new();
+        let key = Int::new_u8(1);
+        store.set(&key, &Int::new_u8(1), SetTtl::Keep);
+        let version = store.version(&key);
+
+        let seen = store.update(&key, |v| (v.clone(), false));
+        assert_eq!(seen, Some(Int::new_u8(1)));
+        assert_eq!(store.version(&key), version);
+        assert_eq!(store.get(&key), Some(Int::new_u8(1)));
+
+        store.update(&key, |v| (*v = Some(Int::new_u8(2)), true));
+        assert_ne!(store.version(&key), version);
+    }
 }
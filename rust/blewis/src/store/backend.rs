@@ -0,0 +1,445 @@
+//! Backends are where a `Store` keeps its entries. The store handles everything that gives
+//! entries their meaning, such as expiry, versions, memory accounting and the data type
+//! operations, while its backend only has to hold entries by key and offer a handful of atomic
+//! operations on them. Any backend can therefore run every command.
+
+use super::{
+    hasher::{FnvBuildHasher, FxBuildHasher, HashAlgorithm},
+    notify::KeyEvent,
+    Entry, Store,
+};
+use crate::data_type::DataType;
+use std::{collections::hash_map::RandomState, hash::BuildHasher, str::FromStr, sync::Arc};
+
+#[cfg(test)]
+mod bench;
+pub mod dash;
+mod epoch;
+pub mod lock_free;
+
+pub(crate) use dash::DashMapBackend;
+pub(crate) use lock_free::LockFreeBackend;
+
+/// BackendKind picks the backend that every store of the server keeps its entries in
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum BackendKind {
+    /// A `DashMap`, whose shards each sit behind a lock, see `DashMapBackend`
+    #[default]
+    DashMap,
+    /// A hash table that readers never wait on, though writers to the same bucket take a spin
+    /// lock, see `LockFreeBackend`
+    LockFree,
+}
+
+impl FromStr for BackendKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dashmap" => Ok(BackendKind::DashMap),
+            "lock-free" => Ok(BackendKind::LockFree),
+            unknown => anyhow::bail!("unknown backend: {unknown}"),
+        }
+    }
+}
+
+impl BackendKind {
+    /// Creates a backend that hashes keys with `hasher`, with room for `capacity` entries, split
+    /// into `segments` segments, which must be a power of two greater than 1. `None` leaves the
+    /// amount of segments to the backend.
+    pub(crate) fn build(
+        self,
+        hasher: HashAlgorithm,
+        capacity: usize,
+        segments: Option<usize>,
+    ) -> Arc<dyn StorageBackend> {
+        match hasher {
+            HashAlgorithm::SipHash => self.build_with(RandomState::new(), capacity, segments),
+            HashAlgorithm::FxHash => self.build_with(FxBuildHasher::default(), capacity, segments),
+            HashAlgorithm::Fnv => self.build_with(FnvBuildHasher::default(), capacity, segments),
+        }
+    }
+
+    fn build_with<S: BuildHasher + Clone + Send + Sync + 'static>(
+        self,
+        hasher: S,
+        capacity: usize,
+        segments: Option<usize>,
+    ) -> Arc<dyn StorageBackend> {
+        match (self, segments) {
+            (BackendKind::DashMap, None) => {
+                Arc::new(DashMapBackend::with_capacity_and_hasher(capacity, hasher))
+            }
+            (BackendKind::DashMap, Some(segments)) => {
+                Arc::new(DashMapBackend::with_capacity_and_hasher_and_shard_amount(
+                    capacity, hasher, segments,
+                ))
+            }
+            (BackendKind::LockFree, None) => {
+                Arc::new(LockFreeBackend::with_capacity_and_hasher(capacity, hasher))
+            }
+            (BackendKind::LockFree, Some(segments)) => Arc::new(
+                LockFreeBackend::with_capacity_hasher_and_segments(capacity, hasher, segments),
+            ),
+        }
+    }
+}
+
+/// What `StorageBackend::update` does with the entry of a key once it has been looked at
+pub(crate) enum Write {
+    /// Leave the entry as it is, along with any changes made to it in place
+    Keep,
+    /// Remove the entry, if there is one
+    Remove,
+    /// Write a new entry in place of any existing one
+    Insert(Entry),
+}
+
+/// StorageBackend is a concurrent map from keys to entries. Every operation on a single key must
+/// be atomic, and the operations on many keys must be atomic as a whole, in that other operations
+/// see either all of their effects or none of them.
+///
+/// A backend is split into segments, which are iterated over one at a time so that walking the
+/// whole backend never has to stop every other operation at once. A key always lives in the same
+/// segment, and the amount of segments never changes.
+///
+/// Entries are handed to closures rather than returned, so that backends are free to hold a lock,
+/// or keep whatever else makes the entry safe to read, for only as long as the closure runs. The
+/// closures are trait objects so that a store can hold any backend, and each one is called
+/// exactly once, apart from those of `visit`.
+pub(crate) trait StorageBackend: Send + Sync {
+    /// The amount of entries held, including expired ones that haven't been removed yet
+    fn len(&self) -> usize;
+
+    /// Runs `f` against the entry held by `key`, if there is one. The access tracking of the
+    /// entry can be updated through its atomics, but nothing else about it can change while `f`
+    /// runs.
+    fn read(&self, key: &DataType, f: &mut dyn FnMut(Option<&Entry>));
+
+    /// Runs `f` against the entries held by every one of `keys`, in the same order, as one
+    /// consistent snapshot. Keys can be given more than once.
+    fn read_many(&self, keys: &[DataType], f: &mut dyn FnMut(&[Option<&Entry>]));
+
+    /// Runs `f` against the entry held by `key`, which it can change in place, and then writes
+    /// back what `f` asks for, all as one atomic operation. Returns the entry that was removed or
+    /// replaced, if any.
+    fn update(
+        &self,
+        key: &DataType,
+        f: &mut dyn FnMut(Option<&mut Entry>) -> Write,
+    ) -> Option<Entry>;
+
+    /// Takes the entries held by every one of `keys` out of the backend, runs `f` against them in
+    /// the same order, and writes back whichever entries `f` leaves, under the same keys, all as
+    /// one atomic operation. The keys must all be different.
+    fn update_many(&self, keys: &[DataType], f: &mut dyn FnMut(&mut [Option<Entry>]));
+
+    /// Writes an entry, returning the entry that it replaced
+    fn insert(&self, key: DataType, entry: Entry) -> Option<Entry>;
+
+    /// Removes the entry held by `key`, returning it alongside the key it was held under
+    fn remove(&self, key: &DataType) -> Option<(DataType, Entry)>;
+
+    /// The amount of segments the backend is split into, which is always a power of two
+    fn segments(&self) -> usize;
+
+    /// The amount of entries held by a segment
+    fn segment_len(&self, segment: usize) -> usize;
+
+    /// Runs `f` against the entries of a segment, starting from its `skip`th entry, until `f`
+    /// returns false or the segment has no entries left. Entries are visited in the same order
+    /// every time, as long as none are added or removed in between. Backends may let the segment
+    /// change while it is visited, in which case entries written to in the meantime may or may
+    /// not be visited.
+    fn visit(&self, segment: usize, skip: usize, f: &mut dyn FnMut(&DataType, &Entry) -> bool);
+
+    /// The position of `key` in the order that a scan walks the backend in, which never changes
+    /// for as long as the backend lives. The top bits of a key's position must be the index of
+    /// its segment, so that the scan walks the backend a segment at a time.
+    fn position(&self, key: &DataType) -> usize;
+
+    /// Removes every entry of a segment, returning them
+    fn drain(&self, segment: usize) -> Vec<(DataType, Entry)>;
+}
+
+impl Store {
+    /// Runs `f` against the entry held by `key`, see `StorageBackend::read`
+    #[inline(always)]
+    pub(super) fn read_entry<R>(&self, key: &DataType, f: impl FnOnce(Option<&Entry>) -> R) -> R {
+        let mut f = Some(f);
+        let mut result = None;
+        self.map.read(key, &mut |entry| {
+            result = f.take().map(|f| f(entry));
+        });
+        result.expect("backend should run the closure once")
+    }
+
+    /// Runs `f` against the entries held by `keys`, see `StorageBackend::read_many`
+    pub(super) fn read_entries<R>(
+        &self,
+        keys: &[DataType],
+        f: impl FnOnce(&[Option<&Entry>]) -> R,
+    ) -> R {
+        let mut f = Some(f);
+        let mut result = None;
+        self.map.read_many(keys, &mut |entries| {
+            result = f.take().map(|f| f(entries));
+        });
+        result.expect("backend should run the closure once")
+    }
+
+    /// Runs `f` against the entry held by `key` and writes back what it asks for, see
+    /// `StorageBackend::update`, accounting for the memory of whatever is written or removed and
+    /// invalidating the key if it changed. Returns what `f` returned alongside the entry that was
+    /// removed or replaced, if any.
+    #[inline(always)]
+    pub(super) fn update_entry<R>(
+        &self,
+        key: &DataType,
+        f: impl FnOnce(Option<&mut Entry>) -> (Write, R),
+    ) -> (R, Option<Entry>) {
+        let mut f = Some(f);
+        let mut result = None;
+        let mut changed = false;
+        let old = self.map.update(key, &mut |mut entry| {
+            let f = f.take().expect("backend should run the closure once");
+            // Every write to an entry in place gives it a new version
+            let version = entry.as_ref().map(|entry| entry.version);
+            let (write, r) = f(entry.as_deref_mut());
+            changed = match &write {
+                Write::Keep => entry.map(|entry| entry.version) != version,
+                Write::Remove => version.is_some(),
+                Write::Insert(_) => true,
+            };
+            if let Write::Insert(new) = &write {
+                self.charge(key, new);
+            }
+            result = Some(r);
+            write
+        });
+
+        if changed {
+            self.invalidate(key);
+        }
+
+        if let Some(old) = &old {
+            self.refund(key, old);
+            // Whatever replaced or removed an expired entry, the entry itself expired
+            if old.expires_at.is_some()
+                && self.notifies(KeyEvent::Expired)
+                && old.is_expired(self.now())
+            {
+                self.notify(KeyEvent::Expired, key);
+            }
+        }
+        (result.expect("backend should run the closure once"), old)
+    }
+
+    /// Runs `f` against the entries held by `keys`, which must all be different, and writes back
+    /// whichever it leaves, see `StorageBackend::update_many`, accounting for their memory and
+    /// invalidating every one of them
+    pub(super) fn update_entries<R>(
+        &self,
+        keys: &[DataType],
+        f: impl FnOnce(&mut [Option<Entry>]) -> R,
+    ) -> R {
+        let mut f = Some(f);
+        let mut result = None;
+        self.map.update_many(keys, &mut |entries| {
+            let f = f.take().expect("backend should run the closure once");
+            for (key, entry) in keys.iter().zip(entries.iter()) {
+                if let Some(entry) = entry {
+                    self.refund(key, entry);
+                }
+            }
+
+            result = Some(f(entries));
+
+            for (key, entry) in keys.iter().zip(entries.iter()) {
+                if let Some(entry) = entry {
+                    self.charge(key, entry);
+                }
+            }
+        });
+
+        for key in keys {
+            self.invalidate(key);
+        }
+        result.expect("backend should run the closure once")
+    }
+
+    /// Removes the entry held by `key`, if `remove` holds for it, as one atomic operation
+    #[inline(always)]
+    pub(super) fn remove_entry_if(
+        &self,
+        key: &DataType,
+        remove: impl FnOnce(&Entry) -> bool,
+    ) -> Option<Entry> {
+        self.update_entry(key, |entry| match entry {
+            Some(entry) if remove(entry) => (Write::Remove, ()),
+            _ => (Write::Keep, ()),
+        })
+        .1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        DashMapBackend, FnvBuildHasher, FxBuildHasher, LockFreeBackend, StorageBackend, Write,
+    };
+    use crate::{
+        command::{decode_command, Command},
+        data_type::{BoopArray, BoopBool, DataType, Int},
+        store::{scan::ScanFilter, Entry, SetTtl, Store},
+    };
+    use bytes::BufMut;
+    use std::{
+        collections::{hash_map::DefaultHasher, HashMap},
+        hash::{Hash, Hasher},
+        sync::{Arc, RwLock},
+    };
+
+    /// The simplest backend there is, a single hash map behind a single lock
+    #[derive(Default)]
+    struct LockedBackend(RwLock<HashMap<DataType, Entry>>);
+
+    impl StorageBackend for LockedBackend {
+        fn len(&self) -> usize {
+            self.0.read().unwrap().len()
+        }
+
+        fn read(&self, key: &DataType, f: &mut dyn FnMut(Option<&Entry>)) {
+            f(self.0.read().unwrap().get(key))
+        }
+
+        fn read_many(&self, keys: &[DataType], f: &mut dyn FnMut(&[Option<&Entry>])) {
+            let map = self.0.read().unwrap();
+            f(&keys.iter().map(|key| map.get(key)).collect::<Vec<_>>())
+        }
+
+        fn update(
+            &self,
+            key: &DataType,
+            f: &mut dyn FnMut(Option<&mut Entry>) -> Write,
+        ) -> Option<Entry> {
+            let mut map = self.0.write().unwrap();
+            match f(map.get_mut(key)) {
+                Write::Keep => None,
+                Write::Remove => map.remove(key),
+                Write::Insert(entry) => map.insert(key.to_owned(), entry),
+            }
+        }
+
+        fn update_many(&self, keys: &[DataType], f: &mut dyn FnMut(&mut [Option<Entry>])) {
+            let mut map = self.0.write().unwrap();
+            let mut entries: Vec<_> = keys.iter().map(|key| map.remove(key)).collect();
+            f(&mut entries);
+            for (key, entry) in keys.iter().zip(entries) {
+                if let Some(entry) = entry {
+                    map.insert(key.to_owned(), entry);
+                }
+            }
+        }
+
+        fn insert(&self, key: DataType, entry: Entry) -> Option<Entry> {
+            self.0.write().unwrap().insert(key, entry)
+        }
+
+        fn remove(&self, key: &DataType) -> Option<(DataType, Entry)> {
+            self.0.write().unwrap().remove_entry(key)
+        }
+
+        fn segments(&self) -> usize {
+            1
+        }
+
+        fn segment_len(&self, _: usize) -> usize {
+            self.len()
+        }
+
+        fn visit(&self, _: usize, skip: usize, f: &mut dyn FnMut(&DataType, &Entry) -> bool) {
+            for (key, entry) in self.0.read().unwrap().iter().skip(skip) {
+                if !f(key, entry) {
+                    break;
+                }
+            }
+        }
+
+        fn position(&self, key: &DataType) -> usize {
+            let mut hasher = DefaultHasher::new();
+            key.hash(&mut hasher);
+            hasher.finish() as usize
+        }
+
+        fn drain(&self, _: usize) -> Vec<(DataType, Entry)> {
+            self.0.write().unwrap().drain().collect()
+        }
+    }
+
+    #[test]
+    fn commands_run_against_any_backend() {
+        run_commands(Arc::new(LockedBackend::default()));
+        run_commands(Arc::new(DashMapBackend::with_capacity_and_hasher(
+            0,
+            FxBuildHasher::default(),
+        )));
+        run_commands(Arc::new(LockFreeBackend::with_capacity_and_hasher(
+            0,
+            FnvBuildHasher::default(),
+        )));
+    }
+
+    fn run_commands(backend: Arc<dyn StorageBackend>) {
+        let store = Store::with_backend(backend);
+        let run = |buf: &[u8]| {
+            let mut buf = bytes::BytesMut::from(buf);
+            let cmd: Command = decode_command(&mut buf).unwrap();
+            cmd.execute(store.clone())
+        };
+
+        let mut set = bytes::BytesMut::new();
+        set.put_slice(&[0x10, 0x00, 0x00, 0x01, 0x00, 0x02]); // SET 1 2
+        assert_eq!(run(&set), Some(BoopBool::new_wrapped(true)));
+        assert_eq!(run(&[0x00, 0x00, 0x01]), Some(Int::new_u8(2))); // GET 1
+        assert_eq!(
+            run(&[0x41, 0x00, 0x02, 0x03, 0x00, 0x01, 0x00, 0x07]),
+            Some(Int::new_u64(1))
+        ); // RPUSH 2 [7]
+        assert_eq!(
+            run(&[0x36, 0x00, 0x01, 0x00, 0x03]),
+            Some(BoopBool::new_wrapped(true))
+        ); // RENAME 1 3
+
+        assert_eq!(store.get(&Int::new_u8(1)), None);
+        assert_eq!(store.get(&Int::new_u8(3)), Some(Int::new_u8(2)));
+        assert_eq!(
+            store.get(&Int::new_u8(2)),
+            Some(BoopArray::new_wrapped(vec![Int::new_u8(7)]))
+        );
+
+        store.mset_atomic(&[
+            (Int::new_u8(4), Int::new_u8(4)),
+            (Int::new_u8(4), Int::new_u8(5)),
+        ]);
+        assert_eq!(store.get(&Int::new_u8(4)), Some(Int::new_u8(5)));
+
+        let (cursor, mut keys) = store.scan(0, 10, &ScanFilter::default());
+        keys.sort();
+        assert_eq!(cursor, 0);
+        assert_eq!(keys, vec![Int::new_u8(2), Int::new_u8(3), Int::new_u8(4)]);
+
+        // Keys move between stores with different backends just the same
+        let other = Store::new();
+        assert!(store.move_to(&Int::new_u8(3), &other));
+        assert_eq!(other.get(&Int::new_u8(3)), Some(Int::new_u8(2)));
+        assert!(other.move_to(&Int::new_u8(3), &store));
+
+        store.set(&Int::new_u8(5), &Int::new_u8(5), SetTtl::ExpireIn(0));
+        assert_eq!(store.get(&Int::new_u8(5)), None);
+
+        store.flush(false);
+        assert_eq!(store.len(), 0);
+        assert_eq!(store.used_memory(), 0);
+    }
+}
@@ -0,0 +1,263 @@
+//! Compares the throughput and tail latency of the backends under mixed loads of reads and
+//! writes, through the same `Store` operations that commands run, and the speed of the hashers on
+//! typical keys. The benchmarks are ignored by default, as they take a while and only mean
+//! anything in a release build:
+//!
+//! `cargo test --release backend::bench -- --ignored --nocapture`
+//!
+//! When reading the results for `LockFreeBackend`, bear in mind what they don't exercise:
+//!  - Only its reads are lock-free. Writers claim a bucket with a spin lock, so a writer that is
+//!    descheduled while holding one stalls every other writer to that bucket.
+//!  - Its array of buckets is sized when it is created and never grows. The store here is filled
+//!    to a fraction of its capacity, but one that holds many more keys has chains that grow
+//!    linearly with them.
+//!  - Every write copies the chain of its bucket and clones the entry it changes, so a write to a
+//!    large value, such as pushing onto a long list, costs as much as the whole value. The values
+//!    written here are only `VALUE_BYTES` long.
+
+use super::BackendKind;
+use crate::{
+    data_type::{BoopArray, BoopString, DataType, Int},
+    store::{
+        hasher::{FnvBuildHasher, FxBuildHasher, HashAlgorithm},
+        random, SetTtl, Store,
+    },
+};
+use bytes::Bytes;
+use std::{
+    collections::hash_map::RandomState,
+    hash::BuildHasher,
+    hint::black_box,
+    thread,
+    time::{Duration, Instant},
+};
+
+/// The amount of keys the store is filled with before every run
+const KEYS: u64 = 100_000;
+
+/// The amount of operations each thread runs
+const OPS_PER_THREAD: usize = 100_000;
+
+/// The bytes of every value written
+const VALUE_BYTES: usize = 64;
+
+/// How many of every 100 operations are reads, for each mix
+const MIXES: [(&str, u64); 3] = [("read-heavy", 95), ("mixed", 50), ("write-heavy", 10)];
+
+/// Which keys the operations go to
+#[derive(Clone, Copy)]
+enum Keys {
+    /// Every key is as likely as any other
+    Uniform,
+    /// Nine out of ten operations go to one in a hundred keys
+    Hot,
+}
+
+impl Keys {
+    fn name(self) -> &'static str {
+        match self {
+            Keys::Uniform => "uniform",
+            Keys::Hot => "hot",
+        }
+    }
+
+    fn pick(self) -> DataType {
+        let n = match self {
+            Keys::Hot if !random().is_multiple_of(10) => random() % (KEYS / 100),
+            _ => random() % KEYS,
+        };
+        key(n)
+    }
+}
+
+fn key(n: u64) -> DataType {
+    BoopString::new_wrapped(Bytes::from(format!("key:{n}")))
+}
+
+fn value() -> DataType {
+    BoopString::new_wrapped(Bytes::from(vec![b'v'; VALUE_BYTES]))
+}
+
+/// The hashers that are compared, each alongside its name
+const HASHERS: [(&str, HashAlgorithm); 3] = [
+    ("siphash", HashAlgorithm::SipHash),
+    ("fxhash", HashAlgorithm::FxHash),
+    ("fnv", HashAlgorithm::Fnv),
+];
+
+/// Fills a store on `backend`, hashing with `hasher`, with every key
+fn filled(backend: BackendKind, hasher: HashAlgorithm) -> Store {
+    let store = Store::with_backend(backend.build(hasher, KEYS as usize, None));
+    let value = value();
+    for n in 0..KEYS {
+        store.set(&key(n), &value, SetTtl::Clear);
+    }
+    store
+}
+
+/// Runs `OPS_PER_THREAD` operations on every one of `threads` threads, returning how long they
+/// took along with the sorted latencies of every operation
+fn run_threads(store: &Store, keys: Keys, reads: u64, threads: usize) -> (Duration, Vec<Duration>) {
+    let start = Instant::now();
+    let mut latencies: Vec<Duration> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..threads)
+            .map(|_| scope.spawn(|| run(store, keys, reads, OPS_PER_THREAD)))
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect()
+    });
+    let elapsed = start.elapsed();
+    latencies.sort_unstable();
+    (elapsed, latencies)
+}
+
+/// Runs `ops` operations, of which `reads` out of every 100 are GETs and the rest are split
+/// between SET, GETSET and GETDEL, returning the latency of each
+fn run(store: &Store, keys: Keys, reads: u64, ops: usize) -> Vec<Duration> {
+    let value = value();
+    let mut latencies = Vec::with_capacity(ops);
+
+    for _ in 0..ops {
+        let key = keys.pick();
+        let op = random() % 100;
+        let start = Instant::now();
+        if op < reads {
+            store.get(&key);
+        } else {
+            match op % 10 {
+                0 => {
+                    store.get_del(&key);
+                }
+                1..=3 => {
+                    store.get_set(&key, &value);
+                }
+                _ => {
+                    store.set(&key, &value, SetTtl::Clear);
+                }
+            }
+        }
+        latencies.push(start.elapsed());
+    }
+
+    latencies
+}
+
+/// The latency below which `percentile` percent of operations finished
+fn percentile(sorted: &[Duration], percentile: f64) -> Duration {
+    let i = ((sorted.len() as f64 * percentile / 100.0) as usize).min(sorted.len() - 1);
+    sorted[i]
+}
+
+#[test]
+#[ignore]
+fn compare_backends() {
+    let cpus = thread::available_parallelism().map_or(4, |n| n.get());
+    let mut threads = vec![1, 4, cpus, cpus * 2];
+    threads.sort_unstable();
+    threads.dedup();
+
+    println!(
+        "{:<10} {:<12} {:<8} {:>7} {:>12} {:>9} {:>9} {:>9} {:>9}",
+        "backend", "mix", "keys", "threads", "ops/s", "p50", "p99", "p99.9", "max"
+    );
+
+    for (mix, reads) in MIXES {
+        for keys in [Keys::Uniform, Keys::Hot] {
+            for &threads in &threads {
+                for backend in [BackendKind::DashMap, BackendKind::LockFree] {
+                    let store = filled(backend, HashAlgorithm::SipHash);
+                    let (elapsed, latencies) = run_threads(&store, keys, reads, threads);
+
+                    println!(
+                        "{:<10} {:<12} {:<8} {:>7} {:>12.0} {:>9?} {:>9?} {:>9?} {:>9?}",
+                        format!("{backend:?}"),
+                        mix,
+                        keys.name(),
+                        threads,
+                        latencies.len() as f64 / elapsed.as_secs_f64(),
+                        percentile(&latencies, 50.0),
+                        percentile(&latencies, 99.0),
+                        percentile(&latencies, 99.9),
+                        latencies[latencies.len() - 1],
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Nanoseconds per hash of every key
+fn time_hashes(hasher: &impl BuildHasher, keys: &[DataType]) -> f64 {
+    const ROUNDS: usize = 100;
+
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        for key in keys {
+            black_box(hasher.hash_one(black_box(key)));
+        }
+    }
+    start.elapsed().as_nanos() as f64 / (ROUNDS * keys.len()) as f64
+}
+
+#[test]
+#[ignore]
+fn compare_hashers() {
+    let kinds: [(&str, Vec<DataType>); 4] = [
+        (
+            "int",
+            (0..10_000).map(|n| Int::new_u64(n * 7_919)).collect(),
+        ),
+        ("short string", (0..10_000).map(key).collect()),
+        (
+            "long string",
+            (0..10_000)
+                .map(|n| BoopString::new_wrapped(Bytes::from(format!("{n:0>64}"))))
+                .collect(),
+        ),
+        (
+            "array",
+            (0..10_000)
+                .map(|n| BoopArray::new_wrapped(vec![Int::new_u8(1), key(n), Int::new_u64(n)]))
+                .collect(),
+        ),
+    ];
+
+    println!(
+        "{:<14} {:>12} {:>12} {:>12}",
+        "key", "siphash", "fxhash", "fnv"
+    );
+    for (kind, keys) in &kinds {
+        println!(
+            "{:<14} {:>10.1}ns {:>10.1}ns {:>10.1}ns",
+            kind,
+            time_hashes(&RandomState::new(), keys),
+            time_hashes(&FxBuildHasher::default(), keys),
+            time_hashes(&FnvBuildHasher::default(), keys),
+        );
+    }
+
+    let threads = thread::available_parallelism().map_or(4, |n| n.get());
+    println!(
+        "\n{:<10} {:<10} {:<12} {:>12} {:>9} {:>9}",
+        "backend", "hasher", "mix", "ops/s", "p50", "p99"
+    );
+    for backend in [BackendKind::DashMap, BackendKind::LockFree] {
+        for (mix, reads) in MIXES {
+            for (name, hasher) in HASHERS {
+                let store = filled(backend, hasher);
+                let (elapsed, latencies) = run_threads(&store, Keys::Uniform, reads, threads);
+                println!(
+                    "{:<10} {:<10} {:<12} {:>12.0} {:>9?} {:>9?}",
+                    format!("{backend:?}"),
+                    name,
+                    mix,
+                    latencies.len() as f64 / elapsed.as_secs_f64(),
+                    percentile(&latencies, 50.0),
+                    percentile(&latencies, 99.0),
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,164 @@
+use super::{StorageBackend, Write};
+use crate::{data_type::DataType, store::Entry};
+use dashmap::{mapref::entry::Entry as MapEntry, DashMap, SharedValue};
+use std::{collections::hash_map::RandomState, hash::BuildHasher};
+
+/// DashMapBackend keeps entries in a `DashMap`, which is a concurrent hashmap based on Google's
+/// SwissTable, split into shards that each sit behind their own RwLock. Each shard is a segment
+/// of the backend. Keys are hashed with `S`.
+pub(crate) struct DashMapBackend<S = RandomState> {
+    map: DashMap<DataType, Entry, S>,
+}
+
+impl<S: BuildHasher + Clone> DashMapBackend<S> {
+    pub fn with_capacity_and_hasher(cap: usize, hasher: S) -> Self {
+        DashMapBackend {
+            map: DashMap::with_capacity_and_hasher(cap, hasher),
+        }
+    }
+
+    /// The shard amount must be a power of two greater than 1, or this will panic
+    pub fn with_capacity_and_hasher_and_shard_amount(
+        cap: usize,
+        hasher: S,
+        shard_amount: usize,
+    ) -> Self {
+        DashMapBackend {
+            map: DashMap::with_capacity_and_hasher_and_shard_amount(cap, hasher, shard_amount),
+        }
+    }
+
+    /// Returns the sorted, deduplicated indices of the shards which the given keys live in
+    fn shard_ids(&self, keys: &[DataType]) -> Vec<usize> {
+        let mut ids: Vec<usize> = keys.iter().map(|k| self.map.determine_map(k)).collect();
+        ids.sort_unstable();
+        ids.dedup();
+        ids
+    }
+
+    /// The index into `shard_ids` of the shard that `key` lives in
+    #[inline(always)]
+    fn locked(&self, shard_ids: &[usize], key: &DataType) -> usize {
+        shard_ids
+            .binary_search(&self.map.determine_map(key))
+            .expect("shard of key should be locked")
+    }
+}
+
+impl<S: BuildHasher + Clone + Send + Sync> StorageBackend for DashMapBackend<S> {
+    fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    #[inline(always)]
+    fn read(&self, key: &DataType, f: &mut dyn FnMut(Option<&Entry>)) {
+        f(self.map.get(key).as_deref())
+    }
+
+    fn read_many(&self, keys: &[DataType], f: &mut dyn FnMut(&[Option<&Entry>])) {
+        let shard_ids = self.shard_ids(keys);
+        let shards = self.map.shards();
+
+        // Locks are always taken in ascending shard order so that two batch operations can never
+        // deadlock each other
+        let guards: Vec<_> = shard_ids.iter().map(|i| shards[*i].read()).collect();
+
+        let entries: Vec<Option<&Entry>> = keys
+            .iter()
+            .map(|key| {
+                guards[self.locked(&shard_ids, key)]
+                    .get(key)
+                    .map(SharedValue::get)
+            })
+            .collect();
+
+        f(&entries)
+    }
+
+    #[inline(always)]
+    fn update(
+        &self,
+        key: &DataType,
+        f: &mut dyn FnMut(Option<&mut Entry>) -> Write,
+    ) -> Option<Entry> {
+        match self.map.entry(key.to_owned()) {
+            MapEntry::Occupied(mut entry) => match f(Some(entry.get_mut())) {
+                Write::Keep => None,
+                Write::Remove => Some(entry.remove()),
+                Write::Insert(new) => Some(entry.insert(new)),
+            },
+            MapEntry::Vacant(entry) => {
+                if let Write::Insert(new) = f(None) {
+                    entry.insert(new);
+                }
+                None
+            }
+        }
+    }
+
+    fn update_many(&self, keys: &[DataType], f: &mut dyn FnMut(&mut [Option<Entry>])) {
+        let shard_ids = self.shard_ids(keys);
+        let shards = self.map.shards();
+
+        let mut guards: Vec<_> = shard_ids.iter().map(|i| shards[*i].write()).collect();
+
+        let mut entries: Vec<Option<Entry>> = keys
+            .iter()
+            .map(|key| {
+                guards[self.locked(&shard_ids, key)]
+                    .remove(key)
+                    .map(SharedValue::into_inner)
+            })
+            .collect();
+
+        f(&mut entries);
+
+        for (key, entry) in keys.iter().zip(entries) {
+            if let Some(entry) = entry {
+                guards[self.locked(&shard_ids, key)]
+                    .insert(key.to_owned(), SharedValue::new(entry));
+            }
+        }
+    }
+
+    #[inline(always)]
+    fn insert(&self, key: DataType, entry: Entry) -> Option<Entry> {
+        self.map.insert(key, entry)
+    }
+
+    #[inline(always)]
+    fn remove(&self, key: &DataType) -> Option<(DataType, Entry)> {
+        self.map.remove(key)
+    }
+
+    fn segments(&self) -> usize {
+        self.map.shards().len()
+    }
+
+    fn segment_len(&self, segment: usize) -> usize {
+        self.map.shards()[segment].read().len()
+    }
+
+    fn visit(&self, segment: usize, skip: usize, f: &mut dyn FnMut(&DataType, &Entry) -> bool) {
+        let guard = self.map.shards()[segment].read();
+        for (key, entry) in guard.iter().skip(skip) {
+            if !f(key, entry.get()) {
+                break;
+            }
+        }
+    }
+
+    /// The key's hash rotated so that the bits DashMap uses to pick a shard come first
+    #[inline(always)]
+    fn position(&self, key: &DataType) -> usize {
+        self.map.hash_usize(key).rotate_left(7)
+    }
+
+    fn drain(&self, segment: usize) -> Vec<(DataType, Entry)> {
+        self.map.shards()[segment]
+            .write()
+            .drain()
+            .map(|(key, entry)| (key, entry.into_inner()))
+            .collect()
+    }
+}
@@ -0,0 +1,394 @@
+//! Epoch based memory reclamation, which lets lock-free structures free what they unlink without
+//! any reader ever having to wait.
+//!
+//! A thread pins itself before reading a shared structure, announcing the global epoch it saw,
+//! and unpins once it holds no more references into it. Anything unlinked from a structure is
+//! retired rather than freed, tagged with the epoch it was retired in. The global epoch only
+//! advances once every pinned thread has seen the current one, so once it has advanced twice past
+//! a retired object's epoch, no thread can still be reading the object and it is freed.
+//!
+//! Retired objects are kept in a bag per thread, which is collected every so often as the thread
+//! retires more. The bag of a thread that exits is handed over to whichever thread collects next.
+
+use std::{
+    cell::{Cell, RefCell},
+    marker::PhantomData,
+    ptr,
+    sync::{
+        atomic::{fence, AtomicBool, AtomicPtr, AtomicUsize, Ordering},
+        Mutex, PoisonError,
+    },
+};
+
+/// How many objects a thread retires between attempts to advance the epoch and free its bag
+const COLLECT_EVERY: usize = 64;
+
+/// Set in a participant's state while it is pinned
+const PINNED: usize = 1;
+
+static EPOCH: AtomicUsize = AtomicUsize::new(0);
+
+/// Every participant that has ever been registered. Participants are never freed, but are reused
+/// once the thread they belonged to exits, so there are only ever as many as there have been
+/// threads alive at once.
+static PARTICIPANTS: AtomicPtr<Participant> = AtomicPtr::new(ptr::null_mut());
+
+/// Retired objects left behind by threads that have exited
+static ORPHANS: Mutex<Vec<Garbage>> = Mutex::new(Vec::new());
+
+/// Participant is what a thread announces the epoch it is pinned in through
+struct Participant {
+    /// The epoch the participant is pinned in, shifted left by one, with `PINNED` set while it is
+    /// pinned
+    state: AtomicUsize,
+    in_use: AtomicBool,
+    /// The next participant in `PARTICIPANTS`, which never changes once registered
+    next: *const Participant,
+}
+
+// The only raw pointer points to another participant, which lives forever
+unsafe impl Sync for Participant {}
+
+impl Participant {
+    /// Claims a participant that is no longer in use, or registers a new one if there are none
+    fn claim() -> &'static Participant {
+        let mut current = PARTICIPANTS.load(Ordering::Acquire) as *const Participant;
+        // SAFETY: participants are never freed
+        while let Some(participant) = unsafe { current.as_ref() } {
+            if participant
+                .in_use
+                .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+            {
+                return participant;
+            }
+            current = participant.next;
+        }
+
+        let new = Box::into_raw(Box::new(Participant {
+            state: AtomicUsize::new(0),
+            in_use: AtomicBool::new(true),
+            next: ptr::null(),
+        }));
+        let mut head = PARTICIPANTS.load(Ordering::Relaxed);
+        loop {
+            // SAFETY: the participant isn't shared until the exchange succeeds
+            unsafe { (*new).next = head };
+            match PARTICIPANTS.compare_exchange_weak(
+                head,
+                new,
+                Ordering::Release,
+                Ordering::Relaxed,
+            ) {
+                // SAFETY: participants are never freed
+                Ok(_) => return unsafe { &*new },
+                Err(actual) => head = actual,
+            }
+        }
+    }
+
+    #[inline(always)]
+    fn pin(&self) {
+        let epoch = EPOCH.load(Ordering::Relaxed);
+        self.state.store(epoch << 1 | PINNED, Ordering::Relaxed);
+        // The announcement must be visible before anything is read through the pin
+        fence(Ordering::SeqCst);
+    }
+
+    #[inline(always)]
+    fn unpin(&self) {
+        self.state.store(0, Ordering::Release);
+    }
+
+    fn release(&self) {
+        self.unpin();
+        self.in_use.store(false, Ordering::Release);
+    }
+
+    fn all() -> impl Iterator<Item = &'static Participant> {
+        let mut current = PARTICIPANTS.load(Ordering::Acquire) as *const Participant;
+        std::iter::from_fn(move || {
+            // SAFETY: participants are never freed
+            let participant = unsafe { current.as_ref() }?;
+            current = participant.next;
+            Some(participant)
+        })
+    }
+}
+
+/// An object that has been retired, along with how to free it
+struct Garbage {
+    epoch: usize,
+    ptr: *mut (),
+    free: unsafe fn(*mut ()),
+}
+
+// Only objects that are `Send` are retired, see `Guard::retire`
+unsafe impl Send for Garbage {}
+
+impl Garbage {
+    /// Whether no thread can still be reading the object once the global epoch is `epoch`
+    #[inline(always)]
+    fn is_free(&self, epoch: usize) -> bool {
+        self.epoch + 2 <= epoch
+    }
+}
+
+/// Local is a thread's participant and its bag of retired objects
+struct Local {
+    participant: &'static Participant,
+    /// How many guards the thread holds, as pins nest
+    pins: Cell<usize>,
+    bag: RefCell<Vec<Garbage>>,
+    retired: Cell<usize>,
+}
+
+impl Drop for Local {
+    fn drop(&mut self) {
+        let bag = std::mem::take(self.bag.get_mut());
+        ORPHANS
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .extend(bag);
+        self.participant.release();
+    }
+}
+
+thread_local! {
+    static LOCAL: Local = Local {
+        participant: Participant::claim(),
+        pins: Cell::new(0),
+        bag: RefCell::new(Vec::new()),
+        retired: Cell::new(0),
+    };
+}
+
+/// Guard keeps the thread pinned for as long as it lives, so that nothing it reads from a shared
+/// structure is freed under it. Guards can't be sent to other threads.
+pub(crate) struct Guard {
+    participant: &'static Participant,
+    /// Whether the participant was claimed for this guard alone, which is only the case while the
+    /// thread's own participant is being torn down
+    owned: bool,
+    _not_send: PhantomData<*mut ()>,
+}
+
+/// Pins the current thread until the returned guard is dropped. Pins nest, so a thread that is
+/// already pinned stays pinned in the epoch it was first pinned in.
+pub(crate) fn pin() -> Guard {
+    LOCAL
+        .try_with(|local| {
+            let pins = local.pins.get();
+            if pins == 0 {
+                local.participant.pin();
+            }
+            local.pins.set(pins + 1);
+
+            Guard {
+                participant: local.participant,
+                owned: false,
+                _not_send: PhantomData,
+            }
+        })
+        .unwrap_or_else(|_| {
+            let participant = Participant::claim();
+            participant.pin();
+            Guard {
+                participant,
+                owned: true,
+                _not_send: PhantomData,
+            }
+        })
+}
+
+impl Guard {
+    /// Frees `ptr`, which must have come from `Box::into_raw`, once no thread can still be
+    /// reading it.
+    ///
+    /// # Safety
+    ///
+    /// The object must already be unreachable for any thread that pins itself from now on, and
+    /// must not be retired more than once.
+    pub unsafe fn retire<T: Send>(&self, ptr: *mut T) {
+        unsafe fn free<T>(ptr: *mut ()) {
+            drop(Box::from_raw(ptr as *mut T));
+        }
+
+        // The epoch must be read after the object was unlinked, or it could be freed too early
+        fence(Ordering::SeqCst);
+        let mut garbage = Some(Garbage {
+            epoch: EPOCH.load(Ordering::Relaxed),
+            ptr: ptr as *mut (),
+            free: free::<T>,
+        });
+
+        let _ = LOCAL.try_with(|local| {
+            local.bag.borrow_mut().extend(garbage.take());
+            let retired = local.retired.get() + 1;
+            local.retired.set(retired);
+            if retired.is_multiple_of(COLLECT_EVERY) {
+                collect(local);
+            }
+        });
+
+        if let Some(garbage) = garbage {
+            ORPHANS
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .push(garbage);
+        }
+    }
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        if self.owned {
+            self.participant.release();
+            return;
+        }
+
+        let _ = LOCAL.try_with(|local| {
+            let pins = local.pins.get() - 1;
+            local.pins.set(pins);
+            if pins == 0 {
+                local.participant.unpin();
+            }
+        });
+    }
+}
+
+/// Advances the global epoch if every pinned participant has seen the current one, returning the
+/// global epoch either way
+fn try_advance() -> usize {
+    let epoch = EPOCH.load(Ordering::Relaxed);
+    fence(Ordering::SeqCst);
+
+    let behind = Participant::all().any(|participant| {
+        let state = participant.state.load(Ordering::Relaxed);
+        state & PINNED != 0 && state >> 1 != epoch
+    });
+    if behind {
+        return epoch;
+    }
+
+    fence(Ordering::Acquire);
+    match EPOCH.compare_exchange(epoch, epoch + 1, Ordering::SeqCst, Ordering::Relaxed) {
+        Ok(_) => epoch + 1,
+        Err(actual) => actual,
+    }
+}
+
+/// Frees whatever in the thread's bag, and the orphans if nobody else is freeing them, that no
+/// thread can still be reading
+fn collect(local: &Local) {
+    let epoch = try_advance();
+
+    let free = |bag: &mut Vec<Garbage>| -> Vec<Garbage> {
+        let mut free = Vec::new();
+        let mut i = 0;
+        while i < bag.len() {
+            if bag[i].is_free(epoch) {
+                free.push(bag.swap_remove(i));
+            } else {
+                i += 1;
+            }
+        }
+        free
+    };
+
+    // Objects are freed once the bags are no longer borrowed, in case freeing one retires more
+    let mut ready = free(&mut local.bag.borrow_mut());
+    if let Ok(mut orphans) = ORPHANS.try_lock() {
+        ready.extend(free(&mut orphans));
+    }
+
+    for garbage in ready {
+        // SAFETY: the object was retired through `Guard::retire`, and no thread can be reading it
+        unsafe { (garbage.free)(garbage.ptr) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{collect, pin, LOCAL};
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            mpsc, Arc,
+        },
+        thread,
+        time::Duration,
+    };
+
+    /// Counts how many times it is dropped
+    struct Tracked(Arc<AtomicUsize>);
+
+    impl Drop for Tracked {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn retire(dropped: &Arc<AtomicUsize>) {
+        let guard = pin();
+        // SAFETY: the object was never shared
+        unsafe { guard.retire(Box::into_raw(Box::new(Tracked(dropped.clone())))) };
+    }
+
+    /// Collects the current thread's bag until `done` holds, as other tests' pins can hold the
+    /// epoch back for a while
+    fn collect_until(done: impl Fn() -> bool) -> bool {
+        for _ in 0..1_000 {
+            LOCAL.with(collect);
+            if done() {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        false
+    }
+
+    #[test]
+    fn retired_objects_are_freed_once_unpinned() {
+        let dropped = Arc::new(AtomicUsize::new(0));
+        for _ in 0..10 {
+            retire(&dropped);
+        }
+
+        assert!(collect_until(|| dropped.load(Ordering::SeqCst) == 10));
+    }
+
+    #[test]
+    fn pinned_threads_hold_objects_back() {
+        let dropped = Arc::new(AtomicUsize::new(0));
+        let (pinned_tx, pinned_rx) = mpsc::channel();
+        let (unpin_tx, unpin_rx) = mpsc::channel::<()>();
+
+        let reader = thread::spawn(move || {
+            let _guard = pin();
+            pinned_tx.send(()).unwrap();
+            unpin_rx.recv().unwrap();
+        });
+        pinned_rx.recv().unwrap();
+
+        retire(&dropped);
+        for _ in 0..100 {
+            LOCAL.with(collect);
+        }
+        assert_eq!(dropped.load(Ordering::SeqCst), 0);
+
+        unpin_tx.send(()).unwrap();
+        reader.join().unwrap();
+        assert!(collect_until(|| dropped.load(Ordering::SeqCst) == 1));
+    }
+
+    #[test]
+    fn pins_nest() {
+        let outer = pin();
+        let inner = pin();
+        drop(inner);
+        assert!(LOCAL.with(|local| local.pins.get()) == 1);
+        drop(outer);
+        assert!(LOCAL.with(|local| local.pins.get()) == 0);
+    }
+}
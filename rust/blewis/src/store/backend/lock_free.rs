@@ -0,0 +1,775 @@
+//! A backend whose reads never wait on anything, though its writes do: despite the name, only
+//! reads are lock-free, see the limits below and in `bench`. Keys are hashed into a fixed array
+//! of buckets,
+//! each of which points to an immutable chain of nodes, and every write publishes a new chain in
+//! place of the old one. Readers only ever load a bucket's pointer and walk the chain it points
+//! to, while whatever is unlinked is freed through the epochs of `epoch` once no reader can still
+//! be walking it.
+//!
+//! Writes run arbitrary closures, which must run exactly once and must see the entry as it is,
+//! so they can't simply be retried when they race. Instead a writer claims the bucket by tagging
+//! its pointer, and writers to the same bucket take turns while readers carry on. The claim is a
+//! spin lock on the bucket, so a writer that is descheduled while it holds one stalls every other
+//! writer to that bucket until it runs again. An entry is
+//! copied before it is handed to a write, so that readers of the old chain keep seeing the old
+//! entry, which makes writes to large values cost as much as cloning them.
+//!
+//! Writes to many keys claim all of their buckets, in ascending order so that two of them can't
+//! wait on each other, and then point every bucket to a shared batch, which holds both the old
+//! and the new chain of each bucket. Readers follow whichever of the two the batch says, so
+//! committing the batch flips every bucket to its new chain at once.
+//!
+//! The array of buckets never grows, so a backend that holds many more keys than it was created
+//! with room for ends up with long chains, which every read and write of their keys walks. Every
+//! write also copies the whole chain of its bucket.
+
+use super::{
+    epoch::{self, Guard},
+    StorageBackend, Write,
+};
+use crate::{data_type::DataType, store::Entry};
+use std::{
+    collections::hash_map::RandomState,
+    hash::BuildHasher,
+    hint, mem,
+    ops::Range,
+    sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering},
+    thread,
+};
+
+/// The least amount of buckets a backend is created with
+const DEFAULT_BUCKETS: usize = 1 << 14;
+
+/// The amount of segments a backend is split into unless told otherwise
+const DEFAULT_SEGMENTS: usize = 64;
+
+/// Set on a bucket's pointer while a writer has claimed the bucket
+const CLAIMED: usize = 0b01;
+/// Set on a bucket's pointer while it points to a `Batch` rather than a `Chain`
+const BATCH: usize = 0b10;
+const TAGS: usize = CLAIMED | BATCH;
+
+/// How many times a writer spins on a claimed bucket before yielding its thread
+const SPINS: u32 = 64;
+
+struct Node {
+    hash: u64,
+    key: DataType,
+    entry: Entry,
+}
+
+/// Chain holds the nodes of a bucket, and never changes once published. Every chain is a
+/// separate allocation, empty or not, so that a bucket never points to the same chain twice and
+/// `read_many` can tell whether a bucket has changed by its pointer alone.
+struct Chain {
+    nodes: Box<[*mut Node]>,
+}
+
+// Chains only point to nodes, which hold nothing but keys and entries
+unsafe impl Send for Chain {}
+
+/// Batch is what the buckets written to by `update_many` point to while it publishes their new
+/// chains
+struct Batch {
+    committed: AtomicBool,
+    /// The index, old chain and new chain of every bucket, sorted by index
+    buckets: Vec<(usize, *mut Chain, *mut Chain)>,
+}
+
+unsafe impl Send for Batch {}
+
+/// A counter alone on its cache line, so that writers to different segments don't contend
+#[repr(align(64))]
+#[derive(Default)]
+struct Counter(AtomicUsize);
+
+/// Claim is a writer's hold on a bucket, which is given up unchanged if it is dropped
+struct Claim<'a> {
+    head: &'a AtomicUsize,
+    /// The chain the bucket pointed to when it was claimed
+    chain: *mut Chain,
+}
+
+impl Claim<'_> {
+    /// Points the bucket to a new chain, which gives up the claim
+    fn publish(self, chain: *mut Chain) {
+        self.head.store(chain as usize, Ordering::Release);
+        mem::forget(self);
+    }
+}
+
+impl Drop for Claim<'_> {
+    fn drop(&mut self) {
+        self.head.store(self.chain as usize, Ordering::Release);
+    }
+}
+
+/// LockFreeBackend keeps entries in a hash table that readers never wait on, while writers to the
+/// same bucket wait on each other, see the module docs. Each segment is a range of buckets. Keys are hashed with `S`.
+pub(crate) struct LockFreeBackend<S = RandomState> {
+    /// The pointer to each bucket's chain, or batch, along with its tags
+    buckets: Box<[AtomicUsize]>,
+    bucket_bits: u32,
+    segment_bits: u32,
+    lens: Box<[Counter]>,
+    hasher: S,
+}
+
+impl<S: BuildHasher> LockFreeBackend<S> {
+    pub fn with_capacity_and_hasher(cap: usize, hasher: S) -> Self {
+        Self::with_capacity_hasher_and_segments(cap, hasher, DEFAULT_SEGMENTS)
+    }
+
+    /// The amount of segments must be a power of two greater than 1, or this will panic
+    pub fn with_capacity_hasher_and_segments(cap: usize, hasher: S, segments: usize) -> Self {
+        assert!(
+            segments > 1 && segments.is_power_of_two(),
+            "segments should be a power of two greater than 1"
+        );
+
+        let buckets = cap.next_power_of_two().max(DEFAULT_BUCKETS).max(segments);
+        LockFreeBackend {
+            buckets: (0..buckets).map(|_| AtomicUsize::new(0)).collect(),
+            bucket_bits: buckets.trailing_zeros(),
+            segment_bits: segments.trailing_zeros(),
+            lens: (0..segments).map(|_| Counter::default()).collect(),
+            hasher,
+        }
+    }
+
+    #[inline(always)]
+    fn hash(&self, key: &DataType) -> u64 {
+        self.hasher.hash_one(key)
+    }
+
+    /// The bucket of a hash, which is picked by its top bits
+    #[inline(always)]
+    fn bucket(&self, hash: u64) -> usize {
+        (hash >> (u64::BITS - self.bucket_bits)) as usize
+    }
+
+    #[inline(always)]
+    fn segment(&self, bucket: usize) -> usize {
+        bucket >> (self.bucket_bits - self.segment_bits)
+    }
+
+    fn segment_buckets(&self, segment: usize) -> Range<usize> {
+        let shift = self.bucket_bits - self.segment_bits;
+        segment << shift..(segment + 1) << shift
+    }
+
+    /// The chain a bucket points to, as readers see it
+    #[inline(always)]
+    fn chain(&self, bucket: usize) -> *mut Chain {
+        let head = self.buckets[bucket].load(Ordering::Acquire);
+        if head & BATCH == 0 {
+            return (head & !TAGS) as *mut Chain;
+        }
+
+        // SAFETY: batches are only retired once no bucket points to them
+        let batch = unsafe { &*((head & !TAGS) as *const Batch) };
+        let i = batch
+            .buckets
+            .binary_search_by_key(&bucket, |(bucket, ..)| *bucket)
+            .expect("batch should hold the buckets that point to it");
+        let (_, old, new) = batch.buckets[i];
+        if batch.committed.load(Ordering::Acquire) {
+            new
+        } else {
+            old
+        }
+    }
+
+    /// Waits until the bucket can be claimed, and claims it
+    fn claim(&self, bucket: usize) -> Claim<'_> {
+        let head = &self.buckets[bucket];
+        let mut spins = 0;
+        loop {
+            let current = head.load(Ordering::Relaxed);
+            if current & CLAIMED == 0
+                && head
+                    .compare_exchange_weak(
+                        current,
+                        current | CLAIMED,
+                        Ordering::Acquire,
+                        Ordering::Relaxed,
+                    )
+                    .is_ok()
+            {
+                return Claim {
+                    head,
+                    chain: current as *mut Chain,
+                };
+            }
+
+            if spins < SPINS {
+                spins += 1;
+                hint::spin_loop();
+            } else {
+                thread::yield_now();
+            }
+        }
+    }
+
+    /// Points a claimed bucket to a new chain holding `nodes`, and retires its old chain
+    fn publish(&self, claim: Claim<'_>, nodes: Vec<*mut Node>, guard: &Guard) {
+        let old = claim.chain;
+        claim.publish(new_chain(nodes));
+        // SAFETY: the old chain is no longer reachable from the bucket
+        unsafe { retire_chain(guard, old) };
+    }
+}
+
+impl Default for LockFreeBackend {
+    fn default() -> Self {
+        Self::with_capacity_and_hasher(0, RandomState::new())
+    }
+}
+
+impl<S> Drop for LockFreeBackend<S> {
+    fn drop(&mut self) {
+        for head in self.buckets.iter_mut() {
+            let chain = (*head.get_mut() & !TAGS) as *mut Chain;
+            if chain.is_null() {
+                continue;
+            }
+
+            // SAFETY: nothing else can hold a reference into the backend while it is dropped, and
+            // the nodes of a published chain are never retired
+            unsafe {
+                let chain = Box::from_raw(chain);
+                for node in chain.nodes.iter() {
+                    drop(Box::from_raw(*node));
+                }
+            }
+        }
+    }
+}
+
+#[inline(always)]
+fn new_chain(nodes: Vec<*mut Node>) -> *mut Chain {
+    Box::into_raw(Box::new(Chain {
+        nodes: nodes.into_boxed_slice(),
+    }))
+}
+
+#[inline(always)]
+fn new_node(hash: u64, key: DataType, entry: Entry) -> *mut Node {
+    Box::into_raw(Box::new(Node { hash, key, entry }))
+}
+
+/// The nodes of a chain, of which a null chain has none
+///
+/// # Safety
+///
+/// The chain must not be freed while the nodes are in use
+#[inline(always)]
+unsafe fn nodes<'a>(chain: *const Chain) -> &'a [*mut Node] {
+    chain.as_ref().map_or(&[], |chain| &chain.nodes)
+}
+
+/// The index of the node of `key` in `nodes`, if there is one
+///
+/// # Safety
+///
+/// The nodes must not be freed while they are searched
+#[inline(always)]
+unsafe fn find(nodes: &[*mut Node], hash: u64, key: &DataType) -> Option<usize> {
+    nodes.iter().position(|node| {
+        let node = &**node;
+        node.hash == hash && node.key == *key
+    })
+}
+
+/// # Safety
+///
+/// The chain must no longer be reachable from any bucket
+unsafe fn retire_chain(guard: &Guard, chain: *mut Chain) {
+    if !chain.is_null() {
+        guard.retire(chain);
+    }
+}
+
+/// Copies an entry, so that it can be changed while readers keep reading the original
+fn copy(entry: &Entry) -> Entry {
+    Entry {
+        value: entry.value.clone(),
+        expires_at: entry.expires_at,
+        version: entry.version,
+        modified_at: entry.modified_at,
+        value_size: entry.value_size,
+        access: AtomicU32::new(entry.access.load(Ordering::Relaxed)),
+    }
+}
+
+impl<S: BuildHasher + Send + Sync> StorageBackend for LockFreeBackend<S> {
+    fn len(&self) -> usize {
+        self.lens
+            .iter()
+            .map(|len| len.0.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    #[inline(always)]
+    fn read(&self, key: &DataType, f: &mut dyn FnMut(Option<&Entry>)) {
+        let hash = self.hash(key);
+        let _guard = epoch::pin();
+
+        // SAFETY: nothing reachable is freed while pinned
+        unsafe {
+            let nodes = nodes(self.chain(self.bucket(hash)));
+            f(find(nodes, hash, key).map(|i| &(*nodes[i]).entry))
+        }
+    }
+
+    /// Reads the chain of every key's bucket until two reads in a row find the same chains, at
+    /// which point they must all have been published together
+    fn read_many(&self, keys: &[DataType], f: &mut dyn FnMut(&[Option<&Entry>])) {
+        let located: Vec<(u64, usize)> = keys
+            .iter()
+            .map(|key| {
+                let hash = self.hash(key);
+                (hash, self.bucket(hash))
+            })
+            .collect();
+        let _guard = epoch::pin();
+
+        let collect = || -> Vec<*mut Chain> {
+            located
+                .iter()
+                .map(|(_, bucket)| self.chain(*bucket))
+                .collect()
+        };
+        let mut chains = collect();
+        loop {
+            let again = collect();
+            if again == chains {
+                break;
+            }
+            chains = again;
+        }
+
+        // SAFETY: nothing reachable is freed while pinned
+        let entries: Vec<Option<&Entry>> = unsafe {
+            keys.iter()
+                .zip(&located)
+                .zip(&chains)
+                .map(|((key, (hash, _)), chain)| {
+                    let nodes = nodes(*chain);
+                    find(nodes, *hash, key).map(|i| &(*nodes[i]).entry)
+                })
+                .collect()
+        };
+        f(&entries)
+    }
+
+    /// The entry is handed to `f` as a copy, which is only published if `f` asks for a write or
+    /// changes its version. Otherwise only the copy's access tracking is written back.
+    fn update(
+        &self,
+        key: &DataType,
+        f: &mut dyn FnMut(Option<&mut Entry>) -> Write,
+    ) -> Option<Entry> {
+        let hash = self.hash(key);
+        let bucket = self.bucket(hash);
+        let len = &self.lens[self.segment(bucket)].0;
+        let guard = epoch::pin();
+        let claim = self.claim(bucket);
+
+        // SAFETY: the chain can't be replaced while the bucket is claimed, nor freed while pinned
+        let nodes = unsafe { nodes(claim.chain) };
+        let Some(i) = (unsafe { find(nodes, hash, key) }) else {
+            if let Write::Insert(entry) = f(None) {
+                let mut chain = nodes.to_vec();
+                chain.push(new_node(hash, key.to_owned(), entry));
+                self.publish(claim, chain, &guard);
+                len.fetch_add(1, Ordering::Relaxed);
+            }
+            return None;
+        };
+
+        let node = nodes[i];
+        // SAFETY: as above
+        let current = unsafe { &*node };
+        let mut entry = copy(&current.entry);
+        let access = entry.access.load(Ordering::Relaxed);
+
+        let (replacement, old) = match f(Some(&mut entry)) {
+            Write::Keep if entry.version == current.entry.version => {
+                let accessed = entry.access.load(Ordering::Relaxed);
+                if accessed != access {
+                    current.entry.access.store(accessed, Ordering::Relaxed);
+                }
+                return None;
+            }
+            Write::Keep => (Some(entry), None),
+            Write::Remove => (None, Some(entry)),
+            Write::Insert(new) => (Some(new), Some(entry)),
+        };
+
+        let mut chain = nodes.to_vec();
+        match replacement {
+            Some(entry) => chain[i] = new_node(hash, current.key.clone(), entry),
+            None => {
+                chain.swap_remove(i);
+                len.fetch_sub(1, Ordering::Relaxed);
+            }
+        }
+        self.publish(claim, chain, &guard);
+        // SAFETY: the node is no longer reachable from the bucket
+        unsafe { guard.retire(node) };
+
+        old
+    }
+
+    fn update_many(&self, keys: &[DataType], f: &mut dyn FnMut(&mut [Option<Entry>])) {
+        let located: Vec<(u64, usize)> = keys
+            .iter()
+            .map(|key| {
+                let hash = self.hash(key);
+                (hash, self.bucket(hash))
+            })
+            .collect();
+        let mut buckets: Vec<usize> = located.iter().map(|(_, bucket)| *bucket).collect();
+        buckets.sort_unstable();
+        buckets.dedup();
+        let claimed = |bucket: usize| {
+            buckets
+                .binary_search(&bucket)
+                .expect("bucket of key should be claimed")
+        };
+
+        let guard = epoch::pin();
+        let mut claims: Vec<Claim> = buckets.iter().map(|bucket| self.claim(*bucket)).collect();
+
+        // SAFETY: the chains can't be replaced while their buckets are claimed, nor freed while
+        // pinned
+        let found: Vec<Option<*mut Node>> = unsafe {
+            keys.iter()
+                .zip(&located)
+                .map(|(key, (hash, bucket))| {
+                    let nodes = nodes(claims[claimed(*bucket)].chain);
+                    find(nodes, *hash, key).map(|i| nodes[i])
+                })
+                .collect()
+        };
+        let mut entries: Vec<Option<Entry>> = found
+            .iter()
+            .map(|node| node.map(|node| unsafe { copy(&(*node).entry) }))
+            .collect();
+
+        f(&mut entries);
+
+        let mut chains: Vec<Vec<*mut Node>> = claims
+            .iter()
+            .map(|claim| unsafe { nodes(claim.chain) }.to_vec())
+            .collect();
+        for (((key, (hash, bucket)), node), entry) in
+            keys.iter().zip(&located).zip(&found).zip(entries)
+        {
+            let chain = &mut chains[claimed(*bucket)];
+            let len = &self.lens[self.segment(*bucket)].0;
+            match (node, entry) {
+                (Some(node), Some(entry)) => {
+                    let i = chain.iter().position(|n| n == node).unwrap();
+                    chain[i] = new_node(*hash, key.to_owned(), entry);
+                }
+                (Some(node), None) => {
+                    let i = chain.iter().position(|n| n == node).unwrap();
+                    chain.swap_remove(i);
+                    len.fetch_sub(1, Ordering::Relaxed);
+                }
+                (None, Some(entry)) => {
+                    chain.push(new_node(*hash, key.to_owned(), entry));
+                    len.fetch_add(1, Ordering::Relaxed);
+                }
+                (None, None) => {}
+            }
+        }
+
+        let old: Vec<*mut Chain> = claims.iter().map(|claim| claim.chain).collect();
+        let new: Vec<*mut Chain> = chains.into_iter().map(new_chain).collect();
+        if claims.len() == 1 {
+            claims.pop().unwrap().publish(new[0]);
+        } else {
+            let batch = Box::into_raw(Box::new(Batch {
+                committed: AtomicBool::new(false),
+                buckets: buckets
+                    .iter()
+                    .zip(&old)
+                    .zip(&new)
+                    .map(|((bucket, old), new)| (*bucket, *old, *new))
+                    .collect(),
+            }));
+
+            for claim in &claims {
+                claim
+                    .head
+                    .store(batch as usize | BATCH | CLAIMED, Ordering::Release);
+            }
+            // SAFETY: the batch is only retired once no bucket points to it
+            unsafe { (*batch).committed.store(true, Ordering::Release) };
+            for (claim, new) in claims.into_iter().zip(&new) {
+                claim.publish(*new);
+            }
+
+            // SAFETY: no bucket points to the batch any longer
+            unsafe { guard.retire(batch) };
+        }
+
+        // SAFETY: the old chains, and the nodes that were taken out of them, are no longer
+        // reachable from any bucket
+        unsafe {
+            for chain in old {
+                retire_chain(&guard, chain);
+            }
+            for node in found.into_iter().flatten() {
+                guard.retire(node);
+            }
+        }
+    }
+
+    fn insert(&self, key: DataType, entry: Entry) -> Option<Entry> {
+        let mut entry = Some(entry);
+        self.update(&key, &mut |_| {
+            Write::Insert(entry.take().expect("update should run the closure once"))
+        })
+    }
+
+    fn remove(&self, key: &DataType) -> Option<(DataType, Entry)> {
+        self.update(key, &mut |_| Write::Remove)
+            .map(|entry| (key.to_owned(), entry))
+    }
+
+    fn segments(&self) -> usize {
+        self.lens.len()
+    }
+
+    fn segment_len(&self, segment: usize) -> usize {
+        self.lens[segment].0.load(Ordering::Relaxed)
+    }
+
+    /// Writers carry on while a segment is visited, so entries written to in the meantime may or
+    /// may not be visited, as with a scan
+    fn visit(&self, segment: usize, skip: usize, f: &mut dyn FnMut(&DataType, &Entry) -> bool) {
+        let _guard = epoch::pin();
+        let mut skip = skip;
+
+        for bucket in self.segment_buckets(segment) {
+            // SAFETY: nothing reachable is freed while pinned
+            let nodes = unsafe { nodes(self.chain(bucket)) };
+            if skip >= nodes.len() {
+                skip -= nodes.len();
+                continue;
+            }
+
+            for node in &nodes[skip..] {
+                // SAFETY: as above
+                let node = unsafe { &**node };
+                if !f(&node.key, &node.entry) {
+                    return;
+                }
+            }
+            skip = 0;
+        }
+    }
+
+    /// The key's hash, whose top bits pick its bucket and so its segment
+    #[inline(always)]
+    fn position(&self, key: &DataType) -> usize {
+        (self.hash(key) >> (u64::BITS - usize::BITS)) as usize
+    }
+
+    /// Readers may still be reading the removed entries, so they are copied out rather than
+    /// moved
+    fn drain(&self, segment: usize) -> Vec<(DataType, Entry)> {
+        let guard = epoch::pin();
+        let mut drained = Vec::new();
+
+        for bucket in self.segment_buckets(segment) {
+            let claim = self.claim(bucket);
+            // SAFETY: the chain can't be replaced while the bucket is claimed, nor freed while
+            // pinned
+            let nodes = unsafe { nodes(claim.chain) };
+            if nodes.is_empty() {
+                continue;
+            }
+
+            drained.extend(nodes.iter().map(|node| {
+                // SAFETY: as above
+                let node = unsafe { &**node };
+                (node.key.clone(), copy(&node.entry))
+            }));
+            self.lens[segment]
+                .0
+                .fetch_sub(nodes.len(), Ordering::Relaxed);
+
+            let removed = nodes.to_vec();
+            self.publish(claim, Vec::new(), &guard);
+            for node in removed {
+                // SAFETY: the node is no longer reachable from the bucket
+                unsafe { guard.retire(node) };
+            }
+        }
+
+        drained
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LockFreeBackend;
+    use crate::{
+        data_type::{BoopString, DataType, Int},
+        store::{backend::StorageBackend, list::End, Entry, SetTtl, Store},
+    };
+    use bytes::Bytes;
+    use std::{
+        collections::{hash_map::RandomState, HashSet},
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        thread,
+    };
+
+    fn entry(n: u64) -> Entry {
+        Entry::new(Int::new_u64(n), None, 0)
+    }
+
+    fn value(entry: &Entry) -> u64 {
+        entry.value.as_u64().expect("entry should hold a number")
+    }
+
+    #[test]
+    fn concurrent_writes_are_not_lost() {
+        let store = Store::with_backend(Arc::new(
+            LockFreeBackend::with_capacity_hasher_and_segments(0, RandomState::new(), 2),
+        ));
+        let key = Int::new_u8(1);
+
+        thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    for i in 0..500 {
+                        store.push(&key, vec![Int::new_u64(i)], End::Back).unwrap();
+                    }
+                });
+            }
+        });
+        assert_eq!(store.llen(&key).unwrap(), 8 * 500);
+
+        // Every value written by GETSET is handed back exactly once
+        let seen: Vec<DataType> = thread::scope(|scope| {
+            let handles: Vec<_> = (0..8u64)
+                .map(|t| {
+                    let store = &store;
+                    scope.spawn(move || {
+                        (0..500u64)
+                            .map(|i| {
+                                let value =
+                                    BoopString::new_wrapped(Bytes::from(format!("{t}:{i}")));
+                                store.get_set(&Int::new_u8(2), &value)
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().unwrap())
+                .collect()
+        });
+        let mut seen: HashSet<DataType> = seen.into_iter().collect();
+        seen.extend(store.get_del(&Int::new_u8(2)));
+        assert_eq!(seen.len(), 8 * 500 + 1);
+        assert_eq!(store.get(&Int::new_u8(2)), None);
+    }
+
+    #[test]
+    fn batches_are_seen_whole() {
+        let backend = LockFreeBackend::with_capacity_hasher_and_segments(0, RandomState::new(), 2);
+        let keys: Vec<DataType> = (0..8).map(Int::new_u64).collect();
+        for key in &keys {
+            backend.insert(key.clone(), entry(100));
+        }
+        let done = AtomicBool::new(false);
+
+        thread::scope(|scope| {
+            // Writers move amounts between keys, so their total never changes
+            for t in 0..4 {
+                let (backend, keys, done) = (&backend, &keys, &done);
+                scope.spawn(move || {
+                    for i in 0..2_000 {
+                        let from = (t + i) % keys.len();
+                        let to = (t + i * 3 + 1) % keys.len();
+                        if from == to {
+                            continue;
+                        }
+
+                        backend.update_many(
+                            &[keys[from].clone(), keys[to].clone()],
+                            &mut |entries| {
+                                let (a, b) = (
+                                    value(entries[0].as_ref().unwrap()),
+                                    value(entries[1].as_ref().unwrap()),
+                                );
+                                let moved = a.min(7);
+                                entries[0] = Some(entry(a - moved));
+                                entries[1] = Some(entry(b + moved));
+                            },
+                        );
+                    }
+                    done.store(true, Ordering::Relaxed);
+                });
+            }
+
+            for _ in 0..2 {
+                let (backend, keys, done) = (&backend, &keys, &done);
+                scope.spawn(move || {
+                    while !done.load(Ordering::Relaxed) {
+                        backend.read_many(keys, &mut |entries| {
+                            let total: u64 = entries.iter().map(|e| value(e.unwrap())).sum();
+                            assert_eq!(total, 800);
+                        });
+                    }
+                });
+            }
+        });
+
+        assert_eq!(backend.len(), 8);
+        let drained: u64 = (0..backend.segments())
+            .flat_map(|segment| backend.drain(segment))
+            .map(|(_, entry)| value(&entry))
+            .sum();
+        assert_eq!(drained, 800);
+        assert_eq!(backend.len(), 0);
+    }
+
+    #[test]
+    fn segments_hold_every_key() {
+        let backend = LockFreeBackend::with_capacity_hasher_and_segments(0, RandomState::new(), 4);
+        for i in 0..1_000 {
+            backend.insert(Int::new_u64(i), entry(i));
+        }
+        backend.insert(Int::new_u64(0), entry(0));
+        assert_eq!(backend.len(), 1_000);
+
+        let mut visited = 0;
+        for segment in 0..backend.segments() {
+            let mut in_segment = 0;
+            backend.visit(segment, 0, &mut |key, _| {
+                assert_eq!(backend.position(key) >> (usize::BITS - 2), segment);
+                in_segment += 1;
+                true
+            });
+            assert_eq!(in_segment, backend.segment_len(segment));
+            visited += in_segment;
+        }
+        assert_eq!(visited, 1_000);
+
+        let store = Store::with_backend(Arc::new(backend));
+        store.set(&Int::new_u64(5), &Int::new_u8(1), SetTtl::Clear);
+        assert_eq!(store.get(&Int::new_u64(5)), Some(Int::new_u8(1)));
+    }
+}
@@ -0,0 +1,295 @@
+//! Bit level operations on string values. Bits are numbered MSB-first, in the same order that
+//! BOOP puts bytes on the wire: bit 0 is the most significant bit of the first byte, bit 7 is its
+//! least significant bit and bit 8 is the most significant bit of the second byte.
+
+use super::{
+    list::resolve_range,
+    string::{view_string, with_string},
+    SetTtl, Store,
+};
+use crate::data_type::{BoopError, BoopString, DataType};
+use bytes::Bytes;
+
+/// The bitwise operation that BITOP applies across its source keys
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum BitOp {
+    And,
+    Or,
+    Xor,
+    Not,
+}
+
+/// Returns the index of the byte that holds bit `offset`, and the mask of the bit within it
+#[inline(always)]
+fn locate(offset: u64) -> (u64, u8) {
+    (offset / 8, 0x80 >> (offset % 8))
+}
+
+/// Resolves an optional inclusive byte range to the bytes of `string` that it covers
+fn byte_range(string: &Bytes, range: Option<(i64, i64)>) -> &[u8] {
+    let (start, stop) = range.unwrap_or((0, -1));
+    match resolve_range(start, stop, string.len()) {
+        Some((start, stop)) => &string[start..=stop],
+        None => &[],
+    }
+}
+
+impl Store {
+    /// Sets the bit at `offset` of the string held by `key` and returns its previous value. The
+    /// string is zero-padded if it is too short to hold the bit, up to the configured maximum
+    /// bitmap size, past which a `too_large` error is returned.
+    pub fn set_bit(&self, key: &DataType, offset: u64, bit: bool) -> Result<bool, DataType> {
+        let (byte, mask) = locate(offset);
+        if byte >= self.max_bitmap_len as u64 {
+            return Err(BoopError::too_large());
+        }
+        let byte = byte as usize;
+
+        with_string(self, key, |string| {
+            let grown = string.len() <= byte;
+            if grown {
+                string.resize(byte + 1, 0);
+            }
+
+            let previous = string[byte] & mask != 0;
+            if bit {
+                string[byte] |= mask;
+            } else {
+                string[byte] &= !mask;
+            }
+            Ok((previous, grown || previous != bit))
+        })
+    }
+
+    /// Returns the bit at `offset` of the string held by `key`. Bits past the end of the string are
+    /// always zero.
+    pub fn get_bit(&self, key: &DataType, offset: u64) -> Result<bool, DataType> {
+        let (byte, mask) = locate(offset);
+
+        view_string(self, key, |string| {
+            Ok(usize::try_from(byte)
+                .ok()
+                .and_then(|byte| string.get(byte))
+                .is_some_and(|b| b & mask != 0))
+        })
+    }
+
+    /// Counts the set bits of the string held by `key`, optionally only within an inclusive range of
+    /// bytes, which is resolved in the same way as GETRANGE
+    pub fn bit_count(&self, key: &DataType, range: Option<(i64, i64)>) -> Result<u64, DataType> {
+        view_string(self, key, |string| {
+            Ok(byte_range(string, range)
+                .iter()
+                .map(|b| b.count_ones() as u64)
+                .sum())
+        })
+    }
+
+    /// Returns the offset of the first bit set to `bit` in the string held by `key`, searching from
+    /// byte `start` to byte `stop`, inclusive, or to the end of the string if there is no `stop`.
+    /// The offset is always counted from the start of the string.
+    ///
+    /// When looking for a clear bit without a `stop`, the string is treated as though it were
+    /// padded with zeroes, so a string with every bit set returns the first bit past its end.
+    /// Otherwise, `None` is returned if no bit matches.
+    pub fn bit_pos(
+        &self,
+        key: &DataType,
+        bit: bool,
+        start: i64,
+        stop: Option<i64>,
+    ) -> Result<Option<u64>, DataType> {
+        view_string(self, key, |string| {
+            if string.is_empty() {
+                return Ok((!bit).then_some(0));
+            }
+
+            let Some((start, end)) = resolve_range(start, stop.unwrap_or(-1), string.len()) else {
+                return Ok(None);
+            };
+
+            // A byte that is entirely the opposite of `bit` can't contain a match
+            let skip = if bit { 0x00 } else { 0xFF };
+            let found = string[start..=end]
+                .iter()
+                .enumerate()
+                .find(|(_, b)| **b != skip)
+                .map(|(i, b)| {
+                    let within = if bit {
+                        b.leading_zeros()
+                    } else {
+                        b.leading_ones()
+                    };
+                    (start + i) as u64 * 8 + within as u64
+                });
+
+            Ok(match found {
+                None if !bit && stop.is_none() => Some((end + 1) as u64 * 8),
+                found => found,
+            })
+        })
+    }
+
+    /// Applies `op` across the strings held by `keys`, storing the result in `dest` and returning
+    /// its length. Shorter strings, and keys that don't exist, are treated as though they were
+    /// padded with zeroes to the length of the longest. The result replaces `dest`, along with any
+    /// expiry it had, and an empty result removes it. NOT only uses the first key.
+    ///
+    /// The source keys are read as one atomic snapshot, see `Store::mget`.
+    pub fn bit_op(&self, op: BitOp, dest: &DataType, keys: &[DataType]) -> Result<usize, DataType> {
+        let mut sources = Vec::with_capacity(keys.len());
+        for value in self.mget(keys) {
+            match value {
+                Some(DataType::String(BoopString(bytes))) => sources.push(bytes),
+                None => sources.push(Bytes::new()),
+                Some(_) => return Err(BoopError::wrong_type()),
+            }
+        }
+
+        let len = sources.iter().map(Bytes::len).max().unwrap_or(0);
+        let mut result = vec![0u8; len];
+        for (i, byte) in result.iter_mut().enumerate() {
+            let mut bytes = sources.iter().map(|s| s.get(i).copied().unwrap_or(0));
+            *byte = match op {
+                BitOp::And => bytes.fold(0xFF, |acc, b| acc & b),
+                BitOp::Or => bytes.fold(0x00, |acc, b| acc | b),
+                BitOp::Xor => bytes.fold(0x00, |acc, b| acc ^ b),
+                BitOp::Not => !bytes.next().unwrap_or(0),
+            };
+        }
+
+        if result.is_empty() {
+            self.del(std::slice::from_ref(dest));
+        } else {
+            let result = BoopString::new_wrapped(Bytes::from(result));
+            self.set(dest, &result, SetTtl::Clear);
+        }
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BitOp;
+    use crate::{
+        data_type::{BoopError, BoopString, Int},
+        store::{SetTtl, Store},
+    };
+    use bytes::Bytes;
+
+    fn string(store: &Store, key: u8, bytes: &'static [u8]) {
+        store.set(
+            &Int::new_u8(key),
+            &BoopString::new_wrapped(Bytes::from_static(bytes)),
+            SetTtl::Clear,
+        );
+    }
+
+    #[test]
+    fn bits_are_msb_first() {
+        let store = Store::new();
+        let key = Int::new_u8(1);
+
+        assert_eq!(store.set_bit(&key, 0, true), Ok(false));
+        assert_eq!(store.set_bit(&key, 15, true), Ok(false));
+        assert_eq!(
+            store.get(&key),
+            Some(BoopString::new_wrapped(Bytes::from_static(&[0x80, 0x01])))
+        );
+
+        assert_eq!(store.get_bit(&key, 0), Ok(true));
+        assert_eq!(store.get_bit(&key, 1), Ok(false));
+        assert_eq!(store.get_bit(&key, 15), Ok(true));
+        assert_eq!(store.get_bit(&key, 1_000), Ok(false));
+        assert_eq!(store.get_bit(&key, u64::MAX), Ok(false));
+
+        assert_eq!(store.set_bit(&key, 0, false), Ok(true));
+        assert_eq!(store.get_bit(&key, 0), Ok(false));
+    }
+
+    #[test]
+    fn bitmaps_grow_up_to_the_configured_maximum() {
+        let store = Store::new().with_max_bitmap_len(2);
+        let key = Int::new_u8(1);
+
+        assert_eq!(store.set_bit(&key, 15, true), Ok(false));
+        assert_eq!(store.set_bit(&key, 16, true), Err(BoopError::too_large()));
+        assert_eq!(store.strlen(&key), Ok(2));
+
+        let store = Store::new().with_max_bitmap_len(usize::MAX);
+        assert_eq!(
+            store.set_bit(&key, BoopString::MAX_LEN as u64 * 8, true),
+            Err(BoopError::too_large())
+        );
+    }
+
+    #[test]
+    fn bit_count_with_byte_ranges() {
+        let store = Store::new();
+        string(&store, 1, b"foobar");
+        let key = Int::new_u8(1);
+
+        assert_eq!(store.bit_count(&key, None), Ok(26));
+        assert_eq!(store.bit_count(&key, Some((0, 0))), Ok(4));
+        assert_eq!(store.bit_count(&key, Some((1, 1))), Ok(6));
+        assert_eq!(store.bit_count(&key, Some((-2, -1))), Ok(7));
+        assert_eq!(store.bit_count(&key, Some((4, 1))), Ok(0));
+        assert_eq!(store.bit_count(&Int::new_u8(2), None), Ok(0));
+    }
+
+    #[test]
+    fn bit_pos_finds_set_and_clear_bits() {
+        let store = Store::new();
+        string(&store, 1, &[0xFF, 0xF0, 0x00]);
+        string(&store, 2, &[0x00, 0xFF, 0xF0]);
+        string(&store, 3, &[0xFF, 0xFF]);
+        let key = Int::new_u8(1);
+
+        assert_eq!(store.bit_pos(&key, false, 0, None), Ok(Some(12)));
+        assert_eq!(store.bit_pos(&key, true, 1, None), Ok(Some(8)));
+        assert_eq!(store.bit_pos(&key, true, 2, None), Ok(None));
+        assert_eq!(store.bit_pos(&Int::new_u8(2), true, 0, None), Ok(Some(8)));
+        assert_eq!(store.bit_pos(&Int::new_u8(2), true, -1, None), Ok(Some(16)));
+
+        // A full string has its first clear bit just past the end, unless the range is explicit
+        let full = Int::new_u8(3);
+        assert_eq!(store.bit_pos(&full, false, 0, None), Ok(Some(16)));
+        assert_eq!(store.bit_pos(&full, false, 0, Some(-1)), Ok(None));
+
+        let missing = Int::new_u8(4);
+        assert_eq!(store.bit_pos(&missing, false, 0, None), Ok(Some(0)));
+        assert_eq!(store.bit_pos(&missing, true, 0, None), Ok(None));
+    }
+
+    #[test]
+    fn bit_op_pads_shorter_strings() {
+        let store = Store::new();
+        string(&store, 1, &[0b1100_1100, 0xFF]);
+        string(&store, 2, &[0b1010_1010]);
+        let keys = [Int::new_u8(1), Int::new_u8(2)];
+        let dest = Int::new_u8(10);
+        let result = |store: &Store| match store.get(&dest) {
+            Some(crate::data_type::DataType::String(BoopString(bytes))) => bytes.to_vec(),
+            _ => vec![],
+        };
+
+        assert_eq!(store.bit_op(BitOp::And, &dest, &keys), Ok(2));
+        assert_eq!(result(&store), vec![0b1000_1000, 0x00]);
+        assert_eq!(store.bit_op(BitOp::Or, &dest, &keys), Ok(2));
+        assert_eq!(result(&store), vec![0b1110_1110, 0xFF]);
+        assert_eq!(store.bit_op(BitOp::Xor, &dest, &keys), Ok(2));
+        assert_eq!(result(&store), vec![0b0110_0110, 0xFF]);
+        assert_eq!(store.bit_op(BitOp::Not, &dest, &keys[1..]), Ok(1));
+        assert_eq!(result(&store), vec![0b0101_0101]);
+
+        // Only missing keys leaves an empty result, which removes the destination
+        assert_eq!(store.bit_op(BitOp::Or, &dest, &[Int::new_u8(3)]), Ok(0));
+        assert_eq!(store.get(&dest), None);
+
+        store.set(&Int::new_u8(3), &Int::new_u8(1), SetTtl::Clear);
+        assert_eq!(
+            store.bit_op(BitOp::Or, &dest, &[Int::new_u8(3)]),
+            Err(BoopError::wrong_type())
+        );
+    }
+}
@@ -0,0 +1,379 @@
+use super::{list::End, Store};
+use crate::data_type::{BoopArray, BoopError, DataType};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{sync_channel, Receiver, RecvTimeoutError, SyncSender},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// How long a blocked client sleeps at a time before checking that it is still connected
+const HANGUP_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A key and the element that was popped from the list it holds
+pub type Popped = (DataType, DataType);
+
+/// Waiter is a client blocked in BLPOP or BRPOP. It is registered against every key it is waiting
+/// on, and is served at most once, by whichever side manages to claim it first: a push handing it
+/// an element, or the client itself giving up.
+struct Waiter {
+    end: End,
+    claimed: AtomicBool,
+    sender: SyncSender<Popped>,
+}
+
+impl Waiter {
+    /// Claims the waiter, returning false if it has already been claimed
+    #[inline(always)]
+    fn claim(&self) -> bool {
+        !self.claimed.swap(true, Ordering::AcqRel)
+    }
+}
+
+/// Waiters holds the queue of blocked clients for each key. Clients are served in the order that
+/// they started waiting.
+///
+/// A waiting client costs an entry per key it waits on and a channel; the connection's own thread
+/// sleeps on the channel until it is handed an element, its timeout fires, or its client
+/// disconnects. No thread is spawned per waiter and nothing polls the store.
+#[derive(Default)]
+pub(crate) struct Waiters {
+    queues: Mutex<HashMap<DataType, VecDeque<Arc<Waiter>>>>,
+    /// The amount of clients currently blocked, which lets pushes skip the lock when there are none
+    waiting: AtomicUsize,
+}
+
+impl Waiters {
+    fn register(&self, keys: &[DataType], waiter: &Arc<Waiter>) {
+        let mut queues = self.queues.lock().unwrap();
+        for key in keys {
+            queues
+                .entry(key.to_owned())
+                .or_default()
+                .push_back(waiter.clone());
+        }
+        self.waiting.fetch_add(1, Ordering::AcqRel);
+    }
+
+    fn deregister(&self, keys: &[DataType], waiter: &Arc<Waiter>) {
+        let mut queues = self.queues.lock().unwrap();
+        for key in keys {
+            if let Some(queue) = queues.get_mut(key) {
+                queue.retain(|w| !Arc::ptr_eq(w, waiter));
+                if queue.is_empty() {
+                    queues.remove(key);
+                }
+            }
+        }
+        self.waiting.fetch_sub(1, Ordering::AcqRel);
+    }
+
+    /// Hands elements of `list`, which `key` holds, to the clients blocked on `key` until either
+    /// runs out. Must be called while holding the write lock of the key's shard.
+    pub fn serve(&self, key: &DataType, list: &mut VecDeque<DataType>) {
+        if list.is_empty() || self.waiting.load(Ordering::Acquire) == 0 {
+            return;
+        }
+
+        let mut queues = self.queues.lock().unwrap();
+        let Some(queue) = queues.get_mut(key) else {
+            return;
+        };
+
+        while !list.is_empty() {
+            let Some(waiter) = queue.pop_front() else {
+                break;
+            };
+            // Waiters that were already served through another key, or that timed out, are
+            // dropped from the queue
+            if !waiter.claim() {
+                continue;
+            }
+
+            let element = match waiter.end {
+                End::Front => list.pop_front().unwrap(),
+                End::Back => list.pop_back().unwrap(),
+            };
+            // The channel has room for one element and the waiter can only be claimed once, so
+            // this never blocks. It only fails if the waiter has gone, in which case there is
+            // nobody left to give the element to.
+            let _ = waiter.sender.try_send((key.to_owned(), element));
+        }
+
+        if queue.is_empty() {
+            queues.remove(key);
+        }
+    }
+
+    #[cfg(test)]
+    fn waiting(&self) -> usize {
+        self.waiting.load(Ordering::Acquire)
+    }
+}
+
+impl Store {
+    /// Pops an element from one end of the first non-empty list held by any of `keys`, checked in
+    /// order. If they are all empty, blocks until an element is pushed to one of them or until
+    /// `timeout` passes, returning `None` on a timeout. A timeout of `None` blocks indefinitely.
+    /// Clients blocked on the same key are served in FIFO order.
+    ///
+    /// While it waits, `hung_up` is checked every `HANGUP_CHECK_INTERVAL` to find out if the client
+    /// has disconnected, in which case it stops waiting and returns `None`. An element handed to
+    /// it just as it noticed is pushed back to where it was popped from, rather than lost.
+    pub fn blocking_pop(
+        &self,
+        keys: &[DataType],
+        end: End,
+        timeout: Option<Duration>,
+        hung_up: &dyn Fn() -> bool,
+    ) -> Result<Option<Popped>, DataType> {
+        let popped = self.shared(|| {
+            for key in keys {
+                if let Some(element) = self.pop(key, end)? {
+                    return Ok(Some((key.to_owned(), element)));
+                }
+            }
+            Ok(None)
+        })?;
+
+        // A transaction holds the store to itself, so nothing could be pushed while it waits
+        if popped.is_some() || self.in_transaction() {
+            return Ok(popped);
+        }
+
+        let (sender, receiver) = sync_channel(1);
+        let waiter = Arc::new(Waiter {
+            end,
+            claimed: AtomicBool::new(false),
+            sender,
+        });
+
+        self.waiters.register(keys, &waiter);
+        let result = self.wait(keys, &waiter, &receiver, timeout, hung_up);
+        self.waiters.deregister(keys, &waiter);
+
+        result
+    }
+
+    fn wait(
+        &self,
+        keys: &[DataType],
+        waiter: &Waiter,
+        receiver: &Receiver<Popped>,
+        timeout: Option<Duration>,
+        hung_up: &dyn Fn() -> bool,
+    ) -> Result<Option<Popped>, DataType> {
+        // An element may have been pushed between the first attempt and registering, and that push
+        // would not have seen this waiter. Try once more, claiming the waiter under the shard lock
+        // so that a push to one of the other keys can't also serve it.
+        let retried = self.shared(|| {
+            for key in keys {
+                let attempt = self.update(key, |value| match value {
+                    Some(DataType::Array(BoopArray(list))) => {
+                        if !waiter.claim() {
+                            return (Err(None), false);
+                        }
+                        let element = match waiter.end {
+                            End::Front => list.pop_front().unwrap(),
+                            End::Back => list.pop_back().unwrap(),
+                        };
+                        if list.is_empty() {
+                            *value = None;
+                        }
+                        (Ok(element), true)
+                    }
+                    None => (Err(None), false),
+                    Some(_) => (Err(waiter.claim().then(BoopError::wrong_type)), false),
+                });
+
+                match attempt {
+                    Ok(element) => return Ok(Some((key.to_owned(), element))),
+                    Err(Some(err)) => return Err(err),
+                    // Already served through another key, so the element is on its way
+                    Err(None) if waiter.claimed.load(Ordering::Acquire) => break,
+                    Err(None) => {}
+                }
+            }
+            Ok(None)
+        })?;
+        if retried.is_some() {
+            return Ok(retried);
+        }
+
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            let wait = deadline.map_or(HANGUP_CHECK_INTERVAL, |deadline| {
+                deadline
+                    .saturating_duration_since(Instant::now())
+                    .min(HANGUP_CHECK_INTERVAL)
+            });
+            match receiver.recv_timeout(wait) {
+                Ok(popped) => return Ok(Some(popped)),
+                // The waiter holds the sender, so this can't happen while it waits
+                Err(RecvTimeoutError::Disconnected) => return Ok(None),
+                Err(RecvTimeoutError::Timeout) => {}
+            }
+
+            let hung_up = hung_up();
+            if !hung_up && deadline.is_none_or(|deadline| Instant::now() < deadline) {
+                continue;
+            }
+            if waiter.claim() {
+                return Ok(None);
+            }
+
+            // A push claimed the waiter just as the wait ended, and is about to send its element
+            let popped = receiver.recv().ok();
+            if !hung_up {
+                return Ok(popped);
+            }
+            if let Some((key, element)) = popped {
+                let _ = self.shared(|| self.push(&key, vec![element], waiter.end));
+            }
+            return Ok(None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::list::End;
+    use crate::{
+        data_type::{BoopError, Int},
+        store::{SetTtl, Store},
+    };
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        thread,
+        time::Duration,
+    };
+
+    fn wait_for_waiters(store: &Store, n: usize) {
+        while store.waiters.waiting() != n {
+            thread::yield_now();
+        }
+    }
+
+    #[test]
+    fn pops_immediately_when_a_list_has_elements() {
+        let store = Store::new();
+        store
+            .push(&Int::new_u8(2), vec![Int::new_u8(10)], End::Back)
+            .unwrap();
+
+        let keys = [Int::new_u8(1), Int::new_u8(2)];
+        assert_eq!(
+            store.blocking_pop(&keys, End::Front, Some(Duration::from_secs(5)), &|| false),
+            Ok(Some((Int::new_u8(2), Int::new_u8(10))))
+        );
+        assert_eq!(store.waiters.waiting(), 0);
+    }
+
+    #[test]
+    fn times_out_when_nothing_is_pushed() {
+        let store = Store::new();
+        let keys = [Int::new_u8(1)];
+
+        assert_eq!(
+            store.blocking_pop(&keys, End::Back, Some(Duration::from_millis(10)), &|| false),
+            Ok(None)
+        );
+        assert_eq!(store.waiters.waiting(), 0);
+        assert!(store.waiters.queues.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn wrong_type_is_returned_without_blocking() {
+        let store = Store::new();
+        store.set(&Int::new_u8(1), &Int::new_u8(1), SetTtl::Clear);
+
+        assert_eq!(
+            store.blocking_pop(&[Int::new_u8(1)], End::Front, None, &|| false),
+            Err(BoopError::wrong_type())
+        );
+    }
+
+    #[test]
+    fn push_wakes_waiters_in_fifo_order() {
+        let store = Store::new();
+        let key = Int::new_u8(1);
+
+        let mut handles = vec![];
+        for i in 0..3 {
+            let waiter = store.clone();
+            let key = key.clone();
+            handles.push(thread::spawn(move || {
+                waiter.blocking_pop(&[key], End::Front, Some(Duration::from_secs(10)), &|| false)
+            }));
+            wait_for_waiters(&store, i + 1);
+        }
+
+        let elements = (0..4).map(Int::new_u8).collect();
+        assert_eq!(store.push(&key, elements, End::Back), Ok(4));
+
+        for (i, handle) in handles.into_iter().enumerate() {
+            assert_eq!(
+                handle.join().unwrap(),
+                Ok(Some((key.clone(), Int::new_u8(i as u8))))
+            );
+        }
+        // Only the element nobody was waiting for is left in the list
+        assert_eq!(store.lrange(&key, 0, -1), Ok(vec![Int::new_u8(3)]));
+    }
+
+    #[test]
+    fn waiter_on_several_keys_is_only_served_once() {
+        let store = Store::new();
+        let keys = [Int::new_u8(1), Int::new_u8(2)];
+
+        let handle = {
+            let store = store.clone();
+            let keys = keys.clone();
+            thread::spawn(move || store.blocking_pop(&keys, End::Back, None, &|| false))
+        };
+        wait_for_waiters(&store, 1);
+
+        store
+            .push(&keys[1], vec![Int::new_u8(20)], End::Back)
+            .unwrap();
+        store
+            .push(&keys[0], vec![Int::new_u8(10)], End::Back)
+            .unwrap();
+
+        assert_eq!(
+            handle.join().unwrap(),
+            Ok(Some((keys[1].clone(), Int::new_u8(20))))
+        );
+        assert_eq!(store.lrange(&keys[0], 0, -1), Ok(vec![Int::new_u8(10)]));
+        assert_eq!(store.llen(&keys[1]), Ok(0));
+    }
+
+    #[test]
+    fn stops_waiting_once_the_client_hangs_up() {
+        let store = Store::new();
+        let key = Int::new_u8(1);
+        let hung_up = Arc::new(AtomicBool::new(false));
+
+        let handle = {
+            let (store, key, hung_up) = (store.clone(), key.clone(), hung_up.clone());
+            thread::spawn(move || {
+                store.blocking_pop(&[key], End::Front, None, &|| hung_up.load(Ordering::SeqCst))
+            })
+        };
+        wait_for_waiters(&store, 1);
+
+        hung_up.store(true, Ordering::SeqCst);
+        assert_eq!(handle.join().unwrap(), Ok(None));
+        assert_eq!(store.waiters.waiting(), 0);
+
+        // Nobody is waiting any more, so the element stays in the list
+        store.push(&key, vec![Int::new_u8(10)], End::Back).unwrap();
+        assert_eq!(store.lrange(&key, 0, -1), Ok(vec![Int::new_u8(10)]));
+    }
+}
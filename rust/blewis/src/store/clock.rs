@@ -0,0 +1,43 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Clock is the source of time used by the store when working out whether an entry has expired.
+/// It is a trait so that tests can control the passing of time, instead of having to sleep.
+pub trait Clock: Send + Sync {
+    /// The current time, in milliseconds since the UNIX epoch
+    fn now_ms(&self) -> u64;
+}
+
+/// The wall clock of the machine that the server is running on
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    #[inline(always)]
+    fn now_ms(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0)
+    }
+}
+
+/// A clock that only moves when it is told to
+#[cfg(test)]
+pub struct ManualClock(std::sync::atomic::AtomicU64);
+
+#[cfg(test)]
+impl ManualClock {
+    pub fn new(start_ms: u64) -> Self {
+        ManualClock(std::sync::atomic::AtomicU64::new(start_ms))
+    }
+
+    pub fn advance(&self, ms: u64) {
+        self.0.fetch_add(ms, std::sync::atomic::Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+impl Clock for ManualClock {
+    fn now_ms(&self) -> u64 {
+        self.0.load(std::sync::atomic::Ordering::SeqCst)
+    }
+}
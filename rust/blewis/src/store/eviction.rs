@@ -0,0 +1,364 @@
+//! Eviction removes keys to make room for writes once a store has used up its memory limit. Each
+//! key to evict is picked by sampling a handful of entries and removing the best candidate for
+//! the store's policy, so eviction is approximate, but the store never has to keep its keys in
+//! any order to support it.
+
+use super::{notify::KeyEvent, random, Entry, Store};
+use crate::data_type::{BoopError, DataType};
+use std::{str::FromStr, sync::atomic::Ordering};
+
+/// How many entries are sampled to pick each key that is evicted
+const EVICTION_SAMPLES: usize = 5;
+
+/// The counter that a new key starts out with under LFU, so that it isn't the first key to be
+/// evicted before it has had the chance to be read
+const LFU_INIT: u8 = 5;
+
+/// How slowly the LFU counter grows. Each access increments it with a chance of
+/// `1 / ((counter - LFU_INIT) * LFU_LOG_FACTOR + 1)`, so a counter of 255 takes around a million
+/// accesses to reach.
+const LFU_LOG_FACTOR: u64 = 10;
+
+/// How many minutes it takes for an LFU counter that isn't accessed to decay by one
+const LFU_DECAY_MINUTES: u32 = 1;
+
+/// EvictionPolicy decides what happens when a write needs more memory than the store has left
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum EvictionPolicy {
+    /// Evict nothing, and refuse writes with an `out_of_memory` error instead
+    #[default]
+    NoEviction,
+    /// Evict the keys that were accessed least recently
+    AllKeysLru,
+    /// Evict the keys that are accessed least frequently
+    AllKeysLfu,
+    /// Evict the keys that are closest to expiring, only ever evicting keys that have an expiry
+    VolatileTtl,
+    /// Evict keys at random
+    Random,
+}
+
+impl FromStr for EvictionPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "noeviction" => Ok(EvictionPolicy::NoEviction),
+            "allkeys-lru" => Ok(EvictionPolicy::AllKeysLru),
+            "allkeys-lfu" => Ok(EvictionPolicy::AllKeysLfu),
+            "volatile-ttl" => Ok(EvictionPolicy::VolatileTtl),
+            "random" => Ok(EvictionPolicy::Random),
+            unknown => anyhow::bail!("unknown eviction policy: {unknown}"),
+        }
+    }
+}
+
+impl EvictionPolicy {
+    /// The name of the policy, as it is given to `--eviction-policy`
+    pub fn name(self) -> &'static str {
+        match self {
+            EvictionPolicy::NoEviction => "noeviction",
+            EvictionPolicy::AllKeysLru => "allkeys-lru",
+            EvictionPolicy::AllKeysLfu => "allkeys-lfu",
+            EvictionPolicy::VolatileTtl => "volatile-ttl",
+            EvictionPolicy::Random => "random",
+        }
+    }
+}
+
+/// The LRU clock, which is the time in seconds, wrapping around every 136 years
+#[inline(always)]
+fn lru_clock(now: u64) -> u32 {
+    (now / 1_000) as u32
+}
+
+/// The LFU clock, which is the time in minutes, wrapping around every 45 days
+#[inline(always)]
+fn lfu_clock(now: u64) -> u32 {
+    (now / 60_000) as u32 & 0xFFFF
+}
+
+/// Packs an LFU counter with the time it was last decayed at into the entry's access tracking. The
+/// counter takes the lowest 8 bits and the 16 bit clock sits above it, in bits 8 to 23, leaving
+/// the top 8 bits unused
+#[inline(always)]
+fn lfu_pack(now: u64, counter: u8) -> u32 {
+    lfu_clock(now) << 8 | counter as u32
+}
+
+/// Unpacks an LFU counter, decaying it by the time that has passed since it was last decayed
+#[inline(always)]
+fn lfu_counter(access: u32, now: u64) -> u8 {
+    let elapsed = lfu_clock(now).wrapping_sub(access >> 8) & 0xFFFF;
+    let counter = (access & 0xFF) as u8;
+    counter.saturating_sub((elapsed / LFU_DECAY_MINUTES).min(255) as u8)
+}
+
+/// Increments an LFU counter logarithmically, see `LFU_LOG_FACTOR`
+#[inline(always)]
+fn lfu_increment(counter: u8) -> u8 {
+    if counter == u8::MAX {
+        return counter;
+    }
+
+    let base = counter.saturating_sub(LFU_INIT) as u64;
+    if random().is_multiple_of(base * LFU_LOG_FACTOR + 1) {
+        counter + 1
+    } else {
+        counter
+    }
+}
+
+impl Store {
+    /// Creates an entry for a value that is about to be written, with its access tracking
+    /// started
+    #[inline(always)]
+    pub(super) fn new_entry(&self, value: DataType, expires_at: Option<u64>, now: u64) -> Entry {
+        let entry = Entry::new(value, expires_at, now);
+        let access = match self.memory.policy {
+            EvictionPolicy::AllKeysLru => lru_clock(now),
+            EvictionPolicy::AllKeysLfu => lfu_pack(now, LFU_INIT),
+            _ => 0,
+        };
+        entry.access.store(access, Ordering::Relaxed);
+        entry
+    }
+
+    /// Records that the entry has been read or written. Only the policy that is in use is
+    /// tracked, and the tracking is a relaxed atomic store, so readers never wait on each other.
+    #[inline(always)]
+    pub(super) fn accessed(&self, entry: &Entry, now: u64) {
+        match self.memory.policy {
+            EvictionPolicy::AllKeysLru => entry.access.store(lru_clock(now), Ordering::Relaxed),
+            EvictionPolicy::AllKeysLfu => {
+                let counter = lfu_counter(entry.access.load(Ordering::Relaxed), now);
+                entry
+                    .access
+                    .store(lfu_pack(now, lfu_increment(counter)), Ordering::Relaxed);
+            }
+            _ => {}
+        }
+    }
+
+    /// Makes sure that the store is within its memory limit before a write, evicting keys as its
+    /// policy allows. Returns an `out_of_memory` error if the store is over its limit and the
+    /// policy is not to evict, or there is nothing left that the policy can evict.
+    pub fn make_room(&self) -> Result<(), DataType> {
+        let limit = self.memory.limit;
+        if limit == 0 {
+            return Ok(());
+        }
+
+        while self.used_memory() > limit {
+            if self.memory.policy == EvictionPolicy::NoEviction || !self.evict_one() {
+                return Err(BoopError::out_of_memory());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// How strongly the entry should be evicted under the store's policy, where the highest is
+    /// evicted first, or `None` if the policy never evicts it. Expired entries always go first.
+    fn eviction_score(&self, entry: &Entry, now: u64) -> Option<u64> {
+        if entry.is_expired(now) {
+            return Some(u64::MAX);
+        }
+
+        let access = entry.access.load(Ordering::Relaxed);
+        match self.memory.policy {
+            EvictionPolicy::NoEviction => None,
+            EvictionPolicy::AllKeysLru => Some(lru_clock(now).wrapping_sub(access) as u64),
+            EvictionPolicy::AllKeysLfu => Some(u8::MAX as u64 - lfu_counter(access, now) as u64),
+            EvictionPolicy::VolatileTtl => entry.expires_at.map(|at| u64::MAX - 1 - at),
+            EvictionPolicy::Random => Some(0),
+        }
+    }
+
+    /// Evicts a single key, picked as the best of a sample of `EVICTION_SAMPLES` entries that the
+    /// policy can evict. The sample is taken from one shard after another, starting from a random
+    /// one, until enough evictable entries are found, so that shards holding only a few keys
+    /// don't leave the choice to chance. Returns false if no shard had anything to evict.
+    fn evict_one(&self) -> bool {
+        let now = self.now();
+        let segments = self.map.segments();
+        let start = random() as usize % segments;
+
+        let mut candidates = 0;
+        let mut victim: Option<(u64, DataType)> = None;
+        for i in 0..segments {
+            let segment = (start + i) % segments;
+            let len = self.map.segment_len(segment);
+            if len == 0 {
+                continue;
+            }
+
+            // The sample wraps around to the start of the shard if it starts near its end
+            let samples = EVICTION_SAMPLES.min(len);
+            let mut sampled = 0;
+            let mut sample = |key: &DataType, entry: &Entry| {
+                if sampled == samples {
+                    return false;
+                }
+                sampled += 1;
+                if let Some(score) = self.eviction_score(entry, now) {
+                    candidates += 1;
+                    if victim.as_ref().is_none_or(|(best, _)| score > *best) {
+                        victim = Some((score, key.to_owned()));
+                    }
+                }
+                sampled < samples
+            };
+            self.map
+                .visit(segment, random() as usize % len, &mut sample);
+            self.map.visit(segment, 0, &mut sample);
+
+            if candidates >= EVICTION_SAMPLES {
+                break;
+            }
+        }
+
+        let Some((_, key)) = victim else {
+            return false;
+        };
+        if let Some((key, entry)) = self.map.remove(&key) {
+            self.refund(&key, &entry);
+            self.invalidate(&key);
+            let event = if entry.is_expired(now) {
+                KeyEvent::Expired
+            } else {
+                KeyEvent::Evicted
+            };
+            self.notify(event, &key);
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{lfu_counter, lfu_increment, lfu_pack, EvictionPolicy, LFU_INIT};
+    use crate::{
+        data_type::{BoopError, BoopString, Int},
+        store::{clock::ManualClock, SetTtl, Store},
+    };
+    use bytes::Bytes;
+    use std::sync::Arc;
+
+    /// A store with room for around `keys` keys holding 100 byte strings
+    fn store(keys: usize, policy: EvictionPolicy) -> (Arc<ManualClock>, Store) {
+        let clock = Arc::new(ManualClock::new(1_000_000));
+        let probe = Store::new();
+        probe.set(&Int::new_u16(0), &value(), SetTtl::Clear);
+        let store =
+            Store::with_clock(clock.clone()).with_max_memory(probe.used_memory() * keys, policy);
+        (clock, store)
+    }
+
+    fn value() -> crate::data_type::DataType {
+        BoopString::new_wrapped(Bytes::from(vec![0; 100]))
+    }
+
+    /// Writes a key the way a command would, making room for it first
+    fn write(store: &Store, key: u16, ttl: SetTtl) -> bool {
+        let room = store.make_room().is_ok();
+        if room {
+            store.set(&Int::new_u16(key), &value(), ttl);
+        }
+        room
+    }
+
+    #[test]
+    fn noeviction_refuses_writes() {
+        let (_, store) = store(10, EvictionPolicy::NoEviction);
+        for key in 0..11 {
+            assert!(write(&store, key, SetTtl::Clear));
+        }
+
+        // Over the limit, every write is refused until something is deleted
+        assert_eq!(store.make_room(), Err(BoopError::out_of_memory()));
+        store.del(&[Int::new_u16(0), Int::new_u16(1)]);
+        assert!(write(&store, 11, SetTtl::Clear));
+        assert_eq!(store.len(), 10);
+    }
+
+    #[test]
+    fn eviction_keeps_the_store_within_its_limit() {
+        for policy in [
+            EvictionPolicy::AllKeysLru,
+            EvictionPolicy::AllKeysLfu,
+            EvictionPolicy::Random,
+        ] {
+            let (_, store) = store(50, policy);
+            for key in 0..500 {
+                assert!(write(&store, key, SetTtl::Clear));
+            }
+
+            assert!(store.make_room().is_ok());
+            assert!(store.used_memory() <= store.memory.limit);
+            assert!(store.len() >= 40);
+        }
+    }
+
+    #[test]
+    fn lru_evicts_idle_keys_first() {
+        let (clock, store) = store(100, EvictionPolicy::AllKeysLru);
+        for key in 0..100 {
+            write(&store, key, SetTtl::Clear);
+        }
+
+        // The first half are read a minute later, so the second half are the idle ones
+        clock.advance(60_000);
+        for key in 0..50 {
+            store.get(&Int::new_u16(key));
+        }
+        for key in 100..150 {
+            write(&store, key, SetTtl::Clear);
+        }
+
+        let kept = (0..50)
+            .filter(|key| store.get(&Int::new_u16(*key)).is_some())
+            .count();
+        let idle = (50..100)
+            .filter(|key| store.get(&Int::new_u16(*key)).is_some())
+            .count();
+        assert!(kept > idle, "kept {kept} read keys and {idle} idle keys");
+    }
+
+    #[test]
+    fn volatile_ttl_only_evicts_keys_with_an_expiry() {
+        let (_, store) = store(10, EvictionPolicy::VolatileTtl);
+        for key in 0..5 {
+            write(&store, key, SetTtl::Clear);
+        }
+        for key in 5..10 {
+            write(&store, key, SetTtl::ExpireIn(key as u64 * 1_000));
+        }
+
+        // The keys closest to expiring are evicted first
+        write(&store, 10, SetTtl::Clear);
+        write(&store, 11, SetTtl::Clear);
+        assert!(store.get(&Int::new_u16(9)).is_some());
+        assert!((0..5).all(|key| store.get(&Int::new_u16(key)).is_some()));
+
+        for key in 12..30 {
+            write(&store, key, SetTtl::Clear);
+        }
+        assert_eq!(store.make_room(), Err(BoopError::out_of_memory()));
+        assert!((0..5).all(|key| store.get(&Int::new_u16(key)).is_some()));
+    }
+
+    #[test]
+    fn lfu_counters_grow_slowly_and_decay() {
+        let mut counter = LFU_INIT;
+        for _ in 0..1_000 {
+            counter = lfu_increment(counter);
+        }
+        assert!(counter > LFU_INIT && counter < 50, "counter is {counter}");
+
+        let now = 10 * 60_000;
+        assert_eq!(lfu_counter(lfu_pack(now, 20), now), 20);
+        assert_eq!(lfu_counter(lfu_pack(now, 20), now + 3 * 60_000), 17);
+        assert_eq!(lfu_counter(lfu_pack(now, 20), now + 60 * 60_000), 0);
+    }
+}
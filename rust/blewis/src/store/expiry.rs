@@ -0,0 +1,142 @@
+use super::Store;
+use crate::data_type::DataType;
+use std::{thread, time::Duration};
+
+/// The amount of entries that are looked at in a shard before its lock is released
+const SAMPLE_SIZE: usize = 20;
+
+/// If more than a quarter of a sample had expired, the shard is likely to have many more expired
+/// entries, so it is sampled again straight away rather than waiting for the next cycle
+const RESAMPLE_RATIO: usize = 4;
+
+/// Upper bound on the amount of times a single shard is re-sampled in one cycle, so that one shard
+/// full of expired keys can't starve the others
+const MAX_SAMPLES_PER_SHARD: usize = 16;
+
+impl Store {
+    /// Looks at up to `sample` entries of the given shard, starting from the `offset`th entry, and
+    /// removes any that have expired. The shard is only held while the sample is taken; each
+    /// expired key is then removed on its own, re-checking its expiry as it is removed.
+    /// Returns the amount of entries looked at and the amount that were removed.
+    pub(crate) fn sweep_shard(&self, shard: usize, offset: usize, sample: usize) -> (usize, usize) {
+        let now = self.now();
+        let mut visited = 0;
+        let mut expired: Vec<DataType> = Vec::new();
+
+        self.map.visit(shard, offset, &mut |key, entry| {
+            visited += 1;
+            if entry.is_expired(now) {
+                expired.push(key.to_owned());
+            }
+            visited < sample
+        });
+
+        let mut removed = 0;
+        for key in expired {
+            if self
+                .remove_entry_if(&key, |entry| entry.is_expired(now))
+                .is_some()
+            {
+                removed += 1;
+            }
+        }
+
+        (visited, removed)
+    }
+}
+
+/// Sweeper actively removes expired entries from a store, so that keys which are never read again
+/// don't sit in memory forever. It walks each shard a small sample at a time, remembering where it
+/// got to in every shard so that the whole keyspace is covered over a number of cycles.
+pub struct Sweeper {
+    store: Store,
+    cursors: Vec<usize>,
+}
+
+impl Sweeper {
+    pub fn new(store: Store) -> Self {
+        let shards = store.map.segments();
+        Sweeper {
+            store,
+            cursors: vec![0; shards],
+        }
+    }
+
+    /// Runs a single cycle over every shard, returning the amount of entries that were removed
+    pub fn sweep(&mut self) -> usize {
+        let mut total_removed = 0;
+
+        for (shard, cursor) in self.cursors.iter_mut().enumerate() {
+            for _ in 0..MAX_SAMPLES_PER_SHARD {
+                let (visited, removed) = self.store.sweep_shard(shard, *cursor, SAMPLE_SIZE);
+                total_removed += removed;
+
+                // Removed entries shift the ones after them down, so only skip over the survivors
+                *cursor += visited - removed;
+                if visited < SAMPLE_SIZE {
+                    *cursor = 0;
+                }
+
+                if visited < SAMPLE_SIZE || removed * RESAMPLE_RATIO <= visited {
+                    break;
+                }
+            }
+        }
+
+        total_removed
+    }
+}
+
+/// Starts a background thread which sweeps each of the stores for expired entries every
+/// `interval`
+pub fn spawn_sweeper(stores: Vec<Store>, interval: Duration) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let mut sweepers: Vec<Sweeper> = stores.into_iter().map(Sweeper::new).collect();
+        loop {
+            for sweeper in &mut sweepers {
+                sweeper.sweep();
+            }
+            thread::sleep(interval);
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Sweeper;
+    use crate::{
+        data_type::Int,
+        store::{clock::ManualClock, SetTtl, Store},
+    };
+    use std::sync::Arc;
+
+    #[test]
+    fn sweeper_removes_only_expired_entries() {
+        let clock = Arc::new(ManualClock::new(1_000));
+        let store = Store::with_clock(clock.clone());
+
+        for i in 0..1_000_u16 {
+            let ttl = if i % 2 == 0 {
+                SetTtl::ExpireIn(10)
+            } else {
+                SetTtl::Clear
+            };
+            store.set(&Int::new_u16(i), &Int::new_u16(i), ttl);
+        }
+
+        let mut sweeper = Sweeper::new(store.clone());
+        assert_eq!(sweeper.sweep(), 0);
+
+        clock.advance(10);
+        let mut removed = 0;
+        while removed < 500 {
+            let swept = sweeper.sweep();
+            assert!(swept > 0 || removed == 500);
+            removed += swept;
+        }
+
+        assert_eq!(removed, 500);
+        assert_eq!(store.len(), 500);
+        assert!(store.get(&Int::new_u16(1)).is_some());
+    }
+}
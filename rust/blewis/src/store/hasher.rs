@@ -0,0 +1,276 @@
+//! The hashers that a store can hash its keys with. SipHash, the standard library's default, is
+//! keyed with a random secret, so clients can't craft keys that all land in the same place and
+//! slow every operation on them to a crawl. It is also slow for the short keys that stores mostly
+//! hold, so two much faster hashers are provided for servers that only trusted clients talk to.
+//!
+//! The fast hashers are seeded randomly too, but that doesn't make them resistant to crafted keys,
+//! as their structure makes colliding keys easy to find whatever the seed.
+
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    str::FromStr,
+};
+
+/// HashAlgorithm picks the hasher that every store of the server hashes its keys with
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum HashAlgorithm {
+    /// SipHash-1-3 with a random key, see `RandomState`
+    #[default]
+    SipHash,
+    /// The hasher of the Rust compiler, which mixes in a word at a time, see `FxHasher`
+    FxHash,
+    /// FNV-1a, which mixes in a byte at a time, see `FnvHasher`
+    Fnv,
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "siphash" => Ok(HashAlgorithm::SipHash),
+            "fxhash" => Ok(HashAlgorithm::FxHash),
+            "fnv" => Ok(HashAlgorithm::Fnv),
+            unknown => anyhow::bail!("unknown hasher: {unknown}"),
+        }
+    }
+}
+
+/// A random seed, which differs on every call
+fn seed() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+/// The multiplier of `FxHasher`, an odd constant with its bits spread evenly
+const FX_K: u64 = 0xf135_7aea_2e62_a9c5;
+
+/// The finalizer of MurmurHash3, which leaves every bit of the result depending on every bit of
+/// the state, however structured the keys that were hashed into it
+#[inline(always)]
+fn avalanche(mut x: u64) -> u64 {
+    x ^= x >> 33;
+    x = x.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    x ^= x >> 33;
+    x = x.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    x ^ x >> 33
+}
+
+/// FxHasher is the hasher of the Rust compiler. Every word written is mixed into the state with a
+/// rotate, an xor and a multiply, which is about as little work as a hasher can do. That leaves
+/// the low bits of the state depending on little of the input, so the state is run through
+/// `avalanche` before it is handed out, as the tables that keys are hashed into pick buckets by
+/// both the low and the high bits.
+pub struct FxHasher {
+    hash: u64,
+}
+
+impl FxHasher {
+    #[inline(always)]
+    fn add(&mut self, word: u64) {
+        self.hash = (self.hash.rotate_left(5) ^ word).wrapping_mul(FX_K);
+    }
+}
+
+impl Hasher for FxHasher {
+    #[inline]
+    fn write(&mut self, bytes: &[u8]) {
+        let mut chunks = bytes.chunks_exact(8);
+        for chunk in &mut chunks {
+            self.add(u64::from_le_bytes(chunk.try_into().unwrap()));
+        }
+
+        let rest = chunks.remainder();
+        if !rest.is_empty() {
+            let mut word = [0; 8];
+            word[..rest.len()].copy_from_slice(rest);
+            // The length is mixed in so that trailing zero bytes still change the hash
+            self.add(u64::from_le_bytes(word) ^ (rest.len() as u64) << 59);
+        }
+    }
+
+    #[inline]
+    fn write_u8(&mut self, i: u8) {
+        self.add(i as u64);
+    }
+
+    #[inline]
+    fn write_u16(&mut self, i: u16) {
+        self.add(i as u64);
+    }
+
+    #[inline]
+    fn write_u32(&mut self, i: u32) {
+        self.add(i as u64);
+    }
+
+    #[inline]
+    fn write_u64(&mut self, i: u64) {
+        self.add(i);
+    }
+
+    #[inline]
+    fn write_usize(&mut self, i: usize) {
+        self.add(i as u64);
+    }
+
+    #[inline]
+    fn finish(&self) -> u64 {
+        avalanche(self.hash)
+    }
+}
+
+/// Builds `FxHasher`s from a seed that is picked when the builder is created
+#[derive(Clone)]
+pub struct FxBuildHasher {
+    seed: u64,
+}
+
+impl Default for FxBuildHasher {
+    fn default() -> Self {
+        FxBuildHasher { seed: seed() }
+    }
+}
+
+impl BuildHasher for FxBuildHasher {
+    type Hasher = FxHasher;
+
+    #[inline(always)]
+    fn build_hasher(&self) -> FxHasher {
+        FxHasher { hash: self.seed }
+    }
+}
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// FnvHasher is the 64 bit FNV-1a hasher, which xors every byte written into the state and then
+/// multiplies it by a prime. The multiply only carries a byte's bits upwards by a few places, so
+/// the top bits of the state hardly depend on the last bytes written, and the state is run
+/// through `avalanche` before it is handed out just like `FxHasher`'s. It mixes in a byte at a
+/// time, so it is quick for the shortest keys, but it is slow for long ones.
+pub struct FnvHasher {
+    hash: u64,
+}
+
+impl Hasher for FnvHasher {
+    #[inline]
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.hash = (self.hash ^ *byte as u64).wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    #[inline]
+    fn finish(&self) -> u64 {
+        avalanche(self.hash)
+    }
+}
+
+/// Builds `FnvHasher`s from a seed that is picked when the builder is created
+#[derive(Clone)]
+pub struct FnvBuildHasher {
+    seed: u64,
+}
+
+impl Default for FnvBuildHasher {
+    fn default() -> Self {
+        FnvBuildHasher { seed: seed() }
+    }
+}
+
+impl BuildHasher for FnvBuildHasher {
+    type Hasher = FnvHasher;
+
+    #[inline(always)]
+    fn build_hasher(&self) -> FnvHasher {
+        FnvHasher {
+            hash: FNV_OFFSET ^ self.seed,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FnvBuildHasher, FxBuildHasher, HashAlgorithm};
+    use crate::data_type::{BoopArray, BoopString, DataType, Int};
+    use bytes::Bytes;
+    use std::{collections::hash_map::RandomState, hash::BuildHasher};
+
+    fn keys() -> Vec<DataType> {
+        let mut keys: Vec<DataType> = (0..4_096).map(Int::new_u64).collect();
+        keys.extend((0..4_096).map(|i| BoopString::new_wrapped(Bytes::from(format!("user:{i}")))));
+        keys.extend(
+            (0..4_096).map(|i| BoopArray::new_wrapped(vec![Int::new_u8(1), Int::new_u64(i << 32)])),
+        );
+        keys
+    }
+
+    /// Checks that keys spread evenly over 64 buckets, picked both by the top bits of their hash
+    /// and by the bottom bits, as the tables that keys go in use both
+    fn spreads_keys(hasher: impl BuildHasher) {
+        let keys = keys();
+        let mut top = [0; 64];
+        let mut bottom = [0; 64];
+        for key in &keys {
+            let hash = hasher.hash_one(key);
+            top[(hash >> 58) as usize] += 1;
+            bottom[(hash & 63) as usize] += 1;
+        }
+
+        let expected = keys.len() / 64;
+        for count in top.iter().chain(&bottom) {
+            assert!(
+                *count > expected / 2 && *count < expected * 2,
+                "{count} keys in a bucket, expected around {expected}"
+            );
+        }
+    }
+
+    #[test]
+    fn hashers_spread_keys() {
+        spreads_keys(RandomState::new());
+        spreads_keys(FxBuildHasher::default());
+        spreads_keys(FnvBuildHasher::default());
+    }
+
+    #[test]
+    fn hashes_depend_on_the_whole_key() {
+        let hasher = FxBuildHasher::default();
+        let string = |bytes: &[u8]| BoopString::new_wrapped(Bytes::copy_from_slice(bytes));
+
+        assert_eq!(
+            hasher.hash_one(string(b"key")),
+            hasher.hash_one(string(b"key"))
+        );
+        assert_ne!(
+            hasher.hash_one(string(b"key")),
+            hasher.hash_one(string(b"key\0"))
+        );
+        assert_ne!(hasher.hash_one(string(b"")), hasher.hash_one(string(b"\0")));
+        assert_ne!(
+            hasher.hash_one(Int::new_u8(1)),
+            hasher.hash_one(string(b"\x01"))
+        );
+
+        // Different seeds hash the same key differently
+        assert_ne!(
+            FxBuildHasher::default().hash_one(Int::new_u8(1)),
+            FxBuildHasher::default().hash_one(Int::new_u8(1))
+        );
+    }
+
+    #[test]
+    fn parses_names() {
+        assert_eq!(
+            "siphash".parse::<HashAlgorithm>().unwrap(),
+            HashAlgorithm::SipHash
+        );
+        assert_eq!(
+            "fxhash".parse::<HashAlgorithm>().unwrap(),
+            HashAlgorithm::FxHash
+        );
+        assert_eq!("fnv".parse::<HashAlgorithm>().unwrap(), HashAlgorithm::Fnv);
+        assert!("md5".parse::<HashAlgorithm>().is_err());
+    }
+}
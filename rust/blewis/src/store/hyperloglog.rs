@@ -0,0 +1,441 @@
+//! HyperLogLog cardinality estimation. A HyperLogLog estimates how many distinct elements have been
+//! added to it using a fixed 2^14 registers, so it never grows past 12 KiB no matter how many
+//! elements it has seen, at the cost of a standard error of 1.04 / sqrt(2^14), about 0.81%.
+//!
+//! HyperLogLogs are stored as ordinary strings, so they can be read with GET and written back with
+//! SET, and they survive anything else that a string does. A string is only treated as a
+//! HyperLogLog if it starts with `HYLL` and is well formed, and every other string is a wrong type.
+//!
+//! Elements are hashed with a fixed hash function rather than the store's, so that the same element
+//! always lands in the same register, across restarts and between servers.
+
+use super::Store;
+use crate::data_type::{BoopError, BoopString, DataType, Int};
+use bytes::{BufMut, Bytes, BytesMut};
+
+/// The amount of bits of an element's hash that pick its register
+const PRECISION: u32 = 14;
+/// The amount of registers
+const REGISTERS: usize = 1 << PRECISION;
+/// The amount of hash bits left once the register has been picked. A register holds the position
+/// of the lowest set bit among them, so it never holds more than `Q + 1`.
+const Q: u32 = 64 - PRECISION;
+
+const MAGIC: &[u8; 4] = b"HYLL";
+const SPARSE: u8 = 0;
+const DENSE: u8 = 1;
+const HEADER_LEN: usize = MAGIC.len() + 1;
+/// Dense registers are packed into 6 bits each
+const DENSE_LEN: usize = REGISTERS * 6 / 8;
+/// Sparse entries are a u16 register index and a u8 register value
+const SPARSE_ENTRY_LEN: usize = 3;
+/// The most non-zero registers a HyperLogLog keeps in the sparse encoding, before switching to the
+/// dense one
+const SPARSE_MAX_ENTRIES: usize = 1024;
+
+/// The registers of a HyperLogLog, unpacked to one byte each while it is being worked on
+#[derive(Debug, PartialEq, Eq, Clone)]
+struct HyperLogLog {
+    registers: Box<[u8; REGISTERS]>,
+}
+
+impl HyperLogLog {
+    fn new() -> Self {
+        HyperLogLog {
+            registers: Box::new([0; REGISTERS]),
+        }
+    }
+
+    /// Parses a HyperLogLog out of a string, returning `None` if it isn't a well formed one
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        let (header, payload) = bytes.split_at_checked(HEADER_LEN)?;
+        if &header[..MAGIC.len()] != MAGIC {
+            return None;
+        }
+
+        let mut hll = HyperLogLog::new();
+        match header[MAGIC.len()] {
+            SPARSE => {
+                if payload.len() % SPARSE_ENTRY_LEN != 0 {
+                    return None;
+                }
+
+                // Indices are strictly ascending, so every register is only set once
+                let mut next = 0;
+                for entry in payload.chunks_exact(SPARSE_ENTRY_LEN) {
+                    let index = u16::from_be_bytes([entry[0], entry[1]]) as usize;
+                    let value = entry[2];
+                    if index < next || index >= REGISTERS || value == 0 || value as u32 > Q + 1 {
+                        return None;
+                    }
+                    hll.registers[index] = value;
+                    next = index + 1;
+                }
+            }
+            DENSE => {
+                if payload.len() != DENSE_LEN {
+                    return None;
+                }
+
+                // Every 3 bytes hold 4 registers, MSB-first
+                for (packed, registers) in payload
+                    .chunks_exact(3)
+                    .zip(hll.registers.chunks_exact_mut(4))
+                {
+                    let word = u32::from_be_bytes([0, packed[0], packed[1], packed[2]]);
+                    for (i, register) in registers.iter_mut().enumerate() {
+                        *register = (word >> (18 - 6 * i) & 0x3F) as u8;
+                        if *register as u32 > Q + 1 {
+                            return None;
+                        }
+                    }
+                }
+            }
+            _ => return None,
+        }
+
+        Some(hll)
+    }
+
+    /// Serializes the HyperLogLog, using the sparse encoding while few enough registers are set
+    fn encode(&self) -> Bytes {
+        let set = self.registers.iter().filter(|r| **r != 0).count();
+        let mut buf;
+
+        if set <= SPARSE_MAX_ENTRIES {
+            buf = BytesMut::with_capacity(HEADER_LEN + set * SPARSE_ENTRY_LEN);
+            buf.put_slice(MAGIC);
+            buf.put_u8(SPARSE);
+            for (index, value) in self.registers.iter().enumerate() {
+                if *value != 0 {
+                    buf.put_u16(index as u16);
+                    buf.put_u8(*value);
+                }
+            }
+        } else {
+            buf = BytesMut::with_capacity(HEADER_LEN + DENSE_LEN);
+            buf.put_slice(MAGIC);
+            buf.put_u8(DENSE);
+            for registers in self.registers.chunks_exact(4) {
+                let word = registers.iter().fold(0u32, |word, r| word << 6 | *r as u32);
+                buf.put_slice(&word.to_be_bytes()[1..]);
+            }
+        }
+
+        buf.freeze()
+    }
+
+    /// Adds an element by its hash, returning whether any register changed
+    fn add(&mut self, hash: u64) -> bool {
+        let index = (hash & (REGISTERS as u64 - 1)) as usize;
+        // The extra bit caps the value at Q + 1 when every remaining bit is zero
+        let value = ((hash >> PRECISION) | 1 << Q).trailing_zeros() as u8 + 1;
+
+        if value > self.registers[index] {
+            self.registers[index] = value;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Makes this HyperLogLog count the union of itself and `other`
+    fn merge(&mut self, other: &HyperLogLog) {
+        for (register, other) in self.registers.iter_mut().zip(other.registers.iter()) {
+            *register = (*register).max(*other);
+        }
+    }
+
+    /// Estimates the cardinality, using the improved estimator from Otmar Ertl's "New cardinality
+    /// estimation algorithms for HyperLogLog sketches", which stays accurate for small
+    /// cardinalities without switching to linear counting.
+    fn count(&self) -> u64 {
+        let mut histogram = [0u32; Q as usize + 2];
+        for register in self.registers.iter() {
+            histogram[*register as usize] += 1;
+        }
+
+        let m = REGISTERS as f64;
+        let mut z = m * tau(1.0 - histogram[Q as usize + 1] as f64 / m);
+        for k in (1..=Q as usize).rev() {
+            z = 0.5 * (z + histogram[k] as f64);
+        }
+        z += m * sigma(histogram[0] as f64 / m);
+
+        let alpha = 0.5 / std::f64::consts::LN_2;
+        (alpha * m * m / z).round() as u64
+    }
+}
+
+fn sigma(mut x: f64) -> f64 {
+    if x == 1.0 {
+        return f64::INFINITY;
+    }
+
+    let mut y = 1.0;
+    let mut z = x;
+    loop {
+        x *= x;
+        let previous = z;
+        z += x * y;
+        y += y;
+        if z == previous {
+            return z;
+        }
+    }
+}
+
+fn tau(mut x: f64) -> f64 {
+    if x == 0.0 || x == 1.0 {
+        return 0.0;
+    }
+
+    let mut y = 1.0;
+    let mut z = 1.0 - x;
+    loop {
+        x = x.sqrt();
+        let previous = z;
+        y *= 0.5;
+        z -= (1.0 - x).powi(2) * y;
+        if z == previous {
+            return z / 3.0;
+        }
+    }
+}
+
+/// Hashes an element with 64 bit FNV-1a, finished with MurmurHash3's finalizer so that every bit
+/// of the hash depends on every bit of the element. Elements are hashed along with their meta byte,
+/// so the u8 1 and the u16 1 are different elements, as they are different keys. Only integers,
+/// bools and strings can be elements.
+fn hash(element: &DataType) -> Option<u64> {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    let mut write = |bytes: &[u8]| {
+        for byte in bytes {
+            hash ^= *byte as u64;
+            hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    };
+
+    write(&[element.meta_byte()]);
+    match element {
+        DataType::Num(Int::Tiny(v)) => write(&v.to_be_bytes()),
+        DataType::Num(Int::Small(v)) => write(&v.to_be_bytes()),
+        DataType::Num(Int::Medium(v)) => write(&v.to_be_bytes()),
+        DataType::Num(Int::Large(v)) => write(&v.to_be_bytes()),
+        DataType::Num(Int::FloatS(v)) => write(&v.0.to_be_bytes()),
+        DataType::Num(Int::FloatL(v)) => write(&v.0.to_be_bytes()),
+        DataType::Bool(v) => write(&[v.0 as u8]),
+        DataType::String(BoopString(bytes)) => write(bytes),
+        _ => return None,
+    }
+
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    hash ^= hash >> 33;
+    Some(hash)
+}
+
+/// Reads the HyperLogLog held by a value, where a missing value is an empty HyperLogLog
+fn read(value: Option<&DataType>) -> Result<HyperLogLog, DataType> {
+    match value {
+        Some(DataType::String(BoopString(bytes))) => {
+            HyperLogLog::decode(bytes).ok_or_else(BoopError::wrong_type)
+        }
+        None => Ok(HyperLogLog::new()),
+        Some(_) => Err(BoopError::wrong_type()),
+    }
+}
+
+/// Reads the union of the HyperLogLogs held by `keys`, as one atomic snapshot
+fn read_union(store: &Store, keys: &[DataType]) -> Result<HyperLogLog, DataType> {
+    store.view_many(keys, |values| {
+        let mut union = HyperLogLog::new();
+        for value in values {
+            union.merge(&read(*value)?);
+        }
+        Ok(union)
+    })
+}
+
+impl Store {
+    /// Adds `elements` to the HyperLogLog held by `key`, creating it if the key does not exist.
+    /// Returns whether the estimated cardinality may have changed, which is also true when the key
+    /// is created. Elements that aren't integers, bools or strings return a `wrong_type` error, in
+    /// which case none of them are added.
+    pub fn pfadd(&self, key: &DataType, elements: &[DataType]) -> Result<bool, DataType> {
+        let hashes = elements
+            .iter()
+            .map(hash)
+            .collect::<Option<Vec<u64>>>()
+            .ok_or_else(BoopError::wrong_type)?;
+
+        self.update(key, |value| {
+            let created = value.is_none();
+            let mut hll = match read(value.as_ref()) {
+                Ok(hll) => hll,
+                Err(err) => return (Err(err), false),
+            };
+
+            let changed = hashes
+                .into_iter()
+                .fold(false, |changed, hash| hll.add(hash) | changed);
+            if changed || created {
+                *value = Some(BoopString::new_wrapped(hll.encode()));
+            }
+
+            (Ok(changed || created), changed || created)
+        })
+    }
+
+    /// Estimates the amount of distinct elements added to any of the HyperLogLogs held by `keys`.
+    /// Keys that don't exist count as empty HyperLogLogs.
+    pub fn pfcount(&self, keys: &[DataType]) -> Result<u64, DataType> {
+        Ok(read_union(self, keys)?.count())
+    }
+
+    /// Merges the HyperLogLogs held by `keys` into the one held by `dest`, creating it if it does
+    /// not exist. `dest` keeps its expiry, like any other update. The source keys are read as one
+    /// atomic snapshot, see `Store::view_many`.
+    pub fn pfmerge(&self, dest: &DataType, keys: &[DataType]) -> Result<(), DataType> {
+        let union = read_union(self, keys)?;
+
+        self.update(dest, |value| {
+            let mut hll = match read(value.as_ref()) {
+                Ok(hll) => hll,
+                Err(err) => return (Err(err), false),
+            };
+            hll.merge(&union);
+            *value = Some(BoopString::new_wrapped(hll.encode()));
+            (Ok(()), true)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HyperLogLog, DENSE_LEN, HEADER_LEN, REGISTERS};
+    use crate::{
+        data_type::{BoopArray, BoopError, BoopString, DataType, Int},
+        store::{SetTtl, Store},
+    };
+    use bytes::Bytes;
+
+    fn elements(range: std::ops::Range<u64>) -> Vec<DataType> {
+        range.map(Int::new_u64).collect()
+    }
+
+    fn stored_len(store: &Store, key: &DataType) -> usize {
+        match store.get(key) {
+            Some(DataType::String(BoopString(bytes))) => bytes.len(),
+            _ => 0,
+        }
+    }
+
+    #[test]
+    fn estimates_within_the_standard_error() {
+        let store = Store::new();
+        let key = Int::new_u8(1);
+
+        for (added, expected) in [(0, 0u64), (1, 1), (100, 100), (10_000, 10_000)] {
+            let store = Store::new();
+            store.pfadd(&key, &elements(0..added)).unwrap();
+            let count = store.pfcount(&[key.to_owned()]).unwrap();
+            assert!(
+                count.abs_diff(expected) <= expected / 50,
+                "{count} for {expected}"
+            );
+        }
+
+        for start in (0..200_000).step_by(10_000) {
+            store.pfadd(&key, &elements(start..start + 10_000)).unwrap();
+        }
+        // Adding the same elements again doesn't change anything
+        assert_eq!(store.pfadd(&key, &elements(0..1_000)), Ok(false));
+
+        let count = store.pfcount(&[key]).unwrap();
+        assert!(count.abs_diff(200_000) <= 200_000 * 243 / 10_000, "{count}");
+    }
+
+    #[test]
+    fn switches_from_sparse_to_dense() {
+        let store = Store::new();
+        let key = Int::new_u8(1);
+
+        assert_eq!(store.pfadd(&key, &[]), Ok(true));
+        assert_eq!(stored_len(&store, &key), HEADER_LEN);
+        assert_eq!(store.pfadd(&key, &[]), Ok(false));
+
+        store.pfadd(&key, &elements(0..100)).unwrap();
+        assert!(stored_len(&store, &key) < HEADER_LEN + 100 * 3 + 1);
+
+        store.pfadd(&key, &elements(100..10_000)).unwrap();
+        assert_eq!(stored_len(&store, &key), HEADER_LEN + DENSE_LEN);
+    }
+
+    #[test]
+    fn encoding_round_trips() {
+        let mut hll = HyperLogLog::new();
+        for i in 0..REGISTERS {
+            hll.registers[i] = (i % 52) as u8;
+        }
+        assert_eq!(HyperLogLog::decode(&hll.encode()), Some(hll.to_owned()));
+
+        let mut sparse = HyperLogLog::new();
+        sparse.registers[5] = 51;
+        sparse.registers[REGISTERS - 1] = 1;
+        assert_eq!(sparse.encode().len(), HEADER_LEN + 6);
+        assert_eq!(HyperLogLog::decode(&sparse.encode()), Some(sparse));
+
+        // A copy of the string is an identical HyperLogLog
+        let store = Store::new();
+        store.pfadd(&Int::new_u8(1), &elements(0..5_000)).unwrap();
+        let copy = store.get(&Int::new_u8(1)).unwrap();
+        store.set(&Int::new_u8(2), &copy, SetTtl::Clear);
+        assert_eq!(
+            store.pfcount(&[Int::new_u8(1)]),
+            store.pfcount(&[Int::new_u8(2)])
+        );
+    }
+
+    #[test]
+    fn count_and_merge_take_the_union() {
+        let store = Store::new();
+        let (a, b, dest) = (Int::new_u8(1), Int::new_u8(2), Int::new_u8(3));
+        store.pfadd(&a, &elements(0..3_000)).unwrap();
+        store.pfadd(&b, &elements(2_000..5_000)).unwrap();
+        store.pfadd(&dest, &elements(10_000..11_000)).unwrap();
+
+        let union = store.pfcount(&[a.to_owned(), b.to_owned()]).unwrap();
+        assert!(union.abs_diff(5_000) <= 100, "{union}");
+
+        assert_eq!(store.pfmerge(&dest, &[a, b, Int::new_u8(4)]), Ok(()));
+        let merged = store.pfcount(&[dest]).unwrap();
+        assert!(merged.abs_diff(6_000) <= 120, "{merged}");
+    }
+
+    #[test]
+    fn rejects_anything_else() {
+        let store = Store::new();
+        let (string, list) = (Int::new_u8(1), Int::new_u8(2));
+        store.set(
+            &string,
+            &BoopString::new_wrapped(Bytes::from_static(b"HYLL\x01short")),
+            SetTtl::Clear,
+        );
+        store.set(&list, &BoopArray::new_wrapped(vec![]), SetTtl::Clear);
+
+        assert_eq!(store.pfadd(&string, &[]), Err(BoopError::wrong_type()));
+        assert_eq!(store.pfcount(&[list]), Err(BoopError::wrong_type()));
+        assert_eq!(
+            store.pfmerge(&Int::new_u8(3), &[string]),
+            Err(BoopError::wrong_type())
+        );
+        assert_eq!(
+            store.pfadd(&Int::new_u8(3), &[BoopArray::new_wrapped(vec![])]),
+            Err(BoopError::wrong_type())
+        );
+        assert_eq!(store.get(&Int::new_u8(3)), None);
+    }
+}
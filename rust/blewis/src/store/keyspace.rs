@@ -0,0 +1,301 @@
+use super::{backend::Write, Entry, Store};
+use crate::data_type::{BoopBool, DataType};
+use std::{sync::Arc, thread};
+
+impl Store {
+    /// Counts how many of the given keys exist. A key that is given more than once is counted
+    /// every time it is given.
+    pub fn exists(&self, keys: &[DataType]) -> usize {
+        let now = self.now();
+        keys.iter()
+            .filter(|key| {
+                self.read_entry(key, |entry| {
+                    entry.is_some_and(|entry| !entry.is_expired(now))
+                })
+            })
+            .count()
+    }
+
+    /// Removes every one of the given keys, returning how many of them existed
+    pub fn del(&self, keys: &[DataType]) -> usize {
+        keys.iter()
+            .filter(|key| self.get_del(key).is_some())
+            .count()
+    }
+
+    /// Returns the meta byte of the value held by the key, which identifies its variant and, for
+    /// integers, its width. See `DataType::meta_byte`.
+    pub fn value_type(&self, key: &DataType) -> Option<u8> {
+        let now = self.now();
+        self.read_entry(key, |entry| {
+            entry
+                .filter(|entry| !entry.is_expired(now))
+                .map(|entry| entry.value.meta_byte())
+        })
+    }
+
+    /// The amount of entries in the store. This includes entries that have expired but haven't
+    /// been removed yet.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Removes every entry from the store, a segment of the backend at a time. When
+    /// `in_background` is true, the entries are dropped on a separate thread, so that the cost of
+    /// freeing large values isn't paid by the caller.
+    pub fn flush(&self, in_background: bool) {
+        let mut drained = Vec::with_capacity(self.map.len());
+        for segment in 0..self.map.segments() {
+            drained.extend(self.map.drain(segment));
+        }
+
+        for (key, entry) in &drained {
+            self.refund(key, entry);
+        }
+        if !drained.is_empty() {
+            self.invalidate_all();
+        }
+
+        if in_background {
+            thread::spawn(move || drop(drained));
+        }
+    }
+
+    /// Moves the value held by `key` to `new_key`, replacing any value `new_key` held. The expiry
+    /// of the key moves with it. Both keys are written as one atomic operation on the backend, so
+    /// no reader can see both keys or neither of them. Returns false if `key` does not exist.
+    pub fn rename(&self, key: &DataType, new_key: &DataType) -> bool {
+        let now = self.now();
+
+        if key == new_key {
+            return self
+                .update_entry(key, |entry| match entry {
+                    Some(entry) if !entry.is_expired(now) => {
+                        entry.touch(now);
+                        (Write::Keep, true)
+                    }
+                    _ => (Write::Keep, false),
+                })
+                .0;
+        }
+
+        self.update_entries(
+            &[key.to_owned(), new_key.to_owned()],
+            |entries| match entries[0].take() {
+                Some(mut entry) if !entry.is_expired(now) => {
+                    entry.touch(now);
+                    entries[1] = Some(entry);
+                    true
+                }
+                _ => false,
+            },
+        )
+    }
+
+    /// Moves `key` into another store, keeping its expiry, but only if the other store doesn't
+    /// hold the key already. The key is updated in both stores' backends at once, the two
+    /// backends always being entered in address order so that moves in opposite directions can't
+    /// deadlock. Returns whether the key was moved.
+    pub fn move_to(&self, key: &DataType, dest: &Store) -> bool {
+        let (src_addr, dest_addr) = (
+            Arc::as_ptr(&self.map) as *const () as usize,
+            Arc::as_ptr(&dest.map) as *const () as usize,
+        );
+        if src_addr == dest_addr {
+            return false;
+        }
+
+        let (now, dest_now) = (self.now(), dest.now());
+
+        // Takes the entry out of the source, if it can move, leaving a placeholder that is removed
+        // straight after. An expired entry that the destination still holds is replaced.
+        let take = |src: Option<&mut Entry>, dst: Option<&mut Entry>| -> Option<Entry> {
+            if dst.is_some_and(|entry| !entry.is_expired(dest_now)) {
+                return None;
+            }
+            let src = src.filter(|entry| !entry.is_expired(now))?;
+
+            let mut moved = Entry::new(DataType::Bool(BoopBool(false)), None, now);
+            std::mem::swap(&mut moved, src);
+            self.refund(key, &moved);
+            self.charge(key, src);
+            moved.touch(now);
+            Some(moved)
+        };
+        let remove_if = |moved: bool| if moved { Write::Remove } else { Write::Keep };
+        let insert = |moved: Option<Entry>| moved.map_or(Write::Keep, Write::Insert);
+
+        if src_addr < dest_addr {
+            self.update_entry(key, |src| {
+                let moved = dest
+                    .update_entry(key, |dst| {
+                        let moved = take(src, dst);
+                        let moving = moved.is_some();
+                        (insert(moved), moving)
+                    })
+                    .0;
+                (remove_if(moved), moved)
+            })
+            .0
+        } else {
+            dest.update_entry(key, |dst| {
+                let mut moved = None;
+                self.update_entry(key, |src| {
+                    moved = take(src, dst);
+                    (remove_if(moved.is_some()), ())
+                });
+                let moving = moved.is_some();
+                (insert(moved), moving)
+            })
+            .0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        data_type::{BoopArray, BoopBool, Int},
+        store::{clock::ManualClock, SetTtl, Store},
+    };
+    use std::sync::Arc;
+
+    #[test]
+    fn exists_and_del() {
+        let clock = Arc::new(ManualClock::new(1_000));
+        let store = Store::with_clock(clock.clone());
+        store.set(&Int::new_u8(1), &Int::new_u8(1), SetTtl::Clear);
+        store.set(&Int::new_u8(2), &Int::new_u8(2), SetTtl::Clear);
+        store.set(&Int::new_u8(3), &Int::new_u8(3), SetTtl::ExpireIn(10));
+        clock.advance(10);
+
+        let keys = [
+            Int::new_u8(1),
+            Int::new_u8(1),
+            Int::new_u8(2),
+            Int::new_u8(3),
+            Int::new_u8(4),
+        ];
+        assert_eq!(store.exists(&keys), 3);
+        assert_eq!(store.del(&keys), 2);
+        assert_eq!(store.exists(&keys), 0);
+        assert_eq!(store.len(), 0);
+    }
+
+    #[test]
+    fn value_type_reports_variant_and_width() {
+        let store = Store::new();
+        store.set(&Int::new_u8(1), &Int::new_u32(1), SetTtl::Clear);
+        store.set(&Int::new_u8(2), &BoopBool::new_wrapped(true), SetTtl::Clear);
+        store.set(
+            &Int::new_u8(3),
+            &BoopArray::new_wrapped(vec![]),
+            SetTtl::Clear,
+        );
+
+        assert_eq!(store.value_type(&Int::new_u8(1)), Some(0b_0001_0000));
+        assert_eq!(store.value_type(&Int::new_u8(2)), Some(0b_0000_0100));
+        assert_eq!(store.value_type(&Int::new_u8(3)), Some(0b_0000_0011));
+        assert_eq!(store.value_type(&Int::new_u8(4)), None);
+    }
+
+    #[test]
+    fn flush_in_foreground_and_background() {
+        let store = Store::new();
+        for background in [false, true] {
+            for i in 0..100 {
+                store.set(&Int::new_u8(i), &Int::new_u8(i), SetTtl::Clear);
+            }
+            assert_eq!(store.len(), 100);
+
+            store.flush(background);
+            assert_eq!(store.len(), 0);
+            assert!(store.get(&Int::new_u8(1)).is_none());
+        }
+    }
+
+    #[test]
+    fn rename_moves_value_and_expiry() {
+        let store = Store::new();
+        store.set(&Int::new_u8(1), &Int::new_u8(10), SetTtl::ExpireIn(60_000));
+        store.set(&Int::new_u8(2), &Int::new_u8(20), SetTtl::Clear);
+
+        assert!(store.rename(&Int::new_u8(1), &Int::new_u8(2)));
+        assert!(store.get(&Int::new_u8(1)).is_none());
+        assert_eq!(store.get(&Int::new_u8(2)), Some(Int::new_u8(10)));
+        assert!(store.ttl(&Int::new_u8(2)).unwrap().is_some());
+
+        assert!(!store.rename(&Int::new_u8(1), &Int::new_u8(3)));
+        assert!(store.rename(&Int::new_u8(2), &Int::new_u8(2)));
+        assert_eq!(store.get(&Int::new_u8(2)), Some(Int::new_u8(10)));
+    }
+
+    #[test]
+    fn move_to_only_moves_into_a_free_key() {
+        let (src, dest) = (Store::new(), Store::new());
+        src.set(&Int::new_u8(1), &Int::new_u8(10), SetTtl::ExpireIn(60_000));
+        src.set(&Int::new_u8(2), &Int::new_u8(20), SetTtl::Clear);
+        dest.set(&Int::new_u8(2), &Int::new_u8(0), SetTtl::Clear);
+
+        assert!(src.move_to(&Int::new_u8(1), &dest));
+        assert!(src.get(&Int::new_u8(1)).is_none());
+        assert_eq!(dest.get(&Int::new_u8(1)), Some(Int::new_u8(10)));
+        assert!(dest.ttl(&Int::new_u8(1)).unwrap().is_some());
+
+        // Neither store changes if the key is taken, missing, or moved onto its own store
+        assert!(!src.move_to(&Int::new_u8(2), &dest));
+        assert_eq!(src.get(&Int::new_u8(2)), Some(Int::new_u8(20)));
+        assert_eq!(dest.get(&Int::new_u8(2)), Some(Int::new_u8(0)));
+        assert!(!src.move_to(&Int::new_u8(3), &dest));
+        assert!(!src.move_to(&Int::new_u8(2), &src.clone()));
+        assert_eq!(src.get(&Int::new_u8(2)), Some(Int::new_u8(20)));
+    }
+
+    #[test]
+    fn opposite_moves_dont_deadlock() {
+        let (a, b) = (Store::new(), Store::new());
+        let key = Int::new_u8(1);
+        a.set(&key, &Int::new_u8(0), SetTtl::Clear);
+
+        let movers: Vec<_> = [(a.clone(), b.clone()), (b.clone(), a.clone())]
+            .into_iter()
+            .map(|(from, to)| {
+                let key = key.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..1_000 {
+                        from.move_to(&key, &to);
+                    }
+                })
+            })
+            .collect();
+
+        for mover in movers {
+            mover.join().unwrap();
+        }
+        assert_eq!(a.exists(std::slice::from_ref(&key)) + b.exists(&[key]), 1);
+    }
+
+    #[test]
+    fn rename_is_never_observed_half_done() {
+        let store = Store::new();
+        let keys = [Int::new_u16(1), Int::new_u16(2)];
+        store.set(&keys[0], &Int::new_u8(0), SetTtl::Clear);
+
+        let mover = {
+            let store = store.clone();
+            let keys = keys.clone();
+            std::thread::spawn(move || {
+                for i in 0..1_000 {
+                    assert!(store.rename(&keys[i % 2], &keys[(i + 1) % 2]));
+                }
+            })
+        };
+
+        for _ in 0..1_000 {
+            let values = store.mget(&keys);
+            assert_eq!(values.iter().filter(|v| v.is_some()).count(), 1);
+        }
+
+        mover.join().unwrap();
+    }
+}
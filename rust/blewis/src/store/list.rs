@@ -0,0 +1,304 @@
+use super::{written, Store};
+use crate::data_type::{BoopArray, BoopError, DataType};
+use std::collections::VecDeque;
+
+/// Which end of a list an operation works on
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum End {
+    Front,
+    Back,
+}
+
+/// Resolves a possibly negative index, where -1 is the last element, to a position in a list of
+/// length `len`. Returns `None` if the index is out of bounds.
+fn resolve_index(index: i64, len: usize) -> Option<usize> {
+    let resolved = if index < 0 { len as i64 + index } else { index };
+
+    (0..len as i64)
+        .contains(&resolved)
+        .then_some(resolved as usize)
+}
+
+/// Resolves an inclusive range of possibly negative indices to the positions it covers in a list
+/// of length `len`. Indices past either end are clamped, and an empty range returns `None`.
+pub(super) fn resolve_range(start: i64, stop: i64, len: usize) -> Option<(usize, usize)> {
+    let len = len as i64;
+    let start = if start < 0 {
+        (len + start).max(0)
+    } else {
+        start
+    };
+    let stop = if stop < 0 {
+        len + stop
+    } else {
+        stop.min(len - 1)
+    };
+
+    (start <= stop && start < len).then_some((start as usize, stop as usize))
+}
+
+/// Runs `f` against the list held by `key`. Keys that don't exist are treated as an empty list,
+/// which is never written back to the store, and lists that are left empty are removed. Keys
+/// holding anything other than an array return a `wrong_type` error. `f` also returns whether it
+/// changed the list, see `Store::update`.
+fn with_list<R>(
+    store: &Store,
+    key: &DataType,
+    f: impl FnOnce(&mut VecDeque<DataType>) -> Result<(R, bool), DataType>,
+) -> Result<R, DataType> {
+    store.update(key, |value| {
+        let mut list = match value.take() {
+            Some(DataType::Array(BoopArray(list))) => list,
+            None => VecDeque::new(),
+            Some(other) => {
+                *value = Some(other);
+                return (Err(BoopError::wrong_type()), false);
+            }
+        };
+
+        let (result, changed) = written(f(&mut list));
+        if !list.is_empty() {
+            *value = Some(DataType::Array(BoopArray(list)));
+        }
+
+        (result, changed)
+    })
+}
+
+/// Runs `f` against the list held by `key` without modifying it, under the shard's read lock. Keys
+/// that don't exist are treated as an empty list.
+fn view_list<R>(
+    store: &Store,
+    key: &DataType,
+    f: impl FnOnce(&VecDeque<DataType>) -> Result<R, DataType>,
+) -> Result<R, DataType> {
+    store.view(key, |value| match value {
+        Some(DataType::Array(BoopArray(list))) => f(list),
+        None => f(&VecDeque::new()),
+        Some(_) => Err(BoopError::wrong_type()),
+    })
+}
+
+impl Store {
+    /// Pushes elements on to one end of the list held by `key`, creating the list if the key does
+    /// not exist. Elements are pushed one at a time, so pushing `[a, b, c]` to the front results in
+    /// `[c, b, a, ...]`. Returns the length of the list once the elements have been pushed.
+    pub fn push(
+        &self,
+        key: &DataType,
+        elements: Vec<DataType>,
+        end: End,
+    ) -> Result<usize, DataType> {
+        with_list(self, key, |list| {
+            if list.len() + elements.len() > BoopArray::MAX_LEN {
+                return Err(BoopError::too_large());
+            }
+
+            match end {
+                End::Back => list.extend(elements),
+                End::Front => {
+                    for element in elements {
+                        list.push_front(element);
+                    }
+                }
+            }
+
+            // Any clients blocked on this key are served before the shard lock is released, so a
+            // pushed element can never be taken by a non-blocking pop ahead of them
+            let len = list.len();
+            self.waiters.serve(key, list);
+            Ok((len, true))
+        })
+    }
+
+    /// Removes and returns the element at one end of the list held by `key`
+    pub fn pop(&self, key: &DataType, end: End) -> Result<Option<DataType>, DataType> {
+        with_list(self, key, |list| {
+            let popped = match end {
+                End::Back => list.pop_back(),
+                End::Front => list.pop_front(),
+            };
+            let changed = popped.is_some();
+            Ok((popped, changed))
+        })
+    }
+
+    /// Returns the element at `index` of the list held by `key`. Returns `None` if the key does not
+    /// exist, and an `out_of_range` error if the index is outside of the list.
+    pub fn lindex(&self, key: &DataType, index: i64) -> Result<Option<DataType>, DataType> {
+        view_list(self, key, |list| {
+            if list.is_empty() {
+                return Ok(None);
+            }
+
+            match resolve_index(index, list.len()) {
+                Some(i) => Ok(Some(list[i].to_owned())),
+                None => Err(BoopError::out_of_range()),
+            }
+        })
+    }
+
+    /// Returns the elements between `start` and `stop` of the list held by `key`, inclusive
+    pub fn lrange(&self, key: &DataType, start: i64, stop: i64) -> Result<Vec<DataType>, DataType> {
+        view_list(self, key, |list| {
+            Ok(match resolve_range(start, stop, list.len()) {
+                Some((start, stop)) => list.range(start..=stop).cloned().collect(),
+                None => Vec::new(),
+            })
+        })
+    }
+
+    /// Replaces the element at `index` of the list held by `key`. Returns a `no_exist` error if the
+    /// key does not exist, and an `out_of_range` error if the index is outside of the list.
+    pub fn lset(&self, key: &DataType, index: i64, element: DataType) -> Result<(), DataType> {
+        with_list(self, key, |list| {
+            if list.is_empty() {
+                return Err(BoopError::no_exist());
+            }
+
+            match resolve_index(index, list.len()) {
+                Some(i) => {
+                    list[i] = element;
+                    Ok(((), true))
+                }
+                None => Err(BoopError::out_of_range()),
+            }
+        })
+    }
+
+    /// Trims the list held by `key` so that it only holds the elements between `start` and `stop`,
+    /// inclusive. If the range is empty, the key is removed.
+    pub fn ltrim(&self, key: &DataType, start: i64, stop: i64) -> Result<(), DataType> {
+        with_list(self, key, |list| {
+            let len = list.len();
+            match resolve_range(start, stop, len) {
+                Some((start, stop)) => {
+                    list.truncate(stop + 1);
+                    list.drain(..start);
+                }
+                None => list.clear(),
+            }
+            Ok(((), list.len() != len))
+        })
+    }
+
+    /// Returns the length of the list held by `key`, which is zero if the key does not exist
+    pub fn llen(&self, key: &DataType) -> Result<usize, DataType> {
+        view_list(self, key, |list| Ok(list.len()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{resolve_index, resolve_range, End};
+    use crate::{
+        data_type::{BoopArray, BoopError, DataType, Int},
+        store::{SetTtl, Store},
+    };
+
+    fn ints(values: &[u8]) -> Vec<DataType> {
+        values.iter().map(|v| Int::new_u8(*v)).collect()
+    }
+
+    #[test]
+    fn indices_and_ranges() {
+        assert_eq!(resolve_index(0, 3), Some(0));
+        assert_eq!(resolve_index(-1, 3), Some(2));
+        assert_eq!(resolve_index(-3, 3), Some(0));
+        assert_eq!(resolve_index(-4, 3), None);
+        assert_eq!(resolve_index(3, 3), None);
+        assert_eq!(resolve_index(0, 0), None);
+
+        assert_eq!(resolve_range(0, -1, 3), Some((0, 2)));
+        assert_eq!(resolve_range(-100, 100, 3), Some((0, 2)));
+        assert_eq!(resolve_range(1, 1, 3), Some((1, 1)));
+        assert_eq!(resolve_range(2, 1, 3), None);
+        assert_eq!(resolve_range(3, 5, 3), None);
+        assert_eq!(resolve_range(0, -4, 3), None);
+        assert_eq!(resolve_range(0, -1, 0), None);
+    }
+
+    #[test]
+    fn push_and_pop_both_ends() {
+        let store = Store::new();
+        let key = Int::new_u8(1);
+
+        assert_eq!(store.push(&key, ints(&[1, 2, 3]), End::Front), Ok(3));
+        assert_eq!(store.push(&key, ints(&[4, 5]), End::Back), Ok(5));
+        assert_eq!(store.lrange(&key, 0, -1), Ok(ints(&[3, 2, 1, 4, 5])));
+
+        assert_eq!(store.pop(&key, End::Front), Ok(Some(Int::new_u8(3))));
+        assert_eq!(store.pop(&key, End::Back), Ok(Some(Int::new_u8(5))));
+        assert_eq!(store.llen(&key), Ok(3));
+    }
+
+    #[test]
+    fn emptied_lists_are_removed() {
+        let store = Store::new();
+        let key = Int::new_u8(1);
+
+        store.push(&key, ints(&[1]), End::Back).unwrap();
+        assert_eq!(store.pop(&key, End::Back), Ok(Some(Int::new_u8(1))));
+        assert!(store.get(&key).is_none());
+        assert_eq!(store.pop(&key, End::Front), Ok(None));
+        assert_eq!(store.llen(&key), Ok(0));
+        assert!(store.get(&key).is_none());
+    }
+
+    #[test]
+    fn index_set_and_trim() {
+        let store = Store::new();
+        let key = Int::new_u8(1);
+
+        assert_eq!(store.lindex(&key, 0), Ok(None));
+        assert_eq!(
+            store.lset(&key, 0, Int::new_u8(1)),
+            Err(BoopError::no_exist())
+        );
+
+        store.push(&key, ints(&[0, 1, 2, 3, 4]), End::Back).unwrap();
+        assert_eq!(store.lindex(&key, -1), Ok(Some(Int::new_u8(4))));
+        assert_eq!(store.lindex(&key, 5), Err(BoopError::out_of_range()));
+
+        assert_eq!(store.lset(&key, -2, Int::new_u8(30)), Ok(()));
+        assert_eq!(
+            store.lset(&key, 10, Int::new_u8(30)),
+            Err(BoopError::out_of_range())
+        );
+
+        assert_eq!(store.ltrim(&key, 1, -2), Ok(()));
+        assert_eq!(store.lrange(&key, 0, -1), Ok(ints(&[1, 2, 30])));
+
+        assert_eq!(store.ltrim(&key, 5, 10), Ok(()));
+        assert!(store.get(&key).is_none());
+    }
+
+    #[test]
+    fn wrong_type_is_left_untouched() {
+        let store = Store::new();
+        let key = Int::new_u8(1);
+        store.set(&key, &Int::new_u8(1), SetTtl::Clear);
+
+        assert_eq!(
+            store.push(&key, ints(&[1]), End::Back),
+            Err(BoopError::wrong_type())
+        );
+        assert_eq!(store.pop(&key, End::Back), Err(BoopError::wrong_type()));
+        assert_eq!(store.llen(&key), Err(BoopError::wrong_type()));
+        assert_eq!(store.get(&key), Some(Int::new_u8(1)));
+    }
+
+    #[test]
+    fn push_respects_max_array_length() {
+        let store = Store::new();
+        let key = Int::new_u8(1);
+
+        let full = vec![Int::new_u8(0); BoopArray::MAX_LEN];
+        assert_eq!(store.push(&key, full, End::Back), Ok(BoopArray::MAX_LEN));
+        assert_eq!(
+            store.push(&key, ints(&[1]), End::Front),
+            Err(BoopError::too_large())
+        );
+        assert_eq!(store.llen(&key), Ok(BoopArray::MAX_LEN));
+    }
+}
@@ -0,0 +1,148 @@
+//! Accounting of the memory held by a store. The estimated footprint of every entry is added to a
+//! running total when the entry is written, and taken away again when it is removed, so the store
+//! always knows roughly how much memory it holds without having to walk its entries.
+
+use super::{eviction::EvictionPolicy, Entry, Store};
+use crate::data_type::DataType;
+use std::{
+    mem::size_of,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// Memory is the memory accounting of a store, shared by every clone of it
+#[derive(Debug, Default)]
+pub(crate) struct Memory {
+    /// The estimated bytes held by every entry of the store
+    used: AtomicUsize,
+    /// The most bytes the store may hold before writes need room to be made for them, or 0 for no
+    /// limit
+    pub limit: usize,
+    pub policy: EvictionPolicy,
+}
+
+impl Memory {
+    pub fn new(limit: usize, policy: EvictionPolicy) -> Self {
+        Memory {
+            used: AtomicUsize::new(0),
+            limit,
+            policy,
+        }
+    }
+}
+
+impl Entry {
+    /// The estimated bytes that the entry takes up in the store under `key`: the key and entry
+    /// themselves, along with whatever both of them hold on the heap
+    #[inline(always)]
+    pub fn footprint(&self, key: &DataType) -> usize {
+        size_of::<(DataType, Entry)>() + key.heap_size() + self.value_size as usize
+    }
+}
+
+impl Store {
+    /// The estimated bytes held by every entry of the store. It is an estimate of what the keys
+    /// and values hold, and doesn't count the spare capacity of the store's own tables.
+    pub fn used_memory(&self) -> usize {
+        self.memory.used.load(Ordering::Relaxed)
+    }
+
+    /// The most bytes the store may hold before writes need room to be made for them, or 0 for no
+    /// limit
+    pub fn max_memory(&self) -> usize {
+        self.memory.limit
+    }
+
+    /// How the store makes room for writes once it is over its memory limit
+    pub fn eviction_policy(&self) -> EvictionPolicy {
+        self.memory.policy
+    }
+
+    /// The estimated bytes that `key` takes up in the store, measuring the first `samples` members
+    /// of every collection in its value, or every member when `samples` is 0. Returns `None` if
+    /// the key does not exist. Estimating a key's usage doesn't count as accessing it.
+    pub fn memory_usage(&self, key: &DataType, samples: usize) -> Option<usize> {
+        let now = self.now();
+        self.read_entry(key, |entry| {
+            let entry = entry.filter(|entry| !entry.is_expired(now))?;
+            Some(
+                size_of::<(DataType, Entry)>()
+                    + key.heap_size_sampled(samples)
+                    + entry.value.heap_size_sampled(samples),
+            )
+        })
+    }
+
+    /// Adds an entry that has just been written under `key` to the running total
+    #[inline(always)]
+    pub(super) fn charge(&self, key: &DataType, entry: &Entry) {
+        self.memory
+            .used
+            .fetch_add(entry.footprint(key), Ordering::Relaxed);
+    }
+
+    /// Takes an entry that has just been removed from `key` away from the running total
+    #[inline(always)]
+    pub(super) fn refund(&self, key: &DataType, entry: &Entry) {
+        self.memory
+            .used
+            .fetch_sub(entry.footprint(key), Ordering::Relaxed);
+    }
+
+    /// Accounts for an entry's value being replaced in place, given the estimated size of the
+    /// value that was replaced
+    #[inline(always)]
+    pub(super) fn resized(&self, replaced: u32, entry: &Entry) {
+        let used = &self.memory.used;
+        used.fetch_add(entry.value_size as usize, Ordering::Relaxed);
+        used.fetch_sub(replaced as usize, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        data_type::{BoopArray, BoopString, Int},
+        store::{list::End, SetTtl, Store},
+    };
+    use bytes::Bytes;
+
+    #[test]
+    fn used_memory_follows_every_write() {
+        let store = Store::new();
+        let key = Int::new_u8(1);
+        assert_eq!(store.used_memory(), 0);
+
+        store.set(&key, &Int::new_u8(1), SetTtl::Clear);
+        let small = store.used_memory();
+        assert!(small > 0);
+
+        store.set(
+            &key,
+            &BoopString::new_wrapped(Bytes::from(vec![0; 1_000])),
+            SetTtl::Clear,
+        );
+        assert_eq!(store.used_memory(), small + 1_000);
+
+        store.del(std::slice::from_ref(&key));
+        assert_eq!(store.used_memory(), 0);
+
+        // Values that are changed in place are counted too
+        let item = || BoopString::new_wrapped(Bytes::from_static(b"item"));
+        store.push(&key, vec![item()], End::Back).unwrap();
+        let one = store.used_memory();
+        store.push(&key, vec![item()], End::Back).unwrap();
+        assert!(store.used_memory() > one);
+        store.pop(&key, End::Back).unwrap();
+        assert_eq!(store.used_memory(), one);
+
+        store.rename(&key, &BoopString::new_wrapped(Bytes::from_static(b"key")));
+        assert_eq!(store.used_memory(), one + 3);
+
+        store.mset(&[
+            (Int::new_u8(2), BoopArray::new_wrapped(vec![])),
+            (Int::new_u8(3), Int::new_u8(3)),
+        ]);
+        store.flush(false);
+        assert_eq!(store.used_memory(), 0);
+    }
+}
@@ -0,0 +1,334 @@
+//! Keyspace notifications, which publish an event through the server's `PubSub` hub whenever a
+//! key is set, deleted, expired or evicted. Every event is published to two channels, so that
+//! clients can pick the events they want either by key or by kind of event:
+//!  - `__keyspace@{db}__:{key}`, with the name of the event as the message. Only keys that are
+//!    strings have a keyspace channel, as channels can only be matched by pattern if they are
+//!    strings, so a single key pattern subscribes to every event of the keys it matches.
+//!  - `__keyevent@{db}__:{event}`, with the key as the message.
+//!
+//! Events are only built when the store has been told to publish them and something is
+//! subscribed to anything at all, so stores that nobody listens to pay one branch per write.
+//!
+//! Every write also invalidates the key for clients that may have cached it, which is just as
+//! cheap while no client is tracking.
+
+use super::Store;
+use crate::{
+    data_type::{BoopString, DataType},
+    pubsub::PubSub,
+};
+use bytes::{BufMut, Bytes, BytesMut};
+use std::{
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+/// KeyEvent is a change to a key that can be published
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum KeyEvent {
+    /// A value was written by SET, or one of its variants
+    Set,
+    /// The key was deleted by DEL or GETDEL
+    Del,
+    /// The key was removed as it had expired, whether lazily or by the sweeper
+    Expired,
+    /// The key was evicted to make room for a write
+    Evicted,
+}
+
+impl KeyEvent {
+    const ALL: [KeyEvent; 4] = [
+        KeyEvent::Set,
+        KeyEvent::Del,
+        KeyEvent::Expired,
+        KeyEvent::Evicted,
+    ];
+
+    /// The name of the event, as it is given to `--keyspace-events` and published
+    pub fn name(self) -> &'static str {
+        match self {
+            KeyEvent::Set => "set",
+            KeyEvent::Del => "del",
+            KeyEvent::Expired => "expired",
+            KeyEvent::Evicted => "evicted",
+        }
+    }
+
+    #[inline(always)]
+    const fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+/// KeyEvents is the set of events that a store publishes
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct KeyEvents(u8);
+
+impl KeyEvents {
+    pub const NONE: KeyEvents = KeyEvents(0);
+    pub const ALL: KeyEvents = KeyEvents(0b1111);
+
+    #[inline(always)]
+    pub fn contains(self, event: KeyEvent) -> bool {
+        self.0 & event.bit() != 0
+    }
+}
+
+impl FromStr for KeyEvents {
+    type Err = anyhow::Error;
+
+    /// Parses `all`, `none`, or a comma separated list of event names, such as `del,expired`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "all" => return Ok(KeyEvents::ALL),
+            "none" => return Ok(KeyEvents::NONE),
+            _ => {}
+        }
+
+        s.split(',').try_fold(KeyEvents::NONE, |events, name| {
+            match KeyEvent::ALL.iter().find(|event| event.name() == name) {
+                Some(event) => Ok(KeyEvents(events.0 | event.bit())),
+                None => anyhow::bail!("unknown keyspace event: {name}"),
+            }
+        })
+    }
+}
+
+/// Notifier is what a store publishes its events through, shared by every clone of it
+pub(crate) struct Notifier {
+    pubsub: PubSub,
+    events: KeyEvents,
+    /// The index of the database the store is behind, which changes when SWAPDB swaps it
+    db: AtomicUsize,
+}
+
+impl Notifier {
+    pub fn new(pubsub: PubSub, events: KeyEvents) -> Self {
+        Notifier {
+            pubsub,
+            events,
+            db: AtomicUsize::new(0),
+        }
+    }
+
+    /// Builds the name of a notification channel, such as `__keyspace@0__:`, followed by `suffix`
+    fn channel(&self, kind: &str, suffix: &[u8]) -> DataType {
+        let db = self.db.load(Ordering::Relaxed);
+        let prefix = format!("__{kind}@{db}__:");
+        let mut channel = BytesMut::with_capacity(prefix.len() + suffix.len());
+        channel.put(prefix.as_bytes());
+        channel.put(suffix);
+        BoopString::new_wrapped(channel.freeze())
+    }
+
+    #[cold]
+    fn publish(&self, event: KeyEvent, key: &DataType) {
+        let name = event.name();
+        if let DataType::String(BoopString(key)) = key {
+            self.pubsub.publish(
+                &self.channel("keyspace", key),
+                &BoopString::new_wrapped(Bytes::from_static(name.as_bytes())),
+            );
+        }
+        self.pubsub
+            .publish(&self.channel("keyevent", name.as_bytes()), key);
+    }
+}
+
+impl Default for Notifier {
+    fn default() -> Self {
+        Notifier::new(PubSub::new(), KeyEvents::NONE)
+    }
+}
+
+impl Store {
+    /// Publishes messages, and the store's events if any, through `pubsub`, which should be shared
+    /// by every store of the server so that a subscriber hears from every database
+    pub fn with_notifications(mut self, pubsub: &PubSub, events: KeyEvents) -> Self {
+        self.notifier = Arc::new(Notifier::new(pubsub.clone(), events));
+        self
+    }
+
+    /// The hub that the store publishes through
+    pub(crate) fn pubsub(&self) -> PubSub {
+        self.notifier.pubsub.clone()
+    }
+
+    /// Sets the index of the database the store is behind, which names its event channels
+    pub(crate) fn set_db(&self, db: usize) {
+        self.notifier.db.store(db, Ordering::Relaxed);
+    }
+
+    /// Publishes a message to a channel, returning the amount of subscriptions it was sent to
+    pub fn publish(&self, channel: &DataType, message: &DataType) -> usize {
+        self.notifier.pubsub.publish(channel, message)
+    }
+
+    /// Publishes an event for `key`, if the store publishes events of its kind and anything is
+    /// listening
+    #[inline(always)]
+    pub(super) fn notify(&self, event: KeyEvent, key: &DataType) {
+        if self.notifies(event) {
+            self.notifier.publish(event, key);
+        }
+    }
+
+    /// Tells every client that may have cached `key` from the store's database that it has
+    /// changed, see `Tracking`
+    #[inline(always)]
+    pub(super) fn invalidate(&self, key: &DataType) {
+        if self.notifier.pubsub.is_tracking() {
+            let db = self.notifier.db.load(Ordering::Relaxed);
+            self.notifier.pubsub.invalidate(db, key);
+        }
+    }
+
+    /// Tells every tracking client that every key it cached may have changed
+    pub(crate) fn invalidate_all(&self) {
+        if self.notifier.pubsub.is_tracking() {
+            self.notifier.pubsub.invalidate_all();
+        }
+    }
+
+    /// Whether an event of the kind would be published right now, which callers can check to skip
+    /// any work needed to find out whether an event happened
+    #[inline(always)]
+    pub(super) fn notifies(&self, event: KeyEvent) -> bool {
+        self.notifier.events.contains(event) && self.notifier.pubsub.has_subscribers()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{KeyEvent, KeyEvents};
+    use crate::{
+        data_type::{BoopArray, BoopString, DataType, Int},
+        pubsub::{PubSub, Subscriber},
+        store::{clock::ManualClock, eviction::EvictionPolicy, expiry::Sweeper, SetTtl, Store},
+    };
+    use bytes::Bytes;
+    use std::{sync::Arc, time::Duration};
+
+    fn string(s: &str) -> DataType {
+        BoopString::new_wrapped(Bytes::copy_from_slice(s.as_bytes()))
+    }
+
+    fn pmessage(pattern: &str, channel: &str, message: DataType) -> DataType {
+        BoopArray::new_wrapped(vec![
+            string("pmessage"),
+            string(pattern),
+            string(channel),
+            message,
+        ])
+    }
+
+    #[test]
+    fn parses_events() {
+        assert_eq!("all".parse::<KeyEvents>().unwrap(), KeyEvents::ALL);
+        assert_eq!("none".parse::<KeyEvents>().unwrap(), KeyEvents::NONE);
+
+        let events: KeyEvents = "del,expired".parse().unwrap();
+        assert!(events.contains(KeyEvent::Del));
+        assert!(events.contains(KeyEvent::Expired));
+        assert!(!events.contains(KeyEvent::Set));
+        assert!(!events.contains(KeyEvent::Evicted));
+
+        assert!("del,renamed".parse::<KeyEvents>().is_err());
+        assert!("".parse::<KeyEvents>().is_err());
+    }
+
+    #[test]
+    fn writes_publish_events() {
+        let pubsub = PubSub::new();
+        let clock = Arc::new(ManualClock::new(0));
+        let store = Store::with_clock(clock.clone()).with_notifications(&pubsub, KeyEvents::ALL);
+        store.set_db(3);
+
+        let mut keys = pubsub.subscriber();
+        keys.psubscribe(vec![Bytes::from_static(b"__keyspace@3__:user:*")]);
+        let mut expired = pubsub.subscriber();
+        expired.subscribe(vec![string("__keyevent@3__:expired")]);
+        let next = |subscriber: &Subscriber| subscriber.next_message(Duration::ZERO);
+
+        store.set(&string("user:1"), &Int::new_u8(1), SetTtl::ExpireIn(10));
+        store.set(&string("order:1"), &Int::new_u8(1), SetTtl::ExpireIn(10));
+        assert_eq!(
+            next(&keys),
+            Some(pmessage(
+                "__keyspace@3__:user:*",
+                "__keyspace@3__:user:1",
+                string("set")
+            ))
+        );
+        assert_eq!(next(&keys), None);
+
+        // Expired keys are published however they are removed, lazily here
+        clock.advance(10);
+        assert_eq!(store.get(&string("user:1")), None);
+        assert_eq!(
+            next(&keys),
+            Some(pmessage(
+                "__keyspace@3__:user:*",
+                "__keyspace@3__:user:1",
+                string("expired")
+            ))
+        );
+        assert_eq!(
+            next(&expired),
+            Some(BoopArray::new_wrapped(vec![
+                string("message"),
+                string("__keyevent@3__:expired"),
+                string("user:1"),
+            ]))
+        );
+
+        // And by the sweeper
+        Sweeper::new(store.clone()).sweep();
+        assert_eq!(
+            next(&expired),
+            Some(BoopArray::new_wrapped(vec![
+                string("message"),
+                string("__keyevent@3__:expired"),
+                string("order:1"),
+            ]))
+        );
+
+        store.set(&string("user:2"), &Int::new_u8(1), SetTtl::Clear);
+        next(&keys);
+        store.del(&[string("user:2"), string("user:3")]);
+        assert_eq!(
+            next(&keys),
+            Some(pmessage(
+                "__keyspace@3__:user:*",
+                "__keyspace@3__:user:2",
+                string("del")
+            ))
+        );
+        assert_eq!(next(&keys), None);
+    }
+
+    #[test]
+    fn only_picked_events_are_published() {
+        let pubsub = PubSub::new();
+        let store = Store::new().with_notifications(&pubsub, "evicted".parse().unwrap());
+        let store = store.with_max_memory(1, EvictionPolicy::Random);
+        let mut subscriber = pubsub.subscriber();
+        subscriber.psubscribe(vec![Bytes::from_static(b"__keyevent@0__:*")]);
+
+        // Integer keys have no keyspace channel, but are still published as key events
+        store.set(&Int::new_u8(1), &Int::new_u8(1), SetTtl::Clear);
+        store.make_room().unwrap();
+        assert_eq!(
+            subscriber.next_message(Duration::ZERO),
+            Some(pmessage(
+                "__keyevent@0__:*",
+                "__keyevent@0__:evicted",
+                Int::new_u8(1)
+            ))
+        );
+        assert_eq!(subscriber.next_message(Duration::ZERO), None);
+    }
+}
@@ -0,0 +1,292 @@
+use super::Store;
+use crate::{data_type::DataType, glob::glob_match};
+use bytes::Bytes;
+use std::collections::BinaryHeap;
+
+/// ScanFilter narrows down which keys are returned by a scan. Filters are applied after entries
+/// have been examined, so a batch can contain fewer keys than were asked for.
+#[derive(Debug, PartialEq, Eq, Default, Clone)]
+pub struct ScanFilter {
+    /// Only return string keys that match this glob pattern. Keys that are not strings never
+    /// match a pattern.
+    pub pattern: Option<Bytes>,
+    /// Only return keys whose value has these variant bits, see `DataType::variant_bits`
+    pub variant: Option<u8>,
+}
+
+impl ScanFilter {
+    fn matches(&self, key: &DataType, value: &DataType) -> bool {
+        if let Some(pattern) = &self.pattern {
+            match key {
+                DataType::String(s) if glob_match(pattern, &s.0) => {}
+                _ => return false,
+            }
+        }
+
+        self.variant
+            .is_none_or(|variant| value.variant_bits() == variant)
+    }
+}
+
+impl Store {
+    /// Walks the keyspace in batches. Examines up to `count` entries whose position in the scan
+    /// order is at or after `cursor`, and returns the keys of those which pass the filter,
+    /// alongside the cursor to pass in to get the next batch. A returned cursor of zero means that
+    /// the whole keyspace has been walked.
+    ///
+    /// Because the cursor is a position in the hash order rather than an index, keys that are
+    /// added or removed between calls can't shift other keys around. So a key which is present for
+    /// the whole scan is always returned, while keys that come and go during the scan may or may
+    /// not be. A key can be returned more than once only if it has the same 64 bit hash as other
+    /// keys, as all keys with the same hash are always examined in the same batch.
+    ///
+    /// The backend is walked a segment at a time, see `StorageBackend::visit`, so nothing has to
+    /// stop for the whole scan. Each batch passes over a segment once, keeping only the `count`
+    /// lowest positions it has seen, so a batch only copies out the keys it returns.
+    pub fn scan(&self, cursor: u64, count: usize, filter: &ScanFilter) -> (u64, Vec<DataType>) {
+        let now = self.now();
+        let segments = self.map.segments();
+        let shift = usize::BITS - segments.trailing_zeros();
+
+        let mut cursor = cursor as usize;
+        let mut shard = cursor.checked_shr(shift).unwrap_or(0);
+        let mut examined = 0;
+        let mut keys = Vec::new();
+
+        while shard < segments && examined < count.max(1) {
+            let need = count.max(1) - examined;
+
+            // The `need` lowest positions at or after the cursor, and the key at each of them if
+            // it is to be returned. Every key sharing the highest position is kept, so that the
+            // next cursor can start after it.
+            let mut batch: BinaryHeap<(usize, Option<DataType>)> = BinaryHeap::new();
+            let mut candidates = 0;
+            self.map.visit(shard, 0, &mut |key, entry| {
+                let pos = self.map.position(key);
+                if pos < cursor {
+                    return true;
+                }
+                candidates += 1;
+                if batch.len() >= need && batch.peek().is_some_and(|(top, _)| pos > *top) {
+                    return true;
+                }
+
+                let returned = !entry.is_expired(now) && filter.matches(key, &entry.value);
+                batch.push((pos, returned.then(|| key.to_owned())));
+                if batch.len() > need {
+                    evict_highest(&mut batch, need);
+                }
+                true
+            });
+
+            let exhausted = candidates <= need;
+            let last = batch.peek().map(|(pos, _)| *pos);
+            examined += batch.len();
+            keys.extend(batch.into_iter().filter_map(|(_, key)| key));
+
+            let next = match (exhausted, last) {
+                (false, Some(last)) => last.checked_add(1),
+                _ => (shard + 1)
+                    .checked_shl(shift)
+                    .filter(|_| shard + 1 < segments),
+            };
+
+            match next {
+                Some(next) => {
+                    cursor = next;
+                    shard = cursor.checked_shr(shift).unwrap_or(0);
+                }
+                None => return (0, keys),
+            }
+        }
+
+        (cursor as u64, keys)
+    }
+}
+
+/// Drops the keys at the highest position in the batch, unless that would leave fewer than `need`
+fn evict_highest(batch: &mut BinaryHeap<(usize, Option<DataType>)>, need: usize) {
+    let Some(top) = batch.peek().map(|(pos, _)| *pos) else {
+        return;
+    };
+    let mut tied = Vec::new();
+    while batch.peek().is_some_and(|(pos, _)| *pos == top) {
+        tied.extend(batch.pop());
+    }
+    if batch.len() < need {
+        batch.extend(tied);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ScanFilter;
+    use crate::{
+        data_type::{BoopBool, BoopString, DataType, Int},
+        store::{clock::ManualClock, SetTtl, Store},
+    };
+    use bytes::Bytes;
+    use std::{collections::HashSet, sync::Arc};
+
+    fn scan_all(store: &Store, count: usize, filter: &ScanFilter) -> Vec<DataType> {
+        let mut cursor = 0;
+        let mut keys = Vec::new();
+        loop {
+            let (next, batch) = store.scan(cursor, count, filter);
+            keys.extend(batch);
+            if next == 0 {
+                return keys;
+            }
+            assert!(next > cursor);
+            cursor = next;
+        }
+    }
+
+    #[test]
+    fn scan_returns_every_key_once() {
+        let store = Store::new();
+        for i in 0..2_000_u16 {
+            store.set(&Int::new_u16(i), &Int::new_u8(0), SetTtl::Clear);
+        }
+
+        for count in [1, 7, 100, 10_000] {
+            let keys = scan_all(&store, count, &ScanFilter::default());
+            assert_eq!(keys.len(), 2_000);
+            let unique: HashSet<DataType> = keys.into_iter().collect();
+            assert_eq!(unique.len(), 2_000);
+        }
+    }
+
+    #[test]
+    fn scan_empty_store() {
+        let store = Store::new();
+        assert_eq!(store.scan(0, 10, &ScanFilter::default()), (0, vec![]));
+    }
+
+    #[test]
+    fn scan_skips_expired_keys() {
+        let clock = Arc::new(ManualClock::new(1_000));
+        let store = Store::with_clock(clock.clone());
+        store.set(&Int::new_u8(1), &Int::new_u8(0), SetTtl::ExpireIn(10));
+        store.set(&Int::new_u8(2), &Int::new_u8(0), SetTtl::Clear);
+        clock.advance(10);
+
+        assert_eq!(
+            scan_all(&store, 10, &ScanFilter::default()),
+            vec![Int::new_u8(2)]
+        );
+    }
+
+    #[test]
+    fn scan_filters() {
+        let store = Store::new();
+        let key = |k: &'static [u8]| BoopString::new_wrapped(Bytes::from_static(k));
+
+        store.set(&key(b"user:1"), &Int::new_u8(0), SetTtl::Clear);
+        store.set(&key(b"user:2"), &BoopBool::new_wrapped(true), SetTtl::Clear);
+        store.set(&key(b"item:1"), &Int::new_u8(0), SetTtl::Clear);
+        store.set(&Int::new_u8(1), &Int::new_u8(0), SetTtl::Clear);
+
+        let by_pattern = ScanFilter {
+            pattern: Some(Bytes::from_static(b"user:*")),
+            variant: None,
+        };
+        let mut keys = scan_all(&store, 1, &by_pattern);
+        keys.sort_by_key(|k| k.to_string());
+        assert_eq!(keys, vec![key(b"user:1"), key(b"user:2")]);
+
+        let by_type = ScanFilter {
+            pattern: None,
+            variant: Some(Int::new_u8(0).variant_bits()),
+        };
+        assert_eq!(scan_all(&store, 2, &by_type).len(), 3);
+
+        let both = ScanFilter {
+            pattern: Some(Bytes::from_static(b"user:*")),
+            variant: Some(BoopBool::new_wrapped(true).variant_bits()),
+        };
+        assert_eq!(scan_all(&store, 3, &both), vec![key(b"user:2")]);
+    }
+
+    #[test]
+    fn scan_never_misses_keys_present_throughout() {
+        let store = Store::new();
+        for i in 0..1_000_u16 {
+            store.set(&Int::new_u16(i), &Int::new_u8(0), SetTtl::Clear);
+        }
+
+        let churn = {
+            let store = store.clone();
+            std::thread::spawn(move || {
+                for round in 0..20_u32 {
+                    for i in 0..500_u32 {
+                        let key = Int::new_u32(100_000 + round * 500 + i);
+                        store.set(&key, &Int::new_u8(0), SetTtl::Clear);
+                    }
+                    for i in 0..500_u32 {
+                        store.get_del(&Int::new_u32(100_000 + round * 500 + i));
+                    }
+                }
+            })
+        };
+
+        let keys: HashSet<DataType> = scan_all(&store, 10, &ScanFilter::default())
+            .into_iter()
+            .collect();
+        churn.join().unwrap();
+
+        for i in 0..1_000_u16 {
+            assert!(keys.contains(&Int::new_u16(i)));
+        }
+    }
+
+    #[test]
+    fn scan_never_misses_keys_overwritten_throughout() {
+        let store = Store::new();
+        for i in 0..1_000_u16 {
+            store.set(&Int::new_u16(i), &Int::new_u8(0), SetTtl::Clear);
+        }
+
+        // Overwriting a key never changes its position, so it is neither missed nor repeated
+        let churn = {
+            let store = store.clone();
+            std::thread::spawn(move || {
+                for round in 0..20_u8 {
+                    for i in 0..1_000_u16 {
+                        store.set(&Int::new_u16(i), &Int::new_u8(round), SetTtl::Clear);
+                    }
+                }
+            })
+        };
+
+        let keys = scan_all(&store, 10, &ScanFilter::default());
+        churn.join().unwrap();
+
+        assert_eq!(keys.len(), 1_000);
+        let unique: HashSet<DataType> = keys.into_iter().collect();
+        for i in 0..1_000_u16 {
+            assert!(unique.contains(&Int::new_u16(i)));
+        }
+    }
+
+    #[test]
+    fn scan_batches_examine_count_entries() {
+        let store = Store::new();
+        for i in 0..2_000_u16 {
+            store.set(&Int::new_u16(i), &Int::new_u8(0), SetTtl::Clear);
+        }
+
+        let (mut cursor, mut batches) = (0, 0);
+        loop {
+            let (next, keys) = store.scan(cursor, 100, &ScanFilter::default());
+            assert!(keys.len() <= 100);
+            batches += 1;
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+        // Every batch but the last in each segment is full
+        assert!(batches <= 2_000 / 100 + store.map.segments());
+    }
+}
@@ -0,0 +1,320 @@
+use super::{random, written, SetTtl, Store};
+use crate::data_type::{BoopError, BoopSet, DataType};
+use std::collections::HashSet;
+
+/// The set operation that SUNION, SINTER and SDIFF, and their STORE variants, apply across keys
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SetOp {
+    Union,
+    Inter,
+    Diff,
+}
+
+/// Runs `f` against the set held by `key`. Keys that don't exist are treated as an empty set, which
+/// is never written back to the store, and sets that are left empty are removed. Keys holding
+/// anything other than a set return a `wrong_type` error. `f` also returns whether it changed the
+/// set, see `Store::update`.
+fn with_set<R>(
+    store: &Store,
+    key: &DataType,
+    f: impl FnOnce(&mut HashSet<DataType>) -> Result<(R, bool), DataType>,
+) -> Result<R, DataType> {
+    store.update(key, |value| {
+        let mut set = match value.take() {
+            Some(DataType::Set(BoopSet(set))) => set,
+            None => HashSet::new(),
+            Some(other) => {
+                *value = Some(other);
+                return (Err(BoopError::wrong_type()), false);
+            }
+        };
+
+        let (result, changed) = written(f(&mut set));
+        if !set.is_empty() {
+            *value = Some(DataType::Set(BoopSet(set)));
+        }
+
+        (result, changed)
+    })
+}
+
+/// Runs `f` against the set held by `key` without modifying it, under the shard's read lock. Keys
+/// that don't exist are treated as an empty set.
+fn view_set<R>(
+    store: &Store,
+    key: &DataType,
+    f: impl FnOnce(&HashSet<DataType>) -> Result<R, DataType>,
+) -> Result<R, DataType> {
+    store.view(key, |value| match value {
+        Some(DataType::Set(BoopSet(set))) => f(set),
+        None => f(&HashSet::new()),
+        Some(_) => Err(BoopError::wrong_type()),
+    })
+}
+
+impl Store {
+    /// Adds `members` to the set held by `key`, creating it if the key does not exist. Returns the
+    /// amount of members that weren't already in the set, or a `too_large` error if the set would
+    /// grow past `BoopSet::MAX_LEN`, in which case it is left untouched.
+    pub fn sadd(&self, key: &DataType, members: Vec<DataType>) -> Result<usize, DataType> {
+        with_set(self, key, |set| {
+            let new: HashSet<&DataType> = members.iter().filter(|m| !set.contains(*m)).collect();
+            if set.len() + new.len() > BoopSet::MAX_LEN {
+                return Err(BoopError::too_large());
+            }
+
+            let added = members
+                .into_iter()
+                .filter(|member| set.insert(member.to_owned()))
+                .count();
+            Ok((added, added > 0))
+        })
+    }
+
+    /// Removes `members` from the set held by `key`, returning how many were removed
+    pub fn srem(&self, key: &DataType, members: &[DataType]) -> Result<usize, DataType> {
+        with_set(self, key, |set| {
+            let removed = members.iter().filter(|member| set.remove(*member)).count();
+            Ok((removed, removed > 0))
+        })
+    }
+
+    /// Returns whether `member` is in the set held by `key`
+    pub fn sismember(&self, key: &DataType, member: &DataType) -> Result<bool, DataType> {
+        view_set(self, key, |set| Ok(set.contains(member)))
+    }
+
+    /// Returns the amount of members in the set held by `key`
+    pub fn scard(&self, key: &DataType) -> Result<usize, DataType> {
+        view_set(self, key, |set| Ok(set.len()))
+    }
+
+    /// Returns every member of the set held by `key`
+    pub fn smembers(&self, key: &DataType) -> Result<HashSet<DataType>, DataType> {
+        view_set(self, key, |set| Ok(set.to_owned()))
+    }
+
+    /// Returns random members of the set held by `key`. A positive `count` returns that many
+    /// distinct members, or the whole set if it is smaller. A negative `count` returns exactly
+    /// `-count` members, which may repeat.
+    pub fn srandmember(&self, key: &DataType, count: i64) -> Result<Vec<DataType>, DataType> {
+        view_set(self, key, |set| {
+            let mut members: Vec<&DataType> = set.iter().collect();
+            if members.is_empty() {
+                return Ok(Vec::new());
+            }
+
+            let len = members.len();
+            let picked = if count < 0 {
+                let count = count.unsigned_abs().min(BoopSet::MAX_LEN as u64) as usize;
+                (0..count)
+                    .map(|_| members[random() as usize % len].to_owned())
+                    .collect()
+            } else {
+                // A partial Fisher-Yates shuffle, which only shuffles as many members as are picked
+                let count = (count as usize).min(len);
+                for i in 0..count {
+                    let j = i + random() as usize % (len - i);
+                    members.swap(i, j);
+                }
+                members[..count].iter().map(|m| (*m).to_owned()).collect()
+            };
+
+            Ok(picked)
+        })
+    }
+
+    /// Applies `op` across the sets held by `keys`, where keys that don't exist are treated as
+    /// empty sets. The keys are read as one atomic snapshot, see `Store::view_many`. Returns a
+    /// `too_large` error if the result would hold more than `BoopSet::MAX_LEN` members.
+    pub fn set_op(&self, op: SetOp, keys: &[DataType]) -> Result<HashSet<DataType>, DataType> {
+        self.view_many(keys, |values| {
+            let empty = HashSet::new();
+            let mut sets = Vec::with_capacity(values.len());
+            for value in values {
+                match value {
+                    Some(DataType::Set(BoopSet(set))) => sets.push(set),
+                    None => sets.push(&empty),
+                    Some(_) => return Err(BoopError::wrong_type()),
+                }
+            }
+
+            let Some((first, rest)) = sets.split_first() else {
+                return Ok(HashSet::new());
+            };
+            let result: HashSet<DataType> = match op {
+                SetOp::Union => sets.iter().flat_map(|set| set.iter()).cloned().collect(),
+                SetOp::Inter => {
+                    // Only the smallest set needs to be walked
+                    let smallest = sets.iter().min_by_key(|set| set.len()).unwrap_or(first);
+                    smallest
+                        .iter()
+                        .filter(|member| sets.iter().all(|set| set.contains(*member)))
+                        .cloned()
+                        .collect()
+                }
+                SetOp::Diff => first
+                    .iter()
+                    .filter(|member| rest.iter().all(|set| !set.contains(*member)))
+                    .cloned()
+                    .collect(),
+            };
+
+            if result.len() > BoopSet::MAX_LEN {
+                return Err(BoopError::too_large());
+            }
+            Ok(result)
+        })
+    }
+
+    /// Applies `op` across the sets held by `keys` like `Store::set_op`, and stores the result in
+    /// `dest`, replacing it and any expiry it had. An empty result removes `dest`. Returns the
+    /// amount of members in the result.
+    pub fn set_op_store(
+        &self,
+        op: SetOp,
+        dest: &DataType,
+        keys: &[DataType],
+    ) -> Result<usize, DataType> {
+        let result = self.set_op(op, keys)?;
+        let len = result.len();
+
+        if result.is_empty() {
+            self.del(std::slice::from_ref(dest));
+        } else {
+            self.set(dest, &DataType::Set(BoopSet(result)), SetTtl::Clear);
+        }
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SetOp;
+    use crate::{
+        data_type::{BoopError, BoopSet, DataType, Int},
+        store::{SetTtl, Store},
+    };
+    use std::collections::HashSet;
+
+    fn ints(values: impl IntoIterator<Item = u8>) -> Vec<DataType> {
+        values.into_iter().map(Int::new_u8).collect()
+    }
+
+    fn set_of(values: impl IntoIterator<Item = u8>) -> HashSet<DataType> {
+        ints(values).into_iter().collect()
+    }
+
+    #[test]
+    fn add_remove_and_membership() {
+        let store = Store::new();
+        let key = Int::new_u8(0);
+
+        assert_eq!(store.sadd(&key, ints([1, 2, 2, 3])), Ok(3));
+        assert_eq!(store.sadd(&key, ints([3, 4])), Ok(1));
+        assert_eq!(store.scard(&key), Ok(4));
+        assert_eq!(store.sismember(&key, &Int::new_u8(4)), Ok(true));
+        assert_eq!(store.sismember(&key, &Int::new_u8(5)), Ok(false));
+        assert_eq!(store.smembers(&key), Ok(set_of(1..=4)));
+
+        assert_eq!(store.srem(&key, &ints([1, 5])), Ok(1));
+        assert_eq!(store.srem(&key, &ints([2, 3, 4])), Ok(3));
+        assert_eq!(store.get(&key), None);
+        assert_eq!(store.scard(&key), Ok(0));
+    }
+
+    #[test]
+    fn random_members() {
+        let store = Store::new();
+        let key = Int::new_u8(0);
+        store.sadd(&key, ints(0..10)).unwrap();
+        let all = set_of(0..10);
+
+        let picked = store.srandmember(&key, 5).unwrap();
+        assert_eq!(picked.len(), 5);
+        assert_eq!(picked.iter().collect::<HashSet<_>>().len(), 5);
+        assert!(picked.iter().all(|m| all.contains(m)));
+
+        assert_eq!(store.srandmember(&key, 100).unwrap().len(), 10);
+        let repeated = store.srandmember(&key, -30).unwrap();
+        assert_eq!(repeated.len(), 30);
+        assert!(repeated.iter().all(|m| all.contains(m)));
+
+        assert_eq!(store.srandmember(&Int::new_u8(1), -5), Ok(vec![]));
+    }
+
+    #[test]
+    fn set_algebra() {
+        let store = Store::new();
+        store.sadd(&Int::new_u8(1), ints([1, 2, 3, 4])).unwrap();
+        store.sadd(&Int::new_u8(2), ints([3, 4, 5])).unwrap();
+        store.sadd(&Int::new_u8(3), ints([4, 6])).unwrap();
+        let keys = ints([1, 2, 3]);
+
+        assert_eq!(store.set_op(SetOp::Union, &keys), Ok(set_of(1..=6)));
+        assert_eq!(store.set_op(SetOp::Inter, &keys), Ok(set_of([4])));
+        assert_eq!(store.set_op(SetOp::Diff, &keys), Ok(set_of([1, 2])));
+
+        // Missing keys are empty sets
+        let with_missing = ints([1, 9]);
+        assert_eq!(store.set_op(SetOp::Inter, &with_missing), Ok(set_of([])));
+        assert_eq!(
+            store.set_op(SetOp::Diff, &with_missing),
+            Ok(set_of([1, 2, 3, 4]))
+        );
+    }
+
+    #[test]
+    fn store_variants_replace_the_destination() {
+        let store = Store::new();
+        store.sadd(&Int::new_u8(1), ints([1, 2])).unwrap();
+        store.sadd(&Int::new_u8(2), ints([2, 3])).unwrap();
+        let dest = Int::new_u8(10);
+        store.set(&dest, &Int::new_u8(0), SetTtl::ExpireIn(60_000));
+
+        assert_eq!(
+            store.set_op_store(SetOp::Union, &dest, &ints([1, 2])),
+            Ok(3)
+        );
+        assert_eq!(store.get(&dest), Some(BoopSet::new_wrapped(ints(1..=3))));
+        assert_eq!(store.ttl(&dest), Some(None));
+
+        // The destination can also be a source
+        assert_eq!(
+            store.set_op_store(SetOp::Inter, &dest, &ints([10, 2])),
+            Ok(2)
+        );
+        assert_eq!(store.get(&dest), Some(BoopSet::new_wrapped(ints([2, 3]))));
+
+        assert_eq!(store.set_op_store(SetOp::Diff, &dest, &ints([1, 1])), Ok(0));
+        assert_eq!(store.get(&dest), None);
+    }
+
+    #[test]
+    fn wrong_type_and_size_limit() {
+        let store = Store::new();
+        store.set(&Int::new_u8(1), &Int::new_u8(1), SetTtl::Clear);
+        assert_eq!(
+            store.sadd(&Int::new_u8(1), ints([1])),
+            Err(BoopError::wrong_type())
+        );
+        assert_eq!(
+            store.set_op(SetOp::Union, &ints([1, 2])),
+            Err(BoopError::wrong_type())
+        );
+
+        let big: Vec<_> = (0..BoopSet::MAX_LEN as u64).map(Int::new_u64).collect();
+        assert_eq!(store.sadd(&Int::new_u8(2), big), Ok(BoopSet::MAX_LEN));
+        assert_eq!(
+            store.sadd(&Int::new_u8(2), vec![Int::new_u64(u64::MAX)]),
+            Err(BoopError::too_large())
+        );
+        store
+            .sadd(&Int::new_u8(3), vec![Int::new_u64(u64::MAX)])
+            .unwrap();
+        assert_eq!(
+            store.set_op(SetOp::Union, &ints([2, 3])),
+            Err(BoopError::too_large())
+        );
+    }
+}
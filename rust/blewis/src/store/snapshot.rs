@@ -0,0 +1,135 @@
+//! Dumping a store's entries for a snapshot, and restoring them from one. The store only knows
+//! how to encode its entries as BOOP records, see `Store::dump`; the snapshot file they go in is
+//! up to the caller, see `crate::snapshot`.
+
+use super::{Entry, Store};
+use crate::{
+    data_type::{BoopArray, DataType, Int},
+    encoder::handle_encode,
+};
+use bytes::{BufMut, BytesMut};
+
+impl Store {
+    /// Encodes every entry that hasn't expired as a record, one segment of the backend at a time,
+    /// handing the records of each segment to `write` once the segment has been visited. Only one
+    /// segment is visited at once, so writes to the rest of the store carry on while it is dumped,
+    /// and only as long as encoding its entries takes. Returns how many entries were dumped.
+    ///
+    /// A record is a BOOP array of the key and its value, followed by its expiry in milliseconds
+    /// since the UNIX epoch, as a u64, if it has one.
+    pub fn dump(
+        &self,
+        mut write: impl FnMut(&[u8]) -> anyhow::Result<()>,
+    ) -> anyhow::Result<usize> {
+        let mut buf = BytesMut::new();
+        let mut dumped = 0;
+
+        for segment in 0..self.map.segments() {
+            let now = self.now();
+            let mut result = Ok(());
+            self.map.visit(segment, 0, &mut |key, entry| {
+                if !entry.is_expired(now) {
+                    result = encode_record(key, entry, &mut buf);
+                    dumped += 1;
+                }
+                result.is_ok()
+            });
+            result?;
+
+            write(&buf)?;
+            buf.clear();
+        }
+
+        Ok(dumped)
+    }
+
+    /// Writes an entry restored from a snapshot, replacing anything the key held. Nothing is
+    /// published or invalidated, as nobody can have read the key before the snapshot was loaded.
+    /// Entries that have expired since the snapshot was taken are left out, returning false.
+    pub fn restore(&self, key: DataType, value: DataType, expires_at: Option<u64>) -> bool {
+        let now = self.now();
+        if expires_at.is_some_and(|at| at <= now) {
+            return false;
+        }
+
+        let entry = self.new_entry(value, expires_at, now);
+        self.charge(&key, &entry);
+        if let Some(old) = self.map.insert(key.clone(), entry) {
+            self.refund(&key, &old);
+        }
+        true
+    }
+}
+
+/// Encodes an entry as a record, see `Store::dump`
+fn encode_record(key: &DataType, entry: &Entry, buf: &mut BytesMut) -> anyhow::Result<()> {
+    let expiry = entry.expires_at.map(Int::new_u64);
+    buf.put_u8(BoopArray::new_wrapped(Vec::new()).meta_byte());
+    buf.put_u16(if expiry.is_some() { 3 } else { 2 });
+    handle_encode(key, buf)?;
+    handle_encode(&entry.value, buf)?;
+    if let Some(expiry) = &expiry {
+        handle_encode(expiry, buf)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        data_type::{BoopArray, DataType, Int},
+        decoder::handle_decode,
+        store::{clock::ManualClock, SetTtl, Store},
+    };
+    use bytes::BytesMut;
+    use std::sync::Arc;
+
+    #[test]
+    fn dumped_entries_restore() {
+        let clock = Arc::new(ManualClock::new(1_000));
+        let store = Store::with_clock(clock.clone());
+        store.set(&Int::new_u8(1), &Int::new_u8(10), SetTtl::Clear);
+        store.set(&Int::new_u8(2), &Int::new_u8(20), SetTtl::ExpireIn(50));
+        store.set(&Int::new_u8(3), &Int::new_u8(30), SetTtl::ExpireIn(10));
+        clock.advance(10);
+
+        let mut buf = BytesMut::new();
+        let dumped = store
+            .dump(|records| {
+                buf.extend_from_slice(records);
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(dumped, 2);
+
+        let mut records = Vec::new();
+        while !buf.is_empty() {
+            let DataType::Array(BoopArray(record)) = handle_decode(&mut buf).unwrap() else {
+                panic!("expected a record");
+            };
+            records.push(record);
+        }
+        records.sort();
+        assert_eq!(
+            records,
+            vec![
+                vec![Int::new_u8(1), Int::new_u8(10)],
+                vec![Int::new_u8(2), Int::new_u8(20), Int::new_u64(1_050)],
+            ]
+        );
+
+        let restored = Store::with_clock(clock.clone());
+        for record in records {
+            let mut record = record.into_iter();
+            let (key, value) = (record.next().unwrap(), record.next().unwrap());
+            let expires_at = record.next().and_then(|at| at.as_u64());
+            assert!(restored.restore(key, value, expires_at));
+        }
+        assert_eq!(restored.get(&Int::new_u8(1)), Some(Int::new_u8(10)));
+        assert_eq!(restored.ttl(&Int::new_u8(2)), Some(Some(40)));
+        assert!(restored.used_memory() > 0);
+
+        assert!(!restored.restore(Int::new_u8(4), Int::new_u8(0), Some(1_010)));
+        assert_eq!(restored.len(), 2);
+    }
+}
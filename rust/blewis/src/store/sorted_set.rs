@@ -0,0 +1,376 @@
+use super::{list::resolve_range, written, Store};
+use crate::data_type::{BoopError, BoopSortedSet, DataType};
+use ordered_float::OrderedFloat;
+use std::collections::HashSet;
+
+/// Which members ZRANGE returns
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ZRangeBy {
+    /// The members between two ranks, inclusive. Negative ranks count from the end of the set.
+    Rank { start: i64, stop: i64 },
+    /// The members with a score between `min` and `max`, inclusive unless the bound is marked as
+    /// exclusive. `offset` members are skipped, and at most `count` are returned.
+    Score {
+        min: OrderedFloat<f64>,
+        max: OrderedFloat<f64>,
+        min_exclusive: bool,
+        max_exclusive: bool,
+        offset: usize,
+        count: Option<usize>,
+    },
+}
+
+/// Runs `f` against the sorted set held by `key`. Keys that don't exist are treated as an empty
+/// set, which is never written back to the store, and sets that are left empty are removed. Keys
+/// holding anything other than a sorted set return a `wrong_type` error. `f` also returns whether
+/// it changed the set, see `Store::update`.
+fn with_sorted_set<R>(
+    store: &Store,
+    key: &DataType,
+    f: impl FnOnce(&mut BoopSortedSet) -> Result<(R, bool), DataType>,
+) -> Result<R, DataType> {
+    store.update(key, |value| {
+        let mut set = match value.take() {
+            Some(DataType::SortedSet(set)) => set,
+            None => BoopSortedSet::new(),
+            Some(other) => {
+                *value = Some(other);
+                return (Err(BoopError::wrong_type()), false);
+            }
+        };
+
+        let (result, changed) = written(f(&mut set));
+        if !set.is_empty() {
+            *value = Some(DataType::SortedSet(set));
+        }
+
+        (result, changed)
+    })
+}
+
+/// Runs `f` against the sorted set held by `key` without modifying it, under the shard's read lock.
+/// Keys that don't exist are treated as an empty set.
+fn view_sorted_set<R>(
+    store: &Store,
+    key: &DataType,
+    f: impl FnOnce(&BoopSortedSet) -> Result<R, DataType>,
+) -> Result<R, DataType> {
+    store.view(key, |value| match value {
+        Some(DataType::SortedSet(set)) => f(set),
+        None => f(&BoopSortedSet::new()),
+        Some(_) => Err(BoopError::wrong_type()),
+    })
+}
+
+impl Store {
+    /// Adds the members of `entries` to the sorted set held by `key` with their scores, updating the
+    /// score of members that already exist. With `nx`, existing members are left untouched, and
+    /// with `xx`, new members are not added. Returns the amount of members that were added, or a
+    /// `too_large` error if the set would grow past `BoopSortedSet::MAX_LEN`, in which case it is
+    /// left untouched.
+    pub fn zadd(
+        &self,
+        key: &DataType,
+        entries: Vec<(f64, DataType)>,
+        nx: bool,
+        xx: bool,
+    ) -> Result<usize, DataType> {
+        with_sorted_set(self, key, |set| {
+            let new_members: HashSet<&DataType> = entries
+                .iter()
+                .map(|(_, member)| member)
+                .filter(|member| set.score(member).is_none())
+                .collect();
+            let added = if xx { 0 } else { new_members.len() };
+            if set.len() + added > BoopSortedSet::MAX_LEN {
+                return Err(BoopError::too_large());
+            }
+
+            let mut changed = false;
+            for (score, member) in entries {
+                let current = set.score(&member);
+                if (nx && current.is_some()) || (xx && current.is_none()) {
+                    continue;
+                }
+                changed |= current != Some(score);
+                set.insert(member, score);
+            }
+
+            Ok((added, changed))
+        })
+    }
+
+    /// Removes `members` from the sorted set held by `key`, returning how many were removed
+    pub fn zrem(&self, key: &DataType, members: &[DataType]) -> Result<usize, DataType> {
+        with_sorted_set(self, key, |set| {
+            let removed = members
+                .iter()
+                .filter(|member| set.remove(member).is_some())
+                .count();
+            Ok((removed, removed > 0))
+        })
+    }
+
+    /// Returns the score of `member` in the sorted set held by `key`
+    pub fn zscore(&self, key: &DataType, member: &DataType) -> Result<Option<f64>, DataType> {
+        view_sorted_set(self, key, |set| Ok(set.score(member)))
+    }
+
+    /// Adds `by` to the score of `member` in the sorted set held by `key`, adding the member with a
+    /// score of `by` if it doesn't exist, and returns the new score. Returns a `not_a_number` error
+    /// if the new score would be NaN, such as when adding -inf to inf.
+    pub fn zincrby(&self, key: &DataType, by: f64, member: DataType) -> Result<f64, DataType> {
+        with_sorted_set(self, key, |set| {
+            let score = match set.score(&member) {
+                Some(score) => score + by,
+                None if set.len() >= BoopSortedSet::MAX_LEN => return Err(BoopError::too_large()),
+                None => by,
+            };
+            if score.is_nan() {
+                return Err(BoopError::not_a_number());
+            }
+
+            set.insert(member, score);
+            Ok((score, true))
+        })
+    }
+
+    /// Returns the rank of `member` in the sorted set held by `key`, counting from the lowest
+    /// score, or from the highest score with `rev`
+    pub fn zrank(
+        &self,
+        key: &DataType,
+        member: &DataType,
+        rev: bool,
+    ) -> Result<Option<usize>, DataType> {
+        view_sorted_set(self, key, |set| {
+            Ok(set
+                .rank(member)
+                .map(|rank| if rev { set.len() - 1 - rank } else { rank }))
+        })
+    }
+
+    /// Returns the members of the sorted set held by `key` which are selected by `by`, along with
+    /// their scores, ordered from the lowest score, or from the highest with `rev`. With `rev`,
+    /// ranks count from the highest score. Takes O(log n + k) for k members, plus any offset.
+    pub fn zrange(
+        &self,
+        key: &DataType,
+        by: ZRangeBy,
+        rev: bool,
+    ) -> Result<Vec<(DataType, f64)>, DataType> {
+        view_sorted_set(self, key, |set| {
+            let len = set.len();
+            let owned = |(member, score): (&DataType, f64)| (member.to_owned(), score);
+
+            match by {
+                ZRangeBy::Rank { start, stop } => {
+                    let Some((start, stop)) = resolve_range(start, stop, len) else {
+                        return Ok(Vec::new());
+                    };
+                    let first = if rev { len - 1 - start } else { start };
+
+                    Ok(set
+                        .iter_from(first, rev)
+                        .take(stop - start + 1)
+                        .map(owned)
+                        .collect())
+                }
+                ZRangeBy::Score {
+                    min,
+                    max,
+                    min_exclusive,
+                    max_exclusive,
+                    offset,
+                    count,
+                } => {
+                    // The members in range are those ranked from `first` up to, but not including, `end`
+                    let first = set.rank_of_score(min.0, min_exclusive);
+                    let end = set.rank_of_score(max.0, !max_exclusive);
+                    if first >= end {
+                        return Ok(Vec::new());
+                    }
+
+                    let start = if rev { end - 1 } else { first };
+                    Ok(set
+                        .iter_from(start, rev)
+                        .take(end - first)
+                        .skip(offset)
+                        .take(count.unwrap_or(usize::MAX))
+                        .map(owned)
+                        .collect())
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ZRangeBy;
+    use crate::{
+        data_type::{BoopError, BoopSortedSet, Int},
+        store::{SetTtl, Store},
+    };
+    use ordered_float::OrderedFloat;
+
+    fn leaderboard() -> Store {
+        let store = Store::new();
+        let entries = (1..=5).map(|i| (i as f64 * 10.0, Int::new_u8(i))).collect();
+        assert_eq!(store.zadd(&Int::new_u8(0), entries, false, false), Ok(5));
+        store
+    }
+
+    fn by_score(min: f64, max: f64, offset: usize, count: Option<usize>) -> ZRangeBy {
+        ZRangeBy::Score {
+            min: OrderedFloat(min),
+            max: OrderedFloat(max),
+            min_exclusive: false,
+            max_exclusive: false,
+            offset,
+            count,
+        }
+    }
+
+    fn members(store: &Store, by: ZRangeBy, rev: bool) -> Vec<u8> {
+        store
+            .zrange(&Int::new_u8(0), by, rev)
+            .unwrap()
+            .into_iter()
+            .map(|(member, _)| member.as_u64().unwrap() as u8)
+            .collect()
+    }
+
+    #[test]
+    fn zadd_flags_and_zscore() {
+        let store = leaderboard();
+        let key = Int::new_u8(0);
+
+        // NX only adds new members, XX only updates existing ones
+        let entries = vec![(0.0, Int::new_u8(1)), (60.0, Int::new_u8(6))];
+        assert_eq!(store.zadd(&key, entries.clone(), true, false), Ok(1));
+        assert_eq!(store.zscore(&key, &Int::new_u8(1)), Ok(Some(10.0)));
+
+        let entries = vec![(0.0, Int::new_u8(1)), (70.0, Int::new_u8(7))];
+        assert_eq!(store.zadd(&key, entries, false, true), Ok(0));
+        assert_eq!(store.zscore(&key, &Int::new_u8(1)), Ok(Some(0.0)));
+        assert_eq!(store.zscore(&key, &Int::new_u8(7)), Ok(None));
+
+        // Giving the same new member twice only counts it once
+        let entries = vec![(1.0, Int::new_u8(8)), (2.0, Int::new_u8(8))];
+        assert_eq!(store.zadd(&key, entries, false, false), Ok(1));
+        assert_eq!(store.zscore(&key, &Int::new_u8(8)), Ok(Some(2.0)));
+    }
+
+    #[test]
+    fn zrem_removes_emptied_sets() {
+        let store = leaderboard();
+        let key = Int::new_u8(0);
+
+        let members: Vec<_> = (0..=5).map(Int::new_u8).collect();
+        assert_eq!(store.zrem(&key, &members[..2]), Ok(1));
+        assert_eq!(store.zrem(&key, &members), Ok(4));
+        assert_eq!(store.get(&key), None);
+    }
+
+    #[test]
+    fn zincrby_and_zrank() {
+        let store = leaderboard();
+        let key = Int::new_u8(0);
+
+        assert_eq!(store.zincrby(&key, 45.0, Int::new_u8(1)), Ok(55.0));
+        assert_eq!(store.zrank(&key, &Int::new_u8(1), false), Ok(Some(4)));
+        assert_eq!(store.zrank(&key, &Int::new_u8(1), true), Ok(Some(0)));
+        assert_eq!(store.zrank(&key, &Int::new_u8(2), false), Ok(Some(0)));
+        assert_eq!(store.zrank(&key, &Int::new_u8(9), false), Ok(None));
+
+        assert_eq!(store.zincrby(&key, -1.5, Int::new_u8(9)), Ok(-1.5));
+        assert_eq!(
+            store.zincrby(&key, f64::INFINITY, Int::new_u8(2)),
+            Ok(f64::INFINITY)
+        );
+        assert_eq!(
+            store.zincrby(&key, f64::NEG_INFINITY, Int::new_u8(2)),
+            Err(BoopError::not_a_number())
+        );
+    }
+
+    #[test]
+    fn zrange_by_rank() {
+        let store = leaderboard();
+        let rank = |start, stop| ZRangeBy::Rank { start, stop };
+
+        assert_eq!(members(&store, rank(0, -1), false), vec![1, 2, 3, 4, 5]);
+        assert_eq!(members(&store, rank(1, 2), false), vec![2, 3]);
+        assert_eq!(members(&store, rank(0, 1), true), vec![5, 4]);
+        assert_eq!(members(&store, rank(-2, 100), true), vec![2, 1]);
+        assert_eq!(members(&store, rank(3, 1), false), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn zrange_by_score_with_limits() {
+        let store = leaderboard();
+
+        assert_eq!(
+            members(&store, by_score(20.0, 40.0, 0, None), false),
+            vec![2, 3, 4]
+        );
+        assert_eq!(
+            members(&store, by_score(20.0, 40.0, 0, None), true),
+            vec![4, 3, 2]
+        );
+        assert_eq!(
+            members(
+                &store,
+                by_score(f64::NEG_INFINITY, f64::INFINITY, 1, Some(2)),
+                false
+            ),
+            vec![2, 3]
+        );
+        assert_eq!(
+            members(&store, by_score(0.0, 100.0, 1, Some(2)), true),
+            vec![4, 3]
+        );
+        assert_eq!(
+            members(&store, by_score(41.0, 49.0, 0, None), false),
+            Vec::<u8>::new()
+        );
+
+        let exclusive = ZRangeBy::Score {
+            min: OrderedFloat(20.0),
+            max: OrderedFloat(40.0),
+            min_exclusive: true,
+            max_exclusive: true,
+            offset: 0,
+            count: None,
+        };
+        assert_eq!(members(&store, exclusive, false), vec![3]);
+    }
+
+    #[test]
+    fn size_limit_and_wrong_type() {
+        let store = Store::new();
+        let key = Int::new_u8(0);
+        let entries = (0..BoopSortedSet::MAX_LEN)
+            .map(|i| (i as f64, Int::new_u64(i as u64)))
+            .collect();
+        assert_eq!(
+            store.zadd(&key, entries, false, false),
+            Ok(BoopSortedSet::MAX_LEN)
+        );
+        assert_eq!(
+            store.zadd(&key, vec![(0.0, Int::new_u8(0))], false, false),
+            Err(BoopError::too_large())
+        );
+        assert_eq!(
+            store.zincrby(&key, 1.0, Int::new_u8(0)),
+            Err(BoopError::too_large())
+        );
+
+        store.set(&key, &Int::new_u8(1), SetTtl::Clear);
+        assert_eq!(
+            store.zscore(&key, &Int::new_u8(1)),
+            Err(BoopError::wrong_type())
+        );
+    }
+}
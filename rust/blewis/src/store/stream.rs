@@ -0,0 +1,603 @@
+use super::{written, Store};
+use crate::data_type::{BoopError, BoopStream, ConsumerGroup, DataType, PendingEntry, StreamId};
+
+/// The field/value pairs of a stream entry
+pub type Fields = Vec<(DataType, DataType)>;
+
+/// How XADD picks the ID of a new entry
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum XAddId {
+    /// Use the current time, or the ID after the last one if the clock hasn't moved past it
+    Auto,
+    /// Use the given millisecond, with the next free sequence number within it
+    AutoSeq(u64),
+    Explicit(StreamId),
+}
+
+/// Which entries XTRIM, or XADD with a trim, removes from the start of a stream
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum XTrim {
+    /// Remove the oldest entries until at most this many are left
+    MaxLen(usize),
+    /// Remove every entry with an ID lower than this one
+    MinId(StreamId),
+}
+
+/// A summary of an entry in a consumer group's pending entries list
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Pending {
+    pub id: StreamId,
+    pub consumer: DataType,
+    /// Milliseconds since the entry was last delivered
+    pub idle: u64,
+    pub deliveries: u64,
+}
+
+/// Runs `f` against the stream held by `key`. Keys that don't exist are treated as an empty stream,
+/// which is only written back to the store if `f` leaves something in it. Unlike other types, a
+/// stream left empty is kept, as it still holds its last ID and its consumer groups. Keys holding
+/// anything other than a stream return a `wrong_type` error. `f` also returns whether it changed
+/// the stream, see `Store::update`.
+fn with_stream<R>(
+    store: &Store,
+    key: &DataType,
+    f: impl FnOnce(&mut BoopStream) -> Result<(R, bool), DataType>,
+) -> Result<R, DataType> {
+    store.update(key, |value| {
+        let existed = value.is_some();
+        let mut stream = match value.take() {
+            Some(DataType::Stream(stream)) => stream,
+            None => BoopStream::default(),
+            Some(other) => {
+                *value = Some(other);
+                return (Err(BoopError::wrong_type()), false);
+            }
+        };
+
+        let (result, changed) = written(f(&mut stream));
+        let untouched = stream.entries.is_empty()
+            && stream.groups.is_empty()
+            && stream.last_id == StreamId::MIN;
+        if existed || !untouched {
+            *value = Some(DataType::Stream(stream));
+        }
+
+        (result, changed)
+    })
+}
+
+/// Runs `f` against the stream held by `key` without modifying it, under the shard's read lock.
+/// Keys that don't exist are treated as an empty stream.
+fn view_stream<R>(
+    store: &Store,
+    key: &DataType,
+    f: impl FnOnce(&BoopStream) -> Result<R, DataType>,
+) -> Result<R, DataType> {
+    store.view(key, |value| match value {
+        Some(DataType::Stream(stream)) => f(stream),
+        None => f(&BoopStream::default()),
+        Some(_) => Err(BoopError::wrong_type()),
+    })
+}
+
+/// Removes entries from the start of `stream`, returning how many were removed. Pending entries
+/// lists are left as they are, so a consumer can still acknowledge a trimmed entry.
+fn trim(stream: &mut BoopStream, trim: XTrim) -> usize {
+    let len = stream.entries.len();
+    match trim {
+        XTrim::MaxLen(max) => {
+            while stream.entries.len() > max {
+                stream.entries.pop_first();
+            }
+        }
+        XTrim::MinId(min) => stream.entries = stream.entries.split_off(&min),
+    }
+    len - stream.entries.len()
+}
+
+impl Store {
+    /// Appends an entry to the stream held by `key`, creating it if the key does not exist, and
+    /// then applies `trim` if there is one. Returns the ID of the new entry.
+    ///
+    /// IDs have to be strictly increasing, so an explicit ID, or a millisecond for
+    /// `XAddId::AutoSeq`, that isn't past the last ID returns an `out_of_range` error. A stream
+    /// that would grow past `BoopStream::MAX_LEN` returns a `too_large` error. Either error leaves
+    /// the stream untouched.
+    pub fn xadd(
+        &self,
+        key: &DataType,
+        id: XAddId,
+        fields: Fields,
+        trim_by: Option<XTrim>,
+    ) -> Result<StreamId, DataType> {
+        if fields.len() > BoopStream::MAX_LEN {
+            return Err(BoopError::too_large());
+        }
+        let now = self.now();
+
+        with_stream(self, key, |stream| {
+            let last = stream.last_id;
+            let id = match id {
+                XAddId::Auto if now > last.ms => Some(StreamId::new(now, 0)),
+                XAddId::Auto => last.next(),
+                XAddId::AutoSeq(ms) if ms > last.ms || (ms == 0 && last == StreamId::MIN) => {
+                    // 0-0 is never a valid ID
+                    Some(StreamId::new(ms, (ms == 0) as u64))
+                }
+                XAddId::AutoSeq(ms) if ms == last.ms => {
+                    last.seq.checked_add(1).map(|seq| StreamId::new(ms, seq))
+                }
+                XAddId::AutoSeq(_) => None,
+                XAddId::Explicit(id) => (id > last).then_some(id),
+            }
+            .ok_or_else(BoopError::out_of_range)?;
+
+            stream.entries.insert(id, fields);
+            stream.last_id = id;
+            if let Some(trim_by) = trim_by {
+                trim(stream, trim_by);
+            }
+
+            // A trim that removed anything always brings the stream back within its limit
+            if stream.entries.len() > BoopStream::MAX_LEN {
+                stream.entries.remove(&id);
+                stream.last_id = last;
+                return Err(BoopError::too_large());
+            }
+            Ok((id, true))
+        })
+    }
+
+    /// Returns the entries of the stream held by `key` with IDs between `start` and `end`,
+    /// inclusive, up to `count` of them. `rev` returns them from the newest, but `start` is still
+    /// the lowest ID.
+    pub fn xrange(
+        &self,
+        key: &DataType,
+        start: StreamId,
+        end: StreamId,
+        count: Option<usize>,
+        rev: bool,
+    ) -> Result<Vec<(StreamId, Fields)>, DataType> {
+        view_stream(self, key, |stream| {
+            if start > end {
+                return Ok(Vec::new());
+            }
+
+            let range = stream.entries.range(start..=end);
+            let count = count.unwrap_or(usize::MAX);
+            let copy = |(id, fields): (&StreamId, &Fields)| (*id, fields.to_owned());
+            Ok(if rev {
+                range.rev().take(count).map(copy).collect()
+            } else {
+                range.take(count).map(copy).collect()
+            })
+        })
+    }
+
+    /// Returns the amount of entries in the stream held by `key`
+    pub fn xlen(&self, key: &DataType) -> Result<usize, DataType> {
+        view_stream(self, key, |stream| Ok(stream.entries.len()))
+    }
+
+    /// Removes entries from the start of the stream held by `key`, returning how many were removed
+    pub fn xtrim(&self, key: &DataType, trim_by: XTrim) -> Result<usize, DataType> {
+        with_stream(self, key, |stream| {
+            let trimmed = trim(stream, trim_by);
+            Ok((trimmed, trimmed > 0))
+        })
+    }
+
+    /// Creates a consumer group on the stream held by `key`, which will deliver the entries after
+    /// `start`, or only entries added from now on if there is no `start`. Returns false if the
+    /// group already exists, in which case it is left as it was.
+    ///
+    /// A key that doesn't exist returns a `no_exist` error, unless `mkstream` is set, in which case
+    /// an empty stream is created to hold the group.
+    pub fn xgroup_create(
+        &self,
+        key: &DataType,
+        group: &DataType,
+        start: Option<StreamId>,
+        mkstream: bool,
+    ) -> Result<bool, DataType> {
+        let create = |stream: &mut BoopStream| {
+            if stream.groups.contains_key(group) {
+                return Ok(false);
+            }
+            if stream.groups.len() >= BoopStream::MAX_LEN {
+                return Err(BoopError::too_large());
+            }
+
+            let group_state = ConsumerGroup {
+                last_delivered: start.unwrap_or(stream.last_id),
+                ..Default::default()
+            };
+            stream.groups.insert(group.to_owned(), group_state);
+            Ok(true)
+        };
+
+        self.update(key, |value| match value {
+            Some(DataType::Stream(stream)) => {
+                let created = create(stream);
+                let changed = matches!(created, Ok(true));
+                (created, changed)
+            }
+            Some(_) => (Err(BoopError::wrong_type()), false),
+            None if mkstream => {
+                let mut stream = BoopStream::default();
+                let created = create(&mut stream);
+                *value = Some(DataType::Stream(stream));
+                (created, true)
+            }
+            None => (Err(BoopError::no_exist()), false),
+        })
+    }
+
+    /// Reads entries of the stream held by `key` on behalf of `consumer` in `group`, up to `count`
+    /// of them.
+    ///
+    /// Without `from`, the group's next undelivered entries are delivered to the consumer and
+    /// added to the group's pending entries list, unless `noack` is set. They stay pending until
+    /// they are acknowledged with `Store::xack`, which gives at-least-once delivery.
+    ///
+    /// With `from`, the entries already pending for this consumer with IDs after `from` are
+    /// delivered again, which is how a consumer recovers after a restart. Their fields are `None`
+    /// if the entry has since been trimmed.
+    ///
+    /// A group that doesn't exist returns a `no_exist` error. A pending entries list that is full,
+    /// at `BoopStream::MAX_LEN` entries, returns a `too_large` error instead of new entries.
+    pub fn xreadgroup(
+        &self,
+        key: &DataType,
+        group: &DataType,
+        consumer: &DataType,
+        from: Option<StreamId>,
+        count: Option<usize>,
+        noack: bool,
+    ) -> Result<Vec<(StreamId, Option<Fields>)>, DataType> {
+        let now = self.now();
+        let count = count.unwrap_or(usize::MAX);
+
+        with_stream(self, key, |stream| {
+            let BoopStream {
+                entries, groups, ..
+            } = stream;
+            let group = groups.get_mut(group).ok_or_else(BoopError::no_exist)?;
+
+            let Some(from) = from else {
+                let room = BoopStream::MAX_LEN - group.pending.len();
+                let count = if noack { count } else { count.min(room) };
+
+                let mut delivered = entries
+                    .range(group.last_delivered..)
+                    .skip_while(|(id, _)| **id == group.last_delivered)
+                    .peekable();
+                if count == 0 && delivered.peek().is_some() {
+                    return Err(BoopError::too_large());
+                }
+
+                let delivered: Vec<(StreamId, Option<Fields>)> = delivered
+                    .take(count)
+                    .map(|(id, fields)| (*id, Some(fields.to_owned())))
+                    .collect();
+
+                for (id, _) in &delivered {
+                    group.last_delivered = *id;
+                    if !noack {
+                        let pending = PendingEntry {
+                            consumer: consumer.to_owned(),
+                            delivered_at: now,
+                            deliveries: 1,
+                        };
+                        group.pending.insert(*id, pending);
+                    }
+                }
+                let changed = !delivered.is_empty();
+                return Ok((delivered, changed));
+            };
+
+            let redelivered: Vec<(StreamId, Option<Fields>)> = group
+                .pending
+                .iter_mut()
+                .filter(|(id, pending)| **id > from && pending.consumer == *consumer)
+                .take(count)
+                .map(|(id, pending)| {
+                    pending.delivered_at = now;
+                    pending.deliveries += 1;
+                    (*id, entries.get(id).cloned())
+                })
+                .collect();
+            let changed = !redelivered.is_empty();
+            Ok((redelivered, changed))
+        })
+    }
+
+    /// Acknowledges entries that were delivered to `group`, removing them from its pending entries
+    /// list. Returns how many were pending, which is zero if the group doesn't exist.
+    pub fn xack(
+        &self,
+        key: &DataType,
+        group: &DataType,
+        ids: &[StreamId],
+    ) -> Result<usize, DataType> {
+        with_stream(self, key, |stream| {
+            let acked = match stream.groups.get_mut(group) {
+                Some(group) => ids
+                    .iter()
+                    .filter(|id| group.pending.remove(id).is_some())
+                    .count(),
+                None => 0,
+            };
+            Ok((acked, acked > 0))
+        })
+    }
+
+    /// Returns the pending entries list of `group`, in ID order. A group that doesn't exist
+    /// returns a `no_exist` error.
+    pub fn xpending(&self, key: &DataType, group: &DataType) -> Result<Vec<Pending>, DataType> {
+        let now = self.now();
+
+        view_stream(self, key, |stream| {
+            let group = stream.groups.get(group).ok_or_else(BoopError::no_exist)?;
+
+            Ok(group
+                .pending
+                .iter()
+                .map(|(id, pending)| Pending {
+                    id: *id,
+                    consumer: pending.consumer.to_owned(),
+                    idle: now.saturating_sub(pending.delivered_at),
+                    deliveries: pending.deliveries,
+                })
+                .collect())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{XAddId, XTrim};
+    use crate::{
+        data_type::{BoopError, BoopStream, DataType, Int, StreamId},
+        store::{clock::ManualClock, SetTtl, Store},
+    };
+    use std::sync::Arc;
+
+    fn fields(v: u8) -> Vec<(DataType, DataType)> {
+        vec![(Int::new_u8(0), Int::new_u8(v))]
+    }
+
+    fn add(store: &Store, key: &DataType, ms: u64, seq: u64) {
+        let id = StreamId::new(ms, seq);
+        assert_eq!(
+            store.xadd(key, XAddId::Explicit(id), fields(seq as u8), None),
+            Ok(id)
+        );
+    }
+
+    #[test]
+    fn ids_always_increase() {
+        let clock = Arc::new(ManualClock::new(1_000));
+        let store = Store::with_clock(clock.clone());
+        let key = Int::new_u8(1);
+
+        assert_eq!(
+            store.xadd(&key, XAddId::Auto, fields(0), None),
+            Ok(StreamId::new(1_000, 0))
+        );
+        assert_eq!(
+            store.xadd(&key, XAddId::Auto, fields(0), None),
+            Ok(StreamId::new(1_000, 1))
+        );
+        clock.advance(1);
+        assert_eq!(
+            store.xadd(&key, XAddId::Auto, fields(0), None),
+            Ok(StreamId::new(1_001, 0))
+        );
+        assert_eq!(
+            store.xadd(&key, XAddId::AutoSeq(1_001), fields(0), None),
+            Ok(StreamId::new(1_001, 1))
+        );
+        assert_eq!(
+            store.xadd(&key, XAddId::AutoSeq(1_000), fields(0), None),
+            Err(BoopError::out_of_range())
+        );
+        assert_eq!(
+            store.xadd(
+                &key,
+                XAddId::Explicit(StreamId::new(1_001, 1)),
+                fields(0),
+                None
+            ),
+            Err(BoopError::out_of_range())
+        );
+        assert_eq!(store.xlen(&key), Ok(4));
+
+        // The clock going backwards doesn't make IDs go backwards
+        let store = Store::with_clock(Arc::new(ManualClock::new(5)));
+        add(&store, &key, 10, 0);
+        assert_eq!(
+            store.xadd(&key, XAddId::Auto, fields(0), None),
+            Ok(StreamId::new(10, 1))
+        );
+
+        let store = Store::new();
+        assert_eq!(
+            store.xadd(&key, XAddId::AutoSeq(0), fields(0), None),
+            Ok(StreamId::new(0, 1))
+        );
+        assert_eq!(
+            store.xadd(&key, XAddId::Explicit(StreamId::MIN), fields(0), None),
+            Err(BoopError::out_of_range())
+        );
+    }
+
+    #[test]
+    fn range_and_trim() {
+        let store = Store::new();
+        let key = Int::new_u8(1);
+        for seq in 1..=5 {
+            add(&store, &key, 1, seq);
+        }
+
+        let ids = |entries: Vec<(StreamId, Vec<(DataType, DataType)>)>| {
+            entries
+                .into_iter()
+                .map(|(id, _)| id.seq)
+                .collect::<Vec<_>>()
+        };
+        let range = |start, end, count, rev| {
+            ids(store
+                .xrange(
+                    &key,
+                    StreamId::new(1, start),
+                    StreamId::new(1, end),
+                    count,
+                    rev,
+                )
+                .unwrap())
+        };
+        assert_eq!(range(2, 4, None, false), vec![2, 3, 4]);
+        assert_eq!(range(0, 9, Some(2), false), vec![1, 2]);
+        assert_eq!(range(0, 9, Some(2), true), vec![5, 4]);
+        assert_eq!(range(4, 2, None, false), Vec::<u64>::new());
+
+        assert_eq!(store.xtrim(&key, XTrim::MinId(StreamId::new(1, 2))), Ok(1));
+        assert_eq!(store.xtrim(&key, XTrim::MaxLen(2)), Ok(2));
+        assert_eq!(range(0, 9, None, false), vec![4, 5]);
+
+        // An emptied stream is kept, along with its last ID
+        assert_eq!(store.xtrim(&key, XTrim::MaxLen(0)), Ok(2));
+        assert_eq!(store.xlen(&key), Ok(0));
+        assert_eq!(
+            store.xadd(&key, XAddId::AutoSeq(1), fields(0), Some(XTrim::MaxLen(0))),
+            Ok(StreamId::new(1, 6))
+        );
+        assert_eq!(store.xtrim(&Int::new_u8(2), XTrim::MaxLen(0)), Ok(0));
+        assert_eq!(store.get(&Int::new_u8(2)), None);
+
+        store.set(&Int::new_u8(3), &Int::new_u8(1), SetTtl::Clear);
+        assert_eq!(store.xlen(&Int::new_u8(3)), Err(BoopError::wrong_type()));
+    }
+
+    #[test]
+    fn streams_are_bounded() {
+        let store = Store::new();
+        let key = Int::new_u8(1);
+        for seq in 1..=BoopStream::MAX_LEN as u64 {
+            add(&store, &key, 0, seq);
+        }
+
+        let next = XAddId::Explicit(StreamId::new(1, 0));
+        assert_eq!(
+            store.xadd(&key, next, fields(0), None),
+            Err(BoopError::too_large())
+        );
+        assert_eq!(store.xlen(&key), Ok(BoopStream::MAX_LEN));
+        assert_eq!(
+            store.xadd(&key, next, fields(0), Some(XTrim::MaxLen(10))),
+            Ok(StreamId::new(1, 0))
+        );
+        assert_eq!(store.xlen(&key), Ok(10));
+    }
+
+    #[test]
+    fn consumer_groups_deliver_at_least_once() {
+        let clock = Arc::new(ManualClock::new(100));
+        let store = Store::with_clock(clock.clone());
+        let (key, group) = (Int::new_u8(1), Int::new_u8(2));
+        let (alice, bob) = (Int::new_u8(10), Int::new_u8(11));
+
+        assert_eq!(
+            store.xgroup_create(&key, &group, None, false),
+            Err(BoopError::no_exist())
+        );
+        assert_eq!(store.xgroup_create(&key, &group, None, true), Ok(true));
+        assert_eq!(store.xgroup_create(&key, &group, None, true), Ok(false));
+        assert_eq!(
+            store.xreadgroup(&key, &Int::new_u8(3), &alice, None, None, false),
+            Err(BoopError::no_exist())
+        );
+
+        for seq in 1..=3 {
+            add(&store, &key, 1, seq);
+        }
+
+        let read = |consumer: &DataType, from, count| {
+            store
+                .xreadgroup(&key, &group, consumer, from, count, false)
+                .unwrap()
+                .into_iter()
+                .map(|(id, fields)| (id.seq, fields.is_some()))
+                .collect::<Vec<_>>()
+        };
+
+        // New entries are shared out between consumers
+        assert_eq!(read(&alice, None, Some(2)), vec![(1, true), (2, true)]);
+        assert_eq!(read(&bob, None, None), vec![(3, true)]);
+        assert_eq!(read(&bob, None, None), vec![]);
+
+        // Alice acknowledges one entry and crashes, then reads her history when she comes back
+        let id = |seq| StreamId::new(1, seq);
+        assert_eq!(store.xack(&key, &group, &[id(1), id(9)]), Ok(1));
+        store.xtrim(&key, XTrim::MaxLen(0)).unwrap();
+        clock.advance(50);
+        assert_eq!(read(&alice, Some(StreamId::MIN), None), vec![(2, false)]);
+
+        let pending = store.xpending(&key, &group).unwrap();
+        assert_eq!(pending.len(), 2);
+        assert_eq!(
+            (
+                pending[0].id,
+                &pending[0].consumer,
+                pending[0].idle,
+                pending[0].deliveries
+            ),
+            (id(2), &alice, 0, 2)
+        );
+        assert_eq!(
+            (
+                pending[1].id,
+                &pending[1].consumer,
+                pending[1].idle,
+                pending[1].deliveries
+            ),
+            (id(3), &bob, 50, 1)
+        );
+
+        assert_eq!(store.xack(&key, &group, &[id(2), id(3)]), Ok(2));
+        assert_eq!(store.xpending(&key, &group), Ok(vec![]));
+
+        // NOACK entries are never pending
+        add(&store, &key, 2, 0);
+        assert_eq!(
+            store
+                .xreadgroup(&key, &group, &bob, None, None, true)
+                .map(|entries| entries.len()),
+            Ok(1)
+        );
+        assert_eq!(store.xpending(&key, &group), Ok(vec![]));
+    }
+
+    #[test]
+    fn groups_can_start_anywhere() {
+        let store = Store::new();
+        let key = Int::new_u8(1);
+        for seq in 1..=3 {
+            add(&store, &key, 1, seq);
+        }
+
+        let (all, new) = (Int::new_u8(2), Int::new_u8(3));
+        store
+            .xgroup_create(&key, &all, Some(StreamId::MIN), false)
+            .unwrap();
+        store.xgroup_create(&key, &new, None, false).unwrap();
+        add(&store, &key, 1, 4);
+
+        let consumer = Int::new_u8(10);
+        let read = |group| store.xreadgroup(&key, group, &consumer, None, None, false);
+        assert_eq!(read(&all).map(|entries| entries.len()), Ok(4));
+        assert_eq!(read(&new).map(|entries| entries.len()), Ok(1));
+    }
+}
@@ -0,0 +1,215 @@
+use super::{list::resolve_range, written, Store};
+use crate::data_type::{BoopError, BoopString, DataType};
+use bytes::Bytes;
+
+/// Runs `f` against the bytes of the string held by `key`. Keys that don't exist are treated as an
+/// empty string, which is only written back to the store if `f` leaves it non-empty. Keys holding
+/// anything other than a string return a `wrong_type` error. `f` also returns whether it changed
+/// the string, see `Store::update`.
+///
+/// The bytes are only copied if the stored `Bytes` is shared with a reader, such as a client that
+/// is still holding the result of a GET.
+pub(super) fn with_string<R>(
+    store: &Store,
+    key: &DataType,
+    f: impl FnOnce(&mut Vec<u8>) -> Result<(R, bool), DataType>,
+) -> Result<R, DataType> {
+    store.update(key, |value| {
+        let existed = value.is_some();
+        let mut string = match value.take() {
+            Some(DataType::String(BoopString(bytes))) => Vec::from(bytes),
+            None => Vec::new(),
+            Some(other) => {
+                *value = Some(other);
+                return (Err(BoopError::wrong_type()), false);
+            }
+        };
+
+        let (result, changed) = written(f(&mut string));
+        if existed || !string.is_empty() {
+            *value = Some(BoopString::new_wrapped(Bytes::from(string)));
+        }
+
+        (result, changed)
+    })
+}
+
+/// Runs `f` against the bytes of the string held by `key` without modifying it, under the shard's
+/// read lock. Keys that don't exist are treated as an empty string.
+pub(super) fn view_string<R>(
+    store: &Store,
+    key: &DataType,
+    f: impl FnOnce(&Bytes) -> Result<R, DataType>,
+) -> Result<R, DataType> {
+    store.view(key, |value| match value {
+        Some(DataType::String(BoopString(bytes))) => f(bytes),
+        None => f(&Bytes::new()),
+        Some(_) => Err(BoopError::wrong_type()),
+    })
+}
+
+impl Store {
+    /// Appends `suffix` to the string held by `key`, creating it if the key does not exist. Returns
+    /// the length of the string once appended, or a `too_large` error if it would grow past
+    /// `BoopString::MAX_LEN`, in which case the string is left untouched.
+    pub fn append(&self, key: &DataType, suffix: &[u8]) -> Result<usize, DataType> {
+        with_string(self, key, |string| {
+            if string.len() + suffix.len() > BoopString::MAX_LEN {
+                return Err(BoopError::too_large());
+            }
+
+            string.extend_from_slice(suffix);
+            Ok((string.len(), !suffix.is_empty()))
+        })
+    }
+
+    /// Returns the length of the string held by `key`, which is zero if the key does not exist
+    pub fn strlen(&self, key: &DataType) -> Result<usize, DataType> {
+        view_string(self, key, |string| Ok(string.len()))
+    }
+
+    /// Returns the bytes between `start` and `stop` of the string held by `key`, inclusive. Negative
+    /// offsets count from the end of the string, and offsets past either end are clamped.
+    pub fn get_range(&self, key: &DataType, start: i64, stop: i64) -> Result<Bytes, DataType> {
+        view_string(self, key, |string| {
+            Ok(match resolve_range(start, stop, string.len()) {
+                Some((start, stop)) => string.slice(start..=stop),
+                None => Bytes::new(),
+            })
+        })
+    }
+
+    /// Overwrites the string held by `key` with `bytes`, starting at `offset`. If the string is
+    /// shorter than `offset`, it is padded with zero bytes first. Returns the length of the string
+    /// once written, or a `too_large` error if it would grow past `BoopString::MAX_LEN`.
+    pub fn set_range(
+        &self,
+        key: &DataType,
+        offset: usize,
+        bytes: &[u8],
+    ) -> Result<usize, DataType> {
+        with_string(self, key, |string| {
+            if bytes.is_empty() {
+                return Ok((string.len(), false));
+            }
+
+            let end = offset.saturating_add(bytes.len());
+            if end > BoopString::MAX_LEN {
+                return Err(BoopError::too_large());
+            }
+
+            if string.len() < end {
+                string.resize(end, 0);
+            }
+            string[offset..end].copy_from_slice(bytes);
+            Ok((string.len(), true))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        data_type::{BoopError, BoopString, Int},
+        store::{SetTtl, Store},
+    };
+    use bytes::Bytes;
+
+    #[test]
+    fn append_and_strlen() {
+        let store = Store::new();
+        let key = Int::new_u8(1);
+
+        assert_eq!(store.strlen(&key), Ok(0));
+        assert_eq!(store.append(&key, b"hello"), Ok(5));
+        assert_eq!(store.append(&key, b" world"), Ok(11));
+        assert_eq!(store.strlen(&key), Ok(11));
+        assert_eq!(
+            store.get(&key),
+            Some(BoopString::new_wrapped(Bytes::from_static(b"hello world")))
+        );
+    }
+
+    #[test]
+    fn get_range_clamps_and_counts_from_the_end() {
+        let store = Store::new();
+        let key = Int::new_u8(1);
+        store.append(&key, b"This is a string").unwrap();
+
+        assert_eq!(store.get_range(&key, 0, 3), Ok(Bytes::from_static(b"This")));
+        assert_eq!(
+            store.get_range(&key, -3, -1),
+            Ok(Bytes::from_static(b"ing"))
+        );
+        assert_eq!(
+            store.get_range(&key, 10, 100),
+            Ok(Bytes::from_static(b"string"))
+        );
+        assert_eq!(store.get_range(&key, 5, 1), Ok(Bytes::new()));
+        assert_eq!(store.get_range(&Int::new_u8(2), 0, -1), Ok(Bytes::new()));
+    }
+
+    #[test]
+    fn set_range_overwrites_and_zero_pads() {
+        let store = Store::new();
+        let key = Int::new_u8(1);
+        store.append(&key, b"Hello World").unwrap();
+
+        assert_eq!(store.set_range(&key, 6, b"Redis"), Ok(11));
+        assert_eq!(
+            store.get_range(&key, 0, -1),
+            Ok(Bytes::from_static(b"Hello Redis"))
+        );
+
+        let padded = Int::new_u8(2);
+        assert_eq!(store.set_range(&padded, 3, b"ab"), Ok(5));
+        assert_eq!(
+            store.get_range(&padded, 0, -1),
+            Ok(Bytes::from_static(b"\0\0\0ab"))
+        );
+
+        // An empty write to a missing key doesn't create it
+        assert_eq!(store.set_range(&Int::new_u8(3), 10, b""), Ok(0));
+        assert_eq!(store.get(&Int::new_u8(3)), None);
+    }
+
+    #[test]
+    fn length_limit_is_respected() {
+        let store = Store::new();
+        let key = Int::new_u8(1);
+        store
+            .set_range(&key, BoopString::MAX_LEN - 1, b"a")
+            .unwrap();
+
+        assert_eq!(store.append(&key, b"b"), Err(BoopError::too_large()));
+        assert_eq!(
+            store.set_range(&key, BoopString::MAX_LEN, b"b"),
+            Err(BoopError::too_large())
+        );
+        assert_eq!(
+            store.set_range(&key, usize::MAX, b"b"),
+            Err(BoopError::too_large())
+        );
+        assert_eq!(store.strlen(&key), Ok(BoopString::MAX_LEN));
+    }
+
+    #[test]
+    fn wrong_type_and_ttl() {
+        let store = Store::new();
+        let key = Int::new_u8(1);
+        store.set(&key, &Int::new_u8(1), SetTtl::Clear);
+
+        assert_eq!(store.append(&key, b"a"), Err(BoopError::wrong_type()));
+        assert_eq!(store.strlen(&key), Err(BoopError::wrong_type()));
+        assert_eq!(store.get(&key), Some(Int::new_u8(1)));
+
+        let string = Int::new_u8(2);
+        store.set(
+            &string,
+            &BoopString::new_wrapped(Bytes::from_static(b"a")),
+            SetTtl::ExpireIn(60_000),
+        );
+        store.append(&string, b"b").unwrap();
+        assert!(store.ttl(&string).unwrap().is_some());
+    }
+}
@@ -0,0 +1,218 @@
+//! Isolation for transactions. Every command runs while holding the store's gate shared, so any
+//! amount of them can run at once, while a transaction holds it exclusively so that no other
+//! command can observe or change the store part way through it.
+
+use super::Store;
+use crate::data_type::DataType;
+use std::{
+    cell::Cell,
+    sync::{Arc, PoisonError},
+};
+
+thread_local! {
+    /// The gate that this thread holds exclusively, if any, identified by its address. The
+    /// commands of a transaction run on the thread that holds the gate, and must not try to take
+    /// it again.
+    static EXCLUSIVE: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Clears the thread's exclusive gate once a transaction finishes, even if it panicked
+struct Exclusive(usize);
+
+impl Drop for Exclusive {
+    fn drop(&mut self) {
+        EXCLUSIVE.with(|held| held.set(self.0));
+    }
+}
+
+impl Store {
+    /// Whether this thread is running a transaction against this store
+    pub(super) fn in_transaction(&self) -> bool {
+        EXCLUSIVE.with(|held| held.get()) == Arc::as_ptr(&self.gate) as usize
+    }
+
+    /// Runs `f` alongside any other commands, but never part way through a transaction. Inside a
+    /// transaction `f` simply runs, as the transaction already holds the store to itself.
+    pub fn shared<R>(&self, f: impl FnOnce() -> R) -> R {
+        if self.in_transaction() {
+            return f();
+        }
+
+        let _gate = self.gate.read().unwrap_or_else(PoisonError::into_inner);
+        f()
+    }
+
+    /// Runs `f` alongside any other commands, like `Store::shared`, for a command that works on
+    /// both this store and `other`. The two gates are always taken in address order, so two such
+    /// commands can never deadlock each other. It must not be called inside a transaction.
+    pub fn shared_with<R>(&self, other: &Store, f: impl FnOnce() -> R) -> R {
+        if Arc::ptr_eq(&self.gate, &other.gate) {
+            return self.shared(f);
+        }
+
+        let (first, second) = if Arc::as_ptr(&self.gate) < Arc::as_ptr(&other.gate) {
+            (self, other)
+        } else {
+            (other, self)
+        };
+        first.shared(|| second.shared(f))
+    }
+
+    /// Runs `f` as a transaction, once every command that is already running has finished, and
+    /// without any other command running until it returns. Commands that `f` runs through
+    /// `Store::shared` run straight away.
+    ///
+    /// The gates of `others` are held exclusively as well, so that none of them change until `f`
+    /// returns either, though commands against them don't run inside the transaction. The gates
+    /// are taken in address order, like in `Store::shared_with`, so a transaction can never
+    /// deadlock with a command that works on several stores.
+    pub fn exclusive<R>(&self, others: &[Store], f: impl FnOnce() -> R) -> R {
+        if self.in_transaction() {
+            return f();
+        }
+
+        let mut gates: Vec<_> = others.iter().chain([self]).map(|s| &s.gate).collect();
+        gates.sort_by_key(|gate| Arc::as_ptr(gate));
+        gates.dedup_by(|a, b| Arc::ptr_eq(a, b));
+        let _gates: Vec<_> = gates
+            .into_iter()
+            .map(|gate| gate.write().unwrap_or_else(PoisonError::into_inner))
+            .collect();
+
+        let previous = EXCLUSIVE.with(|held| held.replace(Arc::as_ptr(&self.gate) as usize));
+        let _exclusive = Exclusive(previous);
+        f()
+    }
+
+    /// Returns the version of the entry held by `key`, or `None` if the key does not exist. The
+    /// version changes every time the key is written to, including when its expiry changes, and is
+    /// never reused, so a key that still has the same version has not been touched since.
+    ///
+    /// A write that leaves the value as it was, such as removing a member that wasn't in a set,
+    /// may still change the version.
+    pub fn version(&self, key: &DataType) -> Option<u64> {
+        let now = self.now();
+        self.read_entry(key, |entry| {
+            entry
+                .filter(|entry| !entry.is_expired(now))
+                .map(|entry| entry.version)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        data_type::{BoopArray, Int},
+        store::{list::End, SetTtl, Store},
+    };
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        thread,
+        time::Duration,
+    };
+
+    #[test]
+    fn versions_change_on_every_write() {
+        let store = Store::new();
+        let key = Int::new_u8(1);
+        assert_eq!(store.version(&key), None);
+
+        store.set(&key, &Int::new_u8(1), SetTtl::Clear);
+        let mut seen = vec![store.version(&key).unwrap()];
+        let mut check = |store: &Store| {
+            let version = store.version(&key).unwrap();
+            assert!(!seen.contains(&version));
+            seen.push(version);
+        };
+
+        store.set(&key, &Int::new_u8(1), SetTtl::Clear);
+        check(&store);
+        store.expire(&key, 1_000);
+        check(&store);
+        store.persist(&key);
+        check(&store);
+        store.del(std::slice::from_ref(&key));
+        assert_eq!(store.version(&key), None);
+        store.push(&key, vec![Int::new_u8(1)], End::Back).unwrap();
+        check(&store);
+        store.push(&key, vec![Int::new_u8(2)], End::Back).unwrap();
+        check(&store);
+
+        // Reading never changes the version
+        let version = store.version(&key);
+        store.get(&key);
+        store.llen(&key).unwrap();
+        assert_eq!(store.version(&key), version);
+
+        store.rename(&key, &Int::new_u8(2));
+        assert_eq!(store.version(&key), None);
+        assert!(!seen.contains(&store.version(&Int::new_u8(2)).unwrap()));
+    }
+
+    #[test]
+    fn exclusive_waits_for_shared_and_nests() {
+        let store = Store::new();
+        let running = Arc::new(AtomicBool::new(false));
+
+        let reader = {
+            let (store, running) = (store.clone(), running.clone());
+            thread::spawn(move || {
+                store.shared(|| {
+                    running.store(true, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(50));
+                    store.set(&Int::new_u8(1), &Int::new_u8(1), SetTtl::Clear);
+                })
+            })
+        };
+        while !running.load(Ordering::SeqCst) {
+            thread::yield_now();
+        }
+
+        // The transaction only starts once the command holding the gate has finished, and the
+        // commands it runs don't wait on the gate it already holds
+        let seen = store.exclusive(&[], || {
+            store.shared(|| store.exclusive(&[], || store.get(&Int::new_u8(1))))
+        });
+        assert_eq!(seen, Some(Int::new_u8(1)));
+        reader.join().unwrap();
+
+        // Once the transaction has finished, the gate is free again
+        store.shared(|| {
+            store.set(
+                &Int::new_u8(2),
+                &BoopArray::new_wrapped(vec![]),
+                SetTtl::Clear,
+            )
+        });
+        assert!(!store.in_transaction());
+    }
+
+    #[test]
+    fn exclusive_holds_every_gate() {
+        let (store, other) = (Store::new(), Store::new());
+
+        let written = store.exclusive(&[other.clone(), store.clone()], || {
+            let writer = {
+                let other = other.clone();
+                thread::spawn(move || {
+                    other.shared(|| other.set(&Int::new_u8(1), &Int::new_u8(1), SetTtl::Clear))
+                })
+            };
+            thread::sleep(Duration::from_millis(50));
+
+            // Only the store the transaction runs against is written to straight away
+            assert!(store.in_transaction() && !other.in_transaction());
+            store.shared(|| store.set(&Int::new_u8(1), &Int::new_u8(1), SetTtl::Clear));
+            assert_eq!(other.get(&Int::new_u8(1)), None);
+            writer
+        });
+
+        written.join().unwrap();
+        assert_eq!(other.get(&Int::new_u8(1)), Some(Int::new_u8(1)));
+        assert!(!store.in_transaction());
+    }
+}